@@ -1,12 +1,18 @@
-use reqwest::blocking::Client;
 use serde_json;
-use std::{fs::File, io::BufReader, path::Path};
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read},
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use toml;
 use url::Url;
 use zip::ZipArchive;
 
 use crate::config::Config;
 use crate::error::{Error, Result};
-use crate::http::Http;
+use crate::http::{ClientIdentity, Http};
 use serde::Deserialize;
 use serde::Serialize;
 
@@ -19,19 +25,25 @@ pub trait AuthPlusApi {
 pub struct AuthPlus;
 
 impl AuthPlusApi for AuthPlus {
+    /// Fetch a token with the OAuth2 client, or else set up any client certificate for mutual TLS.
     fn refresh_token(config: &mut Config) -> Result<Option<AccessToken>> {
         if let Some(oauth2) = config.credentials()?.oauth2()? {
             debug!("fetching access token from auth-plus server {}", oauth2.server);
-            let req = Client::new()
+            let req = Http::client()
                 .post(&format!("{}/token", oauth2.server))
                 .basic_auth(oauth2.client_id, Some(oauth2.client_secret))
                 .form(&[("grant_type", "client_credentials")]);
 
-            let resp = Http::send(req, None)?.json()?;
+            let mut resp: AccessToken = Http::send_live(req, None)?.json()?;
+            resp.set_expiry(SystemTime::now());
             debug!("{:?}", resp);
             Ok(Some(resp))
         } else {
             debug!("skipping oauth2 authentication...");
+            if let Some(identity) = config.credentials()?.identity() {
+                debug!("using the client certificate from the credentials");
+                Http::use_credentials_identity(identity);
+            }
             Ok(None)
         }
     }
@@ -42,10 +54,47 @@ impl AuthPlusApi for AuthPlus {
 pub struct AccessToken {
     pub access_token: String,
     pub scope: Option<String>,
+    /// A namespace read from the credentials when the scope has none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials_namespace: Option<String>,
+    /// A namespace set with `--namespace` or the config, taking precedence over the scope.
+    #[serde(skip)]
+    pub namespace_override: Option<String>,
+    /// Seconds the token is valid for, as returned by the token endpoint.
+    #[serde(default, skip_serializing)]
+    pub expires_in: Option<u64>,
+    /// When the token expires, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
 }
 
+/// Refresh tokens this close to expiry, so requests do not start failing part way through a command.
+pub const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
 impl AccessToken {
+    /// Record when the token expires from the `expires_in` of a fresh token.
+    pub fn set_expiry(&mut self, now: SystemTime) {
+        if let Some(expires_in) = self.expires_in {
+            self.expires_at = Some(unix_secs(now) + expires_in);
+        }
+    }
+
+    /// Check whether the token expires within `margin` of `now`. Tokens without an expiry are kept.
+    pub fn expires_within(&self, now: SystemTime, margin: Duration) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= unix_secs(now) + margin.as_secs(),
+            None => false,
+        }
+    }
+
+    /// Return when the token expires, if known.
+    pub fn expiry(&self) -> Option<SystemTime> { self.expires_at.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)) }
+
+    /// Read the namespace from the token scope, falling back to the credentials.
     pub fn namespace(&self) -> Result<String> {
+        if let Some(ref namespace) = self.namespace_override {
+            return Ok(namespace.clone());
+        }
         let token_scope = self.scope.clone().unwrap_or("".to_owned()).clone();
 
         let scopes = token_scope
@@ -56,34 +105,103 @@ impl AccessToken {
 
         match scopes.len() {
             1 => Ok(scopes.first().unwrap().to_string()),
-            0 => Err(Error::Token("namespace not found".into())),
+            0 => self
+                .credentials_namespace
+                .clone()
+                .ok_or_else(|| Error::Token("namespace not found in token scope or credentials".into())),
             _ => Err(Error::Token(format!("multiple namespaces found: {:?}", scopes))),
         }
     }
 }
 
-/// Parsed credentials from `treehub.json` in `credentials.zip`.
+fn unix_secs(time: SystemTime) -> u64 { time.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0) }
+
+/// Parsed credentials from `treehub.json` in `credentials.zip`, or from a flat JSON/TOML file.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Credentials {
     no_auth: Option<bool>,
     oauth2: Option<OAuth2>,
     ostree: Ostree,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reposerver: Option<Url>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<String>,
+    /// A client certificate for mutual TLS, from `autoprov_credentials.p12` in a zip.
+    #[serde(skip)]
+    identity: Option<ClientIdentity>,
 }
 
+/// The PKCS#12 client certificate in `credentials.zip`, with an empty password.
+const IDENTITY_FILE: &str = "autoprov_credentials.p12";
+
 impl Credentials {
-    pub fn parse(credentials_zip: impl AsRef<Path>) -> Result<Self> {
-        debug!("reading treehub.json from zip file: {:?}", credentials_zip.as_ref());
-        let file = File::open(credentials_zip)?;
-        let mut archive = ZipArchive::new(BufReader::new(file))?;
-        let treehub = archive.by_name("treehub.json")?;
-        Ok(serde_json::from_reader(treehub)?)
+    /// Parse credentials from a zip, `.json` or `.toml` file, detected by extension.
+    pub fn parse(credentials: impl AsRef<Path>) -> Result<Self> {
+        let path = credentials.as_ref();
+        match CredentialsFormat::from_path(path) {
+            CredentialsFormat::Json => {
+                debug!("reading credentials from json file: {:?}", path);
+                Ok(serde_json::from_slice(&fs::read(path)?)?)
+            }
+            CredentialsFormat::Toml => {
+                debug!("reading credentials from toml file: {:?}", path);
+                Ok(toml::from_str(&fs::read_to_string(path)?)?)
+            }
+            CredentialsFormat::Zip => {
+                debug!("reading treehub.json from zip file: {:?}", path);
+                let file = File::open(path)?;
+                let mut archive = ZipArchive::new(BufReader::new(file))?;
+                let mut credentials: Credentials = serde_json::from_reader(archive.by_name("treehub.json")?)?;
+                if let Ok(mut p12) = archive.by_name(IDENTITY_FILE) {
+                    debug!("reading client certificate {} from zip file", IDENTITY_FILE);
+                    let mut der = Vec::new();
+                    p12.read_to_end(&mut der)?;
+                    credentials.identity = Some(ClientIdentity::from_pkcs12(der, "")?);
+                }
+                Ok(credentials)
+            }
+        }
+    }
+
+    /// Read the TUF Reposerver URL from `tufrepo.url` in a zip, or the `reposerver` field of a flat file.
+    pub fn reposerver_url(credentials: impl AsRef<Path>) -> Result<Url> {
+        let path = credentials.as_ref();
+        match CredentialsFormat::from_path(path) {
+            CredentialsFormat::Zip => {
+                debug!("reading tufrepo.url from credentials.zip");
+                let file = File::open(path)?;
+                let mut archive = ZipArchive::new(BufReader::new(file))?;
+                let mut tufrepo = archive.by_name("tufrepo.url")?;
+                let mut contents = String::new();
+                let _ = tufrepo.read_to_string(&mut contents)?;
+                Ok(Url::from_str(contents.trim())?)
+            }
+            _ => Self::parse(path)?.reposerver.ok_or_else(|| {
+                Error::NotFound(
+                    "Reposerver URL".into(),
+                    Some("Pass --reposerver or set `reposerver` in the credentials file.".into()),
+                )
+            }),
+        }
     }
 
+    /// Return the treehub URL of the OSTree repository.
+    pub fn treehub_url(&self) -> &Url { &self.ostree.server }
+
+    /// Return the namespace set in the credentials, if any.
+    pub fn namespace(&self) -> Option<&str> { self.namespace.as_deref() }
+
+    /// Return the client certificate for mutual TLS, if the credentials have one.
+    pub fn identity(&self) -> Option<&ClientIdentity> { self.identity.as_ref() }
+
+    /// Return the OAuth2 client, or `None` when requests don't need a token.
     fn oauth2(&self) -> Result<Option<OAuth2>> {
         if let Some(true) = self.no_auth {
             Ok(None)
         } else if let Some(ref oauth2) = self.oauth2 {
             Ok(Some(oauth2.clone()))
+        } else if self.identity.is_some() {
+            Ok(None)
         } else {
             Err(Error::Auth("no parseable auth method from credentials.zip".into()))
         }
@@ -101,3 +219,160 @@ struct OAuth2 {
 struct Ostree {
     server: Url,
 }
+
+/// Supported credentials file formats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CredentialsFormat {
+    Zip,
+    Json,
+    Toml,
+}
+
+impl CredentialsFormat {
+    /// Detect the format from the file extension, defaulting to a zip.
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_lowercase()) {
+            Some(ref ext) if ext == "json" => CredentialsFormat::Json,
+            Some(ref ext) if ext == "toml" => CredentialsFormat::Toml,
+            _ => CredentialsFormat::Zip,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, io::Write, path::PathBuf};
+    use zip::{write::FileOptions, ZipWriter};
+
+    const TREEHUB: &str = r#"{
+        "oauth2": {"server": "https://auth.example.com", "client_id": "id", "client_secret": "secret"},
+        "ostree": {"server": "https://treehub.example.com/api/v3/"}
+    }"#;
+
+    fn test_file(name: &str) -> PathBuf { env::temp_dir().join(format!("ota-cli-{}-{}", std::process::id(), name)) }
+
+    #[test]
+    fn parse_credentials_zip() {
+        let path = test_file("credentials.zip");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("treehub.json", FileOptions::default()).unwrap();
+        zip.write_all(TREEHUB.as_bytes()).unwrap();
+        zip.start_file("tufrepo.url", FileOptions::default()).unwrap();
+        zip.write_all(b"https://repo.example.com/\n").unwrap();
+        zip.finish().unwrap();
+
+        let credentials = Credentials::parse(&path).expect("parse zip");
+        assert_eq!(credentials.oauth2().unwrap().unwrap().client_id, "id");
+        assert_eq!(Credentials::reposerver_url(&path).unwrap().as_str(), "https://repo.example.com/");
+    }
+
+    #[test]
+    fn parse_client_certificate_from_zip() {
+        let key = openssl::pkey::PKey::generate_ed25519().unwrap();
+        let mut name = openssl::x509::X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "device").unwrap();
+        let name = name.build();
+        let mut cert = openssl::x509::X509Builder::new().unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&openssl::asn1::Asn1Time::days_from_now(0).unwrap()).unwrap();
+        cert.set_not_after(&openssl::asn1::Asn1Time::days_from_now(1).unwrap()).unwrap();
+        cert.sign(&key, openssl::hash::MessageDigest::null()).unwrap();
+        let cert = cert.build();
+        ClientIdentity::from_pem(&cert.to_pem().unwrap(), &key.private_key_to_pem_pkcs8().unwrap()).expect("pem identity");
+        assert!(ClientIdentity::from_pem(&cert.to_pem().unwrap(), b"not a key").is_err());
+
+        let p12 = openssl::pkcs12::Pkcs12::builder().pkey(&key).cert(&cert).build2("").unwrap().to_der().unwrap();
+        let path = test_file("cert-credentials.zip");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("treehub.json", FileOptions::default()).unwrap();
+        zip.write_all(br#"{"ostree": {"server": "https://treehub.example.com/api/v3/"}}"#).unwrap();
+        zip.start_file(IDENTITY_FILE, FileOptions::default()).unwrap();
+        zip.write_all(&p12).unwrap();
+        zip.finish().unwrap();
+
+        let credentials = Credentials::parse(&path).expect("parse zip");
+        let settings = crate::http::HttpSettings { identity: credentials.identity().cloned(), ..Default::default() };
+        Http::build_client(&settings).expect("client with certificate");
+        assert!(credentials.oauth2().unwrap().is_none());
+        assert!(ClientIdentity::from_pkcs12(p12, "wrong").is_err());
+    }
+
+    #[test]
+    fn parse_flat_credentials() {
+        let json = test_file("credentials.json");
+        fs::write(&json, TREEHUB).unwrap();
+        let credentials = Credentials::parse(&json).expect("parse json");
+        assert_eq!(credentials.oauth2().unwrap().unwrap().server, "https://auth.example.com");
+        assert!(Credentials::reposerver_url(&json).is_err());
+
+        let toml = test_file("credentials.toml");
+        fs::write(&toml, r#"
+            reposerver = "https://repo.example.com/"
+
+            [oauth2]
+            server = "https://auth.example.com"
+            client_id = "id"
+            client_secret = "secret"
+
+            [ostree]
+            server = "https://treehub.example.com/api/v3/"
+        "#).unwrap();
+        let credentials = Credentials::parse(&toml).expect("parse toml");
+        assert_eq!(credentials.oauth2().unwrap().unwrap().client_secret, "secret");
+        assert_eq!(Credentials::reposerver_url(&toml).unwrap().as_str(), "https://repo.example.com/");
+    }
+
+    fn token(scope: Option<&str>, credentials_namespace: Option<&str>) -> AccessToken {
+        AccessToken {
+            access_token: "token".into(),
+            scope: scope.map(String::from),
+            credentials_namespace: credentials_namespace.map(String::from),
+            namespace_override: None,
+            expires_in: None,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn namespace_from_scope() {
+        let token = token(Some("openid namespace.acme"), Some("other"));
+        assert_eq!(token.namespace().unwrap(), "acme");
+    }
+
+    #[test]
+    fn namespace_override_beats_scope() {
+        let mut token = token(Some("namespace.acme namespace.other"), None);
+        assert!(token.namespace().is_err());
+        token.namespace_override = Some("other".into());
+        assert_eq!(token.namespace().unwrap(), "other");
+    }
+
+    #[test]
+    fn namespace_falls_back_to_credentials() {
+        assert_eq!(token(Some("openid"), Some("acme")).namespace().unwrap(), "acme");
+        assert_eq!(token(None, Some("acme")).namespace().unwrap(), "acme");
+    }
+
+    #[test]
+    fn token_expiry() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut fresh: AccessToken = serde_json::from_value(json!({"access_token": "t", "scope": null, "expires_in": 3600})).unwrap();
+        fresh.set_expiry(now);
+        assert_eq!(fresh.expires_at, Some(4_600));
+        assert!(!fresh.expires_within(now, EXPIRY_MARGIN));
+        assert!(fresh.expires_within(now + Duration::from_secs(3_550), EXPIRY_MARGIN));
+        assert_eq!(serde_json::to_value(&fresh).unwrap(), json!({"access_token": "t", "scope": null, "expires_at": 4600}));
+        assert!(!token(None, None).expires_within(now, EXPIRY_MARGIN));
+    }
+
+    #[test]
+    fn namespace_missing_from_both() {
+        match token(Some("openid"), None).namespace() {
+            Err(Error::Token(msg)) => assert!(msg.contains("scope or credentials")),
+            other => panic!("expected a token error, got {:?}", other),
+        }
+    }
+}
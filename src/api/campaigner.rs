@@ -1,107 +1,898 @@
-use clap::ArgMatches;
-use reqwest::blocking::{Client, Response};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
 use uuid::Uuid;
 
+use crate::cli::{CampaignCancelArgs, CampaignDevicesArgs, CampaignListArgs, CampaignResolveArgs, CampaignUpdateArgs, CampaignWaitArgs};
+use crate::command::{confirm, missing, CommandResult, TableResult};
 use crate::config::Config;
-use crate::error::Result;
-use crate::http::{Http, HttpMethods};
+use crate::error::{Error, Result};
+use crate::http::{Http, Pagination, Request};
 
 /// Available Campaigner API methods.
 pub trait CampaignerApi {
-    fn create_campaign(_: &mut Config, update: Uuid, name: &str, groups: &[Uuid]) -> Result<Response>;
-    fn launch_campaign(_: &mut Config, campaign: Uuid) -> Result<Response>;
-    fn cancel_campaign(_: &mut Config, campaign: Uuid) -> Result<Response>;
+    fn create_campaign(_: &mut Config, update: Uuid, name: &str, groups: &[Uuid], approval_needed: bool) -> Result<CommandResult>;
+    fn update_campaign(_: &mut Config, campaign: Uuid, name: Option<&str>, description: Option<&str>, groups: Option<&[Uuid]>) -> Result<CommandResult>;
+    fn launch_campaign(_: &mut Config, campaign: Uuid) -> Result<CommandResult>;
+    fn cancel_campaign(_: &mut Config, campaign: Uuid, reason: Option<&str>) -> Result<CommandResult>;
+    fn retry_failed(_: &mut Config, campaign: Uuid, failure_code: Option<&str>) -> Result<CommandResult>;
 
-    fn list_updates(_: &mut Config) -> Result<Response>;
-    fn create_update(_: &mut Config, update: Uuid, name: &str, description: &str) -> Result<Response>;
+    fn list_updates(_: &mut Config) -> Result<CommandResult>;
+    fn create_update(_: &mut Config, update: Uuid, name: &str, description: &str) -> Result<CommandResult>;
 
-    fn list_campaign_info(_: &mut Config, campaign: Uuid) -> Result<Response>;
-    fn list_campaign_stats(_: &mut Config, campaign: Uuid) -> Result<Response>;
-    fn list_all_campaigns(_: &mut Config) -> Result<Response>;
+    fn list_campaign_info(_: &mut Config, campaign: Uuid) -> Result<CommandResult>;
+    fn list_campaign_stats(_: &mut Config, campaign: Uuid) -> Result<CommandResult>;
+    fn list_all_campaigns(_: &mut Config, status: Option<CampaignStatus>) -> Result<CommandResult>;
+    fn list_campaign_devices(_: &mut Config, campaign: Uuid, failed_only: bool) -> Result<CommandResult>;
+    fn list_approvals(_: &mut Config, campaign: Uuid) -> Result<CommandResult>;
+    fn resolve_approval(_: &mut Config, campaign: Uuid, device: Uuid, approved: bool, reason: Option<&str>) -> Result<CommandResult>;
 }
 
 /// Make API calls to manage campaigns.
 pub struct Campaigner;
 
-impl<'a> Campaigner {
-    /// Parse CLI arguments to create a new campaign.
-    pub fn create_from_args(config: &mut Config, args: &ArgMatches<'a>) -> Result<Response> {
-        let update = args.value_of("update").expect("--update").parse()?;
-        let name = args.value_of("name").expect("--name");
-        let groups = args
-            .values_of("groups")
-            .expect("--groups")
-            .map(Uuid::parse_str)
-            .collect::<::std::result::Result<Vec<_>, _>>()?;
-        Self::create_campaign(config, update, name, &groups)
-    }
-
-    /// Parse CLI arguments to list campaign information.
-    pub fn list_from_args(config: &mut Config, args: &ArgMatches<'a>) -> Result<Response> {
-        let campaign = || args.value_of("campaign").expect("--campaign flag").parse();
-        if args.is_present("all") {
-            Self::list_all_campaigns(config)
-        } else if args.is_present("stats") {
+impl Campaigner {
+    /// Approve, or with `--reject` reject, the pending approvals of some campaign devices.
+    ///
+    /// Every device is attempted, and the command fails with the number of failed devices if any failed.
+    pub fn resolve_from_args(config: &mut Config, cmd: &CampaignResolveArgs) -> Result<CommandResult> {
+        let approved = !cmd.reject;
+        if !approved {
+            confirm(cmd.yes, &format!("Reject the update of {} devices in campaign {}?", cmd.devices.len(), cmd.campaign))?;
+        }
+
+        let mut failed = 0;
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["device", "approved", "status"]);
+        for &device in &cmd.devices {
+            let status = match Self::resolve_approval(config, cmd.campaign, device, approved, cmd.reason.as_deref())? {
+                CommandResult::Http(resp) => resp.status(),
+                _ => unreachable!("resolving an approval returns the response"),
+            };
+            result.add_row(vec![device.to_string().into(), approved.into(), status.as_u16().into()]);
+            if !status.is_success() {
+                warn!("resolving approval of device {} failed with {}", device, status);
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(Error::Failed(format!("resolving approvals failed for {} of {} devices", failed, cmd.devices.len())));
+        }
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// Change the name, description or groups of a campaign that has not been launched.
+    pub fn update_from_args(config: &mut Config, cmd: &CampaignUpdateArgs) -> Result<CommandResult> {
+        let groups = Some(&cmd.groups[..]).filter(|groups| !groups.is_empty());
+        let (name, description) = (cmd.name.as_deref(), cmd.description.as_deref());
+        if name.is_none() && description.is_none() && groups.is_none() {
+            return Err(Error::Args("one of --name, --description or --groups required".into()));
+        }
+        match Self::fetch_campaign(config, cmd.campaign)?.status {
+            Some(ref status) if status != "prepared" => {
+                Err(Error::Args(format!("campaign {} is {}, only campaigns that have not been launched can be updated", cmd.campaign, status)))
+            }
+            _ => Self::update_campaign(config, cmd.campaign, name, description, groups),
+        }
+    }
+
+    /// Cancel a campaign once confirmed.
+    pub fn cancel_from_args(config: &mut Config, cmd: &CampaignCancelArgs) -> Result<CommandResult> {
+        confirm(cmd.yes, &format!("Cancel campaign {}?", cmd.campaign))?;
+        Self::cancel_campaign(config, cmd.campaign, cmd.reason.as_deref())
+    }
+
+    /// List campaign information.
+    pub fn list_from_args(config: &mut Config, cmd: &CampaignListArgs) -> Result<CommandResult> {
+        let campaign = || cmd.campaign.ok_or_else(|| missing("campaign"));
+        if cmd.all {
+            let filter = CampaignFilter::from_args(cmd)?;
+            let mut req = Self::list_all_campaigns_request(config, filter.status)?;
+            if let Some(ref name) = filter.name_contains {
+                req = req.query("nameContains", name);
+            }
+            let (headers, page) = Http::execute_pages(req, Pagination::new(cmd.limit, cmd.all_pages)?, config.token()?)?;
+            Ok(Self::campaign_table(headers, &serde_json::to_vec(&page)?, &filter)?.into())
+        } else if cmd.stats {
             Self::list_campaign_stats(config, campaign()?)
         } else {
             Self::list_campaign_info(config, campaign()?)
         }
     }
+
+    /// List the devices processed by a campaign with their results.
+    pub fn devices_from_args(config: &mut Config, cmd: &CampaignDevicesArgs) -> Result<CommandResult> {
+        let req = Self::list_campaign_devices_request(config, cmd.campaign, cmd.failed_only)?;
+        let (headers, page) = Http::execute_pages(req, Pagination::new(cmd.limit, cmd.all_pages)?, config.token()?)?;
+        Ok(Self::device_table(headers, &serde_json::to_vec(&page)?, cmd.failed_only)?.into())
+    }
+
+    /// Fetch a single campaign.
+    pub fn fetch_campaign(config: &mut Config, campaign: Uuid) -> Result<Campaign> {
+        Http::execute_json(Self::list_campaign_info_request(config, campaign)?, config.token()?)
+    }
+
+    /// Fetch the progress of a campaign.
+    pub fn fetch_stats(config: &mut Config, campaign: Uuid) -> Result<CampaignStats> {
+        Http::execute_json(Self::list_campaign_stats_request(config, campaign)?, config.token()?)
+    }
+
+    /// Poll the stats of a campaign until it finishes, failing once it is cancelled,
+    /// more devices fail than the options allow, or the timeout passes.
+    pub fn wait(config: &mut Config, campaign: Uuid, opts: WaitOptions) -> Result<CommandResult> {
+        let started = Instant::now();
+        loop {
+            let (headers, body) = Http::execute_body(Self::list_campaign_stats_request(config, campaign)?, config.token()?)?;
+            let stats: CampaignStats = serde_json::from_slice(&body)?;
+            let status = stats.other.get("status").and_then(Value::as_str).unwrap_or("unknown");
+            let failed = stats.failed_count().unwrap_or(0);
+            info!(
+                "campaign {} is {}: {} of {} devices finished, {} failed",
+                campaign,
+                status,
+                stats.finished.unwrap_or(0),
+                stats.affected.unwrap_or(0),
+                failed
+            );
+            opts.check_failures(failed, stats.affected.unwrap_or(0))?;
+
+            match status {
+                "finished" => return Ok(Self::stats_result(headers, body, &stats).into()),
+                "cancelled" => return Err(Error::Verify(format!("campaign {} was cancelled", campaign))),
+                _ => (),
+            }
+            if let Some(timeout) = opts.timeout {
+                if started.elapsed() + opts.interval > timeout {
+                    return Err(Error::Timeout(format!(
+                        "campaign {} still {} after {}",
+                        campaign,
+                        status,
+                        humantime::format_duration(timeout)
+                    )));
+                }
+            }
+            thread::sleep(opts.interval);
+        }
+    }
+
+    /// Render campaign stats as a table of groups and a total row.
+    fn stats_result(headers: HeaderMap, body: Vec<u8>, stats: &CampaignStats) -> TableResult {
+        let mut result = TableResult::new(headers, body, &["group", "processed", "affected", "failed", "successful"]);
+        for (group, counts) in &stats.stats {
+            result.add_row(vec![group.clone().into(), counts.processed.into(), counts.affected.into(), counts.failed.into(), counts.successful.into()]);
+        }
+        let failed = stats.failed_count();
+        let successful = stats.finished.map(|finished| finished.saturating_sub(failed.unwrap_or(0)));
+        result.add_row(vec!["total".into(), stats.processed.into(), stats.affected.into(), failed.into(), successful.into()]);
+        result
+    }
+
+    /// Parse a page of campaign devices into a table, keeping only the failed ones for `failed_only`.
+    fn device_table(headers: HeaderMap, body: &[u8], failed_only: bool) -> Result<TableResult> {
+        let mut page: Value = serde_json::from_slice(body)?;
+        let values = match page.get_mut("values").map(Value::take) {
+            Some(Value::Array(values)) => values,
+            _ => return Err(Error::Parse("campaign device list has no `values`".into())),
+        };
+        let values = values
+            .into_iter()
+            .filter(|device| !failed_only || device.get("status").and_then(Value::as_str) == Some("failed"))
+            .collect::<Vec<_>>();
+
+        let field = |device: &Value, name: &str| device.get(name).cloned().unwrap_or(Value::Null);
+        let mut result = TableResult::new(headers, Vec::new(), &["device", "status", "result code", "result description"]);
+        for device in &values {
+            result.add_row(vec![field(device, "deviceId"), field(device, "status"), field(device, "resultCode"), field(device, "resultDescription")]);
+        }
+        page["values"] = Value::Array(values);
+        result.response = serde_json::to_vec(&page)?;
+        Ok(result)
+    }
+
+    /// Parse a page of campaigns into a table, keeping only those matching the filter.
+    fn campaign_table(headers: HeaderMap, body: &[u8], filter: &CampaignFilter) -> Result<TableResult> {
+        let mut page: Value = serde_json::from_slice(body)?;
+        let values = match page.get_mut("values").map(Value::take) {
+            Some(Value::Array(values)) => values,
+            _ => return Err(Error::Parse("campaign list has no `values`".into())),
+        };
+        let mut values = values.into_iter().filter(|campaign| filter.matches(campaign)).collect::<Vec<_>>();
+        if let Some(sort) = filter.sort {
+            sort.apply(&mut values);
+        }
+
+        let field = |campaign: &Value, name: &str| campaign.get(name).cloned().unwrap_or(Value::Null);
+        let mut result = TableResult::new(headers, Vec::new(), &["id", "name", "status", "created at"]);
+        for campaign in &values {
+            match campaign {
+                Value::String(_) => result.add_row(vec![campaign.clone(), Value::Null, Value::Null, Value::Null]),
+                _ => result.add_row(vec![field(campaign, "id"), field(campaign, "name"), field(campaign, "status"), field(campaign, "createdAt")]),
+            }
+        }
+        page["values"] = Value::Array(values);
+        result.response = serde_json::to_vec(&page)?;
+        Ok(result)
+    }
 }
 
 impl CampaignerApi for Campaigner {
-    fn create_campaign(config: &mut Config, update: Uuid, name: &str, groups: &[Uuid]) -> Result<Response> {
+    fn create_campaign(config: &mut Config, update: Uuid, name: &str, groups: &[Uuid], approval_needed: bool) -> Result<CommandResult> {
         debug!("creating campaign {} with update {} for groups: {:?}", name, update, groups);
-        let req = Client::new()
-            .post(&format!("{}api/v2/campaigns", config.campaigner))
-            .json(&json!({"update": format!("{}", update), "name": name, "groups": groups}));
-        Http::send(req, config.token()?)
+        Ok(Http::execute(Self::create_campaign_request(config, update, name, groups, approval_needed)?, config.token()?)?.into())
+    }
+
+    fn update_campaign(config: &mut Config, campaign: Uuid, name: Option<&str>, description: Option<&str>, groups: Option<&[Uuid]>) -> Result<CommandResult> {
+        debug!("updating campaign {} with name {:?}, description {:?} and groups {:?}", campaign, name, description, groups);
+        Ok(Http::execute(Self::update_campaign_request(config, campaign, name, description, groups)?, config.token()?)?.into())
     }
 
-    fn launch_campaign(config: &mut Config, campaign: Uuid) -> Result<Response> {
+    fn launch_campaign(config: &mut Config, campaign: Uuid) -> Result<CommandResult> {
         debug!("launching campaign {}", campaign);
-        let req = Client::new().post(&format!("{}api/v2/campaigns/{}/launch", config.campaigner, campaign));
-        Http::send(req, config.token()?)
+        Ok(Http::execute(Self::launch_campaign_request(config, campaign)?, config.token()?)?.into())
+    }
+
+    fn cancel_campaign(config: &mut Config, campaign: Uuid, reason: Option<&str>) -> Result<CommandResult> {
+        debug!("cancelling campaign {} with reason {:?}", campaign, reason);
+        Ok(Http::execute(Self::cancel_campaign_request(config, campaign, reason)?, config.token()?)?.into())
     }
 
-    fn cancel_campaign(config: &mut Config, campaign: Uuid) -> Result<Response> {
-        debug!("cancelling campaign {}", campaign);
-        Http::post(
-            &format!("{}api/v2/campaigns/{}/cancel", config.campaigner, campaign),
-            config.token()?,
-        )
+    fn retry_failed(config: &mut Config, campaign: Uuid, failure_code: Option<&str>) -> Result<CommandResult> {
+        debug!("retrying failed devices of campaign {} with failure code {:?}", campaign, failure_code);
+        Ok(Http::execute(Self::retry_failed_request(config, campaign, failure_code)?, config.token()?)?.into())
     }
 
-    fn list_updates(config: &mut Config) -> Result<Response> {
+    fn list_updates(config: &mut Config) -> Result<CommandResult> {
         debug!("getting list of campaigner updates ");
-        Http::get(&format!("{}api/v2/updates", config.campaigner), config.token()?)
+        Ok(Http::execute(Self::list_updates_request(config)?, config.token()?)?.into())
     }
 
-    fn create_update(config: &mut Config, update: Uuid, name: &str, description: &str) -> Result<Response> {
+    fn create_update(config: &mut Config, update: Uuid, name: &str, description: &str) -> Result<CommandResult> {
         debug!("creating update ");
-
-        let req = Client::new()
-            .post(&format!("{}api/v2/updates", config.campaigner))
-            .json(&json!({"name": name, "description": description, "updateSource": {"id": format!("{}", update), "sourceType": "multi_target" }} ));
-
-        Http::send(req, config.token()?)
+        Ok(Http::execute(Self::create_update_request(config, update, name, description)?, config.token()?)?.into())
     }
 
-    fn list_campaign_info(config: &mut Config, campaign: Uuid) -> Result<Response> {
+    fn list_campaign_info(config: &mut Config, campaign: Uuid) -> Result<CommandResult> {
         debug!("getting info for campaign {}", campaign);
-        Http::get(&format!("{}api/v2/campaigns/{}", config.campaigner, campaign), config.token()?)
+        let (headers, body) = Http::execute_body(Self::list_campaign_info_request(config, campaign)?, config.token()?)?;
+        let campaign: Campaign = serde_json::from_slice(&body)?;
+        let mut result = TableResult::new(headers, body, &["id", "name", "update", "status", "groups"]);
+        result.add_row(vec![
+            campaign.id.to_string().into(),
+            campaign.name.into(),
+            campaign.update.map(|update| update.to_string()).into(),
+            campaign.status.into(),
+            campaign.groups.iter().map(Uuid::to_string).collect::<Vec<_>>().into(),
+        ]);
+        Ok(result.into())
     }
 
-    fn list_campaign_stats(config: &mut Config, campaign: Uuid) -> Result<Response> {
+    /// Show the progress of a campaign per group, followed by the campaign total.
+    fn list_campaign_stats(config: &mut Config, campaign: Uuid) -> Result<CommandResult> {
         debug!("getting stats for campaign {}", campaign);
-        Http::get(
-            &format!("{}api/v2/campaigns/{}/stats", config.campaigner, campaign),
-            config.token()?,
-        )
+        let (headers, body) = Http::execute_body(Self::list_campaign_stats_request(config, campaign)?, config.token()?)?;
+        let stats: CampaignStats = serde_json::from_slice(&body)?;
+        Ok(Self::stats_result(headers, body, &stats).into())
+    }
+
+    fn list_all_campaigns(config: &mut Config, status: Option<CampaignStatus>) -> Result<CommandResult> {
+        debug!("getting a list of campaigns with status {:?}", status);
+        let (headers, body) = Http::execute_body(Self::list_all_campaigns_request(config, status)?, config.token()?)?;
+        let filter = CampaignFilter {
+            status,
+            ..CampaignFilter::default()
+        };
+        Ok(Self::campaign_table(headers, &body, &filter)?.into())
+    }
+
+    fn list_campaign_devices(config: &mut Config, campaign: Uuid, failed_only: bool) -> Result<CommandResult> {
+        debug!("getting devices of campaign {} (failed only: {})", campaign, failed_only);
+        let (headers, body) = Http::execute_body(Self::list_campaign_devices_request(config, campaign, failed_only)?, config.token()?)?;
+        Ok(Self::device_table(headers, &body, failed_only)?.into())
+    }
+
+    fn list_approvals(config: &mut Config, campaign: Uuid) -> Result<CommandResult> {
+        debug!("getting pending approvals of campaign {}", campaign);
+        let (headers, page) = Http::execute_pages(Self::list_approvals_request(config, campaign)?, Pagination::default(), config.token()?)?;
+        let mut result = TableResult::new(headers, serde_json::to_vec(&page)?, &["device", "requested at"]);
+        for approval in page["values"].as_array().into_iter().flatten() {
+            let field = |name: &str| approval.get(name).cloned().unwrap_or(Value::Null);
+            result.add_row(vec![field("deviceId"), field("requestedAt")]);
+        }
+        Ok(result.into())
+    }
+
+    fn resolve_approval(config: &mut Config, campaign: Uuid, device: Uuid, approved: bool, reason: Option<&str>) -> Result<CommandResult> {
+        debug!("resolving approval of device {} in campaign {}: approved {}", device, campaign, approved);
+        Ok(Http::execute(Self::resolve_approval_request(config, campaign, device, approved, reason)?, config.token()?)?.into())
+    }
+}
+
+/// Requests shared by the blocking and async Campaigner APIs.
+impl Campaigner {
+    /// Campaigns needing approval are only sent `approvalNeeded`, leaving the server default otherwise.
+    pub(crate) fn create_campaign_request(config: &Config, update: Uuid, name: &str, groups: &[Uuid], approval_needed: bool) -> Result<Request> {
+        let mut body = json!({"update": format!("{}", update), "name": name, "groups": groups});
+        if approval_needed {
+            body["approvalNeeded"] = true.into();
+        }
+        Ok(Request::post(format!("{}api/v2/campaigns", config.campaigner))?.json(body))
+    }
+
+    /// Only the given fields are sent, with the description as campaign metadata.
+    pub(crate) fn update_campaign_request(config: &Config, campaign: Uuid, name: Option<&str>, description: Option<&str>, groups: Option<&[Uuid]>) -> Result<Request> {
+        let mut body = Map::new();
+        if let Some(name) = name {
+            body.insert("name".into(), name.into());
+        }
+        if let Some(description) = description {
+            body.insert("metadata".into(), json!([{"type": "DESCRIPTION", "value": description}]));
+        }
+        if let Some(groups) = groups {
+            body.insert("groups".into(), json!(groups));
+        }
+        Ok(Request::put(format!("{}api/v2/campaigns/{}", config.campaigner, campaign))?.json(Value::Object(body)))
+    }
+
+    pub(crate) fn launch_campaign_request(config: &Config, campaign: Uuid) -> Result<Request> {
+        Request::post(format!("{}api/v2/campaigns/{}/launch", config.campaigner, campaign))
+    }
+
+    pub(crate) fn cancel_campaign_request(config: &Config, campaign: Uuid, reason: Option<&str>) -> Result<Request> {
+        let req = Request::post(format!("{}api/v2/campaigns/{}/cancel", config.campaigner, campaign))?;
+        Ok(match reason {
+            Some(reason) => req.json(json!({ "reason": reason })),
+            None => req,
+        })
+    }
+
+    pub(crate) fn retry_failed_request(config: &Config, campaign: Uuid, failure_code: Option<&str>) -> Result<Request> {
+        let req = Request::post(format!("{}api/v2/campaigns/{}/retry-failed", config.campaigner, campaign))?;
+        Ok(match failure_code {
+            Some(code) => req.json(json!({ "failureCode": code })),
+            None => req,
+        })
+    }
+
+    pub(crate) fn list_updates_request(config: &Config) -> Result<Request> { Request::get(format!("{}api/v2/updates", config.campaigner)) }
+
+    pub(crate) fn create_update_request(config: &Config, update: Uuid, name: &str, description: &str) -> Result<Request> {
+        Ok(Request::post(format!("{}api/v2/updates", config.campaigner))?.json(json!({
+            "name": name,
+            "description": description,
+            "updateSource": {"id": format!("{}", update), "sourceType": "multi_target"}
+        })))
+    }
+
+    pub(crate) fn list_campaign_info_request(config: &Config, campaign: Uuid) -> Result<Request> {
+        Request::get(format!("{}api/v2/campaigns/{}", config.campaigner, campaign))
+    }
+
+    pub(crate) fn list_campaign_stats_request(config: &Config, campaign: Uuid) -> Result<Request> {
+        Request::get(format!("{}api/v2/campaigns/{}/stats", config.campaigner, campaign))
+    }
+
+    /// The result of each device processed by a campaign, asking the server for only failed ones with `failed_only`.
+    pub(crate) fn list_campaign_devices_request(config: &Config, campaign: Uuid, failed_only: bool) -> Result<Request> {
+        let req = Request::get(format!("{}api/v2/campaigns/{}/devices", config.campaigner, campaign))?;
+        Ok(if failed_only { req.query("status", "failed") } else { req })
+    }
+
+    pub(crate) fn list_approvals_request(config: &Config, campaign: Uuid) -> Result<Request> {
+        Request::get(format!("{}api/v2/campaigns/{}/approvals", config.campaigner, campaign))
+    }
+
+    pub(crate) fn resolve_approval_request(config: &Config, campaign: Uuid, device: Uuid, approved: bool, reason: Option<&str>) -> Result<Request> {
+        let mut body = json!({ "approved": approved });
+        if let Some(reason) = reason {
+            body["reason"] = reason.into();
+        }
+        Ok(Request::put(format!("{}api/v2/campaigns/{}/approvals/{}", config.campaigner, campaign, device))?.json(body))
+    }
+
+    pub(crate) fn list_all_campaigns_request(config: &Config, status: Option<CampaignStatus>) -> Result<Request> {
+        let req = Request::get(format!("{}api/v2/campaigns", config.campaigner))?;
+        Ok(match status {
+            Some(status) => req.query("status", status),
+            None => req,
+        })
+    }
+}
+
+/// A campaign applying an update to some groups of devices.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Campaign {
+    pub id: Uuid,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub groups: Vec<Uuid>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// The progress of a campaign, in total and for each group.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CampaignStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub processed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub affected: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished: Option<u64>,
+    /// Either the failed devices or their count.
+    #[serde(default)]
+    pub failed: Value,
+    #[serde(default)]
+    pub stats: BTreeMap<String, GroupStats>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl CampaignStats {
+    fn failed_count(&self) -> Option<u64> {
+        match self.failed {
+            Value::Array(ref devices) => Some(devices.len() as u64),
+            ref count => count.as_u64(),
+        }
+    }
+}
+
+/// How long to wait for a campaign to finish and how many failed devices to tolerate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WaitOptions {
+    pub timeout: Option<Duration>,
+    pub interval: Duration,
+    pub max_failures: u64,
+    /// The share of affected devices allowed to fail, from 0 to 1.
+    pub max_failure_rate: Option<f64>,
+}
+
+impl WaitOptions {
+    /// Parse `--timeout`, `--interval`, `--max-failures` and `--max-failure-rate` CLI arguments.
+    pub fn from_args(cmd: &CampaignWaitArgs) -> Result<Self> {
+        let duration = |flag: &str, value: &Option<String>| {
+            value
+                .as_ref()
+                .map(|value| humantime::parse_duration(value).map_err(|err| Error::Args(format!("--{} `{}`: {}", flag, value, err))))
+                .transpose()
+        };
+        let max_failure_rate = match cmd.max_failure_rate {
+            Some(ref rate) => match rate.trim_end_matches('%').parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => Some(percent / 100.0),
+                _ => return Err(Error::Args(format!("--max-failure-rate must be a percentage, got {}", rate))),
+            },
+            None => None,
+        };
+        Ok(WaitOptions {
+            timeout: duration("timeout", &cmd.timeout)?,
+            interval: duration("interval", &cmd.interval)?.unwrap_or(Duration::from_secs(30)),
+            max_failures: cmd.max_failures.unwrap_or(0),
+            max_failure_rate,
+        })
+    }
+
+    /// Fail when more devices failed than `--max-failures` or `--max-failure-rate` allow.
+    ///
+    /// With a rate, only the rate applies.
+    fn check_failures(&self, failed: u64, affected: u64) -> Result<()> {
+        match self.max_failure_rate {
+            Some(rate) if affected > 0 && failed as f64 / affected as f64 > rate => Err(Error::Verify(format!(
+                "{} of {} devices failed, more than the {}% allowed",
+                failed,
+                affected,
+                rate * 100.0
+            ))),
+            Some(_) => Ok(()),
+            None if failed > self.max_failures => {
+                Err(Error::Verify(format!("{} devices failed, more than the {} allowed", failed, self.max_failures)))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// The progress of a campaign for a single group.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GroupStats {
+    #[serde(default)]
+    pub processed: u64,
+    #[serde(default)]
+    pub affected: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub successful: Option<u64>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// Filters and ordering for listing campaigns.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CampaignFilter {
+    pub status: Option<CampaignStatus>,
+    pub name_contains: Option<String>,
+    pub sort: Option<CampaignSort>,
+}
+
+impl CampaignFilter {
+    /// Parse `--status`, `--name-contains` and `--sort` CLI arguments.
+    pub fn from_args(cmd: &CampaignListArgs) -> Result<Self> {
+        Ok(CampaignFilter {
+            status: cmd.status.as_deref().map(str::parse).transpose()?,
+            name_contains: cmd.name_contains.clone(),
+            sort: cmd.sort.as_deref().map(str::parse).transpose()?,
+        })
+    }
+
+    /// Whether a listed campaign matches, keeping campaigns without the filtered field.
+    fn matches(&self, campaign: &Value) -> bool {
+        let field = |name: &str| campaign.get(name).and_then(Value::as_str);
+        let status = match (self.status, field("status")) {
+            (Some(wanted), Some(status)) => wanted.to_string() == status,
+            _ => true,
+        };
+        let name = match (&self.name_contains, field("name")) {
+            (Some(wanted), Some(name)) => name.to_lowercase().contains(&wanted.to_lowercase()),
+            _ => true,
+        };
+        status && name
+    }
+}
+
+/// Orders for listing campaigns.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CampaignSort {
+    Newest,
+    Oldest,
+    Name,
+}
+
+impl CampaignSort {
+    /// Sort campaigns by their `createdAt` timestamp or name, keeping the server order for ties.
+    fn apply(self, campaigns: &mut [Value]) {
+        let key = |campaign: &Value, name: &str| campaign.get(name).and_then(Value::as_str).unwrap_or("").to_string();
+        match self {
+            CampaignSort::Newest => campaigns.sort_by_key(|campaign| std::cmp::Reverse(key(campaign, "createdAt"))),
+            CampaignSort::Oldest => campaigns.sort_by_key(|campaign| key(campaign, "createdAt")),
+            CampaignSort::Name => campaigns.sort_by_key(|campaign| key(campaign, "name").to_lowercase()),
+        }
+    }
+}
+
+impl FromStr for CampaignSort {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match s.to_lowercase().as_ref() {
+            "newest" => Ok(CampaignSort::Newest),
+            "oldest" => Ok(CampaignSort::Oldest),
+            "name"   => Ok(CampaignSort::Name),
+            _ => Err(Error::Args(format!("unknown campaign order `{}`, expected newest, oldest or name", s))),
+        }
+    }
+}
+
+/// Available campaign statuses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CampaignStatus {
+    Prepared,
+    Launched,
+    Finished,
+    Cancelled,
+}
+
+impl FromStr for CampaignStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match s.to_lowercase().as_ref() {
+            "prepared"  => Ok(CampaignStatus::Prepared),
+            "launched"  => Ok(CampaignStatus::Launched),
+            "finished"  => Ok(CampaignStatus::Finished),
+            "cancelled" => Ok(CampaignStatus::Cancelled),
+            _ => Err(Error::Args(format!("unknown campaign status `{}`, expected prepared, launched, finished or cancelled", s))),
+        }
+    }
+}
+
+impl Display for CampaignStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let text = match self {
+            CampaignStatus::Prepared  => "prepared",
+            CampaignStatus::Launched  => "launched",
+            CampaignStatus::Finished  => "finished",
+            CampaignStatus::Cancelled => "cancelled",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+    use structopt::StructOpt;
+
+    fn fixture() -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "total": 3, "offset": 0, "limit": 50,
+            "values": [
+                {"id": "00000000-0000-0000-0000-000000000001", "name": "spring", "status": "launched", "createdAt": "2020-03-01T00:00:00Z"},
+                {"id": "00000000-0000-0000-0000-000000000002", "name": "summer", "status": "finished", "createdAt": "2020-06-01T00:00:00Z"},
+                {"id": "00000000-0000-0000-0000-000000000003", "name": "autumn", "status": "launched", "createdAt": "2020-09-01T00:00:00Z"}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn cancel_sends_reason() {
+        let server = MockServer::start(|_| MockResponse::status(200));
+        let campaign = "00000000-0000-0000-0000-000000000001".parse().unwrap();
+        Campaigner::cancel_campaign(&mut server.config(), campaign, Some("bad firmware")).expect("cancel");
+        Campaigner::cancel_campaign(&mut server.config(), campaign, None).expect("cancel");
+
+        let requests = server.requests();
+        assert_eq!(requests[0].route(), "/api/v2/campaigns/00000000-0000-0000-0000-000000000001/cancel");
+        assert_eq!(requests[0].json(), json!({"reason": "bad firmware"}));
+        assert!(requests[1].body.is_empty());
+    }
+
+    #[test]
+    fn update_prepared_campaign() {
+        let server = MockServer::start(|req| match (req.method.as_ref(), req.route()) {
+            ("GET", "/api/v2/campaigns/00000000-0000-0000-0000-000000000001") => {
+                MockResponse::json(200, json!({"id": "00000000-0000-0000-0000-000000000001", "name": "spring", "status": "prepared"}))
+            }
+            ("GET", _) => MockResponse::json(200, json!({"id": "00000000-0000-0000-0000-000000000002", "name": "summer", "status": "launched"})),
+            _ => MockResponse::status(200),
+        });
+        let group = "00000000-0000-0000-0000-0000000000aa";
+        let cmd = CampaignUpdateArgs::from_iter(&["update", "--campaign", "00000000-0000-0000-0000-000000000001", "--description", "eu rollout", "--groups", group]);
+        Campaigner::update_from_args(&mut server.config(), &cmd).expect("update");
+        let cmd = CampaignUpdateArgs::from_iter(&["update", "--campaign", "00000000-0000-0000-0000-000000000002", "--name", "autumn"]);
+        match Campaigner::update_from_args(&mut server.config(), &cmd) {
+            Err(Error::Args(msg)) => assert!(msg.contains("is launched")),
+            other => panic!("expected an args error, got {:?}", other.err()),
+        }
+        let cmd = CampaignUpdateArgs::from_iter(&["update", "--campaign", "00000000-0000-0000-0000-000000000001"]);
+        assert!(Campaigner::update_from_args(&mut server.config(), &cmd).is_err());
+
+        let puts = server.requests().into_iter().filter(|req| req.method == "PUT").collect::<Vec<_>>();
+        assert_eq!(puts.len(), 1);
+        assert_eq!(puts[0].route(), "/api/v2/campaigns/00000000-0000-0000-0000-000000000001");
+        assert_eq!(puts[0].json(), json!({"metadata": [{"type": "DESCRIPTION", "value": "eu rollout"}], "groups": [group]}));
+    }
+
+    #[test]
+    fn retry_failed_sends_failure_code() {
+        let server = MockServer::start(|_| MockResponse::json(201, json!({"id": "00000000-0000-0000-0000-000000000002"})));
+        let campaign = "00000000-0000-0000-0000-000000000001".parse().unwrap();
+        Campaigner::retry_failed(&mut server.config(), campaign, Some("download_failed")).expect("retry");
+        Campaigner::retry_failed(&mut server.config(), campaign, None).expect("retry");
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].route(), "/api/v2/campaigns/00000000-0000-0000-0000-000000000001/retry-failed");
+        assert_eq!(requests[0].json(), json!({"failureCode": "download_failed"}));
+        assert!(requests[1].body.is_empty());
+    }
+
+    #[test]
+    fn list_campaign_device_results() {
+        let server = MockServer::start(|req| match (req.method.as_ref(), req.route()) {
+            ("GET", "/api/v2/campaigns/00000000-0000-0000-0000-000000000001/devices") => MockResponse::json(200, json!({"total": 2, "offset": 0, "limit": 50, "values": [
+                {"deviceId": "00000000-0000-0000-0000-0000000000d1", "status": "successful", "resultCode": "OK", "resultDescription": null},
+                {"deviceId": "00000000-0000-0000-0000-0000000000d2", "status": "failed", "resultCode": "DOWNLOAD_FAILED", "resultDescription": "timeout"}
+            ]})),
+            _ => MockResponse::status(404),
+        });
+        let campaign = "00000000-0000-0000-0000-000000000001";
+        let rows = |result: CommandResult| match result {
+            CommandResult::Table(result) => result.rows,
+            _ => panic!("expected a table"),
+        };
+
+        let all = rows(Campaigner::devices_from_args(&mut server.config(), &CampaignDevicesArgs::from_iter(&["devices", "--campaign", campaign])).expect("devices"));
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0]["result code"], "OK");
+        let failed = rows(
+            Campaigner::devices_from_args(&mut server.config(), &CampaignDevicesArgs::from_iter(&["devices", "--campaign", campaign, "--failed-only"])).expect("failed"),
+        );
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["device"], "00000000-0000-0000-0000-0000000000d2");
+        assert_eq!(failed[0]["result description"], "timeout");
+
+        let paths = server.requests().into_iter().map(|req| req.path).collect::<Vec<_>>();
+        assert_eq!(paths[1], format!("/api/v2/campaigns/{}/devices?status=failed", campaign));
+    }
+
+    #[test]
+    fn list_and_resolve_approvals() {
+        let server = MockServer::start(|req| match (req.method.as_ref(), req.route()) {
+            ("GET", "/api/v2/campaigns/00000000-0000-0000-0000-000000000001/approvals") => MockResponse::json(200, json!({"total": 1, "offset": 0, "limit": 50, "values": [
+                {"deviceId": "00000000-0000-0000-0000-0000000000d1", "requestedAt": "2020-09-01T00:00:00Z"}
+            ]})),
+            ("PUT", route) if route.ends_with("0d1") => MockResponse::status(204),
+            _ => MockResponse::status(404),
+        });
+        let campaign = "00000000-0000-0000-0000-000000000001";
+        match Campaigner::list_approvals(&mut server.config(), campaign.parse().unwrap()).expect("approvals") {
+            CommandResult::Table(result) => assert_eq!(result.rows[0]["device"], "00000000-0000-0000-0000-0000000000d1"),
+            _ => panic!("expected a table"),
+        }
+
+        let cmd = CampaignResolveArgs::from_iter(&[
+            "resolve", "--campaign", campaign, "--reject", "--reason", "maintenance", "--yes", "--devices",
+            "00000000-0000-0000-0000-0000000000d1", "00000000-0000-0000-0000-0000000000d2",
+        ]);
+        match Campaigner::resolve_from_args(&mut server.config(), &cmd) {
+            Err(err @ Error::Failed(_)) => {
+                assert_eq!(err.to_string(), "Failed: resolving approvals failed for 1 of 2 devices");
+                assert_eq!(err.exit_code(), crate::error::EXIT_FAILURE);
+            }
+            other => panic!("expected a failed device, got {:?}", other.err()),
+        }
+        let puts = server.requests().into_iter().filter(|req| req.method == "PUT").collect::<Vec<_>>();
+        assert_eq!(puts.len(), 2);
+        assert_eq!(puts[0].route(), format!("/api/v2/campaigns/{}/approvals/00000000-0000-0000-0000-0000000000d1", campaign));
+        assert_eq!(puts[0].json(), json!({"approved": false, "reason": "maintenance"}));
+
+        let cmd = CampaignResolveArgs::from_iter(&["resolve", "--campaign", campaign, "--devices", "00000000-0000-0000-0000-0000000000d1"]);
+        match Campaigner::resolve_from_args(&mut server.config(), &cmd).expect("approve") {
+            CommandResult::Table(result) => assert_eq!(result.rows[0]["status"], 204),
+            _ => panic!("expected a table"),
+        }
+
+        let req = Campaigner::create_campaign_request(&server.config(), Uuid::nil(), "spring", &[], true).unwrap();
+        assert_eq!(req.body.unwrap()["approvalNeeded"], true);
+    }
+
+    #[test]
+    fn filter_campaigns_by_status() {
+        let launched = CampaignFilter {
+            status: Some(CampaignStatus::Launched),
+            ..CampaignFilter::default()
+        };
+        let result = Campaigner::campaign_table(HeaderMap::new(), &fixture(), &launched).expect("table");
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0]["name"], "spring");
+        assert_eq!(result.rows[1]["name"], "autumn");
+
+        let raw: Value = serde_json::from_slice(&result.response).unwrap();
+        assert_eq!(raw["values"].as_array().unwrap().len(), 2);
+        assert_eq!(raw["limit"], 50);
+
+        let all = Campaigner::campaign_table(HeaderMap::new(), &fixture(), &CampaignFilter::default()).expect("table");
+        assert_eq!(all.rows.len(), 3);
+    }
+
+    #[test]
+    fn filter_and_sort_campaigns() {
+        let cmd = CampaignListArgs::from_iter(&["list", "--all", "--name-contains", "U", "--sort", "newest"]);
+        let filter = CampaignFilter::from_args(&cmd).expect("filter");
+        let result = Campaigner::campaign_table(HeaderMap::new(), &fixture(), &filter).expect("table");
+        let rows = result.rows.iter().map(|row| (row["name"].as_str().unwrap(), row["created at"].as_str().unwrap())).collect::<Vec<_>>();
+        assert_eq!(rows, vec![("autumn", "2020-09-01T00:00:00Z"), ("summer", "2020-06-01T00:00:00Z")]);
+
+        assert_eq!("Oldest".parse::<CampaignSort>().unwrap(), CampaignSort::Oldest);
+        assert!("latest".parse::<CampaignSort>().is_err());
+    }
+
+    #[test]
+    fn campaign_stats_per_group() {
+        let server = MockServer::start(|_| {
+            MockResponse::json(200, json!({
+                "campaign": "00000000-0000-0000-0000-000000000001",
+                "status": "launched",
+                "finished": 4, "cancelled": 0, "processed": 10, "affected": 6,
+                "failed": ["00000000-0000-0000-0000-00000000000a"],
+                "stats": {
+                    "00000000-0000-0000-0000-0000000000g1": {"processed": 7, "affected": 4},
+                    "00000000-0000-0000-0000-0000000000g2": {"processed": 3, "affected": 2, "failed": 1, "successful": 1}
+                }
+            }))
+        });
+        let campaign = "00000000-0000-0000-0000-000000000001".parse().unwrap();
+        let result = match Campaigner::list_campaign_stats(&mut server.config(), campaign).expect("stats") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(server.requests()[0].route(), "/api/v2/campaigns/00000000-0000-0000-0000-000000000001/stats");
+        assert_eq!(result.rows.len(), 3);
+        assert_eq!(result.rows[0]["processed"], 7);
+        assert_eq!(result.rows[0]["failed"], Value::Null);
+        assert_eq!(result.rows[1]["failed"], 1);
+        assert_eq!(result.rows[1]["successful"], 1);
+        assert_eq!(result.rows[2]["group"], "total");
+        assert_eq!(result.rows[2]["affected"], 6);
+        assert_eq!(result.rows[2]["failed"], 1);
+        assert_eq!(result.rows[2]["successful"], 3);
+    }
+
+    #[test]
+    fn typed_campaign_info() {
+        let server = MockServer::start(|_| {
+            MockResponse::json(200, json!({
+                "id": "00000000-0000-0000-0000-000000000001",
+                "name": "rollout",
+                "update": "00000000-0000-0000-0000-000000000002",
+                "status": "launched",
+                "groups": ["00000000-0000-0000-0000-000000000003"],
+                "createdAt": "2020-01-01T00:00:00Z"
+            }))
+        });
+        let id = "00000000-0000-0000-0000-000000000001".parse().unwrap();
+        let campaign = Campaigner::fetch_campaign(&mut server.config(), id).expect("campaign");
+        assert_eq!(campaign.name, "rollout");
+        assert_eq!(campaign.groups.len(), 1);
+        assert_eq!(campaign.other["createdAt"], "2020-01-01T00:00:00Z");
+
+        let result = match Campaigner::list_campaign_info(&mut server.config(), id).expect("info") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows[0]["status"], "launched");
+        assert_eq!(result.rows[0]["groups"], json!(["00000000-0000-0000-0000-000000000003"]));
+    }
+
+    #[test]
+    fn wait_for_campaign() {
+        let polls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = polls.clone();
+        let server = MockServer::start(move |_| {
+            let finished = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) >= 2;
+            let (status, failed) = if finished { ("finished", json!([1, 2])) } else { ("launched", json!([1])) };
+            MockResponse::json(200, json!({"status": status, "processed": 10, "affected": 10, "finished": 5 + 5 * finished as u64, "failed": failed}))
+        });
+        let campaign = Uuid::nil();
+        let opts = WaitOptions {
+            timeout: None,
+            interval: Duration::from_millis(1),
+            max_failures: 2,
+            max_failure_rate: None,
+        };
+        let result = match Campaigner::wait(&mut server.config(), campaign, opts).expect("wait") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(polls.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert_eq!(result.rows[0]["group"], "total");
+        assert_eq!(result.rows[0]["failed"], 2);
+
+        polls.store(0, std::sync::atomic::Ordering::SeqCst);
+        match Campaigner::wait(&mut server.config(), campaign, WaitOptions { max_failures: 0, ..opts }) {
+            Err(Error::Verify(_)) => (),
+            other => panic!("expected too many failures, got {:?}", other.err()),
+        }
+        polls.store(0, std::sync::atomic::Ordering::SeqCst);
+        match Campaigner::wait(&mut server.config(), campaign, WaitOptions { max_failures: 0, max_failure_rate: Some(0.1), ..opts }) {
+            Err(Error::Verify(_)) => assert_eq!(polls.load(std::sync::atomic::Ordering::SeqCst), 3),
+            other => panic!("expected the failure rate to be exceeded, got {:?}", other.err()),
+        }
+        polls.store(0, std::sync::atomic::Ordering::SeqCst);
+        match Campaigner::wait(&mut server.config(), campaign, WaitOptions { timeout: Some(Duration::from_millis(1)), ..opts }) {
+            Err(err @ Error::Timeout(_)) => assert_eq!(err.exit_code(), crate::error::EXIT_TIMEOUT),
+            other => panic!("expected a timeout, got {:?}", other.err()),
+        }
     }
 
-    fn list_all_campaigns(config: &mut Config) -> Result<Response> {
-        debug!("getting a list of campaigns");
-        Http::get(&format!("{}api/v2/campaigns", config.campaigner), config.token()?)
+    #[test]
+    fn parse_campaign_status() {
+        assert_eq!("Cancelled".parse::<CampaignStatus>().unwrap(), CampaignStatus::Cancelled);
+        assert!("running".parse::<CampaignStatus>().is_err());
     }
 }
@@ -0,0 +1,219 @@
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{fs, path::Path};
+
+use crate::cli::{DelegationAddTargetArgs, DelegationCreateArgs, DelegationDeleteArgs};
+use crate::command::{confirm, CommandResult, TableResult};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::http::{Http, Request};
+
+/// Available reposerver delegation API methods.
+pub trait DelegationApi {
+    /// List the trusted delegations of the repository as a table.
+    fn list_delegations(_: &mut Config) -> Result<CommandResult>;
+    /// Replace the trusted delegations of the repository.
+    fn put_delegations(_: &mut Config, delegations: &[TrustedDelegation]) -> Result<CommandResult>;
+    /// Add public keys that trusted delegations may refer to.
+    fn add_delegation_keys(_: &mut Config, keys: &Value) -> Result<CommandResult>;
+    /// Upload the signed targets metadata of a delegated role.
+    fn put_delegated_targets(_: &mut Config, name: &str, metadata: &Value) -> Result<CommandResult>;
+}
+
+/// Make API calls to manage the trusted delegations of the reposerver.
+pub struct Delegations;
+
+impl Delegations {
+    /// Fetch the trusted delegations of the repository.
+    pub fn fetch_delegations(config: &mut Config) -> Result<Vec<TrustedDelegation>> {
+        let (_, _, delegations) = Self::fetch(config)?;
+        Ok(delegations)
+    }
+
+    /// Add a trusted delegation, uploading any `--keys` first.
+    pub fn create_from_args(config: &mut Config, cmd: &DelegationCreateArgs) -> Result<CommandResult> {
+        let delegation = TrustedDelegation {
+            name: cmd.name.clone(),
+            keyids: cmd.keyids.clone(),
+            paths: cmd.paths.clone(),
+            threshold: cmd.threshold.unwrap_or(1),
+            terminating: cmd.terminating,
+            other: Map::new(),
+        };
+        if delegation.threshold < 1 || delegation.threshold as usize > delegation.keyids.len() {
+            return Err(Error::Args(format!("--threshold must be between 1 and the {} key ids", delegation.keyids.len())));
+        }
+
+        let mut delegations = Self::fetch_delegations(config)?;
+        if delegations.iter().any(|existing| existing.name == delegation.name) {
+            return Err(Error::Conflict(format!("delegation `{}` already exists", delegation.name)));
+        }
+        if let Some(ref keys) = cmd.keys {
+            debug!("adding trusted delegation keys");
+            Http::execute_body(Self::add_delegation_keys_request(config, &read_json(keys)?)?, config.token()?)?;
+        }
+        delegations.push(delegation);
+        Self::put_delegations(config, &delegations)
+    }
+
+    /// Remove a trusted delegation once confirmed.
+    pub fn delete_from_args(config: &mut Config, cmd: &DelegationDeleteArgs) -> Result<CommandResult> {
+        let name = &cmd.name;
+        let mut delegations = Self::fetch_delegations(config)?;
+        let count = delegations.len();
+        delegations.retain(|delegation| delegation.name != *name);
+        if delegations.len() == count {
+            return Err(Error::NotFound(format!("Delegation `{}`", name), None));
+        }
+        confirm(cmd.yes, &format!("Delete delegation {}?", name))?;
+        Self::put_delegations(config, &delegations)
+    }
+
+    /// Upload the targets metadata of a delegated role.
+    pub fn add_target_from_args(config: &mut Config, cmd: &DelegationAddTargetArgs) -> Result<CommandResult> {
+        let metadata = read_json(&cmd.file)?;
+        if metadata.get("signed").is_none() || metadata.get("signatures").is_none() {
+            return Err(Error::Parse("delegated targets metadata needs `signed` and `signatures` fields".into()));
+        }
+        Self::put_delegated_targets(config, &cmd.name, &metadata)
+    }
+
+    /// Fetch the trusted delegations, keeping the raw response.
+    fn fetch(config: &mut Config) -> Result<(HeaderMap, Vec<u8>, Vec<TrustedDelegation>)> {
+        debug!("fetching trusted delegations");
+        let (headers, body) = Http::execute_body(Self::list_delegations_request(config)?, config.token()?)?;
+        let delegations = TrustedDelegation::parse_list(&body)?;
+        Ok((headers, body, delegations))
+    }
+}
+
+impl DelegationApi for Delegations {
+    fn list_delegations(config: &mut Config) -> Result<CommandResult> {
+        let (headers, body, delegations) = Self::fetch(config)?;
+        let mut result = TableResult::new(headers, body, &["name", "keyids", "paths", "threshold", "terminating"]);
+        for delegation in delegations {
+            result.add_row(vec![
+                delegation.name.into(),
+                delegation.keyids.into(),
+                delegation.paths.into(),
+                delegation.threshold.into(),
+                delegation.terminating.into(),
+            ]);
+        }
+        Ok(result.into())
+    }
+
+    fn put_delegations(config: &mut Config, delegations: &[TrustedDelegation]) -> Result<CommandResult> {
+        debug!("replacing trusted delegations: {:?}", delegations);
+        Ok(Http::execute(Self::put_delegations_request(config, delegations)?, config.token()?)?.into())
+    }
+
+    fn add_delegation_keys(config: &mut Config, keys: &Value) -> Result<CommandResult> {
+        debug!("adding trusted delegation keys");
+        Ok(Http::execute(Self::add_delegation_keys_request(config, keys)?, config.token()?)?.into())
+    }
+
+    fn put_delegated_targets(config: &mut Config, name: &str, metadata: &Value) -> Result<CommandResult> {
+        debug!("uploading targets metadata for delegation {}", name);
+        Ok(Http::execute(Self::put_delegated_targets_request(config, name, metadata)?, config.token()?)?.into())
+    }
+}
+
+/// Requests shared by the blocking and async delegation APIs.
+impl Delegations {
+    pub(crate) fn list_delegations_request(config: &Config) -> Result<Request> {
+        Request::get(format!("{}api/v1/user_repo/trusted-delegations", config.reposerver))
+    }
+
+    pub(crate) fn put_delegations_request(config: &Config, delegations: &[TrustedDelegation]) -> Result<Request> {
+        Ok(Request::put(format!("{}api/v1/user_repo/trusted-delegations", config.reposerver))?.json(serde_json::to_value(delegations)?))
+    }
+
+    pub(crate) fn add_delegation_keys_request(config: &Config, keys: &Value) -> Result<Request> {
+        Ok(Request::put(format!("{}api/v1/user_repo/trusted-delegations/keys", config.reposerver))?.json(keys.clone()))
+    }
+
+    pub(crate) fn put_delegated_targets_request(config: &Config, name: &str, metadata: &Value) -> Result<Request> {
+        let url = format!("{}api/v1/user_repo/delegations/{}.json", config.reposerver, urlencoding::encode(name));
+        Ok(Request::put(url)?.json(metadata.clone()))
+    }
+}
+
+/// A delegated role trusted to sign targets matching its paths.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TrustedDelegation {
+    pub name: String,
+    pub keyids: Vec<String>,
+    pub paths: Vec<String>,
+    #[serde(default = "default_threshold")]
+    pub threshold: u32,
+    #[serde(default)]
+    pub terminating: bool,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+fn default_threshold() -> u32 { 1 }
+
+impl TrustedDelegation {
+    /// Parse a list of delegations, or the `roles` of a delegations object.
+    fn parse_list(body: &[u8]) -> Result<Vec<Self>> {
+        match serde_json::from_slice(body)? {
+            Value::Object(mut delegations) => match delegations.remove("roles") {
+                Some(roles) => Ok(serde_json::from_value(roles)?),
+                None => Err(Error::Parse("trusted delegations have no `roles`".into())),
+            },
+            list => Ok(serde_json::from_value(list)?),
+        }
+    }
+}
+
+fn read_json(path: &Path) -> Result<Value> { Ok(serde_json::from_slice(&fs::read(path)?)?) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+    use structopt::StructOpt;
+
+    fn reposerver() -> MockServer {
+        MockServer::start(|req| match (req.method.as_ref(), req.route()) {
+            ("GET", "/api/v1/user_repo/trusted-delegations") => MockResponse::json(200, json!([
+                {"name": "team-a", "keyids": ["k1"], "paths": ["a-*"], "threshold": 1, "terminating": true}
+            ])),
+            ("PUT", _) => MockResponse::status(204),
+            _ => MockResponse::status(404),
+        })
+    }
+
+    #[test]
+    fn create_and_delete_delegations() {
+        let server = reposerver();
+        match Delegations::list_delegations(&mut server.config()).expect("list") {
+            CommandResult::Table(result) => assert_eq!(result.rows[0]["name"], "team-a"),
+            _ => panic!("expected a table"),
+        }
+        let cmd = DelegationCreateArgs::from_iter(&["create", "--name", "team-b", "--keyids", "k2", "k3", "--paths", "b-*", "--threshold", "2"]);
+        Delegations::create_from_args(&mut server.config(), &cmd).expect("create");
+        let cmd = DelegationCreateArgs::from_iter(&["create", "--name", "team-a", "--keyids", "k1", "--paths", "a-*"]);
+        match Delegations::create_from_args(&mut server.config(), &cmd) {
+            Err(Error::Conflict(_)) => (),
+            other => panic!("expected a conflict, got {:?}", other.err()),
+        }
+        let cmd = DelegationDeleteArgs::from_iter(&["delete", "--name", "team-a", "--yes"]);
+        Delegations::delete_from_args(&mut server.config(), &cmd).expect("delete");
+
+        let puts = server.requests().into_iter().filter(|req| req.method == "PUT").collect::<Vec<_>>();
+        assert_eq!(puts.len(), 2);
+        assert_eq!(puts[0].route(), "/api/v1/user_repo/trusted-delegations");
+        assert_eq!(puts[0].json(), json!([
+            {"name": "team-a", "keyids": ["k1"], "paths": ["a-*"], "threshold": 1, "terminating": true},
+            {"name": "team-b", "keyids": ["k2", "k3"], "paths": ["b-*"], "threshold": 2, "terminating": false}
+        ]));
+        assert_eq!(puts[1].json(), json!([]));
+
+        let roles = TrustedDelegation::parse_list(br#"{"keys": {}, "roles": [{"name": "team-c", "keyids": [], "paths": []}]}"#).expect("roles");
+        assert_eq!(roles[0].threshold, 1);
+    }
+}
@@ -1,53 +1,345 @@
-use clap::ArgMatches;
-use reqwest::blocking::{Client, Response};
+use reqwest::{header::HeaderMap, StatusCode};
 use serde::{self, Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
 use std::{
     collections::HashMap,
     fmt::{self, Display, Formatter},
     fs,
+    io::Read,
     path::Path,
     result,
     str::FromStr,
 };
 use toml;
+use urlencoding;
 use uuid::Uuid;
 
+use crate::api::registry::Registry;
+use crate::batch::{self, BatchOptions};
+use crate::cli::{DeviceEcuRemoveArgs, UpdateCreateArgs};
+use crate::command::{confirm, missing, CommandResult, TableResult};
 use crate::config::Config;
 use crate::error::{Error, Result};
-use crate::http::{Http, HttpMethods};
+use crate::http::{Http, Request};
 
 /// Available director API methods.
 pub trait DirectorApi {
     /// Create a new multi-target update.
-    fn create_mtu(_: &mut Config, updates: &TufUpdates) -> Result<Response>;
+    fn create_mtu(_: &mut Config, updates: &TufUpdates) -> Result<CommandResult>;
     /// Launch a multi-target update for a device.
-    fn launch_mtu(_: &mut Config, update: Uuid, device: Uuid) -> Result<Response>;
+    fn launch_mtu(_: &mut Config, update: Uuid, device: Uuid) -> Result<CommandResult>;
+    /// Show the hardware-id to target mapping of a created multi-target update.
+    fn get_mtu(_: &mut Config, update: Uuid) -> Result<CommandResult>;
+    /// Show the queued assignments of a device.
+    fn get_assignments(_: &mut Config, device: Uuid) -> Result<CommandResult>;
+    /// Show the ECUs of a device with their installed images.
+    fn get_device_ecus(_: &mut Config, device: Uuid) -> Result<CommandResult>;
+    /// Remove an ECU from a device, e.g. once it has been replaced.
+    fn delete_ecu(_: &mut Config, device: Uuid, ecu: &str) -> Result<CommandResult>;
 }
 
 /// Make API calls to launch multi-target updates.
 pub struct Director;
 
+impl Director {
+    /// Fetch the targets of a multi-target update.
+    pub fn fetch_mtu(config: &mut Config, update: Uuid) -> Result<Mtu> {
+        let (_, body) = Self::mtu_response(config, update)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Fetch the queued assignments of a device.
+    pub fn fetch_assignments(config: &mut Config, device: Uuid) -> Result<Vec<Assignment>> {
+        Http::execute_json(Self::get_assignments_request(config, device)?, config.token()?)
+    }
+
+    /// Fetch the ECUs of a device with their installed images.
+    pub fn fetch_ecus(config: &mut Config, device: Uuid) -> Result<Vec<Ecu>> {
+        Http::execute_json(Self::get_device_ecus_request(config, device)?, config.token()?)
+    }
+
+    /// List the ECUs of a device, by UUID or name, as a table.
+    pub fn ecus_table(config: &mut Config, device: &str) -> Result<CommandResult> {
+        let device = Registry::resolve_device(config, device)?;
+        Self::get_device_ecus(config, device)
+    }
+
+    /// Remove an ECU from a device once confirmed, so a replacement can take its place.
+    pub fn remove_ecu_from_args(config: &mut Config, cmd: &DeviceEcuRemoveArgs) -> Result<CommandResult> {
+        let device = Registry::resolve_device(config, &cmd.device)?;
+        let serial = &cmd.ecu_serial;
+        let ecu = Self::fetch_ecus(config, device)?
+            .into_iter()
+            .find(|ecu| ecu.id == *serial)
+            .ok_or_else(|| Error::NotFound(format!("ECU `{}` of device {}", serial, device), Some("See `ota device ecus` for its ECUs.".into())))?;
+        let kind = if ecu.primary { "primary ECU" } else { "ECU" };
+        confirm(cmd.yes, &format!("Remove {} {} ({}) from device {}?", kind, ecu.id, ecu.hardware_id, device))?;
+        Self::delete_ecu(config, device, serial)
+    }
+
+    /// Fetch a multi-target update, failing with `NotFound` for an unknown update.
+    fn mtu_response(config: &mut Config, update: Uuid) -> Result<(HeaderMap, Vec<u8>)> {
+        let resp = Http::execute(Self::get_mtu_request(config, update)?, config.token()?)?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(Error::NotFound(format!("Multi-target update {}", update), None));
+        }
+        let mut resp = resp.error_for_status()?;
+        let headers = resp.headers().to_owned();
+        let mut body = Vec::new();
+        resp.read_to_end(&mut body)?;
+        Ok((headers, body))
+    }
+
+    /// Launch a multi-target update for every device in a group, failing when any launch failed.
+    pub fn launch_mtu_to_group(config: &mut Config, update: Uuid, group: &str, opts: BatchOptions) -> Result<CommandResult> {
+        let devices = Registry::group_members(config, group)?;
+        if devices.is_empty() {
+            return Err(Error::Args(format!("group `{}` has no devices", group)));
+        }
+        let items = devices.into_iter().map(|device| (device.to_string(), device)).collect();
+        let launches = batch::run(config, "launching", items, opts, |config, device| {
+            Ok(Http::execute(Self::launch_mtu_request(config, update, device)?, config.token()?)?.error_for_status()?.status())
+        })?;
+
+        info!("launched update {} on {} devices", update, launches.len());
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["device", "status"]);
+        for (device, status) in launches {
+            result.add_row(vec![device.into(), status.as_u16().into()]);
+        }
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// Show the state of a launched multi-target update for each ECU of some devices.
+    ///
+    /// An ECU is `pending` while an assignment for the update is queued, `installed`
+    /// once it reports the update target, and `failed` when neither is the case.
+    pub fn status_mtu(config: &mut Config, update: Uuid, devices: &[Uuid]) -> Result<CommandResult> {
+        let targets = Self::fetch_mtu(config, update)?.targets;
+
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["device", "ecu", "hardware id", "target", "installed", "status"]);
+        let mut counts = HashMap::<&str, usize>::new();
+        for &device in devices {
+            let queue = Self::fetch_assignments(config, device)?;
+            let queued = queue
+                .iter()
+                .filter(|item| item.correlation_id.as_deref().is_some_and(|id| id.ends_with(&update.to_string())))
+                .flat_map(|item| item.targets.keys())
+                .collect::<Vec<_>>();
+            let mut ecus = Self::fetch_ecus(config, device)?;
+            ecus.sort_by(|a, b| b.primary.cmp(&a.primary).then_with(|| a.id.cmp(&b.id)));
+
+            for ecu in ecus {
+                let target = match targets.get(&ecu.hardware_id) {
+                    Some(target) => &target.to.target,
+                    None => continue,
+                };
+                let installed = ecu.image.map(|image| image.filepath);
+                let status = if queued.contains(&&ecu.id) {
+                    "pending"
+                } else if installed.as_ref() == Some(target) {
+                    "installed"
+                } else {
+                    "failed"
+                };
+                *counts.entry(status).or_default() += 1;
+                result.add_row(vec![
+                    device.to_string().into(),
+                    ecu.id.into(),
+                    ecu.hardware_id.into(),
+                    target.clone().into(),
+                    installed.into(),
+                    status.into(),
+                ]);
+            }
+        }
+        info!(
+            "{} installed, {} pending, {} failed",
+            counts.get("installed").unwrap_or(&0),
+            counts.get("pending").unwrap_or(&0),
+            counts.get("failed").unwrap_or(&0)
+        );
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// Parse a multi-target update response body into a table of targets.
+    fn mtu_table(headers: HeaderMap, body: Vec<u8>) -> Result<TableResult> {
+        let mut updates = serde_json::from_slice::<Mtu>(&body)?.targets.into_iter().collect::<Vec<_>>();
+        updates.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut result = TableResult::new(headers, body, &[
+            "hardware id",
+            "target_format",
+            "from",
+            "to",
+            "length",
+            "hash",
+            "generate diff",
+        ]);
+        for (hardware, update) in updates {
+            result.add_row(vec![
+                hardware.into(),
+                update.format.to_string().into(),
+                update.from.map(|from| from.target).into(),
+                update.to.target.into(),
+                update.to.length.into(),
+                update.to.checksum.hash.into(),
+                update.generate_diff.into(),
+            ]);
+        }
+        Ok(result)
+    }
+}
+
 impl DirectorApi for Director {
-    fn create_mtu(config: &mut Config, updates: &TufUpdates) -> Result<Response> {
+    fn create_mtu(config: &mut Config, updates: &TufUpdates) -> Result<CommandResult> {
         debug!("creating multi-target update: {:?}", updates);
-        let req = Client::new()
-            .post(&format!("{}api/v1/multi_target_updates", config.director))
-            .json(updates);
-        Http::send(req, config.token()?)
+        Ok(Http::execute(Self::create_mtu_request(config, updates)?, config.token()?)?.into())
     }
 
-    fn launch_mtu(config: &mut Config, update: Uuid, device: Uuid) -> Result<Response> {
+    fn launch_mtu(config: &mut Config, update: Uuid, device: Uuid) -> Result<CommandResult> {
         debug!("launching multi-target update {} for device {}", update, device);
-        Http::put(
-            &format!("{}api/v1/admin/devices/{}/multi_target_update/{}", config.director, device, update),
-            config.token()?,
-        )
+        Ok(Http::execute(Self::launch_mtu_request(config, update, device)?, config.token()?)?.into())
+    }
+
+    fn get_mtu(config: &mut Config, update: Uuid) -> Result<CommandResult> {
+        debug!("fetching multi-target update {}", update);
+        let (headers, body) = Self::mtu_response(config, update)?;
+        Ok(Self::mtu_table(headers, body)?.into())
+    }
+
+    fn get_assignments(config: &mut Config, device: Uuid) -> Result<CommandResult> {
+        debug!("fetching assignments of device {}", device);
+        let (headers, body) = Http::execute_body(Self::get_assignments_request(config, device)?, config.token()?)?;
+        let queue = serde_json::from_slice::<Vec<Assignment>>(&body)?;
+        if queue.is_empty() {
+            info!("device {} has no pending updates", device);
+        }
+        let mut result = TableResult::new(headers, body, &["correlation id", "ecu", "hardware id", "target", "in flight"]);
+        for assignment in queue {
+            let in_flight = assignment.other.get("inFlight").cloned().unwrap_or(Value::Null);
+            let mut targets = assignment.targets.into_iter().collect::<Vec<_>>();
+            targets.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (ecu, target) in targets {
+                result.add_row(vec![
+                    assignment.correlation_id.clone().into(),
+                    ecu.into(),
+                    target["hardwareId"].clone(),
+                    target["image"]["filepath"].clone(),
+                    in_flight.clone(),
+                ]);
+            }
+        }
+        Ok(result.into())
+    }
+
+    fn get_device_ecus(config: &mut Config, device: Uuid) -> Result<CommandResult> {
+        debug!("fetching ECUs of device {}", device);
+        let resp = Http::execute(Self::get_device_ecus_request(config, device)?, config.token()?)?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(Error::NotFound(
+                format!("Device {} in the director", device),
+                Some("A device is only known to the director once it has reported its ECUs.".into()),
+            ));
+        }
+        let mut resp = resp.error_for_status()?;
+        let headers = resp.headers().to_owned();
+        let mut body = Vec::new();
+        resp.read_to_end(&mut body)?;
+
+        let mut ecus = serde_json::from_slice::<Vec<Ecu>>(&body)?;
+        ecus.sort_by(|a, b| b.primary.cmp(&a.primary).then_with(|| a.id.cmp(&b.id)));
+        let mut result = TableResult::new(headers, body, &["ecu serial", "hardware id", "primary", "installed target", "length", "sha256"]);
+        for ecu in ecus {
+            let (filepath, length, sha256) = match ecu.image {
+                Some(image) => {
+                    let length = image.other.get("size").cloned().unwrap_or(Value::Null);
+                    let sha256 = image.other.get("hash").map_or(Value::Null, |hash| hash["sha256"].clone());
+                    (image.filepath.into(), length, sha256)
+                }
+                None => (Value::Null, Value::Null, Value::Null),
+            };
+            result.add_row(vec![ecu.id.into(), ecu.hardware_id.into(), ecu.primary.into(), filepath, length, sha256]);
+        }
+        Ok(result.into())
+    }
+
+    fn delete_ecu(config: &mut Config, device: Uuid, ecu: &str) -> Result<CommandResult> {
+        debug!("removing ECU {} of device {}", ecu, device);
+        Ok(Http::execute(Self::delete_ecu_request(config, device, ecu)?, config.token()?)?.into())
+    }
+}
+
+/// Requests shared by the blocking and async Director APIs.
+impl Director {
+    pub(crate) fn create_mtu_request(config: &Config, updates: &TufUpdates) -> Result<Request> {
+        Ok(Request::post(format!("{}api/v1/multi_target_updates", config.director))?.json(serde_json::to_value(updates)?))
+    }
+
+    pub(crate) fn launch_mtu_request(config: &Config, update: Uuid, device: Uuid) -> Result<Request> {
+        Request::put(format!("{}api/v1/admin/devices/{}/multi_target_update/{}", config.director, device, update))
+    }
+
+    pub(crate) fn get_mtu_request(config: &Config, update: Uuid) -> Result<Request> {
+        Request::get(format!("{}api/v1/multi_target_updates/{}", config.director, update))
+    }
+
+    pub(crate) fn get_assignments_request(config: &Config, device: Uuid) -> Result<Request> {
+        Request::get(format!("{}api/v1/assignments/{}", config.director, device))
+    }
+
+    pub(crate) fn get_device_ecus_request(config: &Config, device: Uuid) -> Result<Request> {
+        Request::get(format!("{}api/v1/admin/devices/{}", config.director, device))
+    }
+
+    pub(crate) fn delete_ecu_request(config: &Config, device: Uuid, ecu: &str) -> Result<Request> {
+        Request::delete(format!("{}api/v1/admin/devices/{}/ecus/{}", config.director, device, urlencoding::encode(ecu)))
     }
 }
 
 /// An identifier for the type of hardware and applicable `Target`s.
 type HardwareId = String;
 
+/// The targets of a created multi-target update for each hardware id.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(transparent)]
+pub struct Mtu {
+    pub targets: HashMap<HardwareId, TufUpdate>,
+}
+
+/// An assignment queued for a device, with the targets of each ECU.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Assignment {
+    pub correlation_id: Option<String>,
+    #[serde(default)]
+    pub targets: HashMap<String, Value>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// An ECU of a device and the image it last reported.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Ecu {
+    pub id: String,
+    pub hardware_id: HardwareId,
+    #[serde(default)]
+    pub primary: bool,
+    pub image: Option<EcuImage>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// The image an ECU last reported as installed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EcuImage {
+    pub filepath: String,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
 /// Metadata describing an object that can be applied to an ECU.
 #[derive(Serialize, Deserialize)]
 pub struct TargetObject {
@@ -83,6 +375,39 @@ impl TargetRequests {
     }
 }
 
+impl TargetRequests {
+    /// Parse inline CLI arguments into a request for each `--hardware` id.
+    pub fn from_args(cmd: &UpdateCreateArgs) -> Result<Self> {
+        let format = cmd.format.as_deref().ok_or_else(|| missing("format"))?.parse()?;
+        let name = cmd.name.as_deref().ok_or_else(|| missing("name"))?;
+        let version = cmd.version.as_deref().ok_or_else(|| missing("version"))?;
+        if cmd.hardware.is_empty() {
+            return Err(missing("hardware"));
+        }
+        let requests = cmd
+            .hardware
+            .iter()
+            .map(|id| {
+                let request = TargetRequest {
+                    target_format: Some(format),
+                    from: None,
+                    to: TargetObject {
+                        name: name.into(),
+                        version: version.into(),
+                        length: cmd.length,
+                        hash: cmd.hash.clone(),
+                        method: None,
+                        uri: None,
+                    },
+                    generate_diff: None,
+                };
+                (id.clone(), request)
+            })
+            .collect();
+        Ok(Self { requests })
+    }
+}
+
 /// A request to update an ECU to a specific `TufTarget`.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TufUpdate {
@@ -162,12 +487,12 @@ pub enum TargetFormat {
     Ostree,
 }
 
-impl<'a> TargetFormat {
-    /// Parse CLI arguments into a `TargetFormat`.
-    pub fn from_args(args: &ArgMatches<'a>) -> Result<Self> {
-        if args.is_present("binary") {
+impl TargetFormat {
+    /// Parse the `--binary` and `--ostree` CLI flags into a `TargetFormat`.
+    pub fn from_flags(binary: bool, ostree: bool) -> Result<Self> {
+        if binary {
             Ok(TargetFormat::Binary)
-        } else if args.is_present("ostree") {
+        } else if ostree {
             Ok(TargetFormat::Ostree)
         } else {
             Err(Error::Args("Either --binary or --ostree flag is required".into()))
@@ -241,6 +566,8 @@ impl<'de> Deserialize<'de> for ChecksumMethod {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock::{MockResponse, MockServer};
+    use structopt::StructOpt;
 
     #[test]
     fn parse_example_targets() {
@@ -283,4 +610,217 @@ mod tests {
             panic!("another-ecu-type not found");
         }
     }
+
+    #[test]
+    fn convert_explicit_target_formats() {
+        let target = |name: &str, length| TargetObject {
+            name: name.into(),
+            version: "1".into(),
+            length,
+            hash: Some("abcd".into()),
+            method: None,
+            uri: None,
+        };
+        let request = |format, name, length| TargetRequest {
+            target_format: Some(format),
+            from: None,
+            to: target(name, length),
+            generate_diff: None,
+        };
+        let mut requests = HashMap::new();
+        requests.insert("ecu-a".to_string(), request(TargetFormat::Binary, "firmware", Some(1024)));
+        requests.insert("ecu-b".to_string(), request(TargetFormat::Ostree, "my-branch", None));
+
+        let updates = TufUpdates::from(TargetRequests { requests }).expect("updates").targets;
+        assert_eq!(updates["ecu-a"].format, TargetFormat::Binary);
+        assert_eq!(updates["ecu-a"].to.target, "firmware-1");
+        assert_eq!(updates["ecu-a"].to.length, 1024);
+        assert_eq!(updates["ecu-b"].format, TargetFormat::Ostree);
+        assert_eq!(updates["ecu-b"].to.length, 0);
+
+        let json = serde_json::to_value(&TufUpdates { targets: updates }).expect("serialize");
+        assert_eq!(json["targets"]["ecu-a"]["targetFormat"], "BINARY");
+        assert_eq!(json["targets"]["ecu-b"]["targetFormat"], "OSTREE");
+
+        let mut requests = HashMap::new();
+        requests.insert("ecu-a".to_string(), request(TargetFormat::Binary, "firmware", None));
+        assert!(TufUpdates::from(TargetRequests { requests }).is_err());
+    }
+
+    #[test]
+    fn parse_mtu_response() {
+        let body = json!({
+            "ecu-b": {
+                "to": {
+                    "target": "firmware-2.0",
+                    "targetLength": 2048,
+                    "checksum": {"method": "sha256", "hash": "abcd"}
+                },
+                "targetFormat": "BINARY",
+                "generateDiff": false
+            },
+            "ecu-a": {
+                "from": {
+                    "target": "my-branch-1",
+                    "targetLength": 0,
+                    "checksum": {"method": "sha256", "hash": "1"}
+                },
+                "to": {
+                    "target": "my-branch-2",
+                    "targetLength": 0,
+                    "checksum": {"method": "sha256", "hash": "2"},
+                    "uri": null
+                },
+                "targetFormat": "OSTREE",
+                "generateDiff": true
+            }
+        });
+        let result = Director::mtu_table(HeaderMap::new(), serde_json::to_vec(&body).unwrap()).expect("mtu table");
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0]["hardware id"], "ecu-a");
+        assert_eq!(result.rows[0]["target_format"], "OSTREE");
+        assert_eq!(result.rows[0]["from"], "my-branch-1");
+        assert_eq!(result.rows[0]["generate diff"], true);
+        assert_eq!(result.rows[1]["hardware id"], "ecu-b");
+        assert_eq!(result.rows[1]["from"], serde_json::Value::Null);
+        assert_eq!(result.rows[1]["to"], "firmware-2.0");
+        assert_eq!(result.rows[1]["length"], 2048);
+    }
+
+    #[test]
+    fn status_of_launched_mtu() {
+        let update = Uuid::from_u128(1);
+        let (done, waiting) = (Uuid::from_u128(2), Uuid::from_u128(3));
+        let server = MockServer::start(move |req| match req.route() {
+            route if route == format!("/api/v1/multi_target_updates/{}", update) => MockResponse::json(200, json!({
+                "rpi": {"to": {"target": "os-2", "targetLength": 0, "checksum": {"method": "sha256", "hash": "2"}}, "targetFormat": "OSTREE", "generateDiff": false}
+            })),
+            route if route == format!("/api/v1/assignments/{}", waiting) => MockResponse::json(200, json!([
+                {"correlationId": format!("urn:here-ota:mtu:{}", update), "targets": {"ecu-3": {}}, "inFlight": true}
+            ])),
+            route if route.starts_with("/api/v1/assignments/") => MockResponse::json(200, json!([])),
+            route if route == format!("/api/v1/admin/devices/{}", done) => MockResponse::json(200, json!([
+                {"id": "ecu-2b", "hardwareId": "rpi", "primary": false, "image": {"filepath": "os-1"}},
+                {"id": "ecu-2a", "hardwareId": "rpi", "primary": true, "image": {"filepath": "os-2"}},
+                {"id": "ecu-2c", "hardwareId": "other", "primary": false}
+            ])),
+            route if route == format!("/api/v1/admin/devices/{}", waiting) => MockResponse::json(200, json!([
+                {"id": "ecu-3", "hardwareId": "rpi", "primary": true, "image": {"filepath": "os-1"}}
+            ])),
+            _ => MockResponse::status(404),
+        });
+        let result = match Director::status_mtu(&mut server.config(), update, &[done, waiting]).expect("status") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table"),
+        };
+        let statuses = result.rows.iter().map(|row| (row["ecu"].as_str().unwrap(), row["status"].as_str().unwrap())).collect::<Vec<_>>();
+        assert_eq!(statuses, vec![("ecu-2a", "installed"), ("ecu-2b", "failed"), ("ecu-3", "pending")]);
+        assert_eq!(result.rows[1]["installed"], "os-1");
+    }
+
+    #[test]
+    fn launch_mtu_to_group() {
+        let group = Uuid::from_u128(9);
+        let server = MockServer::start(move |req| match (req.method.as_ref(), req.route()) {
+            ("GET", route) if route == format!("/api/v1/device_groups/{}/devices", group) => MockResponse::json(200, json!({
+                "total": 2, "offset": 0, "limit": 50, "values": [Uuid::from_u128(1), Uuid::from_u128(2)]
+            })),
+            ("PUT", route) if route.contains(&Uuid::from_u128(2).to_string()) => MockResponse::status(500),
+            ("PUT", _) => MockResponse::status(200),
+            _ => MockResponse::status(404),
+        });
+        let opts = BatchOptions { concurrency: 2, ..BatchOptions::default() };
+        let err = Director::launch_mtu_to_group(&mut server.config(), Uuid::nil(), &group.to_string(), opts).err().expect("failed launch");
+        assert_eq!(err.to_string(), "Failed: launching failed for 1 of 2 items");
+        assert_eq!(err.exit_code(), crate::error::EXIT_FAILURE);
+        let launches = server.requests().into_iter().filter(|req| req.method == "PUT").count();
+        assert_eq!(launches, 2);
+    }
+
+    #[test]
+    fn list_device_ecus() {
+        let device = Uuid::from_u128(1);
+        let server = MockServer::start(move |req| match req.route() {
+            route if route == format!("/api/v1/admin/devices/{}", device) => MockResponse::json(200, json!([
+                {"id": "mcu-1", "hardwareId": "mcu", "primary": false},
+                {"id": "rpi-1", "hardwareId": "rpi", "primary": true, "image": {"filepath": "os-2", "size": 12, "hash": {"sha256": "aa"}}}
+            ])),
+            _ => MockResponse::status(404),
+        });
+        let result = match Director::ecus_table(&mut server.config(), &device.to_string()).expect("ecus") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table"),
+        };
+        assert_eq!(result.rows[0]["ecu serial"], "rpi-1");
+        assert_eq!(result.rows[0]["installed target"], "os-2");
+        assert_eq!((&result.rows[0]["length"], &result.rows[0]["sha256"]), (&json!(12), &json!("aa")));
+        assert_eq!((&result.rows[1]["primary"], &result.rows[1]["installed target"]), (&json!(false), &Value::Null));
+
+        match Director::get_device_ecus(&mut server.config(), Uuid::nil()) {
+            Err(Error::NotFound(..)) => (),
+            other => panic!("expected not found, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn remove_device_ecu() {
+        let device = Uuid::from_u128(1);
+        let server = MockServer::start(move |req| match (req.method.as_ref(), req.route()) {
+            ("GET", route) if route == format!("/api/v1/admin/devices/{}", device) => MockResponse::json(200, json!([
+                {"id": "rpi-1", "hardwareId": "rpi", "primary": true},
+                {"id": "mcu 1", "hardwareId": "mcu", "primary": false}
+            ])),
+            ("DELETE", _) => MockResponse::status(200),
+            _ => MockResponse::status(404),
+        });
+        let remove = |serial: &str| {
+            let cmd = DeviceEcuRemoveArgs::from_iter(&["remove", "--device", &device.to_string(), "--ecu-serial", serial, "--yes"]);
+            Director::remove_ecu_from_args(&mut server.config(), &cmd)
+        };
+
+        remove("mcu 1").expect("remove");
+        let deleted = server.requests().into_iter().find(|req| req.method == "DELETE").expect("delete");
+        assert_eq!(deleted.route(), format!("/api/v1/admin/devices/{}/ecus/mcu%201", device));
+        match remove("cam-1") {
+            Err(Error::NotFound(..)) => (),
+            other => panic!("expected not found, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn pending_assignments() {
+        let device = Uuid::from_u128(1);
+        let server = MockServer::start(move |req| match req.route() {
+            route if route == format!("/api/v1/assignments/{}", device) => MockResponse::json(200, json!([{
+                "correlationId": "urn:here-ota:mtu:00000000-0000-0000-0000-000000000002",
+                "targets": {
+                    "mcu-1": {"hardwareId": "mcu", "image": {"filepath": "mcu-2", "size": 3}},
+                    "rpi-1": {"hardwareId": "rpi", "image": {"filepath": "os-2", "size": 12}}
+                },
+                "inFlight": true
+            }])),
+            _ => MockResponse::json(200, json!([])),
+        });
+        let result = match Director::get_assignments(&mut server.config(), device).expect("assignments") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table"),
+        };
+        let targets = result.rows.iter().map(|row| (row["ecu"].as_str().unwrap(), row["target"].as_str().unwrap())).collect::<Vec<_>>();
+        assert_eq!(targets, vec![("mcu-1", "mcu-2"), ("rpi-1", "os-2")]);
+        assert_eq!((&result.rows[1]["hardware id"], &result.rows[1]["in flight"]), (&json!("rpi"), &json!(true)));
+
+        match Director::get_assignments(&mut server.config(), Uuid::nil()).expect("empty queue") {
+            CommandResult::Table(result) => assert!(result.rows.is_empty()),
+            _ => panic!("expected a table"),
+        }
+    }
+
+    #[test]
+    fn show_unknown_mtu() {
+        let server = MockServer::start(|_| MockResponse::status(404));
+        match Director::get_mtu(&mut server.config(), Uuid::nil()) {
+            Err(Error::NotFound(..)) => (),
+            other => panic!("expected not found, got {:?}", other.err()),
+        }
+    }
 }
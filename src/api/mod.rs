@@ -1,5 +1,11 @@
 pub mod auth_plus;
 pub mod campaigner;
+pub mod delegation;
 pub mod director;
+#[cfg(feature = "async")]
+pub mod nonblocking;
 pub mod registry;
+pub mod repo;
 pub mod reposerver;
+pub mod treehub;
+pub mod upload;
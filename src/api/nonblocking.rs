@@ -0,0 +1,314 @@
+//! Async equivalents of the API traits, enabled by the `async` feature.
+//!
+//! Requests are built by the same functions as the blocking APIs. Since the
+//! async APIs never fetch a token themselves, the `Config` should already hold
+//! one (e.g. from `Config::with_token`) unless the credentials disable auth.
+
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::api::{
+    campaigner::{CampaignStatus, Campaigner},
+    delegation::{Delegations, TrustedDelegation},
+    director::{Director, TufUpdates},
+    registry::{DeviceType, GroupType, Registry},
+    repo::Repo,
+    reposerver::Reposerver,
+};
+use crate::config::Config;
+use crate::http::{ApiFuture, AsyncHttp};
+
+/// Available async Device Registry API methods.
+pub trait AsyncRegistryApi {
+    fn create_device(_: &Config, name: &str, id: &str, kind: DeviceType) -> ApiFuture;
+    fn delete_device(_: &Config, device: Uuid) -> ApiFuture;
+    fn update_device(_: &Config, device: Uuid, name: &str) -> ApiFuture;
+    fn list_device(_: &Config, device: Uuid) -> ApiFuture;
+    fn list_all_devices(_: &Config) -> ApiFuture;
+    fn search_devices(_: &Config, name: &str) -> ApiFuture;
+    fn list_events(_: &Config, device: Uuid) -> ApiFuture;
+
+    fn create_group(_: &Config, name: &str, group_type: GroupType, expression: Option<&str>) -> ApiFuture;
+    fn search_groups(_: &Config, name: &str) -> ApiFuture;
+    fn rename_group(_: &Config, group: Uuid, name: &str) -> ApiFuture;
+    fn delete_group(_: &Config, group: Uuid) -> ApiFuture;
+    fn add_to_group(_: &Config, group: Uuid, device: Uuid) -> ApiFuture;
+    fn remove_from_group(_: &Config, group: Uuid, device: Uuid) -> ApiFuture;
+
+    fn list_groups(_: &Config, device: Uuid) -> ApiFuture;
+    fn list_devices(_: &Config, group: Uuid) -> ApiFuture;
+    fn list_all_groups(_: &Config) -> ApiFuture;
+}
+
+impl AsyncRegistryApi for Registry {
+    fn create_device(config: &Config, name: &str, id: &str, kind: DeviceType) -> ApiFuture {
+        AsyncHttp::execute(Self::create_device_request(config, name, id, kind), config.token.clone())
+    }
+
+    fn delete_device(config: &Config, device: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::delete_device_request(config, device), config.token.clone())
+    }
+
+    fn update_device(config: &Config, device: Uuid, name: &str) -> ApiFuture {
+        AsyncHttp::execute(Self::update_device_request(config, device, name), config.token.clone())
+    }
+
+    fn list_device(config: &Config, device: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::list_device_request(config, device), config.token.clone())
+    }
+
+    fn list_all_devices(config: &Config) -> ApiFuture { AsyncHttp::execute(Self::list_all_devices_request(config), config.token.clone()) }
+
+    fn search_devices(config: &Config, name: &str) -> ApiFuture {
+        AsyncHttp::execute(Self::search_devices_request(config, name), config.token.clone())
+    }
+
+    fn list_events(config: &Config, device: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::list_events_request(config, device), config.token.clone())
+    }
+
+    fn create_group(config: &Config, name: &str, group_type: GroupType, expression: Option<&str>) -> ApiFuture {
+        AsyncHttp::execute(Self::create_group_request(config, name, group_type, expression), config.token.clone())
+    }
+
+    fn search_groups(config: &Config, name: &str) -> ApiFuture {
+        AsyncHttp::execute(Self::search_groups_request(config, name), config.token.clone())
+    }
+
+    fn rename_group(config: &Config, group: Uuid, name: &str) -> ApiFuture {
+        AsyncHttp::execute(Self::rename_group_request(config, group, name), config.token.clone())
+    }
+
+    fn delete_group(config: &Config, group: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::delete_group_request(config, group), config.token.clone())
+    }
+
+    fn add_to_group(config: &Config, group: Uuid, device: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::add_to_group_request(config, group, device), config.token.clone())
+    }
+
+    fn remove_from_group(config: &Config, group: Uuid, device: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::remove_from_group_request(config, group, device), config.token.clone())
+    }
+
+    fn list_groups(config: &Config, device: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::list_groups_request(config, device), config.token.clone())
+    }
+
+    fn list_devices(config: &Config, group: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::list_devices_request(config, group), config.token.clone())
+    }
+
+    fn list_all_groups(config: &Config) -> ApiFuture { AsyncHttp::execute(Self::list_all_groups_request(config), config.token.clone()) }
+}
+
+/// Available async Director API methods.
+pub trait AsyncDirectorApi {
+    fn create_mtu(_: &Config, updates: &TufUpdates) -> ApiFuture;
+    fn launch_mtu(_: &Config, update: Uuid, device: Uuid) -> ApiFuture;
+    fn get_mtu(_: &Config, update: Uuid) -> ApiFuture;
+    fn get_assignments(_: &Config, device: Uuid) -> ApiFuture;
+    fn get_device_ecus(_: &Config, device: Uuid) -> ApiFuture;
+    fn delete_ecu(_: &Config, device: Uuid, ecu: &str) -> ApiFuture;
+}
+
+impl AsyncDirectorApi for Director {
+    fn create_mtu(config: &Config, updates: &TufUpdates) -> ApiFuture {
+        AsyncHttp::execute(Self::create_mtu_request(config, updates), config.token.clone())
+    }
+
+    fn launch_mtu(config: &Config, update: Uuid, device: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::launch_mtu_request(config, update, device), config.token.clone())
+    }
+
+    fn get_mtu(config: &Config, update: Uuid) -> ApiFuture { AsyncHttp::execute(Self::get_mtu_request(config, update), config.token.clone()) }
+
+    fn get_assignments(config: &Config, device: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::get_assignments_request(config, device), config.token.clone())
+    }
+
+    fn get_device_ecus(config: &Config, device: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::get_device_ecus_request(config, device), config.token.clone())
+    }
+
+    fn delete_ecu(config: &Config, device: Uuid, ecu: &str) -> ApiFuture {
+        AsyncHttp::execute(Self::delete_ecu_request(config, device, ecu), config.token.clone())
+    }
+}
+
+/// Available async Campaigner API methods.
+pub trait AsyncCampaignerApi {
+    fn create_campaign(_: &Config, update: Uuid, name: &str, groups: &[Uuid], approval_needed: bool) -> ApiFuture;
+    fn update_campaign(_: &Config, campaign: Uuid, name: Option<&str>, description: Option<&str>, groups: Option<&[Uuid]>) -> ApiFuture;
+    fn launch_campaign(_: &Config, campaign: Uuid) -> ApiFuture;
+    fn cancel_campaign(_: &Config, campaign: Uuid, reason: Option<&str>) -> ApiFuture;
+    fn retry_failed(_: &Config, campaign: Uuid, failure_code: Option<&str>) -> ApiFuture;
+
+    fn list_updates(_: &Config) -> ApiFuture;
+    fn create_update(_: &Config, update: Uuid, name: &str, description: &str) -> ApiFuture;
+
+    fn list_campaign_info(_: &Config, campaign: Uuid) -> ApiFuture;
+    fn list_campaign_stats(_: &Config, campaign: Uuid) -> ApiFuture;
+    fn list_all_campaigns(_: &Config, status: Option<CampaignStatus>) -> ApiFuture;
+    fn list_campaign_devices(_: &Config, campaign: Uuid, failed_only: bool) -> ApiFuture;
+    fn list_approvals(_: &Config, campaign: Uuid) -> ApiFuture;
+    fn resolve_approval(_: &Config, campaign: Uuid, device: Uuid, approved: bool, reason: Option<&str>) -> ApiFuture;
+}
+
+impl AsyncCampaignerApi for Campaigner {
+    fn create_campaign(config: &Config, update: Uuid, name: &str, groups: &[Uuid], approval_needed: bool) -> ApiFuture {
+        AsyncHttp::execute(Self::create_campaign_request(config, update, name, groups, approval_needed), config.token.clone())
+    }
+
+    fn update_campaign(config: &Config, campaign: Uuid, name: Option<&str>, description: Option<&str>, groups: Option<&[Uuid]>) -> ApiFuture {
+        AsyncHttp::execute(Self::update_campaign_request(config, campaign, name, description, groups), config.token.clone())
+    }
+
+    fn launch_campaign(config: &Config, campaign: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::launch_campaign_request(config, campaign), config.token.clone())
+    }
+
+    fn cancel_campaign(config: &Config, campaign: Uuid, reason: Option<&str>) -> ApiFuture {
+        AsyncHttp::execute(Self::cancel_campaign_request(config, campaign, reason), config.token.clone())
+    }
+
+    fn retry_failed(config: &Config, campaign: Uuid, failure_code: Option<&str>) -> ApiFuture {
+        AsyncHttp::execute(Self::retry_failed_request(config, campaign, failure_code), config.token.clone())
+    }
+
+    fn list_updates(config: &Config) -> ApiFuture { AsyncHttp::execute(Self::list_updates_request(config), config.token.clone()) }
+
+    fn create_update(config: &Config, update: Uuid, name: &str, description: &str) -> ApiFuture {
+        AsyncHttp::execute(Self::create_update_request(config, update, name, description), config.token.clone())
+    }
+
+    fn list_campaign_info(config: &Config, campaign: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::list_campaign_info_request(config, campaign), config.token.clone())
+    }
+
+    fn list_campaign_stats(config: &Config, campaign: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::list_campaign_stats_request(config, campaign), config.token.clone())
+    }
+
+    fn list_all_campaigns(config: &Config, status: Option<CampaignStatus>) -> ApiFuture {
+        AsyncHttp::execute(Self::list_all_campaigns_request(config, status), config.token.clone())
+    }
+
+    fn list_campaign_devices(config: &Config, campaign: Uuid, failed_only: bool) -> ApiFuture {
+        AsyncHttp::execute(Self::list_campaign_devices_request(config, campaign, failed_only), config.token.clone())
+    }
+
+    fn list_approvals(config: &Config, campaign: Uuid) -> ApiFuture {
+        AsyncHttp::execute(Self::list_approvals_request(config, campaign), config.token.clone())
+    }
+
+    fn resolve_approval(config: &Config, campaign: Uuid, device: Uuid, approved: bool, reason: Option<&str>) -> ApiFuture {
+        AsyncHttp::execute(Self::resolve_approval_request(config, campaign, device, approved, reason), config.token.clone())
+    }
+}
+
+/// Available async TUF Reposerver API methods.
+///
+/// Package uploads use multipart bodies built from files, so they are only
+/// available from the blocking `ReposerverApi`.
+pub trait AsyncReposerverApi {
+    fn get_package(_: &Config, name: &str, version: &str) -> ApiFuture;
+    fn list_packages(_: &Config) -> ApiFuture;
+    fn delete_package(_: &Config, filename: &str) -> ApiFuture;
+}
+
+impl AsyncReposerverApi for Reposerver {
+    fn get_package(config: &Config, name: &str, version: &str) -> ApiFuture {
+        AsyncHttp::execute(Self::get_package_request(config, name, version), config.token.clone())
+    }
+
+    fn list_packages(config: &Config) -> ApiFuture { AsyncHttp::execute(Self::targets_request(config), config.token.clone()) }
+
+    fn delete_package(config: &Config, filename: &str) -> ApiFuture {
+        AsyncHttp::execute(Self::delete_package_request(config, filename), config.token.clone())
+    }
+}
+
+/// Available async reposerver root metadata API methods.
+pub trait AsyncRepoApi {
+    fn get_root(_: &Config, version: Option<u64>) -> ApiFuture;
+    fn put_root(_: &Config, root: &Value) -> ApiFuture;
+    fn get_targets(_: &Config) -> ApiFuture;
+    fn put_targets(_: &Config, targets: &Value, checksum: &str) -> ApiFuture;
+}
+
+impl AsyncRepoApi for Repo {
+    fn get_root(config: &Config, version: Option<u64>) -> ApiFuture { AsyncHttp::execute(Self::get_root_request(config, version), config.token.clone()) }
+
+    fn put_root(config: &Config, root: &Value) -> ApiFuture { AsyncHttp::execute(Self::put_root_request(config, root), config.token.clone()) }
+
+    fn get_targets(config: &Config) -> ApiFuture { AsyncHttp::execute(Reposerver::targets_request(config), config.token.clone()) }
+
+    fn put_targets(config: &Config, targets: &Value, checksum: &str) -> ApiFuture {
+        AsyncHttp::execute(Self::put_targets_request(config, targets, checksum), config.token.clone())
+    }
+}
+
+/// Available async reposerver delegation API methods.
+pub trait AsyncDelegationApi {
+    fn list_delegations(_: &Config) -> ApiFuture;
+    fn put_delegations(_: &Config, delegations: &[TrustedDelegation]) -> ApiFuture;
+    fn add_delegation_keys(_: &Config, keys: &Value) -> ApiFuture;
+    fn put_delegated_targets(_: &Config, name: &str, metadata: &Value) -> ApiFuture;
+}
+
+impl AsyncDelegationApi for Delegations {
+    fn list_delegations(config: &Config) -> ApiFuture { AsyncHttp::execute(Self::list_delegations_request(config), config.token.clone()) }
+
+    fn put_delegations(config: &Config, delegations: &[TrustedDelegation]) -> ApiFuture {
+        AsyncHttp::execute(Self::put_delegations_request(config, delegations), config.token.clone())
+    }
+
+    fn add_delegation_keys(config: &Config, keys: &Value) -> ApiFuture {
+        AsyncHttp::execute(Self::add_delegation_keys_request(config, keys), config.token.clone())
+    }
+
+    fn put_delegated_targets(config: &Config, name: &str, metadata: &Value) -> ApiFuture {
+        AsyncHttp::execute(Self::put_delegated_targets_request(config, name, metadata), config.token.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+    use std::future::Future;
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().expect("runtime").block_on(future)
+    }
+
+    #[test]
+    fn async_list_all_devices() {
+        let server = MockServer::start(|_| MockResponse::json(200, json!({"values": []})));
+        let config = server.config();
+        let resp = block_on(<Registry as AsyncRegistryApi>::list_all_devices(&config)).expect("response");
+        assert_eq!(resp.status(), 200);
+
+        let req = &server.requests()[0];
+        assert_eq!((req.method.as_str(), req.route()), ("GET", "/api/v1/devices"));
+        assert_eq!(req.headers["authorization"], "Bearer token");
+        assert_eq!(req.headers["x-ats-namespace"], "default");
+    }
+
+    #[test]
+    fn async_requests_match_blocking() {
+        let server = MockServer::start(|_| MockResponse::status(201));
+        let mut config = server.config();
+        let groups = [Uuid::nil()];
+
+        block_on(<Campaigner as AsyncCampaignerApi>::create_campaign(&config, Uuid::nil(), "spring", &groups, true)).expect("async");
+        <Campaigner as crate::api::campaigner::CampaignerApi>::create_campaign(&mut config, Uuid::nil(), "spring", &groups, true).expect("blocking");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].path, requests[1].path);
+        assert_eq!(requests[0].json(), requests[1].json());
+        assert_eq!(requests[0].json()["name"], "spring");
+    }
+}
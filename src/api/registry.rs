@@ -1,154 +1,854 @@
-use clap::ArgMatches;
-use reqwest::blocking::{Client, Response};
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::{
+    collections::{HashMap, HashSet},
     fmt::{self, Display, Formatter},
+    fs,
+    path::Path,
     str::FromStr,
+    thread,
+    time::{Duration, SystemTime},
 };
 use uuid::Uuid;
 
+use crate::batch::{self, BatchOptions};
+use crate::cli::{DeviceListArgs, GroupCreateArgs, GroupDeleteArgs, GroupListArgs};
+use crate::command::{confirm, missing, CommandResult, TableResult};
 use crate::config::Config;
 use crate::error::{Error, Result};
-use crate::http::{Http, HttpMethods};
+use crate::http::{Http, Pagination, Request};
 
 /// Available Device Registry API methods.
+///
+/// Listings are rendered from the typed `Device`, `Group` and `Members`
+/// results, while changes return the registry response.
 pub trait RegistryApi {
-    fn create_device(_: &mut Config, name: &str, id: &str, kind: DeviceType) -> Result<Response>;
-    fn delete_device(_: &mut Config, device: Uuid) -> Result<Response>;
-    fn list_device(_: &mut Config, device: Uuid) -> Result<Response>;
-    fn list_all_devices(_: &mut Config) -> Result<Response>;
+    fn create_device(_: &mut Config, name: &str, id: &str, kind: DeviceType) -> Result<CommandResult>;
+    fn delete_device(_: &mut Config, device: Uuid) -> Result<CommandResult>;
+    fn update_device(_: &mut Config, device: Uuid, name: &str) -> Result<CommandResult>;
+    fn list_device(_: &mut Config, device: Uuid) -> Result<CommandResult>;
+    fn list_all_devices(_: &mut Config) -> Result<CommandResult>;
+    fn search_devices(_: &mut Config, name: &str) -> Result<CommandResult>;
+    fn list_events(_: &mut Config, device: Uuid) -> Result<CommandResult>;
 
-    fn create_group(_: &mut Config, name: &str, group_type: GroupType) -> Result<Response>;
-    fn rename_group(_: &mut Config, group: Uuid, name: &str) -> Result<Response>;
-    fn add_to_group(_: &mut Config, group: Uuid, device: Uuid) -> Result<Response>;
-    fn remove_from_group(_: &mut Config, group: Uuid, device: Uuid) -> Result<Response>;
+    fn create_group(_: &mut Config, name: &str, group_type: GroupType, expression: Option<&str>) -> Result<CommandResult>;
+    fn search_groups(_: &mut Config, name: &str) -> Result<CommandResult>;
+    fn rename_group(_: &mut Config, group: Uuid, name: &str) -> Result<CommandResult>;
+    fn delete_group(_: &mut Config, group: Uuid) -> Result<CommandResult>;
+    fn add_to_group(_: &mut Config, group: Uuid, device: Uuid) -> Result<CommandResult>;
+    fn remove_from_group(_: &mut Config, group: Uuid, device: Uuid) -> Result<CommandResult>;
 
-    fn list_groups(_: &mut Config, device: Uuid) -> Result<Response>;
-    fn list_devices(_: &mut Config, group: Uuid) -> Result<Response>;
-    fn list_all_groups(_: &mut Config) -> Result<Response>;
+    fn list_groups(_: &mut Config, device: Uuid) -> Result<CommandResult>;
+    fn list_devices(_: &mut Config, group: Uuid) -> Result<CommandResult>;
+    fn list_all_groups(_: &mut Config) -> Result<CommandResult>;
 }
 
 /// Make API calls to manage device groups.
 pub struct Registry;
 
-impl<'a> Registry {
+/// The number of times a conflicting group membership change is retried.
+const CONFLICT_RETRIES: u32 = 3;
+/// The delay before the first retry, doubling after each attempt.
+const CONFLICT_BACKOFF: Duration = Duration::from_millis(100);
+
+impl Registry {
     /// Parse args as device listing preferences.
-    pub fn list_device_args(config: &mut Config, args: &ArgMatches<'a>) -> Result<Response> {
+    pub fn list_device_args(config: &mut Config, cmd: &DeviceListArgs) -> Result<CommandResult> {
+        let search = DeviceSearch::from_args(cmd)?;
         #[cfg_attr(rustfmt, rustfmt_skip)]
-        match parse_list_args(args)? {
-            (true, _, _)         => Self::list_all_devices(config),
+        match (cmd.all, cmd.device, cmd.group) {
             (_, Some(device), _) => Self::list_device(config, device),
             (_, _, Some(group))  => Self::list_devices(config, group),
-            _ => Err(Error::Args("one of --all, --device, or --group required".into())),
+            (all, _, _) if all || !search.is_empty() => {
+                Self::list_all_device_table(config, &search, SeenFilter::from_args(cmd, SystemTime::now())?, Pagination::new(cmd.limit, cmd.all_pages)?)
+            }
+            _ => Err(Error::Args("one of --all, --device, --group or a search filter required".into())),
+        }
+    }
+
+    /// Fetch a single device.
+    pub fn fetch_device(config: &mut Config, device: Uuid) -> Result<Device> {
+        Http::execute_json(Self::list_device_request(config, device)?, config.token()?)
+    }
+
+    /// Fetch the devices matching a name search.
+    pub fn fetch_devices(config: &mut Config, name: &str) -> Result<DeviceList> {
+        Http::execute_json(Self::search_devices_request(config, name)?, config.token()?)
+    }
+
+    /// Fetch the groups matching a name search.
+    pub fn fetch_groups(config: &mut Config, name: &str) -> Result<GroupList> {
+        Http::execute_json(Self::search_groups_request(config, name)?, config.token()?)
+    }
+
+    /// Fetch the ids of the groups a device belongs to.
+    pub fn fetch_device_groups(config: &mut Config, device: Uuid) -> Result<Vec<Uuid>> {
+        Ok(Http::execute_json::<Members>(Self::list_groups_request(config, device)?, config.token()?)?.values)
+    }
+
+    /// Fetch the events reported by a device.
+    pub fn fetch_events(config: &mut Config, device: Uuid) -> Result<Vec<DeviceEvent>> {
+        let (_, body) = Http::execute_body(Self::list_events_request(config, device)?, config.token()?)?;
+        DeviceEvent::parse_list(&body)
+    }
+
+    /// List all devices as a table, keeping only those matching the last-seen filter.
+    fn list_all_device_table(config: &mut Config, search: &DeviceSearch, seen: Option<SeenFilter>, pages: Pagination) -> Result<CommandResult> {
+        let req = search.query(Self::list_all_devices_request(config)?);
+        let (headers, page) = Http::execute_pages(req, pages, config.token()?)?;
+        let mut list: DeviceList = serde_json::from_value(page)?;
+        if let Some(created_after) = search.created_after {
+            list.retain_created_after(created_after)?;
+        }
+        if let Some(seen) = seen {
+            list.retain_seen(seen)?;
+        }
+        Ok(Self::device_table(headers, serde_json::to_vec(&list)?, &list.values).into())
+    }
+
+    /// Render devices as a table, with `response` as the raw output.
+    fn device_table(headers: HeaderMap, response: Vec<u8>, devices: &[Device]) -> TableResult {
+        let mut result = TableResult::new(headers, response, &["uuid", "name", "device id", "type", "status", "last seen"]);
+        for device in devices {
+            result.add_row(vec![
+                device.uuid.to_string().into(),
+                device.name.clone().into(),
+                device.device_id.clone().into(),
+                device.device_type.clone().into(),
+                device.status.clone().into(),
+                device.last_seen.clone().into(),
+            ]);
         }
+        result
     }
 
     /// Parse args as group listing preferences.
-    pub fn list_group_args(config: &mut Config, args: &ArgMatches<'a>) -> Result<Response> {
+    pub fn list_group_args(config: &mut Config, cmd: &GroupListArgs) -> Result<CommandResult> {
         #[cfg_attr(rustfmt, rustfmt_skip)]
-        match parse_list_args(args)? {
-            (true, _, _)         => Self::list_all_groups(config),
+        match (cmd.all, cmd.device, cmd.group) {
+            (true, _, _)         => Self::list_all_group_table(config, Pagination::new(cmd.limit, cmd.all_pages)?),
             (_, Some(device), _) => Self::list_groups(config, device),
             (_, _, Some(group))  => Self::list_devices(config, group),
             _ => Err(Error::Args("one of --all, --device, or --group required".into())),
         }
     }
+
+    /// Create a static group, or a dynamic group once its expression is validated.
+    pub fn create_group_from_args(config: &mut Config, cmd: &GroupCreateArgs) -> Result<CommandResult> {
+        if cmd.dynamic {
+            let expression = cmd.expression.as_deref().ok_or_else(|| missing("expression"))?;
+            validate_expression(expression)?;
+            Self::create_group(config, &cmd.name, GroupType::Dynamic, Some(expression))
+        } else {
+            Self::create_group(config, &cmd.name, GroupType::Static, None)
+        }
+    }
+
+    /// List all groups as a table, keeping the raw response.
+    fn list_all_group_table(config: &mut Config, pages: Pagination) -> Result<CommandResult> {
+        let (headers, page) = Http::execute_pages(Self::list_all_groups_request(config)?, pages, config.token()?)?;
+        Self::group_table(headers, serde_json::to_vec(&page)?)
+    }
+
+    /// Parse a page of groups into a table, keeping the raw response.
+    fn group_table(headers: HeaderMap, body: Vec<u8>) -> Result<CommandResult> {
+        let list: GroupList = serde_json::from_slice(&body)?;
+
+        let mut result = TableResult::new(headers, body, &["id", "name", "type", "expression"]);
+        for group in list.values {
+            result.add_row(vec![group.id.to_string().into(), group.name.into(), group.group_type.into(), group.expression.into()]);
+        }
+        Ok(result.into())
+    }
+
+    /// Parse a page of group or device ids into a single `column` table, keeping the raw response.
+    fn members_table(headers: HeaderMap, body: Vec<u8>, column: &str) -> Result<CommandResult> {
+        let members: Members = serde_json::from_slice(&body)?;
+
+        let mut result = TableResult::new(headers, body, &[column]);
+        for id in members.values {
+            result.add_row(vec![id.to_string().into()]);
+        }
+        Ok(result.into())
+    }
+
+    /// Create each device listed in a file, returning the outcome of each row.
+    ///
+    /// A failed device fails the import, straight away with `fail_fast` or else once every
+    /// device was attempted. Rows must have distinct device ids.
+    pub fn import_devices(config: &mut Config, rows: Vec<DeviceRow>, opts: BatchOptions) -> Result<CommandResult> {
+        let mut ids = HashSet::new();
+        if let Some(row) = rows.iter().find(|row| !ids.insert(row.id.clone())) {
+            return Err(Error::Args(format!("device id `{}` is listed more than once", row.id)));
+        }
+        let _ = config.token()?;
+        let listed = rows.iter().map(|row| (row.name.clone(), row.id.clone())).collect::<Vec<_>>();
+        let items = rows.into_iter().map(|row| (row.id.clone(), row)).collect();
+        let mut imports = batch::run(config, "importing", items, opts, |config, row: DeviceRow| {
+            let resp = Http::execute(Self::create_device_request(config, &row.name, &row.id, row.kind)?, config.token()?)?.error_for_status()?;
+            if Http::is_dry_run(&resp) {
+                return Ok(None);
+            }
+            Ok(Some(resp.json::<Uuid>()?))
+        })?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        info!("created {} of {} devices", imports.len(), listed.len());
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["name", "id", "uuid", "status"]);
+        for (name, id) in listed {
+            match imports.remove(&id) {
+                Some(Some(uuid)) => result.add_row(vec![name.into(), id.into(), uuid.to_string().into(), "created".into()]),
+                Some(None) => result.add_row(vec![name.into(), id.into(), Value::Null, "dry run".into()]),
+                None => result.add_row(vec![name.into(), id.into(), Value::Null, "skipped".into()]),
+            }
+        }
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// List the events of a device, by UUID or name, as a table.
+    pub fn events_table(config: &mut Config, device: &str) -> Result<CommandResult> {
+        let device = Self::resolve_device(config, device)?;
+        Self::list_events(config, device)
+    }
+
+    /// Parse a list of device events into a table, keeping the raw response.
+    fn events_result(headers: HeaderMap, body: Vec<u8>) -> Result<CommandResult> {
+        let events = DeviceEvent::parse_list(&body)?;
+        let mut result = TableResult::new(headers, body, &["event type", "timestamp", "correlation id"]);
+        for event in events {
+            result.add_row(vec![
+                event.event_type.id.clone().into(),
+                event.device_time.clone().or_else(|| event.received_at.clone()).into(),
+                event.correlation_id().map(String::from).into(),
+            ]);
+        }
+        Ok(result.into())
+    }
+
+    /// Resolve a device by its UUID or, failing that, by its exact name.
+    pub fn resolve_device(config: &mut Config, device: &str) -> Result<Uuid> {
+        if let Ok(uuid) = device.parse() {
+            return Ok(uuid);
+        }
+
+        let page = Self::fetch_devices(config, device)?;
+        let matches = page.values.into_iter().filter(|d| d.name == device).collect::<Vec<_>>();
+        match matches.len() {
+            1 => Ok(matches[0].uuid),
+            0 => Err(Error::NotFound(format!("Device `{}`", device), None)),
+            _ => Err(Error::Args(format!("multiple devices named `{}`, use the device id", device))),
+        }
+    }
+
+    /// Resolve a group by its UUID or exact name, returning `None` when no group has that name.
+    pub fn find_group(config: &mut Config, group: &str) -> Result<Option<Uuid>> {
+        if let Ok(uuid) = group.parse() {
+            return Ok(Some(uuid));
+        }
+
+        let page = Self::fetch_groups(config, group)?;
+        let matches = page.values.into_iter().filter(|g| g.name == group).collect::<Vec<_>>();
+        match matches.len() {
+            0 => Ok(None),
+            1 => Ok(Some(matches[0].id)),
+            _ => Err(Error::Args(format!("multiple groups named `{}`, use the group id", group))),
+        }
+    }
+
+    /// List every device in a group, by UUID or name, fetching all pages.
+    pub fn group_members(config: &mut Config, group: &str) -> Result<Vec<Uuid>> {
+        let id = Self::find_group(config, group)?.ok_or_else(|| Error::NotFound(format!("Group `{}`", group), None))?;
+        let pages = Pagination { limit: None, all_pages: true };
+        let (_, page) = Http::execute_pages(Self::list_devices_request(config, id)?, pages, config.token()?)?;
+        Ok(serde_json::from_value::<Members>(page)?.values)
+    }
+
+    /// Delete a group, by UUID or name, once confirmed with its member count.
+    pub fn delete_group_from_args(config: &mut Config, cmd: &GroupDeleteArgs) -> Result<CommandResult> {
+        let group = &cmd.group;
+        let id = Self::find_group(config, group)?.ok_or_else(|| Error::NotFound(format!("Group `{}`", group), None))?;
+        let members = Self::group_members(config, &id.to_string())?.len();
+        confirm(cmd.yes, &format!("Delete group {} with {} devices?", group, members))?;
+        Self::delete_group(config, id)
+    }
+
+    /// Add a device to a group by UUID or name, optionally creating a missing static group first.
+    pub fn add_device_to_group(
+        config: &mut Config,
+        group: &str,
+        device: &str,
+        create_if_missing: bool,
+        retry_on_conflict: bool,
+    ) -> Result<CommandResult> {
+        let device = Self::resolve_device(config, device)?;
+        let (uuid, status) = match Self::find_group(config, group)? {
+            Some(uuid) => (uuid, "existing"),
+            None if create_if_missing => {
+                let resp = Http::execute(Self::create_group_request(config, group, GroupType::Static, None)?, config.token()?)?;
+                if !resp.status().is_success() {
+                    return Err(Error::Failed(format!("creating group `{}` failed: {}", group, resp.status())));
+                }
+                // A group that was only printed has no id yet, so the device is added to a placeholder.
+                let uuid = if Http::is_dry_run(&resp) { Uuid::nil() } else { resp.json()? };
+                (uuid, "created")
+            }
+            None => Err(Error::NotFound(
+                format!("Group `{}`", group),
+                Some("Pass --create-if-missing to create it.".into()),
+            ))?,
+        };
+        let (headers, membership) = Self::update_membership(config, uuid, device, true, retry_on_conflict)?;
+
+        let body = json!({"group": uuid, "device": device, "status": status, "membership": membership});
+        let mut result = TableResult::new(headers, serde_json::to_vec(&body)?, &["group", "device", "status", "membership"]);
+        result.add_row(vec![uuid.to_string().into(), device.to_string().into(), status.into(), membership.into()]);
+        Ok(result.into())
+    }
+
+    /// Remove a device from a group by UUID or name.
+    pub fn remove_device_from_group(config: &mut Config, group: &str, device: &str, retry_on_conflict: bool) -> Result<CommandResult> {
+        let device = Self::resolve_device(config, device)?;
+        let uuid = Self::find_group(config, group)?.ok_or_else(|| Error::NotFound(format!("Group `{}`", group), None))?;
+        let (headers, membership) = Self::update_membership(config, uuid, device, false, retry_on_conflict)?;
+
+        let body = json!({"group": uuid, "device": device, "membership": membership});
+        let mut result = TableResult::new(headers, serde_json::to_vec(&body)?, &["group", "device", "membership"]);
+        result.add_row(vec![uuid.to_string().into(), device.to_string().into(), membership.into()]);
+        Ok(result.into())
+    }
+
+    /// Add or remove a device from a group, returning the response headers and the membership outcome.
+    ///
+    /// With `retry_on_conflict`, a conflicting change is retried with backoff,
+    /// unless the device's groups show the change is already in place.
+    fn update_membership(config: &mut Config, group: Uuid, device: Uuid, add: bool, retry_on_conflict: bool) -> Result<(HeaderMap, &'static str)> {
+        let mut attempt = 0;
+        loop {
+            let req = if add {
+                Self::add_to_group_request(config, group, device)?
+            } else {
+                Self::remove_from_group_request(config, group, device)?
+            };
+            let resp = Http::execute(req, config.token()?)?;
+            let conflict = resp.status() == StatusCode::CONFLICT || (!add && resp.status() == StatusCode::NOT_FOUND);
+            if !retry_on_conflict || !conflict {
+                let headers = resp.error_for_status()?.headers().to_owned();
+                return Ok((headers, if add { "added" } else { "removed" }));
+            }
+
+            if Self::fetch_device_groups(config, device)?.contains(&group) == add {
+                return Ok((resp.headers().to_owned(), if add { "already a member" } else { "already removed" }));
+            }
+            if attempt >= CONFLICT_RETRIES {
+                return Err(Error::Conflict(format!("group {} membership of device {} kept conflicting", group, device)));
+            }
+            let backoff = CONFLICT_BACKOFF * 2u32.pow(attempt);
+            attempt += 1;
+            debug!("conflict changing group {} membership of {}, retry {} in {:?}", group, device, attempt, backoff);
+            thread::sleep(backoff);
+        }
+    }
+
+    /// Rename a device by UUID or name, returning the old and new names.
+    pub fn rename_device(config: &mut Config, device: &str, name: &str) -> Result<CommandResult> {
+        let uuid = Self::resolve_device(config, device)?;
+        let old = Self::fetch_device(config, uuid)?;
+
+        let resp = Http::execute(Self::update_device_request(config, uuid, name)?, config.token()?)?;
+        if resp.status() == StatusCode::CONFLICT {
+            return Err(Error::Conflict(format!("a device named `{}` already exists", name)));
+        }
+        let headers = resp.error_for_status()?.headers().to_owned();
+
+        let body = json!({"uuid": uuid, "oldName": old.name, "newName": name});
+        let mut result = TableResult::new(headers, serde_json::to_vec(&body)?, &["uuid", "old name", "new name"]);
+        result.add_row(vec![uuid.to_string().into(), old.name.into(), name.into()]);
+        Ok(result.into())
+    }
 }
 
 impl RegistryApi for Registry {
-    fn create_device(config: &mut Config, name: &str, id: &str, kind: DeviceType) -> Result<Response> {
+    fn create_device(config: &mut Config, name: &str, id: &str, kind: DeviceType) -> Result<CommandResult> {
         debug!("creating device {} of type {} with id {}", name, kind, id);
-        let req = Client::new().post(&format!("{}api/v1/devices", config.registry)).query(&[
-            ("deviceName", name),
-            ("deviceId", id),
-            ("deviceType", &format!("{}", kind)),
-        ]);
-        Http::send(req, config.token()?)
+        Ok(Http::execute(Self::create_device_request(config, name, id, kind)?, config.token()?)?.into())
     }
 
-    fn delete_device(config: &mut Config, device: Uuid) -> Result<Response> {
+    fn delete_device(config: &mut Config, device: Uuid) -> Result<CommandResult> {
         debug!("deleting device {}", device);
-        Http::delete(&format!("{}api/v1/devices/{}", config.registry, device), config.token()?)
+        Ok(Http::execute(Self::delete_device_request(config, device)?, config.token()?)?.into())
+    }
+
+    fn update_device(config: &mut Config, device: Uuid, name: &str) -> Result<CommandResult> {
+        debug!("renaming device {} to {}", device, name);
+        Ok(Http::execute(Self::update_device_request(config, device, name)?, config.token()?)?.into())
     }
 
-    fn list_device(config: &mut Config, device: Uuid) -> Result<Response> {
+    fn list_device(config: &mut Config, device: Uuid) -> Result<CommandResult> {
         debug!("listing details for device {}", device);
-        Http::get(&format!("{}api/v1/devices/{}", config.registry, device), config.token()?)
+        let (headers, body) = Http::execute_body(Self::list_device_request(config, device)?, config.token()?)?;
+        let device: Device = serde_json::from_slice(&body)?;
+        Ok(Self::device_table(headers, body, &[device]).into())
     }
 
-    fn list_all_devices(config: &mut Config) -> Result<Response> {
+    fn list_all_devices(config: &mut Config) -> Result<CommandResult> {
         debug!("listing all devices");
-        Http::get(&format!("{}api/v1/devices", config.registry), config.token()?)
+        let (headers, body) = Http::execute_body(Self::list_all_devices_request(config)?, config.token()?)?;
+        let list: DeviceList = serde_json::from_slice(&body)?;
+        Ok(Self::device_table(headers, body, &list.values).into())
+    }
+
+    fn search_devices(config: &mut Config, name: &str) -> Result<CommandResult> {
+        debug!("searching for devices named {}", name);
+        let (headers, body) = Http::execute_body(Self::search_devices_request(config, name)?, config.token()?)?;
+        let list: DeviceList = serde_json::from_slice(&body)?;
+        Ok(Self::device_table(headers, body, &list.values).into())
+    }
+
+    fn list_events(config: &mut Config, device: Uuid) -> Result<CommandResult> {
+        debug!("listing events for device {}", device);
+        let (headers, body) = Http::execute_body(Self::list_events_request(config, device)?, config.token()?)?;
+        Self::events_result(headers, body)
     }
 
-    fn create_group(config: &mut Config, name: &str, group_type: GroupType) -> Result<Response> {
-        debug!("creating device group {}", name);
-        let req = Client::new()
-            .post(&format!("{}api/v1/device_groups", config.registry))
-            .json(&json!({"name": name, "groupType": format!("{}", group_type)}));
-        Http::send(req, config.token()?)
+    fn create_group(config: &mut Config, name: &str, group_type: GroupType, expression: Option<&str>) -> Result<CommandResult> {
+        debug!("creating {} device group {} with expression {:?}", group_type, name, expression);
+        Ok(Http::execute(Self::create_group_request(config, name, group_type, expression)?, config.token()?)?.into())
     }
 
-    fn rename_group(config: &mut Config, group: Uuid, name: &str) -> Result<Response> {
+    fn search_groups(config: &mut Config, name: &str) -> Result<CommandResult> {
+        debug!("searching for groups named {}", name);
+        let (headers, body) = Http::execute_body(Self::search_groups_request(config, name)?, config.token()?)?;
+        Self::group_table(headers, body)
+    }
+
+    fn rename_group(config: &mut Config, group: Uuid, name: &str) -> Result<CommandResult> {
         debug!("renaming group {} to {}", group, name);
-        let req = Client::new()
-            .put(&format!("{}api/v1/device_groups/{}/rename", config.registry, group))
-            .query(&[("groupId", &format!("{}", group), ("groupName", name))]);
-        Http::send(req, config.token()?)
+        Ok(Http::execute(Self::rename_group_request(config, group, name)?, config.token()?)?.into())
     }
 
-    fn add_to_group(config: &mut Config, group: Uuid, device: Uuid) -> Result<Response> {
+    fn delete_group(config: &mut Config, group: Uuid) -> Result<CommandResult> {
+        debug!("deleting group {}", group);
+        Ok(Http::execute(Self::delete_group_request(config, group)?, config.token()?)?.into())
+    }
+
+    fn add_to_group(config: &mut Config, group: Uuid, device: Uuid) -> Result<CommandResult> {
         debug!("adding device {} to group {}", device, group);
-        let req = Client::new()
-            .post(&format!("{}api/v1/device_groups/{}/devices/{}", config.registry, group, device))
-            .query(&[("deviceId", device), ("groupId", group)]);
-        Http::send(req, config.token()?)
+        Ok(Http::execute(Self::add_to_group_request(config, group, device)?, config.token()?)?.into())
     }
 
-    fn remove_from_group(config: &mut Config, group: Uuid, device: Uuid) -> Result<Response> {
+    fn remove_from_group(config: &mut Config, group: Uuid, device: Uuid) -> Result<CommandResult> {
         debug!("removing device {} from group {}", device, group);
-        let req = Client::new()
-            .delete(&format!("{}api/v1/device_groups/{}/devices/{}", config.registry, group, device))
-            .query(&[("deviceId", format!("{}", device)), ("groupId", format!("{}", group))]);
-        Http::send(req, config.token()?)
+        Ok(Http::execute(Self::remove_from_group_request(config, group, device)?, config.token()?)?.into())
     }
 
-    fn list_devices(config: &mut Config, group: Uuid) -> Result<Response> {
+    fn list_devices(config: &mut Config, group: Uuid) -> Result<CommandResult> {
         debug!("listing devices in group {}", group);
-        Http::get(
-            &format!("{}api/v1/device_groups/{}/devices", config.registry, group),
-            config.token()?,
-        )
+        let (headers, body) = Http::execute_body(Self::list_devices_request(config, group)?, config.token()?)?;
+        Self::members_table(headers, body, "device")
     }
 
-    fn list_groups(config: &mut Config, device: Uuid) -> Result<Response> {
+    fn list_groups(config: &mut Config, device: Uuid) -> Result<CommandResult> {
         debug!("listing groups for device {}", device);
-        Http::get(&format!("{}api/v1/devices/{}/groups", config.registry, device), config.token()?)
+        let (headers, body) = Http::execute_body(Self::list_groups_request(config, device)?, config.token()?)?;
+        Self::members_table(headers, body, "group")
     }
 
-    fn list_all_groups(config: &mut Config) -> Result<Response> {
+    fn list_all_groups(config: &mut Config) -> Result<CommandResult> {
         debug!("listing all groups");
-        Http::get(&format!("{}api/v1/device_groups", config.registry), config.token()?)
+        let (headers, body) = Http::execute_body(Self::list_all_groups_request(config)?, config.token()?)?;
+        Self::group_table(headers, body)
     }
 }
 
-/// Available device types.
-#[derive(Clone, Copy, Debug)]
-pub enum DeviceType {
-    Vehicle,
-    Other,
+/// Requests shared by the blocking and async Device Registry APIs.
+impl Registry {
+    pub(crate) fn create_device_request(config: &Config, name: &str, id: &str, kind: DeviceType) -> Result<Request> {
+        Ok(Request::post(format!("{}api/v1/devices", config.registry))?
+            .query("deviceName", name)
+            .query("deviceId", id)
+            .query("deviceType", kind))
+    }
+
+    pub(crate) fn delete_device_request(config: &Config, device: Uuid) -> Result<Request> {
+        Request::delete(format!("{}api/v1/devices/{}", config.registry, device))
+    }
+
+    pub(crate) fn update_device_request(config: &Config, device: Uuid, name: &str) -> Result<Request> {
+        Ok(Request::put(format!("{}api/v1/devices/{}", config.registry, device))?.json(json!({ "deviceName": name })))
+    }
+
+    pub(crate) fn list_device_request(config: &Config, device: Uuid) -> Result<Request> {
+        Request::get(format!("{}api/v1/devices/{}", config.registry, device))
+    }
+
+    pub(crate) fn list_all_devices_request(config: &Config) -> Result<Request> { Request::get(format!("{}api/v1/devices", config.registry)) }
+
+    pub(crate) fn search_devices_request(config: &Config, name: &str) -> Result<Request> {
+        Ok(Request::get(format!("{}api/v1/devices", config.registry))?.query("nameContains", name))
+    }
+
+    pub(crate) fn list_events_request(config: &Config, device: Uuid) -> Result<Request> {
+        Request::get(format!("{}api/v1/devices/{}/events", config.registry, device))
+    }
+
+    pub(crate) fn create_group_request(config: &Config, name: &str, group_type: GroupType, expression: Option<&str>) -> Result<Request> {
+        let mut body = json!({"name": name, "groupType": format!("{}", group_type)});
+        if let Some(expression) = expression {
+            body["expression"] = expression.into();
+        }
+        Ok(Request::post(format!("{}api/v1/device_groups", config.registry))?.json(body))
+    }
+
+    pub(crate) fn search_groups_request(config: &Config, name: &str) -> Result<Request> {
+        Ok(Request::get(format!("{}api/v1/device_groups", config.registry))?.query("nameContains", name))
+    }
+
+    pub(crate) fn rename_group_request(config: &Config, group: Uuid, name: &str) -> Result<Request> {
+        Ok(Request::put(format!("{}api/v1/device_groups/{}/rename", config.registry, group))?
+            .query("groupId", group)
+            .query("groupName", name))
+    }
+
+    pub(crate) fn delete_group_request(config: &Config, group: Uuid) -> Result<Request> {
+        Request::delete(format!("{}api/v1/device_groups/{}", config.registry, group))
+    }
+
+    pub(crate) fn add_to_group_request(config: &Config, group: Uuid, device: Uuid) -> Result<Request> {
+        Ok(Request::post(format!("{}api/v1/device_groups/{}/devices/{}", config.registry, group, device))?
+            .query("deviceId", device)
+            .query("groupId", group))
+    }
+
+    pub(crate) fn remove_from_group_request(config: &Config, group: Uuid, device: Uuid) -> Result<Request> {
+        Ok(Request::delete(format!("{}api/v1/device_groups/{}/devices/{}", config.registry, group, device))?
+            .query("deviceId", device)
+            .query("groupId", group))
+    }
+
+    pub(crate) fn list_devices_request(config: &Config, group: Uuid) -> Result<Request> {
+        Request::get(format!("{}api/v1/device_groups/{}/devices", config.registry, group))
+    }
+
+    pub(crate) fn list_groups_request(config: &Config, device: Uuid) -> Result<Request> {
+        Request::get(format!("{}api/v1/devices/{}/groups", config.registry, device))
+    }
+
+    pub(crate) fn list_all_groups_request(config: &Config) -> Result<Request> { Request::get(format!("{}api/v1/device_groups", config.registry)) }
+}
+
+/// A device as listed by the registry, keeping any other fields for raw output.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    pub uuid: Uuid,
+    #[serde(rename = "deviceName")]
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(rename = "deviceStatus", skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// A page of devices returned by the registry.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceList {
+    pub values: Vec<Device>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl DeviceList {
+    /// Keep only the devices matching a last-seen filter.
+    fn retain_seen(&mut self, seen: SeenFilter) -> Result<()> {
+        let mut kept = Vec::new();
+        for device in self.values.drain(..) {
+            if seen.matches(device.last_seen()?) {
+                kept.push(device);
+            }
+        }
+        self.values = kept;
+        Ok(())
+    }
+
+    /// Keep only the devices created at or after `cutoff`, for registries ignoring `createdAtStart`.
+    fn retain_created_after(&mut self, cutoff: SystemTime) -> Result<()> {
+        let mut kept = Vec::new();
+        for device in self.values.drain(..) {
+            if device.created_at()?.map(|created| created >= cutoff).unwrap_or(false) {
+                kept.push(device);
+            }
+        }
+        self.values = kept;
+        Ok(())
+    }
+}
+
+impl Device {
+    /// Parse the last-seen timestamp, if the device has ever been seen.
+    fn last_seen(&self) -> Result<Option<SystemTime>> {
+        self.last_seen
+            .as_ref()
+            .map(|seen| humantime::parse_rfc3339_weak(seen).map_err(|err| Error::Parse(format!("device last seen `{}`: {}", seen, err))))
+            .transpose()
+    }
+
+    /// Parse the creation timestamp, if the registry returned one.
+    fn created_at(&self) -> Result<Option<SystemTime>> {
+        self.created_at
+            .as_ref()
+            .map(|created| humantime::parse_rfc3339_weak(created).map_err(|err| Error::Parse(format!("device created at `{}`: {}", created, err))))
+            .transpose()
+    }
+}
+
+/// An event reported by a device, such as the start or end of an installation.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceEvent {
+    pub event_type: EventType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<String>,
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// The type and schema version of a device event.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EventType {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+}
+
+impl DeviceEvent {
+    /// Parse a list of events, either as a JSON array or a page of `values`.
+    fn parse_list(body: &[u8]) -> Result<Vec<DeviceEvent>> {
+        match serde_json::from_slice(body)? {
+            Value::Object(mut page) => match page.remove("values") {
+                Some(values) => Ok(serde_json::from_value(values)?),
+                None => Err(Error::Parse("device event list has no `values`".into())),
+            },
+            list => Ok(serde_json::from_value(list)?),
+        }
+    }
+
+    /// The correlation id linking an event to its update, if the payload has one.
+    fn correlation_id(&self) -> Option<&str> { self.payload.get("correlationId").and_then(Value::as_str) }
+}
+
+/// Search parameters for listing devices.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceSearch {
+    pub name_contains: Option<String>,
+    pub device_id: Option<String>,
+    pub created_after: Option<SystemTime>,
+    /// Only devices that belong to no group.
+    pub ungrouped: bool,
+}
+
+impl DeviceSearch {
+    /// Parse `--name-contains`, `--device-id`, `--ungrouped` and `--created-after`, where the latter is a date or RFC 3339 timestamp.
+    pub fn from_args(cmd: &DeviceListArgs) -> Result<Self> {
+        let created_after = cmd
+            .created_after
+            .as_ref()
+            .map(|value| {
+                let timestamp = if value.contains('T') { value.to_string() } else { format!("{}T00:00:00Z", value) };
+                humantime::parse_rfc3339_weak(&timestamp).map_err(|err| Error::Args(format!("--created-after `{}`: {}", value, err)))
+            })
+            .transpose()?;
+        Ok(DeviceSearch {
+            name_contains: cmd.name_contains.clone(),
+            device_id: cmd.device_id.clone(),
+            created_after,
+            ungrouped: cmd.ungrouped,
+        })
+    }
+
+    fn is_empty(&self) -> bool { *self == DeviceSearch::default() }
+
+    /// Add the search parameters to a device list request.
+    fn query(&self, mut req: Request) -> Request {
+        if let Some(ref name) = self.name_contains {
+            req = req.query("nameContains", name);
+        }
+        if let Some(ref id) = self.device_id {
+            req = req.query("deviceId", id);
+        }
+        if let Some(created_after) = self.created_after {
+            req = req.query("createdAtStart", humantime::format_rfc3339_seconds(created_after));
+        }
+        if self.ungrouped {
+            req = req.query("grouped", false);
+        }
+        req
+    }
+}
+
+/// Filter devices by when they were last seen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SeenFilter {
+    /// Seen at or after this time.
+    Since(SystemTime),
+    /// Never seen, or last seen before this time.
+    NotSince(SystemTime),
 }
 
-impl<'a> DeviceType {
-    /// Parse CLI arguments into a `DeviceType`.
-    pub fn from_args(args: &ArgMatches<'a>) -> Result<Self> {
-        if args.is_present("vehicle") {
-            Ok(DeviceType::Vehicle)
-        } else if args.is_present("other") {
-            Ok(DeviceType::Other)
+impl SeenFilter {
+    /// Parse `--seen-since` or `--not-seen-for` durations relative to `now`.
+    pub fn from_args(cmd: &DeviceListArgs, now: SystemTime) -> Result<Option<Self>> {
+        let cutoff = |flag: &str, value: &str| -> Result<SystemTime> {
+            let duration = humantime::parse_duration(value).map_err(|err| Error::Args(format!("--{} `{}`: {}", flag, value, err)))?;
+            now.checked_sub(duration).ok_or_else(|| Error::Args(format!("--{} is too long: {}", flag, value)))
+        };
+        if let Some(ref value) = cmd.seen_since {
+            Ok(Some(SeenFilter::Since(cutoff("seen-since", value)?)))
+        } else if let Some(ref value) = cmd.not_seen_for {
+            Ok(Some(SeenFilter::NotSince(cutoff("not-seen-for", value)?)))
         } else {
-            Err(Error::Args("Either --vehicle or --other flag is required".into()))
+            Ok(None)
+        }
+    }
+
+    fn matches(self, last_seen: Option<SystemTime>) -> bool {
+        match (self, last_seen) {
+            (SeenFilter::Since(cutoff), Some(seen)) => seen >= cutoff,
+            (SeenFilter::Since(_), None) => false,
+            (SeenFilter::NotSince(cutoff), Some(seen)) => seen < cutoff,
+            (SeenFilter::NotSince(_), None) => true,
+        }
+    }
+}
+
+/// A device group as listed by the registry, keeping any other fields for raw output.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Group {
+    pub id: Uuid,
+    #[serde(rename = "groupName")]
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expression: Option<String>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// A page of groups returned by the registry.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GroupList {
+    pub values: Vec<Group>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// A page of ids, either the devices in a group or the groups of a device.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Members {
+    pub values: Vec<Uuid>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// A device to create, read from an import file.
+#[derive(Clone, Debug)]
+pub struct DeviceRow {
+    pub name: String,
+    pub id: String,
+    pub kind: DeviceType,
+}
+
+impl DeviceRow {
+    /// Read a JSON array of `{"name", "id", "type"}` objects from a `.json` file, or CSV rows otherwise.
+    pub fn from_file(path: &Path) -> Result<Vec<Self>> {
+        let text = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::from_json(&text),
+            _ => Self::from_csv(&text),
         }
     }
+
+    fn from_json(text: &str) -> Result<Vec<Self>> {
+        #[derive(Deserialize)]
+        struct Row {
+            name: String,
+            id: String,
+            #[serde(rename = "type")]
+            kind: String,
+        }
+        let rows: Vec<Row> = serde_json::from_str(text)?;
+        rows.into_iter()
+            .enumerate()
+            .map(|(n, row)| {
+                let kind = Self::kind(&row.kind, &format!("device {}", n + 1))?;
+                Ok(DeviceRow { name: row.name, id: row.id, kind })
+            })
+            .collect()
+    }
+
+    /// Parse `name,id,type` rows, with an optional header line naming the columns in any order.
+    fn from_csv(text: &str) -> Result<Vec<Self>> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(n, line)| (n + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .peekable();
+        let fields = |line: &str| line.split(',').map(|field| field.trim().trim_matches('"').to_string()).collect::<Vec<_>>();
+
+        let mut columns = [0, 1, 2];
+        if let Some((_, header)) = lines.peek() {
+            let header = fields(header).into_iter().map(|field| field.to_lowercase()).collect::<Vec<_>>();
+            if header.iter().any(|field| field == "name") {
+                for (column, want) in columns.iter_mut().zip(&["name", "id", "type"]) {
+                    *column = header
+                        .iter()
+                        .position(|field| field == want)
+                        .ok_or_else(|| Error::Parse(format!("CSV header has no `{}` column", want)))?;
+                }
+                lines.next();
+            }
+        }
+
+        lines
+            .map(|(n, line)| {
+                let row = fields(line);
+                let field = |column: usize| match row.get(column) {
+                    Some(field) if !field.is_empty() => Ok(field.clone()),
+                    _ => Err(Error::Parse(format!("line {}: expected name, id and type", n))),
+                };
+                let kind = Self::kind(&field(columns[2])?, &format!("line {}", n))?;
+                Ok(DeviceRow { name: field(columns[0])?, id: field(columns[1])?, kind })
+            })
+            .collect()
+    }
+
+    fn kind(kind: &str, at: &str) -> Result<DeviceType> {
+        kind.parse().map_err(|_| Error::Parse(format!("{}: unknown device type `{}`, expected vehicle or other", at, kind)))
+    }
+}
+
+/// Available device types.
+#[derive(Clone, Copy, Debug)]
+pub enum DeviceType {
+    Vehicle,
+    Other,
 }
 
 impl FromStr for DeviceType {
@@ -206,18 +906,545 @@ impl Display for GroupType {
     }
 }
 
-/// Parse into a tuple of --all, --device, and --group arg values.
-fn parse_list_args<'a>(args: &ArgMatches<'a>) -> Result<(bool, Option<Uuid>, Option<Uuid>)> {
-    let all = args.is_present("all");
-    let device = if let Some(val) = args.value_of("device") {
-        Some(val.parse()?)
-    } else {
-        None
-    };
-    let group = if let Some(val) = args.value_of("group") {
-        Some(val.parse()?)
-    } else {
-        None
-    };
-    Ok((all, device, group))
+/// Check a dynamic group expression against the registry grammar:
+///
+/// ```text
+/// expr := term (("and" | "or") term)*
+/// term := "(" expr ")" | "not" term
+///       | "deviceid" "contains" <word>
+///       | "deviceid" "position" "(" <n> ")" "is" ["not"] <char>
+/// ```
+pub fn validate_expression(expression: &str) -> Result<()> {
+    let spaced = expression.replace('(', " ( ").replace(')', " ) ");
+    let tokens = spaced.split_whitespace().collect::<Vec<_>>();
+    let invalid = |reason: String| Error::Args(format!("invalid group expression `{}`: {}", expression, reason));
+
+    fn token<'t>(tokens: &[&'t str], pos: &mut usize) -> Option<&'t str> {
+        let next = tokens.get(*pos).copied();
+        *pos += 1;
+        next
+    }
+
+    fn expect(tokens: &[&str], pos: &mut usize, wanted: &str) -> std::result::Result<(), String> {
+        match token(tokens, pos) {
+            Some(found) if found.eq_ignore_ascii_case(wanted) => Ok(()),
+            Some(found) => Err(format!("expected `{}`, found `{}`", wanted, found)),
+            None => Err(format!("expected `{}` at the end", wanted)),
+        }
+    }
+
+    fn word(tokens: &[&str], pos: &mut usize, what: &str) -> std::result::Result<String, String> {
+        match token(tokens, pos) {
+            Some(found) if found.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') => Ok(found.into()),
+            Some(found) => Err(format!("expected {}, found `{}`", what, found)),
+            None => Err(format!("expected {} at the end", what)),
+        }
+    }
+
+    fn term(tokens: &[&str], pos: &mut usize) -> std::result::Result<(), String> {
+        match token(tokens, pos).map(str::to_lowercase).as_deref() {
+            Some("(") => {
+                expr(tokens, pos)?;
+                expect(tokens, pos, ")")
+            }
+            Some("not") => term(tokens, pos),
+            Some("deviceid") => match token(tokens, pos).map(str::to_lowercase).as_deref() {
+                Some("contains") => word(tokens, pos, "a value").map(|_| ()),
+                Some("position") => {
+                    expect(tokens, pos, "(")?;
+                    match word(tokens, pos, "a position")?.parse::<u32>() {
+                        Ok(n) if n > 0 => (),
+                        _ => return Err("position must be a positive number".into()),
+                    }
+                    expect(tokens, pos, ")")?;
+                    expect(tokens, pos, "is")?;
+                    let mut value = word(tokens, pos, "a character")?;
+                    if value.eq_ignore_ascii_case("not") {
+                        value = word(tokens, pos, "a character")?;
+                    }
+                    match value.chars().count() {
+                        1 => Ok(()),
+                        _ => Err(format!("expected a single character, found `{}`", value)),
+                    }
+                }
+                Some(found) => Err(format!("expected `contains` or `position`, found `{}`", found)),
+                None => Err("expected `contains` or `position` at the end".into()),
+            },
+            Some(found) => Err(format!("expected `deviceid`, `not` or `(`, found `{}`", found)),
+            None => Err("expected a condition".into()),
+        }
+    }
+
+    fn expr(tokens: &[&str], pos: &mut usize) -> std::result::Result<(), String> {
+        term(tokens, pos)?;
+        while let Some(next) = tokens.get(*pos) {
+            match next.to_lowercase().as_ref() {
+                "and" | "or" => {
+                    *pos += 1;
+                    term(tokens, pos)?;
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    let mut pos = 0;
+    expr(&tokens, &mut pos).map_err(invalid)?;
+    match tokens.get(pos) {
+        Some(found) => Err(invalid(format!("unexpected `{}`", found))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use structopt::StructOpt;
+
+    const DEVICE: &str = "00000000-0000-0000-0000-000000000001";
+
+    fn registry(update_status: u16) -> MockServer {
+        MockServer::start(move |req| match (req.method.as_ref(), req.route()) {
+            ("GET", "/api/v1/devices") => MockResponse::json(200, json!({
+                "total": 2, "offset": 0, "limit": 50,
+                "values": [{"uuid": DEVICE, "deviceName": "truck"}, {"uuid": Uuid::nil(), "deviceName": "truck-2"}]
+            })),
+            ("GET", _) => MockResponse::json(200, json!({"uuid": DEVICE, "deviceName": "truck"})),
+            ("PUT", _) => MockResponse::status(update_status),
+            _ => MockResponse::status(404),
+        })
+    }
+
+    #[test]
+    fn rename_device_by_name() {
+        let server = registry(200);
+        let mut config = server.config();
+        let result = match Registry::rename_device(&mut config, "truck", "lorry").expect("rename") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows[0]["old name"], "truck");
+        assert_eq!(result.rows[0]["new name"], "lorry");
+
+        let update = server.requests().into_iter().find(|r| r.method == "PUT").expect("update request");
+        assert_eq!(update.route(), format!("/api/v1/devices/{}", DEVICE));
+        assert_eq!(update.json(), json!({"deviceName": "lorry"}));
+    }
+
+    #[test]
+    fn rename_device_name_collision() {
+        let server = registry(409);
+        let mut config = server.config();
+        match Registry::rename_device(&mut config, DEVICE, "truck-2") {
+            Err(Error::Conflict(msg)) => assert!(msg.contains("truck-2")),
+            other => panic!("expected a conflict, got {:?}", other.err()),
+        }
+    }
+
+    const GROUP: &str = "00000000-0000-0000-0000-0000000000aa";
+
+    fn group_registry(groups: serde_json::Value) -> MockServer {
+        MockServer::start(move |req| match (req.method.as_ref(), req.route()) {
+            ("GET", "/api/v1/device_groups") => MockResponse::json(200, json!({"values": groups.clone()})),
+            ("POST", "/api/v1/device_groups") => MockResponse::json(201, json!(GROUP)),
+            ("POST", _) => MockResponse::status(200),
+            _ => MockResponse::status(404),
+        })
+    }
+
+    #[test]
+    fn add_to_missing_group_creates_it() {
+        let server = group_registry(json!([{"id": Uuid::nil(), "groupName": "fleet-2"}]));
+        let result = match Registry::add_device_to_group(&mut server.config(), "fleet", DEVICE, true, false).expect("add") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows[0]["group"], GROUP);
+        assert_eq!(result.rows[0]["status"], "created");
+
+        let requests = server.requests();
+        let create = requests.iter().find(|r| r.route() == "/api/v1/device_groups" && r.method == "POST").expect("create");
+        assert_eq!(create.json(), json!({"name": "fleet", "groupType": "static"}));
+        assert!(requests.iter().any(|r| r.route() == format!("/api/v1/device_groups/{}/devices/{}", GROUP, DEVICE)));
+    }
+
+    #[test]
+    fn dry_run_add_to_missing_group() {
+        let server = MockServer::start(|req| match req.method.as_ref() {
+            "GET" => MockResponse::json(200, json!({"values": []})),
+            _ => MockResponse::dry_run(),
+        });
+        let result = match Registry::add_device_to_group(&mut server.config(), "fleet", DEVICE, true, false).expect("dry run") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows[0]["group"], Uuid::nil().to_string());
+        assert_eq!(result.rows[0]["status"], "created");
+        assert_eq!(result.rows[0]["membership"], "added");
+    }
+
+    #[test]
+    fn add_to_existing_group() {
+        let server = group_registry(json!([{"id": GROUP, "groupName": "fleet"}]));
+        let result = match Registry::add_device_to_group(&mut server.config(), "fleet", DEVICE, true, false).expect("add") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows[0]["status"], "existing");
+        assert!(!server.requests().iter().any(|r| r.route() == "/api/v1/device_groups" && r.method == "POST"));
+    }
+
+    #[test]
+    fn add_to_missing_group_without_create() {
+        let server = group_registry(json!([]));
+        match Registry::add_device_to_group(&mut server.config(), "fleet", DEVICE, false, false) {
+            Err(Error::NotFound(..)) => (),
+            other => panic!("expected not found, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn delete_group_by_name() {
+        let server = MockServer::start(|req| match (req.method.as_ref(), req.route()) {
+            ("GET", "/api/v1/device_groups") => MockResponse::json(200, json!({"values": [{"id": GROUP, "groupName": "fleet"}]})),
+            ("GET", _) => MockResponse::json(200, json!({"total": 2, "values": [DEVICE, Uuid::nil()]})),
+            ("DELETE", _) => MockResponse::status(204),
+            _ => MockResponse::status(404),
+        });
+        let cmd = GroupDeleteArgs::from_iter(&["delete", "--group", "fleet", "--yes"]);
+        Registry::delete_group_from_args(&mut server.config(), &cmd).expect("delete");
+
+        let requests = server.requests();
+        assert!(requests.iter().any(|r| r.route() == format!("/api/v1/device_groups/{}/devices", GROUP)));
+        let delete = requests.iter().find(|r| r.method == "DELETE").expect("delete request");
+        assert_eq!(delete.route(), format!("/api/v1/device_groups/{}", GROUP));
+    }
+
+    fn conflicting_registry(conflicts: usize, member: bool) -> MockServer {
+        let attempts = AtomicUsize::new(0);
+        MockServer::start(move |req| match (req.method.as_ref(), req.route()) {
+            ("GET", "/api/v1/device_groups") => MockResponse::json(200, json!({"values": [{"id": GROUP, "groupName": "fleet"}]})),
+            ("GET", _) => MockResponse::json(200, json!({"values": if member { vec![GROUP] } else { vec![] }})),
+            (_, _) if attempts.fetch_add(1, Ordering::SeqCst) < conflicts => {
+                MockResponse::json(409, json!({"code": "conflicting_entity", "description": "conflict"}))
+            }
+            _ => MockResponse::status(200),
+        })
+    }
+
+    fn membership(result: Result<CommandResult>) -> serde_json::Value {
+        match result.expect("membership change") {
+            CommandResult::Table(result) => result.rows[0]["membership"].clone(),
+            _ => panic!("expected a table result"),
+        }
+    }
+
+    #[test]
+    fn add_existing_member_on_conflict() {
+        let server = conflicting_registry(usize::MAX, true);
+        let result = Registry::add_device_to_group(&mut server.config(), "fleet", DEVICE, false, true);
+        assert_eq!(membership(result), "already a member");
+        assert_eq!(server.requests().iter().filter(|r| r.method == "POST").count(), 1);
+
+        let server = conflicting_registry(usize::MAX, true);
+        assert!(Registry::add_device_to_group(&mut server.config(), "fleet", DEVICE, false, false).is_err());
+    }
+
+    #[test]
+    fn retry_transient_conflict() {
+        let server = conflicting_registry(2, false);
+        let result = Registry::add_device_to_group(&mut server.config(), "fleet", DEVICE, false, true);
+        assert_eq!(membership(result), "added");
+        assert_eq!(server.requests().iter().filter(|r| r.method == "POST").count(), 3);
+
+        let server = conflicting_registry(1, false);
+        let result = Registry::remove_device_from_group(&mut server.config(), "fleet", DEVICE, true);
+        assert_eq!(membership(result), "already removed");
+    }
+
+    #[test]
+    fn search_devices_with_filters() {
+        let server = MockServer::start(|_| MockResponse::json(200, json!({
+            "total": 2, "offset": 0, "limit": 50,
+            "values": [
+                {"uuid": DEVICE, "deviceName": "truck-1", "createdAt": "2020-02-01T10:00:00Z"},
+                {"uuid": Uuid::nil(), "deviceName": "truck-2", "createdAt": "2019-12-01T00:00:00Z"}
+            ]
+        })));
+        let cmd = DeviceListArgs::from_iter(&["list", "--name-contains", "truck", "--device-id", "vin 1", "--created-after", "2020-01-01"]);
+        let result = match Registry::list_device_args(&mut server.config(), &cmd).expect("search") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table"),
+        };
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0]["name"], "truck-1");
+        assert_eq!(server.requests()[0].path, "/api/v1/devices?nameContains=truck&deviceId=vin+1&createdAtStart=2020-01-01T00%3A00%3A00Z");
+
+        let timestamp = DeviceSearch::from_args(&DeviceListArgs::from_iter(&["list", "--created-after", "2020-01-01T12:30:00Z"])).expect("timestamp");
+        assert_eq!(timestamp.created_after, Some(humantime::parse_rfc3339("2020-01-01T12:30:00Z").unwrap()));
+        assert!(DeviceSearch::default().is_empty());
+    }
+
+    #[test]
+    fn list_ungrouped_devices() {
+        let server = MockServer::start(|_| MockResponse::json(200, json!({"total": 1, "offset": 0, "limit": 50, "values": [{"uuid": DEVICE, "deviceName": "stray"}]})));
+        let cmd = DeviceListArgs::from_iter(&["list", "--ungrouped", "--name-contains", "st"]);
+        let result = match Registry::list_device_args(&mut server.config(), &cmd).expect("ungrouped") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table"),
+        };
+        assert_eq!(result.rows[0]["name"], "stray");
+        assert_eq!(server.requests()[0].path, "/api/v1/devices?nameContains=st&grouped=false");
+    }
+
+    fn seen_args(args: &[&str]) -> Result<Option<SeenFilter>> {
+        let now = humantime::parse_rfc3339("2020-01-08T00:00:00Z").unwrap();
+        SeenFilter::from_args(&DeviceListArgs::from_iter(["list", "--all"].iter().chain(args)), now)
+    }
+
+    #[test]
+    fn parse_device_list() {
+        let body = json!({
+            "total": 1,
+            "offset": 0,
+            "limit": 50,
+            "values": [{
+                "namespace": "default",
+                "uuid": DEVICE,
+                "deviceName": "car-1",
+                "deviceId": "vin-1",
+                "deviceType": "Vehicle",
+                "lastSeen": "2020-01-01T00:00:00Z",
+                "createdAt": "2019-12-01T00:00:00Z",
+                "activatedAt": null,
+                "deviceStatus": "UpToDate"
+            }]
+        });
+        let list: DeviceList = serde_json::from_value(body.clone()).expect("device list");
+        let device = &list.values[0];
+        assert_eq!(device.uuid.to_string(), DEVICE);
+        assert_eq!(device.name, "car-1");
+        assert_eq!(device.device_id.as_deref(), Some("vin-1"));
+        assert_eq!(device.device_type.as_deref(), Some("Vehicle"));
+        assert_eq!(device.status.as_deref(), Some("UpToDate"));
+        assert_eq!(device.last_seen().unwrap(), Some(humantime::parse_rfc3339("2020-01-01T00:00:00Z").unwrap()));
+        assert_eq!(serde_json::to_value(&list).unwrap(), body);
+
+        let result = Registry::device_table(HeaderMap::new(), Vec::new(), &list.values);
+        assert_eq!(result.rows[0]["name"], "car-1");
+        assert_eq!(result.rows[0]["status"], "UpToDate");
+    }
+
+    #[test]
+    fn parse_group_list() {
+        let body = json!({
+            "total": 2,
+            "offset": 0,
+            "limit": 50,
+            "values": [
+                {"id": GROUP, "groupName": "fleet", "namespace": "default", "groupType": "static", "expression": null},
+                {"id": DEVICE, "groupName": "eu", "namespace": "default", "groupType": "dynamic", "expression": "deviceid contains eu"}
+            ]
+        });
+        let list: GroupList = serde_json::from_value(body.clone()).expect("group list");
+        assert_eq!(list.values[0].name, "fleet");
+        assert_eq!(list.values[0].group_type.as_deref(), Some("static"));
+        assert_eq!(list.values[0].expression, None);
+        assert_eq!(list.values[1].expression.as_deref(), Some("deviceid contains eu"));
+        assert_eq!(list.other["total"], 2);
+
+        let server = MockServer::start(move |_| MockResponse::json(200, body.clone()));
+        let result = match Registry::list_all_group_table(&mut server.config(), Pagination::default()).expect("table") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[1]["type"], "dynamic");
+        let raw: Value = serde_json::from_slice(&result.response).unwrap();
+        assert_eq!(raw["values"][0]["namespace"], "default");
+    }
+
+    #[test]
+    fn parse_group_devices() {
+        let body = json!({"total": 2, "offset": 0, "limit": 50, "values": [DEVICE, GROUP]});
+        let members: Members = serde_json::from_value(body.clone()).expect("group devices");
+        assert_eq!(members.values.len(), 2);
+        assert_eq!(members.values[0].to_string(), DEVICE);
+
+        let server = MockServer::start(move |_| MockResponse::json(200, body.clone()));
+        let group = GROUP.parse().unwrap();
+        let result = match Registry::list_devices(&mut server.config(), group).expect("table") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.columns, vec!["device"]);
+        assert_eq!(result.rows[1]["device"], GROUP);
+    }
+
+    #[test]
+    fn parse_seen_durations() {
+        let at = |time| Some(humantime::parse_rfc3339(time).unwrap());
+        assert_eq!(seen_args(&["--seen-since", "7d"]).unwrap(), Some(SeenFilter::Since(at("2020-01-01T00:00:00Z").unwrap())));
+        assert_eq!(
+            seen_args(&["--not-seen-for", "1h 30m"]).unwrap(),
+            Some(SeenFilter::NotSince(at("2020-01-07T22:30:00Z").unwrap()))
+        );
+        assert_eq!(seen_args(&[]).unwrap(), None);
+        assert!(seen_args(&["--seen-since", "soon"]).is_err());
+    }
+
+    #[test]
+    fn filter_devices_by_last_seen() {
+        let device = |uuid: &str, seen: Option<&str>| json!({"uuid": uuid, "deviceName": uuid, "deviceId": uuid, "lastSeen": seen});
+        let body = json!({"values": [
+            device(DEVICE, Some("2020-01-01T00:00:00Z")),
+            device(GROUP, Some("2019-12-31T23:59:59.999Z")),
+            device("00000000-0000-0000-0000-000000000003", None)
+        ]});
+        let uuids = |seen| {
+            let mut list: DeviceList = serde_json::from_value(body.clone()).unwrap();
+            if let Some(seen) = seen {
+                list.retain_seen(seen).expect("filter");
+            }
+            list.values.into_iter().map(|device| device.uuid.to_string()).collect::<Vec<_>>()
+        };
+        let cutoff = humantime::parse_rfc3339("2020-01-01T00:00:00Z").unwrap();
+
+        assert_eq!(uuids(Some(SeenFilter::Since(cutoff))), vec![DEVICE]);
+        assert_eq!(uuids(Some(SeenFilter::NotSince(cutoff))), vec![GROUP, "00000000-0000-0000-0000-000000000003"]);
+        assert_eq!(uuids(None).len(), 3);
+    }
+
+    #[test]
+    fn device_events_table() {
+        let event = |id: &str, device_time: Option<&str>, correlation: Option<&str>| {
+            json!({
+                "deviceUuid": DEVICE,
+                "eventId": "1",
+                "eventType": {"id": id, "version": 0},
+                "deviceTime": device_time,
+                "receivedAt": "2020-01-01T00:00:05Z",
+                "payload": correlation.map(|id| json!({"correlationId": id, "success": true})).unwrap_or(json!({}))
+            })
+        };
+        let events = json!([
+            event("EcuDownloadStarted", Some("2020-01-01T00:00:00Z"), Some("urn:here-ota:mtu:1")),
+            event("campaign_accepted", None, None)
+        ]);
+        let page = serde_json::to_vec(&json!({"total": 2, "values": events.clone()})).unwrap();
+        assert_eq!(DeviceEvent::parse_list(&page).expect("page").len(), 2);
+
+        let server = MockServer::start(move |_| MockResponse::json(200, events.clone()));
+        let result = match Registry::events_table(&mut server.config(), DEVICE).expect("events") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(server.requests()[0].route(), format!("/api/v1/devices/{}/events", DEVICE));
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0]["event type"], "EcuDownloadStarted");
+        assert_eq!(result.rows[0]["timestamp"], "2020-01-01T00:00:00Z");
+        assert_eq!(result.rows[0]["correlation id"], "urn:here-ota:mtu:1");
+        assert_eq!(result.rows[1]["timestamp"], "2020-01-01T00:00:05Z");
+        assert_eq!(result.rows[1]["correlation id"], Value::Null);
+    }
+
+    #[test]
+    fn dynamic_group_expression() {
+        for valid in &["deviceid contains abc", "DeviceId position(3) is x", "not (deviceid contains a or deviceid position(1) is not b) and deviceid contains c-1"] {
+            validate_expression(valid).expect(valid);
+        }
+        for (invalid, reason) in &[
+            ("", "expected a condition"),
+            ("deviceid contains", "expected a value at the end"),
+            ("deviceid position(0) is x", "position must be a positive number"),
+            ("deviceid position(2) is xy", "expected a single character, found `xy`"),
+            ("(deviceid contains a", "expected `)` at the end"),
+            ("deviceid contains a b", "unexpected `b`"),
+            ("hardware contains a", "expected `deviceid`, `not` or `(`, found `hardware`"),
+        ] {
+            let err = validate_expression(invalid).expect_err(invalid).to_string();
+            assert_eq!(err, format!("Command args: invalid group expression `{}`: {}", invalid, reason));
+        }
+
+        let server = MockServer::start(|_| MockResponse::json(201, json!(DEVICE)));
+        Registry::create_group(&mut server.config(), "eu", GroupType::Dynamic, Some("deviceid contains eu")).expect("create");
+        Registry::create_group(&mut server.config(), "fleet", GroupType::Static, None).expect("create");
+        let requests = server.requests();
+        assert_eq!(requests[0].json(), json!({"name": "eu", "groupType": "dynamic", "expression": "deviceid contains eu"}));
+        assert_eq!(requests[1].json(), json!({"name": "fleet", "groupType": "static"}));
+    }
+
+    #[test]
+    fn import_devices_from_file() {
+        let dir = std::env::temp_dir().join(format!("ota-cli-import-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("devices.csv");
+        fs::write(&csv, "# fleet\nid,name,type\nvin-1, truck, vehicle\n\nvin-2,\"van\",Other\n").unwrap();
+        let rows = DeviceRow::from_file(&csv).expect("csv");
+        assert_eq!(rows.iter().map(|row| (row.name.as_ref(), row.id.as_ref())).collect::<Vec<_>>(), vec![("truck", "vin-1"), ("van", "vin-2")]);
+        fs::write(&csv, "truck,vin-1,vehicle\nvan,vin-2,boat\n").unwrap();
+        assert_eq!(DeviceRow::from_file(&csv).unwrap_err().to_string(), "Parse error: line 2: unknown device type `boat`, expected vehicle or other");
+
+        let json = dir.join("devices.json");
+        fs::write(&json, r#"[{"name": "bus", "id": "vin-3", "type": "vehicle"}, {"name": "taken", "id": "vin-4", "type": "other"}]"#).unwrap();
+        let rows = DeviceRow::from_file(&json).expect("json");
+        assert_eq!(rows.len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let server = MockServer::start(|req| match req.path.contains("deviceId=vin-4") {
+            true => MockResponse::status(409),
+            false => MockResponse::json(201, json!(DEVICE)),
+        });
+        let opts = BatchOptions { fail_fast: false, ..BatchOptions::default() };
+        let result = match Registry::import_devices(&mut server.config(), rows[..1].to_vec(), opts).expect("import") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table"),
+        };
+        assert_eq!(result.rows[0]["name"], "bus");
+        assert_eq!(result.rows[0]["status"], "created");
+        assert_eq!(result.rows[0]["uuid"], DEVICE);
+        assert_eq!(server.requests()[0].route(), "/api/v1/devices");
+
+        let err = Registry::import_devices(&mut server.config(), rows.clone(), opts).err().expect("failed import");
+        assert_eq!(err.to_string(), "Failed: importing failed for 1 of 2 items");
+        assert_eq!(server.requests().len(), 3);
+
+        let opts = BatchOptions { fail_fast: true, concurrency: 1, ..BatchOptions::default() };
+        let mut failing = rows.clone();
+        failing.reverse();
+        let err = Registry::import_devices(&mut server.config(), failing, opts).err().expect("fail fast import");
+        assert!(err.to_string().contains("409"));
+        assert_eq!(server.requests().len(), 4);
+
+        let mut duplicated = rows.clone();
+        duplicated[1].id = duplicated[0].id.clone();
+        let requests = server.requests().len();
+        assert!(matches!(Registry::import_devices(&mut server.config(), duplicated, opts), Err(Error::Args(msg)) if msg.contains("vin-3")));
+        assert_eq!(server.requests().len(), requests);
+    }
+
+    #[test]
+    fn dry_run_import_devices() {
+        let server = MockServer::start(|_| MockResponse::dry_run());
+        let rows = vec![DeviceRow { name: "bus".into(), id: "vin-3".into(), kind: DeviceType::Vehicle }];
+        let result = match Registry::import_devices(&mut server.config(), rows, BatchOptions::default()).expect("dry run") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table"),
+        };
+        assert_eq!(result.rows[0]["status"], "dry run");
+        assert_eq!(result.rows[0]["uuid"], Value::Null);
+    }
+
+    #[test]
+    fn resolve_unknown_device_name() {
+        let server = registry(200);
+        let mut config = server.config();
+        match Registry::resolve_device(&mut config, "bus") {
+            Err(Error::NotFound(..)) => (),
+            other => panic!("expected not found, got {:?}", other.err()),
+        }
+    }
 }
@@ -0,0 +1,436 @@
+use clap::ArgMatches;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    time::SystemTime,
+};
+
+use crate::api::reposerver::Reposerver;
+use crate::cli::{RepoDiffArgs, RootRotateKeysArgs, RootShowArgs, SignPullArgs, SignPushArgs};
+use crate::command::{confirm, missing, CommandResult, TableResult};
+use crate::config::{self, Config};
+use crate::error::{Error, Result};
+use crate::http::{Http, Request};
+
+/// Available reposerver root metadata API methods.
+pub trait RepoApi {
+    /// List the roles of the signed root metadata, or of a previous version of it.
+    fn get_root(_: &mut Config, version: Option<u64>) -> Result<CommandResult>;
+    /// Replace the root metadata with a new signed version.
+    fn put_root(_: &mut Config, root: &Value) -> Result<CommandResult>;
+    /// Fetch the signed targets metadata.
+    fn get_targets(_: &mut Config) -> Result<CommandResult>;
+    /// Replace the targets metadata signed offline, where `checksum` is the SHA256 of the replaced `targets.json`.
+    fn put_targets(_: &mut Config, targets: &Value, checksum: &str) -> Result<CommandResult>;
+}
+
+/// Make API calls to audit and rotate the repository root of trust.
+pub struct Repo;
+
+impl Repo {
+    /// Show the roles of the root metadata, saving the signed root to `--out` when given.
+    pub fn root_table(config: &mut Config, cmd: &RootShowArgs) -> Result<CommandResult> {
+        let (headers, body, root) = Self::fetch(config, cmd.version)?;
+        if let Some(ref out) = cmd.out {
+            config::write_atomic(out, &body)?;
+            info!("saved root version {} to {}", root.signed.version, out.display());
+        }
+        Ok(roles_table(headers, body, &root).into())
+    }
+
+    /// Fetch the signed root metadata, or a previous version of it.
+    pub fn fetch_root(config: &mut Config, version: Option<u64>) -> Result<SignedRoot> {
+        let (_, _, root) = Self::fetch(config, version)?;
+        Ok(root)
+    }
+
+    /// List the keys of the root metadata with the roles each one signs for.
+    pub fn keys_table(config: &mut Config) -> Result<CommandResult> {
+        let (headers, body, root) = Self::fetch(config, None)?;
+        let mut result = TableResult::new(headers, body, &["keyid", "keytype", "roles"]);
+        for (keyid, key) in &root.signed.keys {
+            let roles = root
+                .signed
+                .roles
+                .iter()
+                .filter(|(_, keys)| keys.keyids.contains(keyid))
+                .map(|(role, _)| role.clone())
+                .collect::<Vec<_>>();
+            result.add_row(vec![keyid.clone().into(), key.keytype.clone().into(), roles.into()]);
+        }
+        Ok(result.into())
+    }
+
+    /// Upload a new signed root, once it is checked against the current root.
+    pub fn rotate_from_args(config: &mut Config, cmd: &RootRotateKeysArgs) -> Result<CommandResult> {
+        let value: Value = serde_json::from_slice(&fs::read(&cmd.file)?)?;
+        let next: SignedRoot = serde_json::from_value(value.clone())?;
+        let current = Self::fetch_root(config, None)?;
+        next.check_follows(&current)?;
+        confirm(cmd.yes, &format!("Replace root version {} with version {}?", current.signed.version, next.signed.version))?;
+        Self::put_root(config, &value)
+    }
+
+    /// Save the next, unsigned version of the targets metadata for offline signing.
+    pub fn pull_targets_from_args(config: &mut Config, cmd: &SignPullArgs) -> Result<CommandResult> {
+        let (_, mut targets) = Self::fetch_targets(config)?;
+        let version = targets["signed"]["version"].as_u64().ok_or_else(|| Error::Parse("targets metadata has no version".into()))? + 1;
+        targets["signed"]["version"] = version.into();
+        if let Some(ref expires) = cmd.expires {
+            let duration = humantime::parse_duration(expires).map_err(|err| Error::Args(format!("--expires `{}`: {}", expires, err)))?;
+            targets["signed"]["expires"] = humantime::format_rfc3339_seconds(SystemTime::now() + duration).to_string().into();
+        }
+        targets["signatures"] = json!([]);
+        config::write_atomic(&cmd.out, &serde_json::to_vec_pretty(&targets)?)?;
+        info!("saved unsigned targets version {} to {}", version, cmd.out.display());
+        Ok(CommandResult::Empty)
+    }
+
+    /// Upload targets metadata signed offline, when it follows the current version.
+    pub fn push_targets_from_args(config: &mut Config, cmd: &SignPushArgs) -> Result<CommandResult> {
+        let targets: Value = serde_json::from_slice(&fs::read(&cmd.file)?)?;
+        if targets["signatures"].as_array().map(Vec::is_empty).unwrap_or(true) {
+            return Err(Error::Verify("targets metadata has no signatures, run `ota sign targets` first".into()));
+        }
+        let (checksum, current) = Self::fetch_targets(config)?;
+        let (version, current) = (targets["signed"]["version"].as_u64(), current["signed"]["version"].as_u64());
+        if version.is_none() || version != current.map(|current| current + 1) {
+            return Err(Error::Verify(format!(
+                "expected targets version {}, found {}, pull the latest targets metadata again",
+                current.map(|current| current + 1).unwrap_or(1),
+                version.map(|version| version.to_string()).unwrap_or_else(|| "none".into())
+            )));
+        }
+        Self::put_targets(config, &targets, &checksum)
+    }
+
+    /// Compare the current targets with a saved `targets.json`, or with the targets of the
+    /// namespace given by `--against`, loaded with the global `args`.
+    pub fn diff_from_args(config: &mut Config, cmd: &RepoDiffArgs, args: &ArgMatches<'_>) -> Result<CommandResult> {
+        let (_, current) = Self::fetch_targets(config)?;
+        let (old, source) = match cmd.file {
+            Some(ref file) => (serde_json::from_slice(&fs::read(file)?)?, file.display().to_string()),
+            None => {
+                let namespace = cmd.against.as_deref().ok_or_else(|| missing("against"))?;
+                let mut other = config::with_namespace(namespace, || Config::load_from_args(args))?;
+                (Self::fetch_targets(&mut other)?.1, format!("namespace {}", namespace))
+            }
+        };
+        let result = diff_targets(&old, &current)?;
+        info!("{} targets differ from {}", result.rows.len(), source);
+        Ok(result.into())
+    }
+
+    /// Fetch the signed targets metadata along with the SHA256 of its raw response.
+    fn fetch_targets(config: &mut Config) -> Result<(String, Value)> {
+        debug!("fetching targets metadata");
+        let (_, body) = Http::execute_body(Reposerver::targets_request(config)?, config.token()?)?;
+        let checksum = Sha256::digest(&body).iter().map(|b| format!("{:02x}", b)).collect();
+        Ok((checksum, serde_json::from_slice(&body)?))
+    }
+
+    /// Fetch the signed root, keeping the raw response.
+    fn fetch(config: &mut Config, version: Option<u64>) -> Result<(HeaderMap, Vec<u8>, SignedRoot)> {
+        debug!("fetching root metadata version {:?}", version);
+        let (headers, body) = Http::execute_body(Self::get_root_request(config, version)?, config.token()?)?;
+        let root = serde_json::from_slice(&body)?;
+        Ok((headers, body, root))
+    }
+}
+
+impl RepoApi for Repo {
+    fn get_root(config: &mut Config, version: Option<u64>) -> Result<CommandResult> {
+        let (headers, body, root) = Self::fetch(config, version)?;
+        Ok(roles_table(headers, body, &root).into())
+    }
+
+    fn put_root(config: &mut Config, root: &Value) -> Result<CommandResult> {
+        debug!("uploading new root metadata");
+        Ok(Http::execute(Self::put_root_request(config, root)?, config.token()?)?.into())
+    }
+
+    fn get_targets(config: &mut Config) -> Result<CommandResult> {
+        debug!("fetching targets metadata");
+        Ok(Http::execute(Reposerver::targets_request(config)?, config.token()?)?.into())
+    }
+
+    fn put_targets(config: &mut Config, targets: &Value, checksum: &str) -> Result<CommandResult> {
+        debug!("uploading targets metadata replacing checksum {}", checksum);
+        Ok(Http::execute(Self::put_targets_request(config, targets, checksum)?, config.token()?)?.into())
+    }
+}
+
+/// Requests shared by the blocking and async repo APIs.
+impl Repo {
+    pub(crate) fn get_root_request(config: &Config, version: Option<u64>) -> Result<Request> {
+        match version {
+            Some(version) => Request::get(format!("{}api/v1/user_repo/{}.root.json", config.reposerver, version)),
+            None => Request::get(format!("{}api/v1/user_repo/root.json", config.reposerver)),
+        }
+    }
+
+    pub(crate) fn put_root_request(config: &Config, root: &Value) -> Result<Request> {
+        Ok(Request::put(format!("{}api/v1/user_repo/root", config.reposerver))?.json(root.clone()))
+    }
+
+    pub(crate) fn put_targets_request(config: &Config, targets: &Value, checksum: &str) -> Result<Request> {
+        Ok(Request::put(format!("{}api/v1/user_repo/targets", config.reposerver))?
+            .header("x-ats-role-checksum", checksum)
+            .json(targets.clone()))
+    }
+}
+
+/// List the roles of a signed root with their keys and threshold.
+fn roles_table(headers: HeaderMap, body: Vec<u8>, root: &SignedRoot) -> TableResult {
+    let mut result = TableResult::new(headers, body, &["role", "threshold", "keyids", "version", "expires"]);
+    for (role, keys) in &root.signed.roles {
+        result.add_row(vec![
+            role.clone().into(),
+            keys.threshold.into(),
+            keys.keyids.clone().into(),
+            root.signed.version.into(),
+            root.signed.expires.clone().into(),
+        ]);
+    }
+    result
+}
+
+/// Compare the targets of two `targets.json` files, with a row for each target added, removed or changed
+/// in `new`, naming the fields that changed.
+fn diff_targets(old: &Value, new: &Value) -> Result<TableResult> {
+    let targets = |metadata: &Value| -> Result<Map<String, Value>> {
+        match metadata["signed"]["targets"] {
+            Value::Object(ref targets) => Ok(targets.clone()),
+            _ => Err(Error::Parse("targets metadata has no `signed.targets`".into())),
+        }
+    };
+    let (old, new) = (targets(old)?, targets(new)?);
+    let changed_fields = |before: &Value, after: &Value| {
+        let mut fields = Vec::new();
+        for (prefix, before, after) in [("", before, after), ("custom.", &before["custom"], &after["custom"])] {
+            let keys = before.as_object().into_iter().chain(after.as_object()).flat_map(Map::keys).collect::<BTreeSet<_>>();
+            for key in keys {
+                if before.get(key) != after.get(key) && !(prefix.is_empty() && key == "custom") {
+                    fields.push(Value::from(format!("{}{}", prefix, key)));
+                }
+            }
+        }
+        fields
+    };
+
+    let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["target", "change", "name", "version", "fields"]);
+    for filename in old.keys().chain(new.keys()).collect::<BTreeSet<_>>() {
+        let (change, target, fields) = match (old.get(filename), new.get(filename)) {
+            (None, Some(target)) => ("added", target, Vec::new()),
+            (Some(target), None) => ("removed", target, Vec::new()),
+            (Some(before), Some(after)) if before != after => ("changed", after, changed_fields(before, after)),
+            _ => continue,
+        };
+        result.add_row(vec![
+            filename.clone().into(),
+            change.into(),
+            target["custom"]["name"].clone(),
+            target["custom"]["version"].clone(),
+            fields.into(),
+        ]);
+    }
+    result.response = serde_json::to_vec(&result)?;
+    Ok(result)
+}
+
+/// Signed root metadata, keeping any other fields for raw output.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedRoot {
+    pub signed: RootRole,
+    #[serde(default)]
+    pub signatures: Vec<Value>,
+}
+
+/// The keys and role thresholds of the root of trust.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RootRole {
+    #[serde(rename = "_type", default)]
+    pub kind: String,
+    pub version: u64,
+    #[serde(default)]
+    pub expires: Option<String>,
+    #[serde(default)]
+    pub keys: BTreeMap<String, RootKey>,
+    #[serde(default)]
+    pub roles: BTreeMap<String, RoleKeys>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// A public key trusted by the root.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RootKey {
+    pub keytype: String,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// The keys allowed to sign for a role, and how many signatures it needs.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RoleKeys {
+    pub keyids: Vec<String>,
+    pub threshold: u32,
+}
+
+impl SignedRoot {
+    /// Check that this root can replace `current`: the next version, signed, and with enough keys per role.
+    fn check_follows(&self, current: &SignedRoot) -> Result<()> {
+        let invalid = |reason: String| Err(Error::Verify(format!("new root: {}", reason)));
+        if !self.signed.kind.eq_ignore_ascii_case("root") {
+            return invalid(format!("expected root metadata, found `{}`", self.signed.kind));
+        } else if self.signed.version != current.signed.version + 1 {
+            return invalid(format!("expected version {}, found {}", current.signed.version + 1, self.signed.version));
+        } else if self.signatures.is_empty() {
+            return invalid("it has no signatures".into());
+        }
+        for (role, keys) in &self.signed.roles {
+            if keys.threshold < 1 || keys.threshold as usize > keys.keyids.len() {
+                return invalid(format!("role `{}` has a threshold of {} with {} keys", role, keys.threshold, keys.keyids.len()));
+            }
+            if let Some(missing) = keys.keyids.iter().find(|keyid| !self.signed.keys.contains_key(*keyid)) {
+                return invalid(format!("role `{}` refers to unknown key {}", role, missing));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+    use structopt::StructOpt;
+
+    fn root(version: u64) -> Value {
+        json!({
+            "signed": {
+                "_type": "Root",
+                "version": version,
+                "expires": "2030-01-01T00:00:00Z",
+                "consistent_snapshot": false,
+                "keys": {"k1": {"keytype": "ED25519", "keyval": {"public": "aa"}}, "k2": {"keytype": "RSA", "keyval": {"public": "bb"}}},
+                "roles": {
+                    "root": {"keyids": ["k1"], "threshold": 1},
+                    "targets": {"keyids": ["k1", "k2"], "threshold": 1}
+                }
+            },
+            "signatures": [{"keyid": "k1", "method": "ed25519", "sig": "cc"}]
+        })
+    }
+
+    #[test]
+    fn root_roles_and_keys() {
+        let server = MockServer::start(|req| match req.route() {
+            "/api/v1/user_repo/root.json" => MockResponse::json(200, root(2)),
+            "/api/v1/user_repo/1.root.json" => MockResponse::json(200, root(1)),
+            _ => MockResponse::status(404),
+        });
+        let out = std::env::temp_dir().join(format!("ota-cli-root-{}.json", std::process::id()));
+        let cmd = RootShowArgs::from_iter(&["show", "--version", "1", "--out", out.to_str().unwrap()]);
+        let rows = match Repo::root_table(&mut server.config(), &cmd).expect("root") {
+            CommandResult::Table(result) => result.rows,
+            _ => panic!("expected a table"),
+        };
+        assert_eq!(rows[0]["role"], "root");
+        assert_eq!(rows[1]["keyids"], json!(["k1", "k2"]));
+        assert_eq!(rows[1]["version"], 1);
+        let saved: Value = serde_json::from_slice(&fs::read(&out).unwrap()).unwrap();
+        assert_eq!(saved, root(1));
+        fs::remove_file(&out).unwrap();
+        match Repo::get_root(&mut server.config(), None).expect("current root") {
+            CommandResult::Table(result) => assert_eq!(result.rows[0]["version"], 2),
+            _ => panic!("expected a table"),
+        }
+
+        let rows = match Repo::keys_table(&mut server.config()).expect("keys") {
+            CommandResult::Table(result) => result.rows,
+            _ => panic!("expected a table"),
+        };
+        assert_eq!(rows[0]["keyid"], "k1");
+        assert_eq!(rows[0]["roles"], json!(["root", "targets"]));
+        assert_eq!(rows[1]["roles"], json!(["targets"]));
+    }
+
+    #[test]
+    fn pull_sign_and_push_targets() {
+        let targets = json!({"signed": {"_type": "Targets", "version": 4, "targets": {}}, "signatures": [{"keyid": "online", "sig": "aa"}]});
+        let served = serde_json::to_vec(&targets).unwrap();
+        let checksum = Sha256::digest(&served).iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let server = MockServer::start(move |req| match req.method.as_ref() {
+            "GET" => MockResponse::json(200, serde_json::from_slice(&served).unwrap()),
+            _ => MockResponse::status(204),
+        });
+        let file = std::env::temp_dir().join(format!("ota-cli-targets-{}.json", std::process::id()));
+        let path = file.to_str().unwrap();
+
+        Repo::pull_targets_from_args(&mut server.config(), &SignPullArgs::from_iter(&["pull", "--out", path, "--expires", "30d"])).expect("pull");
+        let mut unsigned: Value = serde_json::from_slice(&fs::read(&file).unwrap()).unwrap();
+        assert_eq!(unsigned["signed"]["version"], 5);
+        assert!(unsigned["signed"]["expires"].is_string());
+        assert_eq!(unsigned["signatures"], json!([]));
+
+        let push = SignPushArgs::from_iter(&["push", "--file", path]);
+        match Repo::push_targets_from_args(&mut server.config(), &push) {
+            Err(Error::Verify(_)) => (),
+            other => panic!("expected unsigned targets to be refused, got {:?}", other.err()),
+        }
+        unsigned["signatures"] = json!([{"keyid": "offline", "method": "ed25519", "sig": "bb"}]);
+        fs::write(&file, serde_json::to_vec(&unsigned).unwrap()).unwrap();
+        Repo::push_targets_from_args(&mut server.config(), &push).expect("push");
+        fs::remove_file(&file).unwrap();
+
+        let put = server.requests().into_iter().find(|req| req.method == "PUT").expect("put");
+        assert_eq!(put.route(), "/api/v1/user_repo/targets");
+        assert_eq!(put.headers["x-ats-role-checksum"], checksum);
+        assert_eq!(put.json()["signed"]["version"], 5);
+    }
+
+    #[test]
+    fn diff_targets_with_snapshot() {
+        let target = |version: &str, sha256: &str| json!({"length": 10, "hashes": {"sha256": sha256}, "custom": {"name": "os", "version": version, "hardwareIds": ["rpi"]}});
+        let old = json!({"signed": {"targets": {"os-1": target("1", "aa"), "os-2": target("2", "bb"), "os-3": target("3", "cc")}}});
+        let mut changed = target("2", "dd");
+        changed["custom"]["hardwareIds"] = json!(["rpi", "rpi4"]);
+        let current = json!({"signed": {"targets": {"os-1": target("1", "aa"), "os-2": changed, "os-4": target("4", "ee")}}});
+        let server = MockServer::start(move |_| MockResponse::json(200, current.clone()));
+        let file = std::env::temp_dir().join(format!("ota-cli-diff-{}.json", std::process::id()));
+        fs::write(&file, serde_json::to_vec(&old).unwrap()).unwrap();
+
+        let cmd = RepoDiffArgs::from_iter(&["diff", "--file", file.to_str().unwrap()]);
+        let rows = match Repo::diff_from_args(&mut server.config(), &cmd, &ArgMatches::default()).expect("diff") {
+            CommandResult::Table(result) => result.rows,
+            _ => panic!("expected a table"),
+        };
+        fs::remove_file(&file).unwrap();
+        let changes = rows.iter().map(|row| (row["target"].as_str().unwrap(), row["change"].as_str().unwrap())).collect::<Vec<_>>();
+        assert_eq!(changes, vec![("os-2", "changed"), ("os-3", "removed"), ("os-4", "added")]);
+        assert_eq!(rows[0]["fields"], json!(["hashes", "custom.hardwareIds"]));
+        assert_eq!(rows[2]["version"], "4");
+        assert!(diff_targets(&json!({"targets": {}}), &old).is_err());
+    }
+
+    #[test]
+    fn new_root_must_follow_current() {
+        let current: SignedRoot = serde_json::from_value(root(2)).unwrap();
+        let next = |value: Value| serde_json::from_value::<SignedRoot>(value).unwrap().check_follows(&current);
+        next(root(3)).expect("next version");
+
+        let err = |value: Value| next(value).unwrap_err().to_string();
+        assert_eq!(err(root(2)), "Verification failed: new root: expected version 3, found 2");
+        let mut unsigned = root(3);
+        unsigned["signatures"] = json!([]);
+        assert_eq!(err(unsigned), "Verification failed: new root: it has no signatures");
+        let mut unknown = root(3);
+        unknown["signed"]["roles"]["root"]["keyids"] = json!(["k3"]);
+        assert_eq!(err(unknown), "Verification failed: new root: role `root` refers to unknown key k3");
+    }
+}
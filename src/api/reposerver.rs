@@ -1,15 +1,31 @@
 use crate::api::director::TargetFormat;
-use crate::command::{CommandResult, TableResult};
+use crate::api::upload::{self, MIN_CHUNK_SIZE};
+use crate::batch::{self, BatchOptions};
+use crate::api::auth_plus::AccessToken;
+use crate::progress::{self, ProgressMode, ProgressReader, ProgressWriter};
+use crate::cli::{PackageAddArgs, PackageDeleteArgs};
+use crate::command::{confirm, missing, CommandResult, TableResult};
 use crate::config::Config;
 use crate::error::{Error, Result};
-use crate::http::{Http, HttpMethods};
-use clap::ArgMatches;
-use comfy_table::Table;
-use reqwest::blocking::multipart::Form;
-use reqwest::blocking::Client;
+use crate::http::{Http, Request};
+use crate::interrupt::{self, PartialFile, Scope};
+use reqwest::blocking::multipart::{Form, Part};
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, ACCEPT_RANGES, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use std::io::Read;
-use std::{collections::HashMap, fs, path::Path};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::io::{self, BufReader, Read, Write};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::AtomicBool,
+};
 use toml;
 use url::Url;
 use urlencoding;
@@ -27,6 +43,39 @@ struct Targets {
 #[derive(Deserialize)]
 struct Target {
     custom: Custom,
+    #[serde(default)]
+    hashes: HashMap<String, String>,
+    #[serde(default)]
+    length: u64,
+}
+
+impl Target {
+    /// Check that the target metadata has the `sha256` hash and any `length` of an uploaded file.
+    fn check(&self, sha256: &str, length: Option<u64>) -> Result<()> {
+        match self.hashes.get("sha256") {
+            Some(actual) if actual.eq_ignore_ascii_case(sha256) => (),
+            actual => return Err(Error::Verify(format!("targets.json has sha256 {}, expected {}", actual.map_or("none", String::as_str), sha256))),
+        }
+        match length {
+            Some(length) if length != self.length => Err(Error::Verify(format!("targets.json has length {}, expected {}", self.length, length))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a downloaded file against the target length and any `sha256` hash.
+    fn verify(&self, path: &Path) -> Result<()> {
+        let length = fs::metadata(path)?.len();
+        if self.length > 0 && length != self.length {
+            return Err(Error::Verify(format!("expected {} bytes, got {}", self.length, length)));
+        }
+        if let Some(expected) = self.hashes.get("sha256") {
+            let (actual, _) = sha256_file(path)?;
+            if !actual.eq_ignore_ascii_case(expected) {
+                return Err(Error::Verify(format!("expected sha256 {}, got {}", expected, actual)));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -40,11 +89,25 @@ struct Custom {
     target_format: TargetFormat,
 }
 
+/// The version and commit of an OSTree target.
+pub(crate) struct OstreeTarget {
+    pub version: String,
+    pub commit: Option<String>,
+}
+
+/// Return the hex SHA256 digest and length of a file.
+pub(crate) fn sha256_file(path: &Path) -> Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let length = io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok((hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect(), length))
+}
+
 /// Available TUF Reposerver API methods.
 pub trait ReposerverApi {
     fn add_package(_: &mut Config, package: TufPackage) -> Result<CommandResult>;
     fn get_package(_: &mut Config, name: &str, version: &str) -> Result<CommandResult>;
     fn list_packages(_: &mut Config) -> Result<CommandResult>;
+    fn delete_package(_: &mut Config, filename: &str) -> Result<CommandResult>;
 }
 
 /// Make API calls to the TUF Reposerver.
@@ -52,78 +115,586 @@ pub struct Reposerver;
 
 impl ReposerverApi for Reposerver {
     fn add_package(config: &mut Config, package: TufPackage) -> Result<CommandResult> {
-        let entry = format!("{}-{}", package.name, package.version);
-        debug!("adding package with entry name {}", entry);
-        let req = Client::new()
-            .put(&format!("{}api/v1/user_repo/targets/{}", config.reposerver, entry))
-            .query(&[
-                ("name", urlencoding::encode(&package.name)),
-                ("version", urlencoding::encode(&package.version)),
-                ("hardwareIds", package.hardware.join(",")),
-                ("targetFormat", format!("{}", package.format)),
-            ])
-            .multipart(match package.target {
-                RepoTarget::Path(path) => Form::new().file("file", path)?,
-                RepoTarget::Url(url) => Form::new().file("fileUri", url.as_str())?,
-            });
-        Ok(Http::send(req, config.token()?)?.into())
+        Ok(Self::put_package(config, package)?.into())
     }
 
     fn get_package(config: &mut Config, name: &str, version: &str) -> Result<CommandResult> {
-        let entry = format!("{}_{}", name, version);
-        debug!("fetching package with entry name {}", entry);
-        Ok(Http::get(&format!("{}api/v1/user_repo/targets/{}", config.reposerver, entry), config.token()?)?.into())
+        debug!("fetching package {} version {}", name, version);
+        Ok(Http::execute(Self::get_package_request(config, name, version)?, config.token()?)?.into())
     }
 
-    fn list_packages(config: &mut Config) -> Result<CommandResult> {
-        let mut res = Http::get(&format!("{}api/v1/user_repo/targets.json", config.reposerver), config.token()?)?;
-        let h = res.headers().to_owned();
-        let mut str_resp: Vec<u8> = vec![];
-        res.read_to_end(&mut str_resp)?;
+    fn list_packages(config: &mut Config) -> Result<CommandResult> { Ok(Self::list_packages_to(config, None)?.into()) }
 
-        let v: TargetRole = serde_json::from_slice(&str_resp)?;
+    fn delete_package(config: &mut Config, filename: &str) -> Result<CommandResult> {
+        debug!("deleting target {}", filename);
+        Ok(Http::execute(Self::delete_package_request(config, filename)?, config.token()?)?.into())
+    }
+}
 
-        let mut table = Table::new();
+/// Requests shared by the blocking and async Reposerver APIs.
+impl Reposerver {
+    pub(crate) fn get_package_request(config: &Config, name: &str, version: &str) -> Result<Request> {
+        Request::get(format!("{}api/v1/user_repo/targets/{}_{}", config.reposerver, name, version))
+    }
 
-        table.set_header(vec![
-            "name",
+    pub(crate) fn delete_package_request(config: &Config, filename: &str) -> Result<Request> {
+        Request::delete(format!("{}api/v1/user_repo/targets/{}", config.reposerver, urlencoding::encode(filename)))
+    }
+
+    pub(crate) fn targets_request(config: &Config) -> Result<Request> { Self::metadata_request(config, "targets") }
+
+    pub(crate) fn metadata_request(config: &Config, role: &str) -> Result<Request> {
+        Request::get(format!("{}api/v1/user_repo/{}.json", config.reposerver, role))
+    }
+}
+
+/// A temporary download, removed with any partial file when dropped or on Ctrl-C.
+struct TempDownload {
+    path: PathBuf,
+    _partial: Vec<PartialFile>,
+}
+
+impl TempDownload {
+    fn new(path: PathBuf) -> Self {
+        let part = Reposerver::part_path(&path);
+        let _partial = [&path, &Reposerver::validator_path(&part), &part].iter().map(|path| PartialFile::track(path)).collect();
+        TempDownload { path, _partial }
+    }
+}
+
+impl Drop for TempDownload {
+    fn drop(&mut self) {
+        let part = Reposerver::part_path(&self.path);
+        for path in &[self.path.clone(), Reposerver::validator_path(&part), part] {
+            if path.exists() {
+                if let Err(err) = fs::remove_file(path) {
+                    warn!("couldn't remove {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
+}
+
+/// A reader writing everything read through it to a sink, if any.
+struct TeeReader<'a, R> {
+    inner: R,
+    copy: Option<&'a mut dyn Write>,
+}
+
+impl<R: Read> Read for TeeReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(copy) = self.copy.as_mut() {
+            copy.write_all(&buf[..read])?;
+        }
+        Ok(read)
+    }
+}
+
+impl Reposerver {
+    /// List the packages of `targets.json`, writing the served body to `raw` as it is read.
+    pub fn list_packages_to(config: &mut Config, raw: Option<&mut dyn Write>) -> Result<TableResult> {
+        let res = Http::execute(Self::targets_request(config)?, config.token()?)?.error_for_status()?;
+        let h = res.headers().to_owned();
+        Self::package_table(h, res, raw)
+    }
+
+    /// Parse a `targets.json` response body into a table of packages.
+    ///
+    /// The body is deserialized as it is read, so signatures and other unused
+    /// metadata are never held in memory. The served bytes are only copied to
+    /// `raw`, for raw output or `--save-response`.
+    fn package_table(headers: HeaderMap, body: impl Read, raw: Option<&mut dyn Write>) -> Result<TableResult> {
+        let mut de = serde_json::Deserializer::from_reader(BufReader::new(TeeReader { inner: body, copy: raw }));
+        let v = TargetRole::deserialize(&mut de)?;
+        de.end()?;
+        let mut result = TableResult::new(headers, Vec::new(), &[
+            "target",
             "name",
             "version",
             "hardware ids",
             "uri",
             "target_format",
+            "length",
+            "sha256",
             "updated at",
         ]);
 
         for (k, v) in v.signed.targets {
-            let hwids = format!("{}", v.custom.hardware_ids.join(", "));
-            let uri = v.custom.uri.map(|u| u.to_string()).unwrap_or("None".to_owned());
-            let target_format = format!("{:?}", v.custom.target_format);
-            table.add_row(vec![
-                k,
-                v.custom.name,
-                v.custom.version,
-                hwids,
-                uri,
-                target_format,
-                v.custom.updated_at,
+            result.add_row(vec![
+                k.into(),
+                v.custom.name.into(),
+                v.custom.version.into(),
+                v.custom.hardware_ids.into(),
+                v.custom.uri.map(|u| u.to_string()).into(),
+                format!("{:?}", v.custom.target_format).into(),
+                v.length.into(),
+                v.hashes.get("sha256").cloned().into(),
+                v.custom.updated_at.into(),
             ]);
         }
 
-        Ok(TableResult::new(h, str_resp, table).into())
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result)
     }
-}
 
-impl Reposerver {
-    /// Upload multiple packages (without batching), returning the final response.
-    pub fn add_packages(config: &mut Config, packages: TufPackages) -> Result<CommandResult> {
-        let mut responses = packages
+    /// Delete a target once confirmed.
+    pub fn delete_from_args(config: &mut Config, cmd: &PackageDeleteArgs) -> Result<CommandResult> {
+        confirm(cmd.yes, &format!("Delete target {}?", cmd.target))?;
+        Self::delete_package(config, &cmd.target)
+    }
+
+    /// Upload a single package, showing the upload progress on a terminal.
+    pub fn add_package_from_args(config: &mut Config, cmd: &PackageAddArgs, progress: ProgressMode) -> Result<CommandResult> {
+        let package = TufPackage::from_args(cmd)?;
+        let label = format!("{}-{}", package.name, package.version);
+        let expected = package.expected();
+        let resp = progress::single("uploading", &label, progress, || Self::put_package(config, package))?;
+        if let (true, Some(expected)) = (resp.status().is_success(), expected) {
+            Self::verify_uploads(config, vec![(label.clone(), expected)])?.remove(&label).unwrap_or(Ok(()))?;
+        }
+        Ok(resp.into())
+    }
+
+    /// Check that `targets.json` lists each uploaded entry with the expected hash and length.
+    ///
+    /// Nothing is checked with `--dry-run`, as nothing was uploaded.
+    fn verify_uploads(config: &mut Config, expected: Vec<(String, (String, Option<u64>))>) -> Result<HashMap<String, Result<()>>> {
+        if expected.is_empty() || Http::dry_run() {
+            return Ok(HashMap::new());
+        }
+        let targets = Self::get_targets(config)?.targets;
+        Ok(expected
+            .into_iter()
+            .map(|(entry, (sha256, length))| {
+                let checked = match targets.get(&entry) {
+                    Some(target) => target.check(&sha256, length),
+                    None => Err(Error::Verify(format!("targets.json has no target {}", entry))),
+                };
+                (entry, checked)
+            })
+            .collect())
+    }
+
+    /// Upload a single package, returning the raw response.
+    fn put_package(config: &mut Config, mut package: TufPackage) -> Result<Response> {
+        package.hash_file()?;
+        let entry = format!("{}-{}", package.name, package.version);
+        debug!("adding package with entry name {}", entry);
+        let mut query = vec![
+            ("name", urlencoding::encode(&package.name)),
+            ("version", urlencoding::encode(&package.version)),
+            ("hardwareIds", package.hardware.join(",")),
+            ("targetFormat", format!("{}", package.format)),
+        ];
+        if let Some(sha256) = package.sha256 {
+            query.push(("checksum", sha256));
+        }
+        if let Some(length) = package.length {
+            query.push(("length", length.to_string()));
+        }
+        if let (RepoTarget::Path(path), Some(chunk_size)) = (&package.target, package.chunk_size) {
+            return upload::upload_chunked(config, &entry, Path::new(path), chunk_size, &query);
+        }
+        let req = Http::client()
+            .put(&format!("{}api/v1/user_repo/targets/{}", config.reposerver, entry))
+            .query(&query)
+            .multipart(match package.target {
+                RepoTarget::Path(path) => Form::new().part("file", Self::file_part(Path::new(&path))?),
+                RepoTarget::Url(url) => Form::new().text("fileUri", url.to_string()),
+            });
+        Http::send(req, config.token()?)
+    }
+
+    /// Stream a file as a multipart part of known length, without reading it into memory.
+    fn file_part(path: &Path) -> Result<Part> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let part = Part::reader_with_length(ProgressReader::new(file, Some(length)), length).mime_str("application/octet-stream")?;
+        Ok(match path.file_name() {
+            Some(name) => part.file_name(name.to_string_lossy().into_owned()),
+            None => part,
+        })
+    }
+
+    /// Upload multiple packages, returning a summary of each upload.
+    ///
+    /// The command fails when any upload failed, or isn't listed in `targets.json` as uploaded.
+    pub fn add_packages(config: &mut Config, packages: TufPackages, opts: BatchOptions) -> Result<CommandResult> {
+        let _ = config.token()?;
+        let packages = packages
             .packages
             .into_iter()
-            .map(|package| Self::add_package(config, package))
-            .collect::<Result<Vec<_>>>()?;
-        let last = responses.len() - 1;
-        Ok(responses.remove(last))
+            .map(|package| (format!("{}-{}", package.name, package.version), package))
+            .collect();
+        let uploads = batch::run(config, "uploading", packages, opts, |config, mut package| {
+            package.hash_file()?;
+            let expected = package.expected();
+            Ok((Self::put_package(config, package)?.error_for_status()?, expected))
+        })?;
+        let expected = uploads.iter().filter_map(|(entry, (_, expected))| Some((entry.clone(), expected.clone()?))).collect();
+        let mut checked = Self::verify_uploads(config, expected)?.into_iter().collect::<Vec<_>>();
+        checked.sort_by(|a, b| a.0.cmp(&b.0));
+        batch::collect("verifying", checked, opts)?;
+
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["target", "status"]);
+        for (entry, (resp, _)) in uploads {
+            result.add_row(vec![entry.into(), resp.status().as_u16().into()]);
+        }
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// Return the latest OSTree target of each ref, keyed by the target name.
+    ///
+    /// The `sha256` hash of an OSTree target is the commit it points to.
+    pub(crate) fn ostree_targets(config: &mut Config) -> Result<BTreeMap<String, OstreeTarget>> {
+        let mut latest = BTreeMap::<String, (String, OstreeTarget)>::new();
+        for target in Self::get_targets(config)?.targets.into_values() {
+            if target.custom.target_format != TargetFormat::Ostree {
+                continue;
+            }
+            let ostree = OstreeTarget {
+                version: target.custom.version,
+                commit: target.hashes.get("sha256").cloned(),
+            };
+            let updated = target.custom.updated_at;
+            match latest.get(&target.custom.name) {
+                Some((at, current)) if (at, &current.version) >= (&updated, &ostree.version) => (),
+                _ => {
+                    latest.insert(target.custom.name, (updated, ostree));
+                }
+            }
+        }
+        Ok(latest.into_iter().map(|(name, (_, target))| (name, target)).collect())
+    }
+
+    /// Fetch and parse the `targets.json` metadata.
+    fn get_targets(config: &mut Config) -> Result<Targets> {
+        let resp = Http::execute(Self::targets_request(config)?, config.token()?)?;
+        Ok(resp.error_for_status()?.json::<TargetRole>()?.signed)
+    }
+
+    /// Download a single target into a file, via a temporary `.part` file.
+    ///
+    /// An existing `.part` file from an interrupted download is resumed with a
+    /// range request when the server accepts ranges and the file is unchanged,
+    /// and restarted otherwise. With an `expected` target, the file is verified
+    /// before being renamed.
+    fn download_target(config: &mut Config, filename: &str, out: &Path, expected: Option<&Target>) -> Result<u64> {
+        let url = format!("{}api/v1/user_repo/targets/{}", config.reposerver, urlencoding::encode(filename));
+        Self::download(&url, config.token()?, out, expected)
+    }
+
+    /// Download a URL into a file as with `download_target`, sending any `token`.
+    ///
+    /// The ETag or Last-Modified date of a fresh download is kept next to the `.part` file and
+    /// sent as `If-Range` on resume, so the server restarts the download when the file changed.
+    /// Both are left in place on Ctrl-C, so the next run resumes the download.
+    fn download(url: &str, token: Option<AccessToken>, out: &Path, expected: Option<&Target>) -> Result<u64> {
+        let part = Self::part_path(out);
+        let validator = Self::validator_path(&part);
+        let offset = fs::metadata(&part).map(|meta| meta.len()).unwrap_or(0);
+        let resume = match fs::read_to_string(&validator) {
+            Ok(value) if offset > 0 && Self::accepts_ranges(url, token.clone())? => Some(value),
+            _ => None,
+        };
+
+        let mut req = Http::client().get(url);
+        if let Some(ref value) = resume {
+            debug!("resuming download of {} from byte {}", url, offset);
+            req = req.header(RANGE, format!("bytes={}-", offset)).header(IF_RANGE, value.as_str());
+        }
+        let resp = Http::send(req, token.clone())?;
+        if resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            debug!("discarding unusable partial download of {}", url);
+            Self::remove_part(&part)?;
+            return Self::download(url, token, out, expected);
+        }
+        let mut resp = resp.error_for_status()?;
+        let (mut file, length) = if resp.status() == StatusCode::PARTIAL_CONTENT {
+            (OpenOptions::new().append(true).open(&part)?, offset)
+        } else {
+            let headers = resp.headers();
+            let value = headers.get(ETAG).filter(|etag| !etag.as_bytes().starts_with(b"W/")).or_else(|| headers.get(LAST_MODIFIED));
+            match value.and_then(|value| value.to_str().ok()) {
+                Some(value) => fs::write(&validator, value)?,
+                None if validator.exists() => fs::remove_file(&validator)?,
+                None => (),
+            }
+            (File::create(&part)?, 0)
+        };
+        let total = resp.content_length().map(|remaining| length + remaining);
+        let length = length + resp.copy_to(&mut ProgressWriter::new(&mut file, length, total))?;
+        if let Some(target) = expected {
+            if let Err(err) = target.verify(&part) {
+                Self::remove_part(&part)?;
+                return Err(err);
+            }
+        }
+        fs::rename(&part, out)?;
+        if validator.exists() {
+            fs::remove_file(&validator)?;
+        }
+        Ok(length)
+    }
+
+    /// The temporary file a download into `out` is written to, e.g. `foo-1.0.part` for `foo-1.0`.
+    fn part_path(out: &Path) -> PathBuf {
+        let mut name = out.file_name().map(OsString::from).unwrap_or_default();
+        name.push(".part");
+        out.with_file_name(name)
+    }
+
+    /// The file keeping the `If-Range` validator of a `.part` file.
+    fn validator_path(part: &Path) -> PathBuf {
+        let mut name = part.file_name().map(OsString::from).unwrap_or_default();
+        name.push(".etag");
+        part.with_file_name(name)
+    }
+
+    /// Remove a `.part` file and its validator.
+    fn remove_part(part: &Path) -> Result<()> {
+        for path in &[part.to_path_buf(), Self::validator_path(part)] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the server advertises `Accept-Ranges: bytes` for a URL.
+    fn accepts_ranges(url: &str, token: Option<AccessToken>) -> Result<bool> {
+        let resp = Http::send(Http::client().head(url), token)?;
+        let ranges = resp.headers().get(ACCEPT_RANGES).and_then(|value| value.to_str().ok());
+        Ok(resp.status().is_success() && ranges == Some("bytes"))
+    }
+
+    /// Download the contents of a package into `out`, or into a file named after the target.
+    ///
+    /// Targets with a URI are fetched from it without credentials, and others from the reposerver.
+    /// With `verify`, the download is checked against the target length and hashes.
+    pub fn download_package(config: &mut Config, name: &str, version: &str, out: Option<&Path>, verify: bool, mode: ProgressMode) -> Result<CommandResult> {
+        let (filename, target) = Self::find_target(config, name, version)?;
+        let path = out.map(PathBuf::from).unwrap_or_else(|| PathBuf::from(filename.replace('/', "_")));
+        let expected = if verify { Some(&target) } else { None };
+        let length = progress::single("downloading", &filename, mode, || match target.custom.uri {
+            Some(ref uri) => Self::download(uri.as_str(), None, &path, expected),
+            None => Self::download_target(config, &filename, &path, expected),
+        })?;
+
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["target", "file", "bytes"]);
+        result.add_row(vec![filename.into(), path.display().to_string().into(), length.into()]);
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// Copy a package from this repository into the repository of the `to` config, e.g. from staging to production.
+    ///
+    /// A target with a URI is added to `to` pointing at the same URI, while any other target is
+    /// downloaded from this reposerver, verified and uploaded. A package `to` already lists with the
+    /// same hash and length is left alone.
+    pub fn promote_package(config: &mut Config, to: &mut Config, name: &str, version: &str, mode: ProgressMode) -> Result<CommandResult> {
+        let (filename, target) = Self::find_target(config, name, version)?;
+        let entry = format!("{}-{}", name, version);
+        let sha256 = target.hashes.get("sha256").cloned();
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["target", "source", "status"]);
+        if let Some(ref sha256) = sha256 {
+            if Self::get_targets(to)?.targets.get(&entry).is_some_and(|existing| existing.check(sha256, Some(target.length)).is_ok()) {
+                info!("{} is already in the target repository", entry);
+                result.add_row(vec![entry.into(), Value::Null, "unchanged".into()]);
+                result.response = serde_json::to_vec(&result)?;
+                return Ok(result.into());
+            }
+        }
+
+        let download = TempDownload::new(env::temp_dir().join(format!("ota-cli-promote-{}-{}", process::id(), filename.replace('/', "_"))));
+        let (source, repo_target) = match target.custom.uri {
+            Some(ref uri) => ("uri", RepoTarget::Url(uri.clone())),
+            None => {
+                progress::single("downloading", &filename, mode, || Self::download_target(config, &filename, &download.path, Some(&target)))?;
+                ("reposerver", RepoTarget::Path(download.path.display().to_string()))
+            }
+        };
+        let package = TufPackage {
+            name: name.into(),
+            version: version.into(),
+            format: target.custom.target_format,
+            hardware: target.custom.hardware_ids.clone(),
+            target: repo_target,
+            sha256,
+            length: Some(target.length).filter(|length| *length > 0),
+            chunk_size: None,
+        };
+        let expected = package.expected();
+        let resp = progress::single("uploading", &entry, mode, || -> Result<Response> {
+            Ok(Self::put_package(to, package)?.error_for_status()?)
+        })?;
+        drop(download);
+        if let Some(expected) = expected {
+            Self::verify_uploads(to, vec![(entry.clone(), expected)])?.remove(&entry).unwrap_or(Ok(()))?;
+        }
+        info!("promoted {} to profile {}", entry, to.profile.as_deref().unwrap_or("default"));
+        result.add_row(vec![entry.into(), source.into(), resp.status().as_u16().into()]);
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// Find the target of a package version, and its filename, in `targets.json`.
+    fn find_target(config: &mut Config, name: &str, version: &str) -> Result<(String, Target)> {
+        Self::get_targets(config)?
+            .targets
+            .into_iter()
+            .find(|(_, target)| target.custom.name == name && target.custom.version == version)
+            .ok_or_else(|| Error::NotFound(format!("Package `{}` version `{}`", name, version), None))
+    }
+
+    /// Save the signed targets metadata into `out_dir`, along with the other roles when `all` is set.
+    ///
+    /// Files are saved byte for byte as served, so signatures can still be checked, unless `pretty` is set.
+    pub fn export_metadata(config: &mut Config, out_dir: &Path, all: bool, pretty: bool) -> Result<CommandResult> {
+        let roles = if all { &["root", "targets", "snapshot", "timestamp"][..] } else { &["targets"][..] };
+        fs::create_dir_all(out_dir)?;
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["role", "file", "bytes"]);
+        for role in roles {
+            let mut resp = Http::execute(Self::metadata_request(config, role)?, config.token()?)?.error_for_status()?;
+            let mut body = Vec::new();
+            resp.read_to_end(&mut body)?;
+            if pretty {
+                body = serde_json::to_vec_pretty(&serde_json::from_slice::<Value>(&body)?)?;
+                body.push(b'\n');
+            }
+            let file = out_dir.join(format!("{}.json", role));
+            fs::write(&file, &body)?;
+            result.add_row(vec![role.to_string().into(), file.display().to_string().into(), body.len().into()]);
+        }
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// Download every matching target into `out_dir`, skipping targets only available by URI.
+    ///
+    /// With `verify`, each download is checked against the target length and hashes. The command
+    /// fails when any download failed.
+    pub fn fetch_all(config: &mut Config, out_dir: &Path, filter: &TargetFilter, verify: bool, opts: BatchOptions) -> Result<CommandResult> {
+        let _running = Scope::batch();
+        Self::fetch_all_until(config, out_dir, filter, verify, opts, interrupt::flag())
+    }
+
+    /// Download matching targets as with `fetch_all`, starting no new downloads once `stop` is set.
+    fn fetch_all_until(
+        config: &mut Config,
+        out_dir: &Path,
+        filter: &TargetFilter,
+        verify: bool,
+        opts: BatchOptions,
+        stop: &AtomicBool,
+    ) -> Result<CommandResult> {
+        fs::create_dir_all(out_dir)?;
+        let targets = Self::get_targets(config)?
+            .targets
+            .into_iter()
+            .filter(|(_, target)| filter.matches(&target.custom.name, &target.custom.hardware_ids))
+            .map(|(filename, target)| (filename.clone(), (filename, target)))
+            .collect();
+
+        let downloads = batch::run_until(config, "downloading", targets, opts, stop, |config, (filename, target): (String, Target)| {
+            match target.custom.uri {
+                Some(ref uri) => Ok(Download::Skipped(uri.clone())),
+                None => {
+                    let path = out_dir.join(filename.replace('/', "_"));
+                    let length = Self::download_target(config, &filename, &path, if verify { Some(&target) } else { None })?;
+                    Ok(Download::Saved(path, length))
+                }
+            }
+        })?;
+
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["target", "status", "detail"]);
+        for (filename, download) in downloads {
+            match download {
+                Download::Saved(path, length) => result.add_row(vec![
+                    filename.into(),
+                    "downloaded".into(),
+                    format!("{} ({} bytes)", path.display(), length).into(),
+                ]),
+                Download::Skipped(uri) => result.add_row(vec![filename.into(), "skipped".into(), uri.to_string().into()]),
+            }
+        }
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// Compare declared packages against the live targets, reporting any drift.
+    ///
+    /// Packages with a `path` and no declared `sha256` are hashed to compare their contents.
+    pub fn diff_packages(config: &mut Config, mut packages: TufPackages, filter: &TargetFilter) -> Result<CommandResult> {
+        for package in packages.packages.iter_mut().filter(|package| filter.matches(&package.name, &package.hardware)) {
+            package.hash_file()?;
+        }
+        let targets = Self::get_targets(config)?;
+        let mut result = Self::diff_table(packages, targets, filter);
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// Classify each target as `missing` from the repo, `changed` or `extra` to the declared packages.
+    fn diff_table(packages: TufPackages, targets: Targets, filter: &TargetFilter) -> TableResult {
+        let mut live = targets
+            .targets
+            .into_iter()
+            .filter(|(_, target)| filter.matches(&target.custom.name, &target.custom.hardware_ids))
+            .collect::<HashMap<_, _>>();
+
+        let mut rows = Vec::new();
+        for package in packages.packages.into_iter().filter(|package| filter.matches(&package.name, &package.hardware)) {
+            let entry = format!("{}-{}", package.name, package.version);
+            match live.remove(&entry) {
+                None => rows.push((entry, "missing", String::new())),
+                Some(target) => {
+                    let changes = package.changes(&target);
+                    if !changes.is_empty() {
+                        rows.push((entry, "changed", changes.join("; ")));
+                    }
+                }
+            }
+        }
+        rows.extend(live.into_keys().map(|entry| (entry, "extra", String::new())));
+        rows.sort();
+
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["target", "status", "detail"]);
+        for (entry, status, detail) in rows {
+            result.add_row(vec![entry.into(), status.into(), detail.into()]);
+        }
+        result
+    }
+}
+
+/// The outcome of downloading a single target.
+enum Download {
+    Saved(PathBuf, u64),
+    Skipped(Url),
+}
+
+/// Filters for selecting targets by package name or hardware id.
+#[derive(Default, Debug)]
+pub struct TargetFilter {
+    pub name: Option<String>,
+    pub hardware: Option<String>,
+}
+
+impl TargetFilter {
+    /// Filter by the `--name` and `--hardware` CLI arguments.
+    pub fn new(name: Option<&str>, hardware: Option<&str>) -> Self {
+        TargetFilter {
+            name: name.map(String::from),
+            hardware: hardware.map(String::from),
+        }
+    }
+
+    fn matches(&self, name: &str, hardware_ids: &[String]) -> bool {
+        let name = self.name.as_ref().map(|filter| filter == name).unwrap_or(true);
+        let hardware = self.hardware.as_ref().map(|hw| hardware_ids.contains(hw)).unwrap_or(true);
+        name && hardware
     }
 }
 
@@ -134,6 +705,7 @@ pub struct PackageMetadata {
     hardware: Vec<String>,
     path: Option<String>,
     url: Option<String>,
+    sha256: Option<String>,
 }
 
 /// A parsed mapping from package names to versions to metadata.
@@ -159,18 +731,98 @@ pub struct TufPackage {
     format: TargetFormat,
     hardware: Vec<String>,
     target: RepoTarget,
+    sha256: Option<String>,
+    length: Option<u64>,
+    chunk_size: Option<u64>,
 }
 
-impl<'a> TufPackage {
+impl TufPackage {
     /// Parse CLI arguments into a `TufPackage`.
-    pub fn from_args(args: &ArgMatches<'a>) -> Result<Self> {
-        Ok(TufPackage {
-            name: args.value_of("name").expect("--name").into(),
-            version: args.value_of("version").expect("--version").into(),
-            format: TargetFormat::from_args(&args)?,
-            hardware: args.values_of("hardware").expect("--hardware").map(String::from).collect(),
-            target: RepoTarget::from_args(&args)?,
-        })
+    ///
+    /// Unless `--sha256` is given, the SHA256 digest and length are read from the `--path` file.
+    pub fn from_args(cmd: &PackageAddArgs) -> Result<Self> {
+        let target = RepoTarget::from_args(cmd)?;
+        let chunk_size = match cmd.chunk_size {
+            Some(mib) if mib << 20 >= MIN_CHUNK_SIZE => Some(mib << 20),
+            Some(mib) => return Err(Error::Args(format!("--chunk-size must be a number of MiB, at least {}, got {}", MIN_CHUNK_SIZE >> 20, mib))),
+            None => None,
+        };
+        if cmd.hardware.is_empty() {
+            return Err(missing("hardware"));
+        }
+        TufPackage {
+            name: cmd.name.clone(),
+            version: cmd.version.clone(),
+            format: TargetFormat::from_flags(cmd.binary, cmd.ostree)?,
+            hardware: cmd.hardware.clone(),
+            target,
+            sha256: cmd.sha256.clone(),
+            length: cmd.length,
+            chunk_size,
+        }
+        .hashed()
+    }
+}
+
+impl TufPackage {
+    fn hashed(mut self) -> Result<Self> {
+        self.hash_file()?;
+        Ok(self)
+    }
+
+    /// Compute the SHA256 digest and length of a `--path` target without a known digest.
+    ///
+    /// A declared length must match the file.
+    fn hash_file(&mut self) -> Result<()> {
+        let path = match (&self.target, &self.sha256) {
+            (RepoTarget::Path(path), None) => path,
+            _ => return Ok(()),
+        };
+        let (sha256, length) = sha256_file(Path::new(path))?;
+        if let Some(declared) = self.length.filter(|declared| *declared != length) {
+            return Err(Error::Args(format!("--length {} does not match the {} bytes of {}", declared, length, path)));
+        }
+        debug!("computed sha256 {} of {}", sha256, path);
+        self.sha256 = Some(sha256);
+        self.length = Some(length);
+        Ok(())
+    }
+
+    /// The SHA256 digest and any length the reposerver should list for this target.
+    fn expected(&self) -> Option<(String, Option<u64>)> { self.sha256.clone().map(|sha256| (sha256, self.length)) }
+
+    /// Describe how the live target metadata differs from this package.
+    ///
+    /// Hashes and lengths are only compared when both sides have one.
+    fn changes(&self, target: &Target) -> Vec<String> {
+        let custom = &target.custom;
+        let mut changes = Vec::new();
+        if self.format != custom.target_format {
+            changes.push(format!("format {} (live {})", self.format, custom.target_format));
+        }
+        let (mut declared, mut live) = (self.hardware.clone(), custom.hardware_ids.clone());
+        declared.sort();
+        live.sort();
+        if declared != live {
+            changes.push(format!("hardware ids {} (live {})", declared.join(", "), live.join(", ")));
+        }
+        let uri = match self.target {
+            RepoTarget::Url(ref url) => Some(url),
+            RepoTarget::Path(_) => None,
+        };
+        if uri != custom.uri.as_ref() {
+            let show = |uri: Option<&Url>| uri.map(|uri| uri.to_string()).unwrap_or_else(|| "none".into());
+            changes.push(format!("uri {} (live {})", show(uri), show(custom.uri.as_ref())));
+        }
+        if let (Some(declared), Some(live)) = (&self.sha256, target.hashes.get("sha256")) {
+            if !declared.eq_ignore_ascii_case(live) {
+                changes.push(format!("sha256 {} (live {})", declared, live));
+            }
+        }
+        if let Some(length) = self.length.filter(|length| target.length > 0 && *length != target.length) {
+            changes.push(format!("length {} (live {})", length, target.length));
+        }
+        changes
     }
 }
 
@@ -215,6 +867,9 @@ impl TufPackages {
                 (None, None)       => Err(Error::Parse("One of `path` or `url` required.".into()))?,
                 (Some(_), Some(_)) => Err(Error::Parse("Either `path` or `url` expected. Not both.".into()))?,
             },
+            sha256: meta.sha256,
+            length: None,
+            chunk_size: None,
         })
     }
 }
@@ -226,12 +881,12 @@ pub enum RepoTarget {
     Url(Url),
 }
 
-impl<'a> RepoTarget {
-    pub fn from_args(args: &ArgMatches<'a>) -> Result<Self> {
-        if let Some(path) = args.value_of("path") {
-            Ok(RepoTarget::Path(path.into()))
-        } else if let Some(url) = args.value_of("url") {
-            Ok(RepoTarget::Url(url.parse()?))
+impl RepoTarget {
+    pub fn from_args(cmd: &PackageAddArgs) -> Result<Self> {
+        if let Some(ref path) = cmd.path {
+            Ok(RepoTarget::Path(path.to_string_lossy().into_owned()))
+        } else if let Some(ref url) = cmd.url {
+            Ok(RepoTarget::Url(url.clone()))
         } else {
             Err(Error::Args("Either --path or --url flag is required".into()))
         }
@@ -241,6 +896,20 @@ impl<'a> RepoTarget {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock::{MockRequest, MockResponse, MockServer};
+    use serde_json::{Map, Value};
+    use structopt::StructOpt;
+
+    #[test]
+    fn delete_package_by_target() {
+        let server = MockServer::start(|_| MockResponse::status(204));
+        let cmd = PackageDeleteArgs::from_iter(&["delete", "--target", "foo 1.0", "--yes"]);
+        Reposerver::delete_from_args(&mut server.config(), &cmd).expect("delete");
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "DELETE");
+        assert_eq!(requests[0].route(), "/api/v1/user_repo/targets/foo%201.0");
+    }
 
     #[test]
     fn parse_example_packages() {
@@ -264,4 +933,624 @@ mod tests {
         assert_eq!(packages[1].target, RepoTarget::Path("/ota/my-branch-01234".into()));
         assert_eq!(packages[1].format, TargetFormat::Ostree);
     }
+
+    fn test_packages(names: &[&str]) -> TufPackages {
+        let path = std::env::temp_dir().join(format!("ota-cli-package-{}", std::process::id()));
+        fs::write(&path, b"contents").expect("write package");
+        TufPackages {
+            packages: names
+                .iter()
+                .map(|name| TufPackage {
+                    name: name.to_string(),
+                    version: "1".into(),
+                    format: TargetFormat::Binary,
+                    hardware: vec!["ecu".into()],
+                    target: RepoTarget::Path(path.to_string_lossy().into()),
+                    sha256: None,
+                    length: None,
+                    chunk_size: None,
+                })
+                .collect(),
+        }
+    }
+
+    const CONTENTS_SHA256: &str = "d1b2a59fbea7e20077af9f91b27e95e865061b270be03ff539ab3b73587882e8";
+
+    fn failing_reposerver() -> MockServer {
+        MockServer::start(|req| match req.route() {
+            "/api/v1/user_repo/targets/bad-1" => MockResponse::status(500),
+            "/api/v1/user_repo/targets.json" => {
+                let target = |sha256: &str| json!({"length": 8, "hashes": {"sha256": sha256}, "custom": custom("foo", "ecu", None)});
+                let mut targets = Map::new();
+                for name in &["good", "other", "extra"] {
+                    targets.insert(format!("{}-1", name), target(CONTENTS_SHA256));
+                }
+                targets.insert("mismatch-1".into(), target(&"0".repeat(64)));
+                MockResponse::json(200, json!({"signed": {"targets": targets}}))
+            }
+            _ => MockResponse::status(204),
+        })
+    }
+
+    fn uploads(server: &MockServer) -> usize { server.requests().iter().filter(|req| req.method == "PUT").count() }
+
+    #[test]
+    fn upload_continues_after_failure() {
+        let server = failing_reposerver();
+        let packages = test_packages(&["bad", "good", "other"]);
+        let err = Reposerver::add_packages(&mut server.config(), packages, BatchOptions::default()).err().expect("failed upload");
+        assert_eq!(err.to_string(), "Failed: uploading failed for 1 of 3 items");
+        assert_eq!(err.exit_code(), crate::error::EXIT_FAILURE);
+        assert_eq!(uploads(&server), 3);
+
+        let result = match Reposerver::add_packages(&mut server.config(), test_packages(&["good", "other"]), BatchOptions::default()).expect("upload") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0]["target"], "good-1");
+        assert_eq!(result.rows[0]["status"], 204);
+    }
+
+    #[test]
+    fn upload_fail_fast_stops_after_failure() {
+        let server = failing_reposerver();
+        let packages = test_packages(&["bad", "good", "other"]);
+        let opts = BatchOptions {
+            concurrency: 1,
+            fail_fast: true,
+            ..BatchOptions::default()
+        };
+        assert!(Reposerver::add_packages(&mut server.config(), packages, opts).is_err());
+        assert_eq!(uploads(&server), 1);
+    }
+
+    #[test]
+    fn concurrent_uploads_report_every_package() {
+        let server = failing_reposerver();
+        let packages = test_packages(&["other", "bad", "good", "extra"]);
+        let opts = BatchOptions {
+            concurrency: 3,
+            ..BatchOptions::default()
+        };
+        let err = Reposerver::add_packages(&mut server.config(), packages, opts).err().expect("failed upload");
+        assert_eq!(err.to_string(), "Failed: uploading failed for 1 of 4 items");
+        assert_eq!(uploads(&server), 4);
+    }
+
+    #[test]
+    fn uploads_are_checked_against_targets() {
+        let server = failing_reposerver();
+        let packages = test_packages(&["good", "mismatch", "missing"]);
+        let err = Reposerver::add_packages(&mut server.config(), packages, BatchOptions::default()).err().expect("failed check");
+        assert!(server.requests().iter().filter(|req| req.method == "PUT").all(|req| req.path.contains(CONTENTS_SHA256) && req.path.contains("length=8")));
+        assert_eq!(err.to_string(), "Failed: verifying failed for 2 of 3 items");
+
+        let expected = vec![("mismatch-1".to_string(), (CONTENTS_SHA256.to_string(), Some(8))), ("missing-1".to_string(), (CONTENTS_SHA256.to_string(), Some(8)))];
+        let mut checked = Reposerver::verify_uploads(&mut server.config(), expected).expect("verify");
+        assert!(checked.remove("mismatch-1").unwrap().unwrap_err().to_string().contains("sha256"));
+        assert!(checked.remove("missing-1").unwrap().unwrap_err().to_string().contains("no target missing-1"));
+    }
+
+    fn custom(name: &str, hardware: &str, uri: Option<&str>) -> Value {
+        json!({
+            "name": name,
+            "version": "1",
+            "hardwareIds": [hardware],
+            "uri": uri,
+            "updatedAt": "2020-01-01T00:00:00Z",
+            "targetFormat": "BINARY"
+        })
+    }
+
+    #[test]
+    fn fetch_all_targets() {
+        let targets = json!({"signed": {"targets": {
+            "foo-1": {"custom": custom("foo", "ecu-a", None)},
+            "bar-1": {"custom": custom("bar", "ecu-a", Some("https://acme.org/bar"))},
+            "baz-1": {"custom": custom("baz", "ecu-b", None)}
+        }}});
+        let server = MockServer::start(move |req| match req.route() {
+            "/api/v1/user_repo/targets.json" => MockResponse::json(200, targets.clone()),
+            "/api/v1/user_repo/targets/foo-1" => MockResponse {
+                status: 200,
+                headers: Vec::new(),
+                body: b"foo contents".to_vec(),
+            },
+            _ => MockResponse::status(404),
+        });
+
+        let out_dir = std::env::temp_dir().join(format!("ota-cli-fetch-all-{}", std::process::id()));
+        let filter = TargetFilter {
+            name: None,
+            hardware: Some("ecu-a".into()),
+        };
+        let result = match Reposerver::fetch_all(&mut server.config(), &out_dir, &filter, false, BatchOptions::default()).expect("fetch") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0]["target"], "bar-1");
+        assert_eq!(result.rows[0]["status"], "skipped");
+        assert_eq!(result.rows[1]["target"], "foo-1");
+        assert_eq!(result.rows[1]["status"], "downloaded");
+        assert_eq!(fs::read(out_dir.join("foo-1")).expect("downloaded file"), b"foo contents");
+        assert!(!out_dir.join("foo-1.part").exists());
+        assert!(!out_dir.join("baz-1").exists());
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn download_package_from_reposerver_or_uri() {
+        let server = MockServer::start(|req| match req.route() {
+            "/api/v1/user_repo/targets.json" => {
+                let uri = format!("http://{}/blobs/bar", req.headers["host"]);
+                MockResponse::json(200, json!({"signed": {"targets": {
+                    "foo-1": {"custom": custom("foo", "ecu", None), "length": 12, "hashes": {"sha256": "5fa3473d182d26c6f26a6cad032ae12092df6e26e12499b4051997b5b723860d"}},
+                    "bar-1": {"custom": custom("bar", "ecu", Some(&uri)), "length": 3}
+                }}}))
+            }
+            "/api/v1/user_repo/targets/foo-1" => MockResponse {
+                status: 200,
+                headers: Vec::new(),
+                body: b"foo contents".to_vec(),
+            },
+            "/blobs/bar" => MockResponse {
+                status: 200,
+                headers: Vec::new(),
+                body: b"bar".to_vec(),
+            },
+            _ => MockResponse::status(404),
+        });
+        let dir = std::env::temp_dir().join(format!("ota-cli-download-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut config = server.config();
+
+        let out = dir.join("foo.bin");
+        let result = match Reposerver::download_package(&mut config, "foo", "1", Some(&out), true, ProgressMode::Hidden).expect("download") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows[0]["bytes"], 12);
+        assert_eq!(fs::read(&out).unwrap(), b"foo contents");
+
+        let out = dir.join("bar.bin");
+        Reposerver::download_package(&mut config, "bar", "1", Some(&out), true, ProgressMode::Hidden).expect("download by uri");
+        assert_eq!(fs::read(&out).unwrap(), b"bar");
+        let requests = server.requests();
+        assert!(requests.iter().find(|req| req.route() == "/api/v1/user_repo/targets/foo-1").unwrap().headers.contains_key("authorization"));
+        assert!(!requests.iter().find(|req| req.route() == "/blobs/bar").unwrap().headers.contains_key("authorization"));
+
+        match Reposerver::download_package(&mut config, "foo", "2", None, false, ProgressMode::Hidden) {
+            Err(Error::NotFound(..)) => (),
+            other => panic!("expected not found, got {:?}", other.err()),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn export_metadata_raw_or_pretty() {
+        let server = MockServer::start(|req| MockResponse {
+            status: 200,
+            headers: Vec::new(),
+            body: format!(r#"{{"signed":{{"_type":"{}"}},"signatures":[]}}"#, req.route().trim_start_matches("/api/v1/user_repo/")).into_bytes(),
+        });
+        let out_dir = std::env::temp_dir().join(format!("ota-cli-export-{}", std::process::id()));
+
+        let result = match Reposerver::export_metadata(&mut server.config(), &out_dir, false, false).expect("export") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(fs::read_to_string(out_dir.join("targets.json")).unwrap(), r#"{"signed":{"_type":"targets.json"},"signatures":[]}"#);
+
+        Reposerver::export_metadata(&mut server.config(), &out_dir, true, true).expect("export all");
+        let routes = server.requests().iter().map(|req| req.route().to_string()).collect::<Vec<_>>();
+        assert_eq!(routes[1..], ["/api/v1/user_repo/root.json", "/api/v1/user_repo/targets.json", "/api/v1/user_repo/snapshot.json", "/api/v1/user_repo/timestamp.json"]);
+        let timestamp = fs::read_to_string(out_dir.join("timestamp.json")).unwrap();
+        assert!(timestamp.starts_with("{\n  \"signatures\": []"));
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    #[test]
+    fn interrupted_fetch_leaves_no_partial_files() {
+        let targets = json!({"signed": {"targets": {
+            "a-1": {"custom": custom("a", "ecu", None)},
+            "b-1": {"custom": custom("b", "ecu", None)},
+            "c-1": {"custom": custom("c", "ecu", None)}
+        }}});
+        let stop = std::sync::Arc::new(AtomicBool::new(false));
+        let interrupt = stop.clone();
+        let server = MockServer::start(move |req| match req.route() {
+            "/api/v1/user_repo/targets.json" => MockResponse::json(200, targets.clone()),
+            _ => {
+                interrupt.store(true, std::sync::atomic::Ordering::SeqCst);
+                MockResponse {
+                    status: 200,
+                    headers: Vec::new(),
+                    body: b"contents".to_vec(),
+                }
+            }
+        });
+
+        let out_dir = std::env::temp_dir().join(format!("ota-cli-interrupt-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&out_dir);
+        let result =
+            match Reposerver::fetch_all_until(&mut server.config(), &out_dir, &TargetFilter::default(), false, BatchOptions::default(), &stop)
+                .expect("fetch")
+            {
+                CommandResult::Table(result) => result,
+                _ => panic!("expected a table result"),
+            };
+
+        assert_eq!(result.rows.len(), 1);
+        let files = fs::read_dir(&out_dir).unwrap().map(|entry| entry.unwrap().file_name()).collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        assert!(files.iter().all(|name| !name.to_string_lossy().ends_with(".part")));
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+
+    fn ranged_reposerver(accept_ranges: bool) -> MockServer { MockServer::start(move |req| ranged_response(req, accept_ranges)) }
+
+    fn ranged_response(req: &MockRequest, accept_ranges: bool) -> MockResponse {
+        match (req.method.as_str(), req.headers.get("range"), req.headers.get("if-range")) {
+            ("HEAD", ..) if accept_ranges => MockResponse::status(200).header("accept-ranges", "bytes"),
+            ("HEAD", ..) => MockResponse::status(200),
+            ("GET", Some(range), Some(etag)) if range == "bytes=4-" && etag == "\"v1\"" => MockResponse {
+                status: 206,
+                headers: vec![("content-range".into(), "bytes 4-11/12".into())],
+                body: b"contents".to_vec(),
+            },
+            ("GET", Some(range), _) if range == "bytes=12-" => MockResponse::status(416),
+            _ => MockResponse {
+                status: 200,
+                headers: vec![("etag".into(), "\"v1\"".into())],
+                body: b"foo contents".to_vec(),
+            },
+        }
+    }
+
+    #[test]
+    fn promote_package_between_repositories() {
+        const FOO_SHA256: &str = "5fa3473d182d26c6f26a6cad032ae12092df6e26e12499b4051997b5b723860d";
+        let staging = MockServer::start(|req| match req.route() {
+            "/api/v1/user_repo/targets.json" => MockResponse::json(200, json!({"signed": {"targets": {
+                "foo-1": {"custom": custom("foo", "ecu", None), "length": 12, "hashes": {"sha256": FOO_SHA256}},
+                "bar-1": {"custom": custom("bar", "ecu", Some("http://blobs.example.com/bar")), "length": 3, "hashes": {"sha256": "aa"}}
+            }}})),
+            "/api/v1/user_repo/targets/foo-1" => MockResponse {
+                status: 200,
+                headers: Vec::new(),
+                body: b"foo contents".to_vec(),
+            },
+            _ => MockResponse::status(404),
+        });
+        let uploaded = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let listed = uploaded.clone();
+        let prod = MockServer::start(move |req| match req.method.as_ref() {
+            "PUT" => {
+                listed.store(true, std::sync::atomic::Ordering::SeqCst);
+                MockResponse::status(204)
+            }
+            _ if listed.load(std::sync::atomic::Ordering::SeqCst) => MockResponse::json(200, json!({"signed": {"targets": {
+                "foo-1": {"custom": custom("foo", "ecu", None), "length": 12, "hashes": {"sha256": FOO_SHA256}}
+            }}})),
+            _ => MockResponse::json(200, json!({"signed": {"targets": {}}})),
+        });
+        let (mut config, mut to) = (staging.config(), prod.config());
+
+        let result = match Reposerver::promote_package(&mut config, &mut to, "foo", "1", ProgressMode::Hidden).expect("promote") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows[0]["source"], "reposerver");
+        let put = prod.requests().into_iter().find(|req| req.method == "PUT").expect("upload");
+        assert!(put.path.starts_with("/api/v1/user_repo/targets/foo-1?") && put.path.contains(FOO_SHA256) && put.path.contains("hardwareIds=ecu"));
+
+        let result = match Reposerver::promote_package(&mut config, &mut to, "foo", "1", ProgressMode::Hidden).expect("promote again") {
+            CommandResult::Table(result) => result,
+            _ => panic!("expected a table result"),
+        };
+        assert_eq!(result.rows[0]["status"], "unchanged");
+        uploaded.store(false, std::sync::atomic::Ordering::SeqCst);
+        Reposerver::promote_package(&mut config, &mut to, "bar", "1", ProgressMode::Hidden).expect_err("bar is not listed after the upload");
+        let put = prod.requests().into_iter().filter(|req| req.method == "PUT").nth(1).expect("uri upload");
+        assert!(String::from_utf8_lossy(&put.body).contains("http://blobs.example.com/bar"));
+    }
+
+    #[test]
+    fn failed_promote_removes_download() {
+        let staging = MockServer::start(|req| match req.route() {
+            "/api/v1/user_repo/targets.json" => MockResponse::json(200, json!({"signed": {"targets": {
+                "qux-1": {"custom": custom("qux", "ecu", None), "length": 12, "hashes": {"sha256": "5fa3473d182d26c6f26a6cad032ae12092df6e26e12499b4051997b5b723860d"}},
+                "quux-1": {"custom": custom("quux", "ecu", None), "length": 12, "hashes": {"sha256": "00"}}
+            }}})),
+            _ => MockResponse {
+                status: 200,
+                headers: vec![("etag".into(), "\"v1\"".into())],
+                body: b"foo contents".to_vec(),
+            },
+        });
+        let prod = MockServer::start(|req| match req.method.as_ref() {
+            "PUT" => MockResponse::status(500),
+            _ => MockResponse::json(200, json!({"signed": {"targets": {}}})),
+        });
+        let (mut config, mut to) = (staging.config(), prod.config());
+
+        for name in &["qux", "quux"] {
+            assert!(Reposerver::promote_package(&mut config, &mut to, name, "1", ProgressMode::Hidden).is_err());
+            let download = std::env::temp_dir().join(format!("ota-cli-promote-{}-{}-1", std::process::id(), name));
+            let part = Reposerver::part_path(&download);
+            assert!(!download.exists() && !part.exists() && !Reposerver::validator_path(&part).exists());
+        }
+    }
+
+    #[test]
+    fn download_resumes_partial_file() {
+        let server = ranged_reposerver(true);
+        let out = std::env::temp_dir().join(format!("ota-cli-resume-{}-1.0", std::process::id()));
+        let part = Reposerver::part_path(&out);
+        assert_eq!(part.file_name().unwrap(), format!("ota-cli-resume-{}-1.0.part", std::process::id()).as_str());
+        fs::write(&part, b"foo ").unwrap();
+        fs::write(Reposerver::validator_path(&part), "\"v1\"").unwrap();
+
+        let length = Reposerver::download_target(&mut server.config(), "foo-1", &out, None).expect("download");
+        assert_eq!(length, 12);
+        assert_eq!(fs::read(&out).unwrap(), b"foo contents");
+        let resumed = server.requests().last().unwrap().headers.clone();
+        assert_eq!(resumed.get("range").map(String::as_str), Some("bytes=4-"));
+        assert_eq!(resumed.get("if-range").map(String::as_str), Some("\"v1\""));
+        assert!(!Reposerver::validator_path(&part).exists());
+
+        fs::write(&part, b"foo ").unwrap();
+        fs::write(Reposerver::validator_path(&part), "\"v0\"").unwrap();
+        assert_eq!(Reposerver::download_target(&mut server.config(), "foo-1", &out, None).expect("changed"), 12);
+        assert_eq!(fs::read(&out).unwrap(), b"foo contents");
+        fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn interrupted_download_still_resumes() {
+        let out = std::env::temp_dir().join(format!("ota-cli-interrupted-{}-1.0", std::process::id()));
+        let part = Reposerver::part_path(&out);
+        let tracked = std::sync::Arc::new(AtomicBool::new(false));
+        let server = {
+            let (part, tracked) = (part.clone(), tracked.clone());
+            MockServer::start(move |req| {
+                // A Ctrl-C now removes every tracked file, so the part and its validator must not be among them.
+                if interrupt::is_partial(&part) || interrupt::is_partial(&Reposerver::validator_path(&part)) {
+                    tracked.store(true, std::sync::atomic::Ordering::SeqCst);
+                }
+                ranged_response(req, true)
+            })
+        };
+        fs::write(&part, b"foo ").unwrap();
+        fs::write(Reposerver::validator_path(&part), "\"v1\"").unwrap();
+
+        assert_eq!(Reposerver::download_target(&mut server.config(), "foo-1", &out, None).expect("resume"), 12);
+        assert!(!tracked.load(std::sync::atomic::Ordering::SeqCst));
+        assert_eq!(fs::read(&out).unwrap(), b"foo contents");
+        assert_eq!(server.requests().last().unwrap().headers.get("range").map(String::as_str), Some("bytes=4-"));
+
+        let download = TempDownload::new(std::env::temp_dir().join(format!("ota-cli-temp-{}", std::process::id())));
+        assert!(interrupt::is_partial(&download.path) && interrupt::is_partial(&Reposerver::part_path(&download.path)));
+        drop(download);
+        fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn download_restarts_unsatisfiable_range() {
+        let server = ranged_reposerver(true);
+        let out = std::env::temp_dir().join(format!("ota-cli-complete-part-{}", std::process::id()));
+        let part = Reposerver::part_path(&out);
+        fs::write(&part, b"foo contents").unwrap();
+        fs::write(Reposerver::validator_path(&part), "\"v1\"").unwrap();
+
+        assert_eq!(Reposerver::download_target(&mut server.config(), "foo-1", &out, None).expect("download"), 12);
+        assert_eq!(fs::read(&out).unwrap(), b"foo contents");
+        assert_eq!(server.requests().iter().filter(|req| req.headers.get("range").is_some_and(|range| range == "bytes=12-")).count(), 1);
+        assert!(!part.exists() && !Reposerver::validator_path(&part).exists());
+        fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn parse_target_hashes_and_length() {
+        let body = json!({"signed": {"targets": {
+            "foo-1": {
+                "hashes": {"sha256": "5fa3473d182d26c6f26a6cad032ae12092df6e26e12499b4051997b5b723860d"},
+                "length": 12,
+                "custom": custom("foo", "ecu", None)
+            },
+            "bar-1": {"custom": custom("bar", "ecu", None)}
+        }}});
+        let result = Reposerver::package_table(HeaderMap::new(), serde_json::to_vec(&body).unwrap().as_slice(), None).expect("table");
+        let row = |target: &str| result.rows.iter().find(|row| row["target"] == target).expect("row").clone();
+        assert_eq!(row("foo-1")["length"], 12);
+        assert_eq!(row("foo-1")["sha256"], "5fa3473d182d26c6f26a6cad032ae12092df6e26e12499b4051997b5b723860d");
+        assert_eq!(row("bar-1")["length"], 0);
+        assert_eq!(row("bar-1")["sha256"], Value::Null);
+    }
+
+    #[test]
+    fn download_verifies_target() {
+        let server = ranged_reposerver(false);
+        let out = std::env::temp_dir().join(format!("ota-cli-verify-{}", std::process::id()));
+        let target = |length, sha256: &str| Target {
+            custom: serde_json::from_value(custom("foo", "ecu", None)).unwrap(),
+            hashes: vec![("sha256".to_string(), sha256.to_string())].into_iter().collect(),
+            length,
+        };
+
+        let valid = target(12, "5fa3473d182d26c6f26a6cad032ae12092df6e26e12499b4051997b5b723860d");
+        Reposerver::download_target(&mut server.config(), "foo-1", &out, Some(&valid)).expect("verified");
+        assert_eq!(fs::read(&out).unwrap(), b"foo contents");
+
+        fs::remove_file(&out).unwrap();
+        let invalid = target(12, "00");
+        assert!(Reposerver::download_target(&mut server.config(), "foo-1", &out, Some(&invalid)).is_err());
+        assert!(!out.exists() && !Reposerver::part_path(&out).exists());
+        assert!(Reposerver::download_target(&mut server.config(), "foo-1", &out, Some(&target(10, ""))).is_err());
+    }
+
+    #[test]
+    fn download_restarts_without_range_support() {
+        let server = ranged_reposerver(false);
+        let out = std::env::temp_dir().join(format!("ota-cli-restart-{}", std::process::id()));
+        fs::write(Reposerver::part_path(&out), b"stale data").unwrap();
+
+        let length = Reposerver::download_target(&mut server.config(), "foo-1", &out, None).expect("download");
+        assert_eq!(length, 12);
+        assert_eq!(fs::read(&out).unwrap(), b"foo contents");
+        assert!(server.requests().iter().all(|req| !req.headers.contains_key("range")));
+        fs::remove_file(&out).unwrap();
+    }
+
+    #[test]
+    fn diff_classifies_targets() {
+        let targets = json!({"targets": {
+            "foo-1": {"custom": custom("foo", "ecu", None)},
+            "bar-1": {"custom": custom("bar", "ecu-b", None)},
+            "old-1": {"custom": custom("old", "ecu", None)}
+        }});
+        let targets: Targets = serde_json::from_value(targets).expect("targets");
+        let packages = test_packages(&["foo", "bar", "new"]);
+
+        let result = Reposerver::diff_table(packages, targets, &TargetFilter::default());
+        assert_eq!(result.rows.len(), 3);
+        assert_eq!(result.rows[0]["target"], "bar-1");
+        assert_eq!(result.rows[0]["status"], "changed");
+        assert_eq!(result.rows[0]["detail"], "hardware ids ecu (live ecu-b)");
+        assert_eq!(result.rows[1]["target"], "new-1");
+        assert_eq!(result.rows[1]["status"], "missing");
+        assert_eq!(result.rows[2]["target"], "old-1");
+        assert_eq!(result.rows[2]["status"], "extra");
+    }
+
+    #[test]
+    fn diff_reports_format_and_uri_changes() {
+        let targets = json!({"targets": {"foo-1": {"custom": custom("foo", "ecu", Some("https://acme.org/foo"))}}});
+        let targets: Targets = serde_json::from_value(targets).expect("targets");
+        let mut packages = test_packages(&["foo"]);
+        packages.packages[0].format = TargetFormat::Ostree;
+
+        let result = Reposerver::diff_table(packages, targets, &TargetFilter::default());
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0]["status"], "changed");
+        assert_eq!(result.rows[0]["detail"], "format OSTREE (live BINARY); uri none (live https://acme.org/foo)");
+    }
+
+    #[test]
+    fn diff_reports_hash_changes() {
+        let mut packages = test_packages(&["foo", "bar"]);
+        let (sha256, length) = match packages.packages[0].target {
+            RepoTarget::Path(ref path) => sha256_file(Path::new(path)).expect("hash"),
+            RepoTarget::Url(_) => unreachable!(),
+        };
+        let targets = json!({"targets": {
+            "foo-1": {"custom": custom("foo", "ecu", None), "length": length, "hashes": {"sha256": sha256}},
+            "bar-1": {"custom": custom("bar", "ecu", None), "length": 3, "hashes": {"sha256": "00"}}
+        }});
+        let targets: Targets = serde_json::from_value(targets).expect("targets");
+        for package in packages.packages.iter_mut() {
+            package.hash_file().expect("hash file");
+        }
+
+        let result = Reposerver::diff_table(packages, targets, &TargetFilter::default());
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0]["target"], "bar-1");
+        assert_eq!(result.rows[0]["detail"], format!("sha256 {} (live 00); length {} (live 3)", sha256, length));
+    }
+
+    #[test]
+    fn package_table_json_matches_rows() {
+        let body = json!({"signed": {"targets": {
+            "foo-1": {"custom": {
+                "name": "foo",
+                "version": "1",
+                "hardwareIds": ["acme-ecu-1", "acme-ecu-2"],
+                "uri": "https://acme.org/downloads/foo",
+                "updatedAt": "2020-01-01T00:00:00Z",
+                "targetFormat": "BINARY"
+            }}
+        }}});
+        let result = Reposerver::package_table(HeaderMap::new(), serde_json::to_vec(&body).unwrap().as_slice(), None).expect("table");
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.table().row_iter().count(), 1);
+
+        let json = serde_json::to_value(&result).expect("serialize");
+        let rows: Vec<Map<String, Value>> = serde_json::from_value(json.clone()).expect("round-trip");
+        assert_eq!(rows, result.rows);
+        assert_eq!(json[0]["target"], "foo-1");
+        assert_eq!(json[0]["hardware ids"], json!(["acme-ecu-1", "acme-ecu-2"]));
+        assert_eq!(json[0]["uri"], "https://acme.org/downloads/foo");
+        assert_eq!(json[0]["target_format"], "Binary");
+    }
+
+    #[test]
+    fn package_table_streams_served_body() {
+        let served = format!(r#"{{"signatures": [], "signed": {{"_type": "Targets", "targets": {{"foo-1": {{"length": 12, "custom": {}}}}}}}}}"#, custom("foo", "ecu", None));
+        let mut raw = Vec::new();
+        let result = Reposerver::package_table(HeaderMap::new(), served.as_bytes(), Some(&mut raw)).expect("table");
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(raw, served.as_bytes());
+        assert_eq!(serde_json::from_slice::<Value>(&result.response).unwrap()[0]["target"], "foo-1");
+    }
+
+    #[test]
+    fn package_table_streams_large_targets() {
+        const TARGETS: usize = 2_000;
+        const PADDING: u64 = 64 << 20;
+        let targets = (0..TARGETS)
+            .map(|n| format!(r#""foo-{n}": {{"length": {n}, "custom": {custom}}}"#, n = n, custom = custom("foo", "ecu", None)))
+            .collect::<Vec<_>>()
+            .join(",");
+        let body = io::Cursor::new(r#"{"signatures": [{"sig": ""#)
+            .chain(io::repeat(b'a').take(PADDING))
+            .chain(io::Cursor::new(format!(r#""}}], "signed": {{"targets": {{{}}}}}}}"#, targets)));
+
+        let result = Reposerver::package_table(HeaderMap::new(), body, None).expect("table");
+        assert_eq!(result.rows.len(), TARGETS);
+        assert!((result.response.len() as u64) < PADDING / 16);
+    }
+
+    #[test]
+    fn add_package_computes_checksum() {
+        let path = std::env::temp_dir().join(format!("ota-cli-checksum-{}", std::process::id()));
+        fs::write(&path, b"foo contents").unwrap();
+        let package = add_args(&["--path", path.to_str().unwrap()]).expect("package");
+        assert_eq!(package.sha256.as_deref(), Some("5fa3473d182d26c6f26a6cad032ae12092df6e26e12499b4051997b5b723860d"));
+        assert_eq!(package.length, Some(12));
+
+        let declared = add_args(&["--sha256", CONTENTS_SHA256, "--path", "/nonexistent/ota-cli-package"]).expect("declared package");
+        assert_eq!(declared.sha256.as_deref(), Some(CONTENTS_SHA256));
+        assert_eq!(declared.length, None);
+
+        let server = MockServer::start(|_| MockResponse::status(204));
+        Reposerver::add_package(&mut server.config(), package).expect("upload");
+        let req = server.requests().remove(0);
+        assert!(req.path.contains("checksum=5fa3473d182d26c6f26a6cad032ae12092df6e26e12499b4051997b5b723860d"));
+        assert!(req.path.contains("length=12"));
+        assert_eq!(req.headers.get("content-length").map(|len| len.parse::<usize>().unwrap()), Some(req.body.len()));
+        let body = String::from_utf8_lossy(&req.body);
+        assert!(body.contains(&format!("filename=\"{}\"", path.file_name().unwrap().to_string_lossy())));
+        assert!(body.contains("\r\n\r\nfoo contents\r\n"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn add_args(args: &[&str]) -> Result<TufPackage> {
+        let base = ["add", "--name", "foo", "--version", "1", "--hardware", "ecu", "--binary"];
+        TufPackage::from_args(&PackageAddArgs::from_iter(base.iter().chain(args)))
+    }
+
+    #[test]
+    fn url_target_is_sent_as_file_uri() {
+        let server = MockServer::start(|_| MockResponse::status(204));
+        let package = add_args(&["--url", "https://artifacts.example.com/foo"]).expect("package");
+        Reposerver::add_package(&mut server.config(), package).expect("upload");
+
+        let body = String::from_utf8_lossy(&server.requests()[0].body).into_owned();
+        assert!(body.contains("name=\"fileUri\"\r\n\r\nhttps://artifacts.example.com/foo"));
+        assert!(!body.contains("filename="));
+    }
 }
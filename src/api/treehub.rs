@@ -0,0 +1,146 @@
+use reqwest::{header::HeaderMap, Method, StatusCode};
+use std::io::Read;
+use url::Url;
+
+use crate::api::reposerver::Reposerver;
+use crate::command::{CommandResult, TableResult};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::http::{Http, Request};
+
+/// Available Treehub API methods.
+pub trait TreehubApi {
+    /// Show the commit a ref points to.
+    fn get_ref(_: &mut Config, name: &str) -> Result<CommandResult>;
+    /// Check that a commit has been pushed, failing with `NotFound` when it has not.
+    fn head_commit(_: &mut Config, commit: &str) -> Result<CommandResult>;
+}
+
+/// Make API calls to inspect the OSTree repository, at the `ostree.server` URL of the credentials.
+pub struct Treehub;
+
+impl Treehub {
+    /// List the refs of the OSTree targets in the reposerver with the commit each one points to.
+    ///
+    /// Treehub has no endpoint listing refs, so the names come from the OSTree targets.
+    pub fn refs_table(config: &mut Config) -> Result<CommandResult> {
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["ref", "commit", "latest target", "target commit"]);
+        for (name, target) in Reposerver::ostree_targets(config)? {
+            let commit = Self::fetch_ref(config, &name)?;
+            result.add_row(vec![name.into(), commit.into(), target.version.into(), target.commit.into()]);
+        }
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    /// Fetch the commit of a ref, or `None` when treehub has no such ref.
+    pub fn fetch_ref(config: &mut Config, name: &str) -> Result<Option<String>> {
+        let server = Self::server(config)?;
+        let resp = Http::execute(Self::get_ref_request(&server, name)?, config.token()?)?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let mut body = String::new();
+        resp.error_for_status()?.read_to_string(&mut body)?;
+        Ok(Some(body.trim().into()))
+    }
+
+    fn server(config: &mut Config) -> Result<Url> { Ok(config.credentials()?.treehub_url().clone()) }
+}
+
+impl TreehubApi for Treehub {
+    fn get_ref(config: &mut Config, name: &str) -> Result<CommandResult> {
+        debug!("fetching ref {}", name);
+        let commit = Self::fetch_ref(config, name)?.ok_or_else(|| Error::NotFound(format!("Ref `{}`", name), None))?;
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["ref", "commit"]);
+        result.add_row(vec![name.into(), commit.into()]);
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+
+    fn head_commit(config: &mut Config, commit: &str) -> Result<CommandResult> {
+        debug!("checking for commit {}", commit);
+        if commit.len() != 64 || !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::Args(format!("expected a 64 character hex commit, got `{}`", commit)));
+        }
+        let server = Self::server(config)?;
+        let resp = Http::execute(Self::head_commit_request(&server, commit)?, config.token()?)?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Err(Error::NotFound(format!("Commit {}", commit), None));
+        }
+        resp.error_for_status()?;
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["commit", "exists"]);
+        result.add_row(vec![commit.into(), true.into()]);
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result.into())
+    }
+}
+
+/// Requests for the Treehub API, relative to the treehub `server` URL.
+impl Treehub {
+    pub(crate) fn get_ref_request(server: &Url, name: &str) -> Result<Request> { Request::get(server.join(&format!("refs/heads/{}", name))?) }
+
+    pub(crate) fn head_commit_request(server: &Url, commit: &str) -> Result<Request> {
+        Request::new(Method::HEAD, server.join(&format!("objects/{}/{}.commit", &commit[..2], &commit[2..]))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::auth_plus::Credentials;
+    use crate::mock::{MockResponse, MockServer};
+    use serde_json::Value;
+
+    const COMMIT: &str = "a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f90";
+
+    fn treehub() -> (MockServer, Config) {
+        let server = MockServer::start(|req| match (req.method.as_ref(), req.route()) {
+            ("GET", "/api/v1/user_repo/targets.json") => MockResponse::json(200, json!({"signed": {"targets": {
+                "os-1": {"custom": {"name": "os", "version": "1", "hardwareIds": ["rpi"], "uri": null, "updatedAt": "", "targetFormat": "OSTREE"}, "hashes": {"sha256": "00"}},
+                "os-2": {"custom": {"name": "os", "version": "2", "hardwareIds": ["rpi"], "uri": null, "updatedAt": "", "targetFormat": "OSTREE"}, "hashes": {"sha256": COMMIT}},
+                "app-1": {"custom": {"name": "app", "version": "1", "hardwareIds": ["rpi"], "uri": null, "updatedAt": "", "targetFormat": "BINARY"}}
+            }}})),
+            ("GET", "/treehub/refs/heads/os") => MockResponse {
+                status: 200,
+                headers: Vec::new(),
+                body: format!("{}\n", COMMIT).into_bytes(),
+            },
+            ("HEAD", route) if route.ends_with(&format!("{}.commit", &COMMIT[2..])) => MockResponse::status(200),
+            _ => MockResponse::status(404),
+        });
+        let mut config = server.config();
+        let treehub = config.reposerver.join("treehub/").unwrap();
+        config.credentials = Some(serde_json::from_value::<Credentials>(json!({"no_auth": true, "ostree": {"server": treehub}})).unwrap());
+        (server, config)
+    }
+
+    fn rows(result: CommandResult) -> Vec<Value> {
+        match result {
+            CommandResult::Table(result) => result.rows.into_iter().map(Value::Object).collect(),
+            _ => panic!("expected a table"),
+        }
+    }
+
+    #[test]
+    fn inspect_refs_and_commits() {
+        let (server, mut config) = treehub();
+        let refs = rows(Treehub::refs_table(&mut config).expect("refs"));
+        assert_eq!(refs, vec![json!({"ref": "os", "commit": COMMIT, "latest target": "2", "target commit": COMMIT})]);
+
+        assert_eq!(rows(Treehub::get_ref(&mut config, "os").expect("ref"))[0]["commit"], COMMIT);
+        match Treehub::get_ref(&mut config, "missing") {
+            Err(Error::NotFound(..)) => (),
+            other => panic!("expected not found, got {:?}", other.err()),
+        }
+
+        Treehub::head_commit(&mut config, COMMIT).expect("exists");
+        match Treehub::head_commit(&mut config, &"0".repeat(64)) {
+            Err(Error::NotFound(..)) => (),
+            other => panic!("expected not found, got {:?}", other.err()),
+        }
+        assert!(Treehub::head_commit(&mut config, "abc").is_err());
+        let head = server.requests().into_iter().find(|req| req.method == "HEAD").expect("head");
+        assert_eq!(head.route(), format!("/treehub/objects/a1/{}.commit", &COMMIT[2..]));
+    }
+}
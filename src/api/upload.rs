@@ -0,0 +1,222 @@
+use md5::Md5;
+use reqwest::blocking::Response;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::{
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::api::reposerver::sha256_file;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::http::{Http, Request};
+use crate::progress;
+
+/// The smallest part size accepted by the storage behind the reposerver, except for the last part.
+pub const MIN_CHUNK_SIZE: u64 = 5 << 20;
+
+const STATE_DIR: &str = "uploads";
+
+/// The progress of a multipart upload, saved after each part so an interrupted upload can resume.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct UploadState {
+    path: PathBuf,
+    length: u64,
+    modified: u64,
+    chunk_size: u64,
+    sha256: String,
+    upload_id: String,
+    parts: Vec<UploadedPart>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct UploadedPart {
+    part: u64,
+    e_tag: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadId {
+    upload_id: String,
+}
+
+#[derive(Deserialize)]
+struct PartUrl {
+    uri: String,
+}
+
+/// Upload a file in parts of `chunk_size` bytes as the `entry` target, then register it with the target `query`.
+///
+/// The uploaded parts are recorded in a state file under the cache directory, so running the
+/// same upload again after a failure skips the parts already uploaded. The state is dropped when
+/// the file has changed since, or once the upload is complete.
+pub(crate) fn upload_chunked(config: &mut Config, entry: &str, path: &Path, chunk_size: u64, query: &[(&str, String)]) -> Result<Response> {
+    let metadata = fs::metadata(path)?;
+    let (length, modified) = (metadata.len(), metadata.modified()?.duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0));
+    let path = path.canonicalize()?;
+    let state_path = state_path(config, entry);
+
+    let mut state = match read_state(&state_path) {
+        Some(state) if state.path == path && state.length == length && state.modified == modified && state.chunk_size == chunk_size => {
+            info!("resuming upload of {} after {} parts", entry, state.parts.len());
+            state
+        }
+        _ => {
+            let sha256 = match query.iter().find(|(key, _)| *key == "checksum") {
+                Some((_, sha256)) => sha256.clone(),
+                None => sha256_file(&path)?.0,
+            };
+            let req = Request::post(format!("{}api/v1/user_repo/uploads/multipart/initiate/{}", config.reposerver, urlencoding::encode(entry)))?.query("fileSize", length);
+            let resp = Http::execute(req, config.token()?)?.error_for_status()?;
+            if Http::is_dry_run(&resp) {
+                // The parts can't be requested without the id of a started upload.
+                return Ok(resp);
+            }
+            let id: UploadId = resp.json()?;
+            debug!("started upload {} of {}", id.upload_id, entry);
+            UploadState {
+                path: path.clone(),
+                length,
+                modified,
+                chunk_size,
+                sha256,
+                upload_id: id.upload_id,
+                parts: Vec::new(),
+            }
+        }
+    };
+    save_state(&state_path, &state)?;
+
+    let count = length.div_ceil(chunk_size).max(1);
+    let mut file = File::open(&path)?;
+    for part in state.parts.len() as u64 + 1..=count {
+        let offset = (part - 1) * chunk_size;
+        let mut chunk = Vec::new();
+        file.seek(SeekFrom::Start(offset))?;
+        (&mut file).take(chunk_size).read_to_end(&mut chunk)?;
+        let md5 = base64::encode(Md5::digest(&chunk));
+
+        let req = Request::get(format!("{}api/v1/user_repo/uploads/multipart/url/{}", config.reposerver, urlencoding::encode(entry)))?
+            .query("part", part)
+            .query("uploadId", &state.upload_id)
+            .query("md5", &md5)
+            .query("contentLength", chunk.len());
+        let url: PartUrl = Http::execute_json(req, config.token()?)?;
+        let resp = Http::send(Http::client().put(&url.uri).header("Content-MD5", md5).body(chunk), None)?.error_for_status()?;
+        let e_tag = resp
+            .headers()
+            .get("etag")
+            .and_then(|etag| etag.to_str().ok())
+            .ok_or_else(|| Error::Parse(format!("no ETag for part {} of {}", part, entry)))?;
+        state.parts.push(UploadedPart { part, e_tag: e_tag.into() });
+        save_state(&state_path, &state)?;
+        progress::report((offset + chunk_size).min(length), Some(length));
+    }
+
+    let mut query = query.to_vec();
+    if !query.iter().any(|(key, _)| *key == "checksum") {
+        query.push(("checksum", state.sha256.clone()));
+    }
+    if !query.iter().any(|(key, _)| *key == "length") {
+        query.push(("length", length.to_string()));
+    }
+    let mut req = Request::put(format!("{}api/v1/user_repo/uploads/multipart/complete/{}", config.reposerver, urlencoding::encode(entry)))?
+        .json(json!({"uploadId": state.upload_id, "partETags": state.parts}));
+    for (key, value) in query {
+        req = req.query(key, value);
+    }
+    let resp = Http::execute(req, config.token()?)?;
+    if resp.status().is_success() {
+        let _ = fs::remove_file(&state_path);
+    }
+    Ok(resp)
+}
+
+fn state_path(config: &Config, entry: &str) -> PathBuf {
+    let dir = config.cache_path().map(Path::to_path_buf).unwrap_or_else(|| std::env::temp_dir().join("ota-cli"));
+    dir.join(STATE_DIR).join(format!("{}.json", urlencoding::encode(entry)))
+}
+
+fn read_state(path: &Path) -> Option<UploadState> {
+    let state = serde_json::from_slice(&fs::read(path).ok()?);
+    if let Err(ref err) = state {
+        warn!("ignoring unreadable upload state {}: {}", path.display(), err);
+    }
+    state.ok()
+}
+
+fn save_state(path: &Path, state: &UploadState) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(fs::write(path, serde_json::to_vec_pretty(state)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn resume_interrupted_upload() {
+        let dir = std::env::temp_dir().join(format!("ota-cli-upload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.bin");
+        fs::write(&path, b"0123456789").unwrap();
+
+        let failed = AtomicBool::new(false);
+        let server = MockServer::start(move |req| match (req.method.as_ref(), req.route()) {
+            ("POST", "/api/v1/user_repo/uploads/multipart/initiate/os-1") => MockResponse::json(200, json!({"uploadId": "up-1"})),
+            ("GET", "/api/v1/user_repo/uploads/multipart/url/os-1") => {
+                let part = req.path.split("part=").nth(1).and_then(|rest| rest.split('&').next()).unwrap().to_string();
+                MockResponse::json(200, json!({"uri": format!("http://{}/storage/{}", req.headers["host"], part)}))
+            }
+            ("PUT", "/storage/2") if !failed.swap(true, Ordering::SeqCst) => MockResponse::status(500),
+            ("PUT", route) if route.starts_with("/storage/") => MockResponse::status(200).header("etag", &format!("\"{}\"", &route[9..])),
+            ("PUT", "/api/v1/user_repo/uploads/multipart/complete/os-1") => MockResponse::status(204),
+            _ => MockResponse::status(404),
+        });
+        let mut config = server.config().with_dir(&dir);
+        let query = [("name", "os".to_string())];
+
+        assert!(upload_chunked(&mut config, "os-1", &path, 4, &query).is_err());
+        assert!(state_path(&config, "os-1").exists());
+        upload_chunked(&mut config, "os-1", &path, 4, &query).expect("resumed upload");
+        assert!(!state_path(&config, "os-1").exists());
+
+        let requests = server.requests();
+        let parts = requests.iter().filter(|req| req.route().starts_with("/storage/")).map(|req| req.body.clone()).collect::<Vec<_>>();
+        assert_eq!(parts, vec![b"0123".to_vec(), b"4567".to_vec(), b"4567".to_vec(), b"89".to_vec()]);
+        assert_eq!(requests.iter().filter(|req| req.route().ends_with("/initiate/os-1")).count(), 1);
+        let complete = requests.last().unwrap();
+        assert!(complete.path.contains("name=os") && complete.path.contains("length=10"));
+        assert!(complete.path.contains("checksum=84d89877f0d4041efb6bf91a16f0248f2fd573e6af05c19f96bedb9f882f7882"));
+        assert_eq!(complete.json(), json!({"uploadId": "up-1", "partETags": [
+            {"part": 1, "eTag": "\"1\""}, {"part": 2, "eTag": "\"2\""}, {"part": 3, "eTag": "\"3\""}
+        ]}));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dry_run_stops_after_initiate() {
+        let dir = std::env::temp_dir().join(format!("ota-cli-upload-dry-run-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.bin");
+        fs::write(&path, b"0123456789").unwrap();
+
+        let server = MockServer::start(|_| MockResponse::dry_run());
+        let mut config = server.config().with_dir(&dir);
+        let resp = upload_chunked(&mut config, "os-1", &path, 4, &[("name", "os".to_string())]).expect("dry run");
+        assert!(Http::is_dry_run(&resp));
+        assert_eq!(server.requests().len(), 1);
+        assert!(server.requests()[0].route().ends_with("/initiate/os-1"));
+        assert!(!state_path(&config, "os-1").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
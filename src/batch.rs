@@ -0,0 +1,165 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+        Mutex,
+    },
+    thread,
+};
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::http::Http;
+use crate::interrupt::{self, Scope};
+use crate::progress::{self, Progress, ProgressMode};
+
+/// Options for running a batch of requests.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchOptions {
+    pub concurrency: usize,
+    pub fail_fast: bool,
+    pub progress: ProgressMode,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            concurrency: 1,
+            fail_fast: false,
+            progress: ProgressMode::default(),
+        }
+    }
+}
+
+impl BatchOptions {
+    /// Check `--concurrency` and `--fail-fast` CLI arguments.
+    pub fn new(concurrency: Option<usize>, fail_fast: bool, progress: ProgressMode) -> Result<Self> {
+        let concurrency = concurrency.unwrap_or(Self::default().concurrency);
+        if concurrency < 1 {
+            return Err(Error::Args("--concurrency must be at least 1".into()));
+        }
+        Ok(BatchOptions { concurrency, fail_fast, progress })
+    }
+}
+
+/// Run `f` over each labelled item using up to `concurrency` threads.
+///
+/// Each thread gets its own copy of the config. With `fail_fast` set, no new
+/// items are started after the first failure. Results are sorted by label, and
+/// any failure fails the batch as with `collect`. After a Ctrl-C, no new items
+/// are started and only finished items are returned.
+pub fn run<T, R, F>(config: &Config, action: &str, items: Vec<(String, T)>, opts: BatchOptions, f: F) -> Result<Vec<(String, R)>>
+where
+    T: Send,
+    R: Send,
+    F: Fn(&mut Config, T) -> Result<R> + Sync,
+{
+    let _running = Scope::batch();
+    run_until(config, action, items, opts, interrupt::flag(), f)
+}
+
+/// Run a batch as with `run`, starting no new items once `stop` is set.
+pub(crate) fn run_until<T, R, F>(
+    config: &Config,
+    action: &str,
+    items: Vec<(String, T)>,
+    opts: BatchOptions,
+    stop: &AtomicBool,
+    f: F,
+) -> Result<Vec<(String, R)>>
+where
+    T: Send,
+    R: Send,
+    F: Fn(&mut Config, T) -> Result<R> + Sync,
+{
+    let total = items.len();
+    let progress = Arc::new(Progress::new(action, items.len(), opts.progress));
+    let workers = opts.concurrency.min(items.len());
+    let queue = Mutex::new(items.into_iter().collect::<VecDeque<_>>());
+    let results = Mutex::new(Vec::new());
+    let abort = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let mut config = config.clone();
+            let (queue, results, abort, f) = (&queue, &results, &abort, &f);
+            let progress = progress.clone();
+            scope.spawn(move || {
+                while !abort.load(Ordering::SeqCst) && !stop.load(Ordering::SeqCst) {
+                    let (label, item) = match queue.lock().unwrap().pop_front() {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    debug!("starting batch item {}", label);
+                    progress.start_item(&label);
+                    progress::track(Some((progress.clone(), label.clone())));
+                    let (observer, name) = (progress.clone(), label.clone());
+                    Http::observe_attempts(Some(Box::new(move |attempt| observer.attempt(&name, attempt))));
+                    let result = f(&mut config, item);
+                    Http::observe_attempts(None);
+                    progress::track(None);
+                    progress.finish_item(&label, result.is_ok());
+                    if result.is_err() && opts.fail_fast {
+                        abort.store(true, Ordering::SeqCst);
+                    }
+                    results.lock().unwrap().push((label, result));
+                }
+            });
+        }
+    });
+    progress.finish();
+    if let Some((used, max)) = Http::retry_usage() {
+        info!("{} used {} of {} retries", action, used, max);
+    }
+
+    let mut results = results.into_inner().unwrap();
+    if stop.load(Ordering::SeqCst) {
+        warn!("{} interrupted after {} of {} items", action, results.len(), total);
+    }
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    collect(action, results, opts)
+}
+
+/// Return the values of a batch once every item succeeded.
+///
+/// With `fail_fast` the first failure is returned as is. Otherwise each failure
+/// is logged and the batch fails with the number of failed items.
+pub fn collect<R>(action: &str, results: Vec<(String, Result<R>)>, opts: BatchOptions) -> Result<Vec<(String, R)>> {
+    let total = results.len();
+    let (mut values, mut failed) = (Vec::with_capacity(total), 0);
+    for (label, result) in results {
+        match result {
+            Ok(value) => values.push((label, value)),
+            Err(err) if opts.fail_fast => return Err(err),
+            Err(err) => {
+                error!("{} {} failed: {}", action, label, err);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(Error::Failed(format!("{} failed for {} of {} items", action, failed, total)));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results() -> Vec<(String, Result<u32>)> {
+        vec![("a".into(), Ok(1)), ("b".into(), Err(Error::NotFound("b".into(), None))), ("c".into(), Err(Error::Args("c".into())))]
+    }
+
+    #[test]
+    fn collect_fails_when_any_item_failed() {
+        let err = collect("testing", results(), BatchOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "Failed: testing failed for 2 of 3 items");
+        assert_eq!(err.exit_code(), crate::error::EXIT_FAILURE);
+
+        let fail_fast = BatchOptions { fail_fast: true, ..BatchOptions::default() };
+        assert!(matches!(collect("testing", results(), fail_fast), Err(Error::NotFound(..))));
+        assert_eq!(collect("testing", vec![("a".into(), Ok(1))], fail_fast).unwrap(), vec![("a".to_string(), 1)]);
+    }
+}
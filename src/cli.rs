@@ -0,0 +1,1456 @@
+use clap::{AppSettings, Shell};
+use std::path::PathBuf;
+use structopt::StructOpt;
+use url::Url;
+use uuid::Uuid;
+
+// The command line of the `ota` binary. Doc comments on these types become the help text, so
+// the types themselves are described with plain comments.
+//
+// Global options are read by each command from its `ArgMatches` rather than from these fields.
+// clap copies their values to every subcommand level, so they work wherever they are placed on
+// the command line. The readers, e.g. `Config::load_from_args`, `Output::from_args` and
+// `ErrorFormat::from_args`, also apply the `OTA_*` environment fallbacks and are shared with
+// `ota shell`, which starts its session from the shell's own arguments and runs each line with
+// the arguments of that line.
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "ota",
+    setting = AppSettings::SubcommandRequiredElseHelp,
+    setting = AppSettings::DeriveDisplayOrder,
+    setting = AppSettings::InferSubcommands,
+    setting = AppSettings::VersionlessSubcommands,
+    setting = AppSettings::UnifiedHelpMessage
+)]
+pub struct Cli {
+    /// Set the logging level
+    #[structopt(short, long, value_name = "level", global = true)]
+    pub level: Option<String>,
+    /// Hide progress output
+    #[structopt(short, long, global = true)]
+    pub quiet: bool,
+    /// Log which config, credentials and service URLs are used
+    #[structopt(long, global = true)]
+    pub verbose: bool,
+    /// Directory for all CLI state. Defaults to $OTA_CONFIG_DIR or the platform config directory
+    #[structopt(name = "config_dir", long = "config-dir", value_name = "dir", global = true)]
+    pub config_dir: Option<PathBuf>,
+    /// Use a named config profile. Defaults to $OTA_PROFILE or the default config
+    #[structopt(long, value_name = "name", global = true)]
+    pub profile: Option<String>,
+    /// Use this bearer token instead of fetching one with the credentials. Defaults to $OTA_TOKEN
+    #[structopt(long, value_name = "token", global = true)]
+    pub token: Option<String>,
+    /// Send this namespace with every request instead of the token namespace, e.g. for admin tokens. Defaults to $OTA_NAMESPACE
+    #[structopt(long, value_name = "name", global = true)]
+    pub namespace: Option<String>,
+    /// Repeat a list command in each of these comma-separated namespaces, merging the results
+    #[structopt(long, value_name = "names", global = true, use_delimiter = true, multiple = false, conflicts_with = "namespace")]
+    pub namespaces: Vec<String>,
+    /// Maximum number of redirects to follow (default: 3)
+    #[structopt(name = "max_redirects", long = "max-redirects", value_name = "n", global = true)]
+    pub max_redirects: Option<usize>,
+    /// Time allowed for each request, e.g. 5m, or 0 for no limit (default: the config timeout or 30s)
+    #[structopt(name = "request_timeout", long = "request-timeout", value_name = "duration", global = true)]
+    pub request_timeout: Option<String>,
+    /// Time allowed to connect, or 0 for no limit (default: the config connect_timeout)
+    #[structopt(name = "connect_timeout", long = "connect-timeout", value_name = "duration", global = true)]
+    pub connect_timeout: Option<String>,
+    /// Send requests through this HTTP(S) proxy (default: the config proxy, then $HTTPS_PROXY or $HTTP_PROXY)
+    #[structopt(long, value_name = "url", global = true)]
+    pub proxy: Option<Url>,
+    /// Client certificate for mutual TLS, as PEM or a .p12/.pfx archive (default: from the credentials)
+    #[structopt(name = "client_cert", long = "client-cert", value_name = "file", global = true)]
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key of --client-cert, if not in the same file
+    #[structopt(name = "client_key", long = "client-key", value_name = "file", global = true, requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+    /// Password of a .p12/.pfx --client-cert
+    #[structopt(name = "client_cert_password", long = "client-cert-password", value_name = "password", global = true, requires = "client_cert")]
+    pub client_cert_password: Option<String>,
+    /// Retry idempotent requests failing with a connection error or --retry-on status (default: 2)
+    #[structopt(long, value_name = "n", global = true)]
+    pub retries: Option<u32>,
+    /// Delay before the first retry, doubling after each (default: 500)
+    #[structopt(name = "retry_backoff", long = "retry-backoff", value_name = "ms", global = true)]
+    pub retry_backoff: Option<u64>,
+    /// Comma-separated response statuses to retry (default: 502,503,504)
+    #[structopt(name = "retry_on", long = "retry-on", value_name = "statuses", global = true)]
+    pub retry_on: Option<String>,
+    /// Fail on 429 responses instead of waiting for Retry-After and retrying
+    #[structopt(name = "no_rate_limit_retry", long = "no-rate-limit-retry", global = true)]
+    pub no_rate_limit_retry: bool,
+    /// Maximum retries across all requests of the command
+    #[structopt(name = "max_retries_total", long = "max-retries-total", value_name = "n", global = true)]
+    pub max_retries_total: Option<u32>,
+    /// Print requests that change server state instead of sending them
+    #[structopt(name = "dry_run", long = "dry-run", global = true)]
+    pub dry_run: bool,
+    /// Trace the method, URL, status, latency and body of every request to stderr. Bodies may hold secrets
+    #[structopt(name = "debug_http", long = "debug-http", global = true)]
+    pub debug_http: bool,
+    /// Trace at most this many bytes of each body with --debug-http, or 0 for none (default: 1024)
+    #[structopt(name = "debug_http_body", long = "debug-http-body", value_name = "bytes", global = true, requires = "debug_http")]
+    pub debug_http_body: Option<usize>,
+    /// Record every request and response of the command to this HTTP Archive file, with credentials redacted
+    #[structopt(long, value_name = "file", global = true)]
+    pub har: Option<PathBuf>,
+    /// Show a spinner for requests slower than this (default: 1000)
+    #[structopt(name = "spinner_after", long = "spinner-after", value_name = "ms", global = true)]
+    pub spinner_after: Option<u64>,
+
+    /// Show a table with the result instead of server json
+    #[structopt(name = "usetables", long = "use-tables", global = true)]
+    pub use_tables: bool,
+    /// Set the output format for command results (default: $OTA_OUTPUT or raw)
+    #[structopt(long, value_name = "format", global = true, possible_values = &["raw", "table", "json", "compact", "yaml", "csv"])]
+    pub output: Option<String>,
+    /// Write command results to this file instead of stdout, as CSV for a .csv file
+    #[structopt(name = "output_file", long = "output-file", value_name = "path", global = true)]
+    pub output_file: Option<PathBuf>,
+    /// Report a failure on stderr as text or a JSON object (default: $OTA_ERRORS or text)
+    #[structopt(long, value_name = "format", global = true, possible_values = &["text", "json"])]
+    pub errors: Option<String>,
+    /// Show only these comma separated table columns, e.g. name,version,hardware
+    #[structopt(long, value_name = "list", global = true)]
+    pub columns: Option<String>,
+    /// Sort table rows by this column
+    #[structopt(name = "sort_by", long = "sort-by", value_name = "column", global = true)]
+    pub sort_by: Option<String>,
+    /// Also write the raw response body to this file, printing a table by default
+    #[structopt(name = "save_response", long = "save-response", value_name = "file", global = true)]
+    pub save_response: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+// Available CLI sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum Command {
+    /// Set config values before starting, asking for any that are not passed
+    #[structopt(setting = AppSettings::DeriveDisplayOrder, setting = AppSettings::UnifiedHelpMessage)]
+    Init(InitArgs),
+    /// Print a shell completion script
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp)]
+    Completions(CompletionsArgs),
+    /// Start an interactive prompt running ota commands with the config and token loaded once
+    Shell,
+    /// Manage the cached access token
+    #[structopt(setting = AppSettings::SubcommandRequiredElseHelp)]
+    Auth(AuthCommand),
+    /// Manage config values
+    #[structopt(setting = AppSettings::SubcommandRequiredElseHelp)]
+    Config(ConfigCommand),
+    /// Manage OTA campaigns
+    #[structopt(
+        alias = "campaigns",
+        setting = AppSettings::SubcommandRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::InferSubcommands,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Campaign(Campaign),
+    /// Manage TUF delegations of the package repository
+    #[structopt(
+        alias = "delegations",
+        setting = AppSettings::SubcommandRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::InferSubcommands,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Delegation(Delegation),
+    /// Manage OTA devices
+    #[structopt(
+        alias = "devices",
+        setting = AppSettings::SubcommandRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::InferSubcommands,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Device(Device),
+    /// Manage device groups
+    #[structopt(
+        alias = "groups",
+        setting = AppSettings::SubcommandRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::InferSubcommands,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Group(Group),
+    /// Manage OTA packages
+    #[structopt(
+        aliases = &["packages", "pkg"],
+        setting = AppSettings::SubcommandRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::InferSubcommands,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Package(Package),
+    /// Audit and rotate the root of trust of the package repository
+    #[structopt(
+        alias = "repository",
+        setting = AppSettings::SubcommandRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Repo(RepoCommand),
+    /// Report on the fleet using data from several services
+    #[structopt(
+        setting = AppSettings::SubcommandRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Report(Report),
+    /// Sign targets metadata offline with a local key
+    #[structopt(
+        setting = AppSettings::SubcommandRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Sign(Sign),
+    /// Inspect the OSTree repository before creating updates
+    #[structopt(
+        alias = "ostree",
+        setting = AppSettings::SubcommandRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Treehub(TreehubCommand),
+    /// Manage multi-target updates
+    #[structopt(
+        alias = "updates",
+        setting = AppSettings::SubcommandRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::InferSubcommands,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Update(Update),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct InitArgs {
+    /// Path to credentials.zip, or a flat .json/.toml credentials file
+    #[structopt(short = "z", long, value_name = "zip")]
+    pub credentials: Option<PathBuf>,
+    /// Campaigner URL
+    #[structopt(short, long, value_name = "url")]
+    pub campaigner: Option<Url>,
+    /// Director URL
+    #[structopt(short, long, value_name = "url")]
+    pub director: Option<Url>,
+    /// Device Registry URL
+    #[structopt(short, long, value_name = "url")]
+    pub registry: Option<Url>,
+    /// Reposerver URL. Overrides reposerver URL inc credentials.zip
+    #[structopt(short = "t", long, value_name = "url")]
+    pub reposerver: Option<Url>,
+    /// Save the URLs without checking that each service is healthy
+    #[structopt(name = "skip_checks", long = "skip-checks")]
+    pub skip_checks: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CompletionsArgs {
+    /// The shell to generate completions for
+    #[structopt(possible_values = &["bash", "zsh", "fish"])]
+    pub shell: Shell,
+}
+
+// Available auth sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum AuthCommand {
+    /// Fetch a new access token, even if the cached one has not expired
+    Refresh,
+}
+
+// Available config sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum ConfigCommand {
+    /// Print the effective config, without the access token
+    Show,
+    /// Print a single config value
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp)]
+    Get(ConfigKeyArgs),
+    /// Update a single config value
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp)]
+    Set(ConfigSetArgs),
+    /// Delete the cached access token, so the next command fetches a new one
+    #[structopt(name = "clear-token", alias = "logout")]
+    ClearToken,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct ConfigKeyArgs {
+    /// One of campaigner, director, registry, reposerver, credentials, proxy, timeout, connect_timeout or namespace
+    pub key: String,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct ConfigSetArgs {
+    /// One of campaigner, director, registry, reposerver, credentials, proxy, timeout, connect_timeout or namespace
+    pub key: String,
+    /// The new value
+    pub value: String,
+}
+
+// Available campaign sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum Campaign {
+    /// List campaign information
+    #[structopt(alias = "ls", setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    List(CampaignListArgs),
+    /// Create a new campaign
+    #[structopt(
+        alias = "new",
+        setting = AppSettings::ArgRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Create(CampaignCreateArgs),
+    /// Change a campaign before it is launched
+    #[structopt(
+        alias = "edit",
+        setting = AppSettings::ArgRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Update(CampaignUpdateArgs),
+    /// Launch a created campaign
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Launch(CampaignArgs),
+    /// Cancel a launched campaign
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Cancel(CampaignCancelArgs),
+    /// Re-queue the devices that failed a campaign
+    #[structopt(alias = "retry-failed", setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Retry(CampaignRetryArgs),
+    /// List the devices processed by a campaign with their results
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Devices(CampaignDevicesArgs),
+    /// List the devices of a campaign waiting for approval
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Approvals(CampaignArgs),
+    /// Approve or reject pending campaign approvals
+    #[structopt(alias = "approve", setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Resolve(CampaignResolveArgs),
+    /// Show the progress of a campaign for each group
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Stats(CampaignArgs),
+    /// Wait for a campaign to finish, failing when too many devices fail
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Wait(CampaignWaitArgs),
+    /// create a campaign update
+    #[structopt(
+        name = "createupdate",
+        alias = "create-update",
+        setting = AppSettings::UnifiedHelpMessage,
+        setting = AppSettings::ArgRequiredElseHelp
+    )]
+    CreateUpdate(CampaignCreateUpdateArgs),
+    /// List campaign updates
+    #[structopt(name = "listupdates", alias = "list-updates", setting = AppSettings::UnifiedHelpMessage)]
+    ListUpdates,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CampaignArgs {
+    /// The campaign id
+    #[structopt(short, long, value_name = "uuid")]
+    pub campaign: Uuid,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CampaignListArgs {
+    /// List all campaigns
+    #[structopt(short, long, conflicts_with_all = &["campaign", "stats"])]
+    pub all: bool,
+    /// Number of campaigns to request per page
+    #[structopt(long, value_name = "n", requires = "all")]
+    pub limit: Option<u64>,
+    /// Fetch every page of campaigns
+    #[structopt(name = "all_pages", long = "all-pages", requires = "all")]
+    pub all_pages: bool,
+    /// The campaign id
+    #[structopt(short, long, value_name = "uuid", conflicts_with = "all")]
+    pub campaign: Option<Uuid>,
+    /// List campaign stats
+    #[structopt(short, long, conflicts_with = "all")]
+    pub stats: bool,
+    /// Only list campaigns with this status (prepared, launched, finished or cancelled)
+    #[structopt(long, value_name = "status", requires = "all")]
+    pub status: Option<String>,
+    /// Only list campaigns whose name contains this text
+    #[structopt(name = "name_contains", long = "name-contains", value_name = "text", requires = "all")]
+    pub name_contains: Option<String>,
+    /// Order campaigns by newest, oldest or name
+    #[structopt(long, value_name = "order", requires = "all")]
+    pub sort: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CampaignCreateArgs {
+    /// Multi-target update id
+    #[structopt(short, long, value_name = "uuid")]
+    pub update: Uuid,
+    /// A campaign name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// Apply the campaign to these groups
+    #[structopt(short, long, value_name = "uuid", required = true)]
+    pub groups: Vec<Uuid>,
+    /// Wait for each device to be approved before installing
+    #[structopt(name = "approval_required", long = "approval-required")]
+    pub approval_required: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CampaignUpdateArgs {
+    /// The campaign id
+    #[structopt(short, long, value_name = "uuid")]
+    pub campaign: Uuid,
+    /// The new campaign name
+    #[structopt(short, long, value_name = "name")]
+    pub name: Option<String>,
+    /// The new campaign description
+    #[structopt(short, long, value_name = "text")]
+    pub description: Option<String>,
+    /// Apply the campaign to these groups instead
+    #[structopt(short, long, value_name = "uuid")]
+    pub groups: Vec<Uuid>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CampaignCancelArgs {
+    /// The campaign id
+    #[structopt(short, long, value_name = "uuid")]
+    pub campaign: Uuid,
+    /// Why the campaign is being cancelled
+    #[structopt(short, long, value_name = "text")]
+    pub reason: Option<String>,
+    /// Cancel without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CampaignRetryArgs {
+    /// The campaign id
+    #[structopt(short, long, value_name = "uuid")]
+    pub campaign: Uuid,
+    /// Only retry devices that failed with this code
+    #[structopt(name = "failure_code", long = "failure-code", value_name = "code")]
+    pub failure_code: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CampaignDevicesArgs {
+    /// The campaign id
+    #[structopt(short, long, value_name = "uuid")]
+    pub campaign: Uuid,
+    /// Only list devices that failed the campaign
+    #[structopt(name = "failed_only", long = "failed-only")]
+    pub failed_only: bool,
+    /// Number of devices to request per page
+    #[structopt(long, value_name = "n")]
+    pub limit: Option<u64>,
+    /// Fetch every page of devices
+    #[structopt(name = "all_pages", long = "all-pages")]
+    pub all_pages: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CampaignResolveArgs {
+    /// The campaign id
+    #[structopt(short, long, value_name = "uuid")]
+    pub campaign: Uuid,
+    /// The devices to resolve
+    #[structopt(short, long, value_name = "uuid", required = true)]
+    pub devices: Vec<Uuid>,
+    /// Reject the update instead of approving it
+    #[structopt(long)]
+    pub reject: bool,
+    /// Why the update was rejected
+    #[structopt(long, value_name = "text")]
+    pub reason: Option<String>,
+    /// Reject without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CampaignWaitArgs {
+    /// The campaign id
+    #[structopt(short, long, value_name = "uuid")]
+    pub campaign: Uuid,
+    /// Give up after this long, e.g. 2h (default: no limit)
+    #[structopt(short, long, value_name = "duration")]
+    pub timeout: Option<String>,
+    /// Time between polls of the campaign stats (default: 30s)
+    #[structopt(short, long, value_name = "duration")]
+    pub interval: Option<String>,
+    /// Number of failed devices to tolerate (default: 0)
+    #[structopt(name = "max_failures", long = "max-failures", value_name = "n")]
+    pub max_failures: Option<u64>,
+    /// Percentage of affected devices allowed to fail
+    #[structopt(name = "max_failure_rate", long = "max-failure-rate", value_name = "percent", conflicts_with = "max_failures")]
+    pub max_failure_rate: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CampaignCreateUpdateArgs {
+    /// Multi-target update id
+    #[structopt(short, long, value_name = "uuid")]
+    pub update: Uuid,
+    /// update name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// The update description
+    #[structopt(short, long, value_name = "description")]
+    pub description: String,
+}
+
+// Available delegation sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum Delegation {
+    /// List the trusted delegations
+    #[structopt(alias = "ls")]
+    List,
+    /// Trust a new delegated role
+    #[structopt(
+        aliases = &["add", "new"],
+        setting = AppSettings::ArgRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Create(DelegationCreateArgs),
+    /// Remove a trusted delegation
+    #[structopt(aliases = &["rm", "remove"], setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Delete(DelegationDeleteArgs),
+    /// Upload the signed targets metadata of a delegated role
+    #[structopt(name = "addtarget", alias = "add-target", setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    AddTarget(DelegationAddTargetArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DelegationCreateArgs {
+    /// The delegated role name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// Key ids allowed to sign for the role
+    #[structopt(short, long, value_name = "id", required = true)]
+    pub keyids: Vec<String>,
+    /// Target paths delegated to the role, e.g. team-a-*
+    #[structopt(short, long, value_name = "pattern", required = true)]
+    pub paths: Vec<String>,
+    /// Number of signatures required (default: 1)
+    #[structopt(short, long, value_name = "n")]
+    pub threshold: Option<u32>,
+    /// Stop looking up later delegations for matching paths
+    #[structopt(long)]
+    pub terminating: bool,
+    /// A JSON file of public keys to add before the role
+    #[structopt(long, value_name = "file")]
+    pub keys: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DelegationDeleteArgs {
+    /// The delegated role name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// Delete without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DelegationAddTargetArgs {
+    /// The delegated role name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// The signed targets metadata file
+    #[structopt(short, long, value_name = "path")]
+    pub file: PathBuf,
+}
+
+// Available device sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum Device {
+    /// List devices
+    #[structopt(alias = "ls", setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    List(DeviceListArgs),
+    /*
+    /// Create a new device
+    #[structopt(
+        aliases = &["add", "new"],
+        setting = AppSettings::ArgRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Create(DeviceCreateArgs),
+    */
+    /// List the events reported by a device
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Events(DeviceArgs),
+    /// List the ECUs of a device with their installed targets
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Ecus(DeviceArgs),
+    /// Manage the ECUs of a device
+    #[structopt(setting = AppSettings::SubcommandRequiredElseHelp)]
+    Ecu(DeviceEcuCommand),
+    /// Create the devices listed in a CSV or JSON file
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Import(DeviceImportArgs),
+    /// Delete an existing device
+    #[structopt(aliases = &["rm", "remove"], setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Delete(DeviceDeleteArgs),
+    /// Rename an existing device
+    #[structopt(
+        alias = "mv",
+        setting = AppSettings::ArgRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Rename(DeviceRenameArgs),
+    /// Poll a device and redraw its status, current campaign and installed images until Ctrl-C
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Watch(DeviceWatchArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DeviceListArgs {
+    /// List all devices
+    #[structopt(short, long, conflicts_with = "device")]
+    pub all: bool,
+    /// Number of devices to request per page
+    #[structopt(long, value_name = "n", requires = "all")]
+    pub limit: Option<u64>,
+    /// Fetch every page of devices
+    #[structopt(name = "all_pages", long = "all-pages", requires = "all")]
+    pub all_pages: bool,
+    /// List information about this device
+    #[structopt(short, long, value_name = "uuid", conflicts_with_all = &["group", "all"])]
+    pub device: Option<Uuid>,
+    /// List the devices in this group
+    #[structopt(short, long, value_name = "uuid", conflicts_with_all = &["device", "all"])]
+    pub group: Option<Uuid>,
+    /// Only list devices seen within this duration, e.g. 2h or 7d
+    #[structopt(name = "seen_since", long = "seen-since", value_name = "duration", requires = "all")]
+    pub seen_since: Option<String>,
+    /// Only list devices not seen for this duration
+    #[structopt(name = "not_seen_for", long = "not-seen-for", value_name = "duration", requires = "all", conflicts_with = "seen_since")]
+    pub not_seen_for: Option<String>,
+    /// Search for devices whose name contains this text
+    #[structopt(name = "name_contains", long = "name-contains", value_name = "text", conflicts_with_all = &["device", "group"])]
+    pub name_contains: Option<String>,
+    /// Search for the device with this identifier (e.g. VIN)
+    #[structopt(name = "device_id", long = "device-id", value_name = "id", conflicts_with_all = &["device", "group"])]
+    pub device_id: Option<String>,
+    /// Search for devices created after this date or RFC 3339 timestamp
+    #[structopt(name = "created_after", long = "created-after", value_name = "date", conflicts_with_all = &["device", "group"])]
+    pub created_after: Option<String>,
+    /// Only list devices that belong to no group
+    #[structopt(long, conflicts_with_all = &["device", "group"])]
+    pub ungrouped: bool,
+}
+
+/*
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DeviceCreateArgs {
+    /// A device display name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// A device identifier (e.g. VIN)
+    #[structopt(short, long, value_name = "id")]
+    pub id: String,
+    /// Vehicle device type
+    #[structopt(short, long, conflicts_with = "other")]
+    pub vehicle: bool,
+    /// Other device type
+    #[structopt(short, long, conflicts_with = "vehicle")]
+    pub other: bool,
+}
+*/
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DeviceArgs {
+    /// The device id or name
+    #[structopt(short, long, value_name = "device")]
+    pub device: String,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum DeviceEcuCommand {
+    /// Remove an ECU from a device, e.g. after replacing it
+    #[structopt(aliases = &["rm", "delete"], setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Remove(DeviceEcuRemoveArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DeviceEcuRemoveArgs {
+    /// The device id or name
+    #[structopt(short, long, value_name = "device")]
+    pub device: String,
+    /// The serial of the ECU to remove
+    #[structopt(short, name = "ecu_serial", long = "ecu-serial", value_name = "serial")]
+    pub ecu_serial: String,
+    /// Remove the ECU without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DeviceWatchArgs {
+    /// The device id or name
+    #[structopt(short, long, value_name = "device")]
+    pub device: String,
+    /// Time between polls of the device (default: 5s)
+    #[structopt(short, long, value_name = "duration")]
+    pub interval: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DeviceImportArgs {
+    /// A file of name, id and type rows
+    #[structopt(short, long, value_name = "path")]
+    pub file: PathBuf,
+    /// Number of devices to create in parallel (default: 1)
+    #[structopt(short, long, value_name = "n")]
+    pub concurrency: Option<usize>,
+    /// Keep creating devices after a failure
+    #[structopt(name = "continue_on_error", long = "continue-on-error")]
+    pub continue_on_error: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DeviceDeleteArgs {
+    /// The device id
+    #[structopt(short, long, value_name = "uuid")]
+    pub device: Uuid,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct DeviceRenameArgs {
+    /// The device id or name
+    #[structopt(short, long, value_name = "device")]
+    pub device: String,
+    /// The new device name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+}
+
+// Available group sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum Group {
+    /// List groups and their devices
+    #[structopt(
+        alias = "ls",
+        setting = AppSettings::ArgRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    List(GroupListArgs),
+    /// Create a new group
+    #[structopt(alias = "new", setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Create(GroupCreateArgs),
+    /// Add a device to a group
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::DeriveDisplayOrder, setting = AppSettings::UnifiedHelpMessage)]
+    Add(GroupAddArgs),
+    /// Remove a device from a group
+    #[structopt(
+        alias = "rm",
+        setting = AppSettings::ArgRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Remove(GroupRemoveArgs),
+    /// Rename an existing group
+    #[structopt(
+        alias = "mv",
+        setting = AppSettings::ArgRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Rename(GroupRenameArgs),
+    /// Delete a group, leaving its devices in place
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Delete(GroupDeleteArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct GroupListArgs {
+    /// List all groups
+    #[structopt(short, long, conflicts_with_all = &["group", "device"])]
+    pub all: bool,
+    /// Number of groups to request per page
+    #[structopt(long, value_name = "n", requires = "all")]
+    pub limit: Option<u64>,
+    /// Fetch every page of groups
+    #[structopt(name = "all_pages", long = "all-pages", requires = "all")]
+    pub all_pages: bool,
+    /// List the devices in this group
+    #[structopt(short, long, value_name = "uuid", conflicts_with_all = &["device", "all"])]
+    pub group: Option<Uuid>,
+    /// List the groups for this device
+    #[structopt(short, long, value_name = "uuid", conflicts_with_all = &["group", "all"])]
+    pub device: Option<Uuid>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct GroupCreateArgs {
+    /// The group name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// Create a dynamic group matching devices by expression
+    #[structopt(long, requires = "expression")]
+    pub dynamic: bool,
+    /// The expression, e.g. "deviceid contains abc"
+    #[structopt(short, long, value_name = "expr", requires = "dynamic")]
+    pub expression: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct GroupAddArgs {
+    /// The group id or name to add the device to
+    #[structopt(short, long, value_name = "group")]
+    pub group: String,
+    /// The device id or name to add
+    #[structopt(short, long, value_name = "device")]
+    pub device: String,
+    /// Create a static group with this name if none exists
+    #[structopt(name = "create_if_missing", long = "create-if-missing")]
+    pub create_if_missing: bool,
+    /// Retry conflicts, treating an existing membership as success
+    #[structopt(name = "retry_on_conflict", long = "retry-on-conflict")]
+    pub retry_on_conflict: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct GroupRemoveArgs {
+    /// The group id or name to remove the device from
+    #[structopt(short, long, value_name = "group")]
+    pub group: String,
+    /// The device id or name to remove
+    #[structopt(short, long, value_name = "device")]
+    pub device: String,
+    /// Retry conflicts, treating a missing membership as success
+    #[structopt(name = "retry_on_conflict", long = "retry-on-conflict")]
+    pub retry_on_conflict: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct GroupRenameArgs {
+    /// The group to rename
+    #[structopt(short, long, value_name = "uuid")]
+    pub group: Uuid,
+    /// The new group name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct GroupDeleteArgs {
+    /// The group id or name to delete
+    #[structopt(short, long, value_name = "group")]
+    pub group: String,
+    /// Delete without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+// Available package sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum Package {
+    /// List available packages
+    #[structopt(alias = "ls")]
+    List,
+    /// Add a single package
+    #[structopt(
+        alias = "create",
+        setting = AppSettings::ArgRequiredElseHelp,
+        setting = AppSettings::DeriveDisplayOrder,
+        setting = AppSettings::UnifiedHelpMessage
+    )]
+    Add(PackageAddArgs),
+    /// Fetch package contents
+    #[structopt(alias = "get", setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Fetch(PackageArgs),
+    /// Delete a package from the repository
+    #[structopt(aliases = &["rm", "remove"], setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Delete(PackageDeleteArgs),
+    /// Download the contents of a package
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::DeriveDisplayOrder, setting = AppSettings::UnifiedHelpMessage)]
+    Download(PackageDownloadArgs),
+    /// Download all package contents into a directory
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::DeriveDisplayOrder, setting = AppSettings::UnifiedHelpMessage)]
+    FetchAll(PackageFetchAllArgs),
+    /// Save the signed repository metadata into a directory
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::DeriveDisplayOrder, setting = AppSettings::UnifiedHelpMessage)]
+    ExportMetadata(PackageExportMetadataArgs),
+    /// Upload multiple packages
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::DeriveDisplayOrder, setting = AppSettings::UnifiedHelpMessage)]
+    Upload(PackageUploadArgs),
+    /// Compare a package metadata file against the live repo
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::DeriveDisplayOrder, setting = AppSettings::UnifiedHelpMessage)]
+    Diff(PackageDiffArgs),
+    /// Copy a package into the repository of another config profile
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::DeriveDisplayOrder, setting = AppSettings::UnifiedHelpMessage)]
+    Promote(PackagePromoteArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct PackageArgs {
+    /// The package name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// The package version
+    #[structopt(short, long, value_name = "version")]
+    pub version: String,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct PackageAddArgs {
+    /// The package name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// The package version
+    #[structopt(short, long, value_name = "version")]
+    pub version: String,
+    /// Package works on these hardware IDs
+    #[structopt(short, long, value_name = "id", required = true)]
+    pub hardware: Vec<String>,
+    /// Path to package contents
+    #[structopt(short, long, value_name = "path", conflicts_with = "url")]
+    pub path: Option<PathBuf>,
+    /// URL to package contents, fetched by the reposerver without credentials
+    #[structopt(short, long, value_name = "url", conflicts_with = "path")]
+    pub url: Option<Url>,
+    /// Binary package format
+    #[structopt(short, long, conflicts_with = "ostree")]
+    pub binary: bool,
+    /// OSTree package format
+    #[structopt(short, long, conflicts_with = "binary")]
+    pub ostree: bool,
+    /// The SHA256 digest of the package contents, computed from --path when not given
+    #[structopt(long, value_name = "hash")]
+    pub sha256: Option<String>,
+    /// The length of the package contents
+    #[structopt(long, value_name = "bytes")]
+    pub length: Option<u64>,
+    /// Upload --path in parts of this size, resuming an interrupted upload
+    #[structopt(name = "chunk_size", long = "chunk-size", value_name = "MiB", requires = "path")]
+    pub chunk_size: Option<u64>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct PackageDeleteArgs {
+    /// The target filename, as shown by `package list`
+    #[structopt(short, long, value_name = "filename")]
+    pub target: String,
+    /// Delete without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct PackageDownloadArgs {
+    /// The package name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// The package version
+    #[structopt(short, long, value_name = "version")]
+    pub version: String,
+    /// File to save the contents in (default: the target filename)
+    #[structopt(short, long, value_name = "file")]
+    pub out: Option<PathBuf>,
+    /// Check the download against the target length and sha256 hash
+    #[structopt(long)]
+    pub verify: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct PackageFetchAllArgs {
+    /// Directory to save the packages in
+    #[structopt(name = "out_dir", short, long = "out-dir", value_name = "dir")]
+    pub out_dir: PathBuf,
+    /// Only fetch packages with this name
+    #[structopt(short, long, value_name = "name")]
+    pub name: Option<String>,
+    /// Only fetch packages for this hardware ID
+    #[structopt(short, long, value_name = "id")]
+    pub hardware: Option<String>,
+    /// Check each download against the target length and sha256 hash
+    #[structopt(long)]
+    pub verify: bool,
+    /// Number of packages to download in parallel (default: 1)
+    #[structopt(short, long, value_name = "n")]
+    pub concurrency: Option<usize>,
+    /// Stop after the first failed download
+    #[structopt(name = "fail_fast", long = "fail-fast")]
+    pub fail_fast: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct PackageExportMetadataArgs {
+    /// Directory to save the metadata in
+    #[structopt(name = "out_dir", short, long = "out-dir", value_name = "dir")]
+    pub out_dir: PathBuf,
+    /// Also save the snapshot, timestamp and root metadata
+    #[structopt(short, long)]
+    pub all: bool,
+    /// Save the metadata exactly as served (default)
+    #[structopt(long, conflicts_with = "pretty")]
+    pub raw: bool,
+    /// Save the metadata as pretty-printed JSON
+    #[structopt(long, conflicts_with = "raw")]
+    pub pretty: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct PackageUploadArgs {
+    /// Package metadata file
+    #[structopt(short, long, value_name = "toml")]
+    pub packages: PathBuf,
+    /// Number of packages to upload in parallel (default: 1)
+    #[structopt(short, long, value_name = "n")]
+    pub concurrency: Option<usize>,
+    /// Stop after the first failed upload
+    #[structopt(name = "fail_fast", long = "fail-fast")]
+    pub fail_fast: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct PackageDiffArgs {
+    /// Package metadata file
+    #[structopt(short, long, value_name = "toml")]
+    pub file: PathBuf,
+    /// Only compare packages with this name
+    #[structopt(short, long, value_name = "name")]
+    pub name: Option<String>,
+    /// Only compare packages for this hardware ID
+    #[structopt(short, long, value_name = "id")]
+    pub hardware: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct PackagePromoteArgs {
+    /// The package name
+    #[structopt(short, long, value_name = "name")]
+    pub name: String,
+    /// The package version
+    #[structopt(short, long, value_name = "version")]
+    pub version: String,
+    /// The profile of the repository to copy the package into, e.g. prod
+    #[structopt(name = "to_profile", long = "to-profile", value_name = "name")]
+    pub to_profile: String,
+}
+
+// Available repo sub-commands, each with its own sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum RepoCommand {
+    /// Manage the signed root metadata
+    #[structopt(setting = AppSettings::SubcommandRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Root(RootCommand),
+    /// Manage the keys of the root of trust
+    #[structopt(setting = AppSettings::SubcommandRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Keys(KeysCommand),
+    /// Compare the current targets with a saved targets.json or the targets of another namespace
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Diff(RepoDiffArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct RepoDiffArgs {
+    /// A targets.json saved earlier, e.g. by package export-metadata
+    #[structopt(short, long, value_name = "path", required_unless = "against", conflicts_with = "against")]
+    pub file: Option<PathBuf>,
+    /// Compare with the targets of this namespace instead
+    #[structopt(short, long, value_name = "namespace")]
+    pub against: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum RootCommand {
+    /// Show the roles of the root metadata
+    #[structopt(setting = AppSettings::UnifiedHelpMessage)]
+    Show(RootShowArgs),
+    /// Replace the root with a new version signed offline
+    #[structopt(alias = "rotatekeys", setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    RotateKeys(RootRotateKeysArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct RootShowArgs {
+    /// Show this previous root version instead of the current one
+    #[structopt(short, long, value_name = "n")]
+    pub version: Option<u64>,
+    /// Save the signed root metadata to this file for audit
+    #[structopt(short, long, value_name = "file")]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct RootRotateKeysArgs {
+    /// The new signed root metadata
+    #[structopt(short, long, value_name = "path")]
+    pub file: PathBuf,
+    /// Replace the root without asking for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum KeysCommand {
+    /// List the root keys and the roles they sign for
+    #[structopt(alias = "ls")]
+    List,
+}
+
+// Available report sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum Report {
+    /// Report the groups and installed image of every device
+    #[structopt(setting = AppSettings::UnifiedHelpMessage)]
+    Fleet(ReportFleetArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct ReportFleetArgs {
+    /// Save the report to this file, as JSON for a .json file and CSV otherwise
+    #[structopt(short, long, value_name = "file")]
+    pub out: Option<PathBuf>,
+}
+
+// Available offline signing sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum Sign {
+    /// Save the next, unsigned version of the targets metadata
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Pull(SignPullArgs),
+    /// Sign a targets metadata file with a private key, which is never uploaded
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::DeriveDisplayOrder, setting = AppSettings::UnifiedHelpMessage)]
+    Targets(SignTargetsArgs),
+    /// Upload signed targets metadata
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Push(SignPushArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct SignPullArgs {
+    /// File to save the unsigned targets metadata in
+    #[structopt(short, long, value_name = "file")]
+    pub out: PathBuf,
+    /// Expire the new version after this duration, e.g. 30d
+    #[structopt(short, long, value_name = "duration")]
+    pub expires: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct SignTargetsArgs {
+    /// The targets metadata to sign
+    #[structopt(short, long, value_name = "file")]
+    pub file: PathBuf,
+    /// An ed25519 or RSA private key in PEM format
+    #[structopt(short, long, value_name = "pem")]
+    pub key: PathBuf,
+    /// Sign as this key id instead of the one computed from the key
+    #[structopt(long, value_name = "id")]
+    pub keyid: Option<String>,
+    /// Save the signed metadata here instead of updating --file
+    #[structopt(short, long, value_name = "file")]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct SignPushArgs {
+    /// The signed targets metadata
+    #[structopt(short, long, value_name = "file")]
+    pub file: PathBuf,
+}
+
+// Available treehub sub-commands, each with its own sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum TreehubCommand {
+    /// Manage the refs of the OSTree targets
+    #[structopt(setting = AppSettings::SubcommandRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Refs(RefsCommand),
+    /// Inspect a single ref
+    #[structopt(setting = AppSettings::SubcommandRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Ref(RefCommand),
+    /// Inspect OSTree commits
+    #[structopt(setting = AppSettings::SubcommandRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Commit(CommitCommand),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum RefsCommand {
+    /// List the refs of the OSTree targets with the commit each points to
+    #[structopt(alias = "ls")]
+    List,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum RefCommand {
+    /// Show the commit a ref points to
+    #[structopt(alias = "get", setting = AppSettings::ArgRequiredElseHelp)]
+    Show(RefShowArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct RefShowArgs {
+    /// The ref name
+    #[structopt(name = "ref", short = "r", long = "ref", value_name = "name")]
+    pub name: String,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum CommitCommand {
+    /// Check that a commit has been pushed to treehub
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp)]
+    Exists(CommitExistsArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct CommitExistsArgs {
+    /// The commit hash
+    #[structopt(short, long, value_name = "hash")]
+    pub commit: String,
+}
+
+// Available update sub-commands.
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub enum Update {
+    /// Create a multi-target update
+    #[structopt(aliases = &["add", "new"], setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Create(UpdateCreateArgs),
+    /// Launch a multi-target update
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Launch(UpdateLaunchArgs),
+    /// Show the targets of a multi-target update
+    #[structopt(alias = "get", setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Show(UpdateArgs),
+    /// Show whether each ECU of some devices is pending, installed or failed
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Status(UpdateStatusArgs),
+    /// Show the updates queued for a device, which it receives on its next poll
+    #[structopt(setting = AppSettings::ArgRequiredElseHelp, setting = AppSettings::UnifiedHelpMessage)]
+    Pending(UpdatePendingArgs),
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct UpdatePendingArgs {
+    /// The device id
+    #[structopt(short, long, value_name = "uuid")]
+    pub device: Uuid,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct UpdateArgs {
+    /// Multi-target update id
+    #[structopt(short, long, value_name = "uuid")]
+    pub update: Uuid,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct UpdateCreateArgs {
+    /// Update targets file
+    #[structopt(short, long, value_name = "toml", conflicts_with = "hardware")]
+    pub targets: Option<PathBuf>,
+    /// Update these hardware IDs to an inline target
+    #[structopt(short, long, value_name = "id", required_unless = "targets")]
+    pub hardware: Vec<String>,
+    /// The target package name
+    #[structopt(short, long, value_name = "name", required_unless = "targets")]
+    pub name: Option<String>,
+    /// The target package version
+    #[structopt(short, long, value_name = "version", required_unless = "targets")]
+    pub version: Option<String>,
+    /// The target format (binary or ostree)
+    #[structopt(short, long, value_name = "format", required_unless = "targets")]
+    pub format: Option<String>,
+    /// The target length, required for binary targets
+    #[structopt(long, value_name = "bytes")]
+    pub length: Option<u64>,
+    /// The target sha256 hash (default: the version)
+    #[structopt(long, value_name = "hash")]
+    pub hash: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct UpdateLaunchArgs {
+    /// Multi-target update id
+    #[structopt(short, long, value_name = "uuid")]
+    pub update: Uuid,
+    /// Apply to this device
+    #[structopt(short, long, value_name = "uuid", required_unless = "group")]
+    pub device: Option<Uuid>,
+    /// Apply to every device in this group, by UUID or name
+    #[structopt(short, long, value_name = "group", conflicts_with = "device")]
+    pub group: Option<String>,
+    /// Number of devices to launch in parallel (default: 1)
+    #[structopt(short, long, value_name = "n", requires = "group")]
+    pub concurrency: Option<usize>,
+    /// Stop after the first failed launch
+    #[structopt(name = "fail_fast", long = "fail-fast", requires = "group")]
+    pub fail_fast: bool,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(no_version)]
+pub struct UpdateStatusArgs {
+    /// Multi-target update id
+    #[structopt(short, long, value_name = "uuid")]
+    pub update: Uuid,
+    /// The devices the update was launched for
+    #[structopt(short, long, value_name = "uuid", required = true)]
+    pub device: Vec<Uuid>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ErrorKind;
+
+    fn parse(args: &[&str]) -> Result<Command, clap::Error> { Cli::from_iter_safe(std::iter::once("ota").chain(args.iter().copied())).map(|cli| cli.command) }
+
+    #[test]
+    fn subcommand_aliases() {
+        assert!(matches!(parse(&["pkg", "ls"]), Ok(Command::Package(Package::List))));
+        assert!(matches!(parse(&["packages", "get", "-n", "os", "-v", "1"]), Ok(Command::Package(Package::Fetch(_)))));
+        assert!(matches!(parse(&["package", "create", "-n", "os", "-v", "1", "-h", "rpi"]), Ok(Command::Package(Package::Add(_)))));
+        assert!(matches!(parse(&["devices", "mv", "-d", "d1", "-n", "d2"]), Ok(Command::Device(Device::Rename(_)))));
+        assert!(matches!(parse(&["device", "rm", "-d", UUID]), Ok(Command::Device(Device::Delete(_)))));
+        assert!(matches!(parse(&["campaigns", "list-updates"]), Ok(Command::Campaign(Campaign::ListUpdates))));
+        assert!(matches!(parse(&["campaign", "edit", "-c", UUID]), Ok(Command::Campaign(Campaign::Update(_)))));
+        assert!(matches!(parse(&["campaign", "create-update", "-u", UUID, "-n", "u", "-d", "d"]), Ok(Command::Campaign(Campaign::CreateUpdate(_)))));
+        assert!(matches!(parse(&["config", "logout"]), Ok(Command::Config(ConfigCommand::ClearToken))));
+        assert!(matches!(parse(&["groups", "new", "-n", "eu"]), Ok(Command::Group(Group::Create(_)))));
+        assert!(matches!(parse(&["group", "rm", "-g", "eu", "-d", "d1"]), Ok(Command::Group(Group::Remove(_)))));
+        assert!(matches!(parse(&["updates", "get", "-u", UUID]), Ok(Command::Update(Update::Show(_)))));
+        assert!(matches!(parse(&["update", "add", "-t", "targets.toml"]), Ok(Command::Update(Update::Create(_)))));
+        assert!(matches!(parse(&["repository", "root", "rotatekeys", "-f", "root.json"]), Ok(Command::Repo(RepoCommand::Root(RootCommand::RotateKeys(_))))));
+        assert!(matches!(parse(&["ostree", "ref", "get", "-r", "os"]), Ok(Command::Treehub(TreehubCommand::Ref(RefCommand::Show(_))))));
+        assert!(matches!(parse(&["dev", "ls", "-a"]), Ok(Command::Device(Device::List(_)))));
+        assert!(matches!(parse(&["device", "ecu", "rm", "-d", "d1", "-e", "ecu-1"]), Ok(Command::Device(Device::Ecu(DeviceEcuCommand::Remove(_))))));
+    }
+
+    #[test]
+    fn typed_args() {
+        match parse(&["campaign", "create", "-u", UUID, "-n", "c", "-g", UUID, UUID, "--approval-required"]) {
+            Ok(Command::Campaign(Campaign::Create(args))) => {
+                assert_eq!(args.update, UUID.parse().unwrap());
+                assert_eq!(args.groups.len(), 2);
+                assert!(args.approval_required);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse(&["--namespaces", "acme,globex", "update", "launch", "-u", UUID, "-g", "eu", "-c", "4"]) {
+            Ok(Command::Update(Update::Launch(args))) => {
+                assert_eq!((args.device, args.group.as_deref(), args.concurrency), (None, Some("eu"), Some(4)));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let args = Cli::clap().get_matches_from(vec!["ota", "campaign", "retry", "-c", UUID, "--failure-code", "E1"]);
+        let args = args.subcommand().1.and_then(|args| args.subcommand().1).expect("retry args");
+        assert_eq!(args.value_of("failure_code"), Some("E1"));
+    }
+
+    #[test]
+    fn validate_args() {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        let cases = vec![
+            (vec!["campaign", "stats", "--campaign", "nope"], ErrorKind::ValueValidation, "--campaign <uuid>"),
+            (vec!["campaign", "create", "-u", UUID, "-n", "c", "-g", UUID, "eu"], ErrorKind::ValueValidation, "--groups <uuid>"),
+            (vec!["campaign", "resolve", "-c", UUID, "--devices", "d1"], ErrorKind::ValueValidation, "--devices <uuid>"),
+            (vec!["campaign", "createupdate", "-u", UUID], ErrorKind::MissingRequiredArgument, "--name <name>"),
+            (vec!["device", "delete", "--device", "d1"], ErrorKind::ValueValidation, "--device <uuid>"),
+            (vec!["group", "rename", "--group", "eu", "--name", "europe"], ErrorKind::ValueValidation, "--group <uuid>"),
+            (vec!["update", "launch", "--device", UUID], ErrorKind::MissingRequiredArgument, "--update <uuid>"),
+            (vec!["update", "status", "-u", UUID, "--device", "d1"], ErrorKind::ValueValidation, "--device <uuid>"),
+            (vec!["init", "--director", "not a url"], ErrorKind::ValueValidation, "--director <url>"),
+            (vec!["package", "add", "-n", "os", "-v", "1", "-h", "rpi", "--length", "ten"], ErrorKind::ValueValidation, "--length <bytes>"),
+            (vec!["--proxy", "::", "package", "list"], ErrorKind::ValueValidation, "--proxy <url>"),
+            (vec!["completions", "tcsh"], ErrorKind::InvalidValue, "tcsh"),
+        ];
+        for (args, kind, flag) in cases {
+            let err = parse(&args).expect_err(&format!("expected an error for {:?}", args));
+            assert_eq!(err.kind, kind, "{}", err.message);
+            assert!(err.message.contains(flag), "{}", err.message);
+        }
+    }
+
+    const UUID: &str = "00000000-0000-0000-0000-000000000001";
+}
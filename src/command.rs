@@ -1,5 +1,8 @@
 use std::collections::HashMap;
-use std::io::{self, Read};
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use clap::ArgMatches;
@@ -8,32 +11,79 @@ use reqwest::header::HeaderMap;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::Value;
+use structopt::StructOpt;
 
 use crate::api::{
-    campaigner::{Campaigner, CampaignerApi},
+    campaigner::{Campaigner, CampaignerApi, WaitOptions},
+    delegation::{DelegationApi, Delegations},
     director::{Director, DirectorApi, TargetRequests, TufUpdates},
-    registry::{DeviceType, GroupType, Registry, RegistryApi},
-    reposerver::{Reposerver, ReposerverApi, TargetPackages, TufPackage, TufPackages},
+    registry::{DeviceRow, Registry, RegistryApi},
+    repo::Repo,
+    treehub::{Treehub, TreehubApi},
+    reposerver::{Reposerver, ReposerverApi, TargetFilter, TargetPackages, TufPackages},
 };
-use crate::config::Config;
+use crate::batch::BatchOptions;
+use crate::cli::{AuthCommand, Campaign, Command, ConfigCommand, Delegation, Device, Group, Package, RepoCommand, Report, Sign, TreehubCommand, Update};
+use crate::cli::{Cli, CommitCommand, DeviceEcuCommand, KeysCommand, RefCommand, RefsCommand, RootCommand};
+use crate::progress::ProgressMode;
+use crate::report;
+use crate::shell;
+use crate::sign;
+use crate::watch;
+use crate::config::{self, Config, SETTABLE_KEYS};
 use crate::error::{Error, Result};
+use crate::table::TableOptions;
+pub use crate::table::TableResult;
 
 /// Execute a command then handle the HTTP `Response`.
 pub trait Exec<'a> {
     fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult>;
 }
 
-/// Available CLI sub-commands.
-#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
-pub enum Command {
-    Init,
-    Campaign,
-    Device,
-    Group,
-    Package,
-    Update,
+/// Ask on stdin to confirm a disruptive action unless `--yes` was passed.
+///
+/// Without a terminal to ask on, the action is refused unless `--yes` was passed.
+pub fn confirm(yes: bool, prompt: &str) -> Result<()> {
+    if yes {
+        return Ok(());
+    } else if !io::stdin().is_terminal() {
+        return Err(Error::Args(format!("{} Pass --yes to confirm.", prompt)));
+    }
+
+    eprint!("{} [y/N] ", prompt);
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_ref() {
+        "y" | "yes" => Ok(()),
+        _ => Err(Error::Command("aborted".into())),
+    }
 }
 
+/// Ask on stdin for the value of a missing `--name` flag until `parse` accepts the answer.
+///
+/// Without a terminal to ask on, the flag is reported as missing.
+pub fn prompt<T>(name: &str, label: &str, parse: impl Fn(&str) -> Result<T>) -> Result<T> {
+    if !io::stdin().is_terminal() {
+        return Err(missing(name));
+    }
+    loop {
+        eprint!("{}: ", label);
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Err(missing(name));
+        }
+        match parse(answer.trim()) {
+            Ok(value) => return Ok(value),
+            Err(err) => eprintln!("{}", err),
+        }
+    }
+}
+
+/// An `Error::Args` naming a missing flag, for args that are only required in some combinations.
+pub fn missing(name: &str) -> Error { Error::Args(format!("missing value for --{}. See --help for usage", flag(name))) }
+
+fn flag(name: &str) -> String { name.replace('_', "-") }
+
 pub enum CommandResult {
     Table(TableResult),
     Http(Response),
@@ -72,302 +122,752 @@ impl From<TableResult> for CommandResult {
     }
 }
 
-pub struct TableResult {
-    pub headers: HeaderMap,
-    pub table: comfy_table::Table,
-    pub response: Vec<u8>,
+/// Available formats for printing a `CommandResult`.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    Raw,
+    Table,
+    Json,
+    Compact,
+    Yaml,
+    Csv,
 }
 
-impl TableResult {
-    pub fn new(headers: HeaderMap, response: Vec<u8>, table: comfy_table::Table) -> TableResult {
-        TableResult { headers, table, response }
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match s.to_lowercase().as_ref() {
+            "raw"   => Ok(OutputFormat::Raw),
+            "table" => Ok(OutputFormat::Table),
+            "json"  => Ok(OutputFormat::Json),
+            "compact" => Ok(OutputFormat::Compact),
+            "yaml"  => Ok(OutputFormat::Yaml),
+            "csv"   => Ok(OutputFormat::Csv),
+            _ => Err(Error::Parse(format!("unknown `OutputFormat`: {}", s))),
+        }
     }
 }
 
-pub fn print_command_result(use_tables: bool, resp: CommandResult) -> Result<()> {
-    debug!("response headers:\n{:#?}", resp.headers());
+/// The environment variable setting the default output format.
+pub const OUTPUT_ENV: &str = "OTA_OUTPUT";
+
+impl<'a> OutputFormat {
+    /// Resolve the output format from `--output`, then `--usetables`, then a `.csv` `--output-file`,
+    /// then `OTA_OUTPUT`, defaulting to a table with `--columns`, `--sort-by` or `--save-response` and raw otherwise.
+    pub fn from_args(args: &ArgMatches<'a>) -> Result<Self> { Self::from_args_with(args, env::var(OUTPUT_ENV).ok()) }
+
+    /// Resolve the output format as with `from_args`, given the value of `OTA_OUTPUT`.
+    fn from_args_with(args: &ArgMatches<'a>, from_env: Option<String>) -> Result<Self> {
+        let csv_file = args.value_of("output_file").is_some_and(|file| file.to_lowercase().ends_with(".csv"));
+        match args.value_of("output") {
+            Some(output) => output.parse(),
+            None if args.is_present("usetables") => Ok(OutputFormat::Table),
+            None if csv_file => Ok(OutputFormat::Csv),
+            None => match from_env {
+                Some(output) => output.parse().map_err(|_| Error::Args(format!("{} must be one of raw, table, json, compact, yaml or csv, got {}", OUTPUT_ENV, output))),
+                None if ["columns", "sort_by", "save_response"].iter().any(|arg| args.is_present(arg)) => Ok(OutputFormat::Table),
+                None => Ok(OutputFormat::Raw),
+            },
+        }
+    }
+}
 
-    match resp {
-        CommandResult::Table(r) if use_tables => {
-            io::copy(&mut r.table.to_string().as_bytes(), &mut io::stdout())?;
-            ()
+/// Where and how to write a command result, from `--output`, `--output-file`, the table options and `--save-response`.
+pub struct Output {
+    pub format: OutputFormat,
+    pub table: TableOptions,
+    save: Option<PathBuf>,
+    file: Option<PathBuf>,
+}
+
+impl<'a> Output {
+    /// Resolve the output options before the command runs, so bad ones fail before any change is made.
+    pub fn from_args(args: &ArgMatches<'a>) -> Result<Self> {
+        Ok(Output {
+            format: OutputFormat::from_args(args)?,
+            table: TableOptions::from_args(args),
+            save: args.value_of("save_response").map(PathBuf::from),
+            file: args.value_of("output_file").map(PathBuf::from),
+        })
+    }
+
+    /// Run a command that writes its served body out as it is read, instead of keeping it in memory.
+    ///
+    /// The body goes to the `--save-response` file and, for raw output, to the `--output-file` or
+    /// stdout, leaving only the rows of the result to `write`.
+    pub fn streamed(&self, run: impl FnOnce(Option<&mut dyn Write>) -> Result<TableResult>) -> Result<CommandResult> {
+        let mut sinks: Vec<Box<dyn Write>> = Vec::new();
+        if let Some(ref path) = self.save {
+            sinks.push(Box::new(fs::File::create(path)?));
+        }
+        if self.format == OutputFormat::Raw {
+            sinks.push(match self.file {
+                Some(ref path) => Box::new(fs::File::create(path)?),
+                None => Box::new(io::stdout()),
+            });
+        }
+        if sinks.is_empty() {
+            return Ok(run(None)?.into());
         }
 
-        CommandResult::Table(r) => {
-            print_http_response(&mut r.response.as_slice())?;
-            ()
+        let mut sink = Tee(sinks);
+        let mut result = run(Some(&mut sink as &mut dyn Write))?;
+        sink.flush()?;
+        if let Some(ref path) = self.save {
+            info!("saved response to {}", path.display());
         }
+        result.streamed = true;
+        Ok(result.into())
+    }
 
-        CommandResult::Http(mut r) => {
-            print_http_response(&mut r)?;
-            ()
+    /// Write a command result to the `--output-file`, or print it.
+    pub fn write(&self, result: CommandResult) -> Result<()> {
+        if let CommandResult::Table(ref table) = result {
+            if table.streamed && self.format == OutputFormat::Raw {
+                return Ok(());
+            }
+        }
+        match self.file {
+            Some(ref path) => write_command_result(self.format, &self.table, self.save.as_deref(), result, &mut fs::File::create(path)?),
+            None => print_command_result(self.format, &self.table, self.save.as_deref(), result),
         }
+    }
+}
+
+/// A writer copying everything to each of its writers.
+struct Tee(Vec<Box<dyn Write>>);
 
-        CommandResult::Empty => (),
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for out in &mut self.0 {
+            out.write_all(buf)?;
+        }
+        Ok(buf.len())
     }
 
-    Ok(())
+    fn flush(&mut self) -> io::Result<()> {
+        for out in &mut self.0 {
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub fn print_command_result(format: OutputFormat, options: &TableOptions, save: Option<&Path>, resp: CommandResult) -> Result<()> {
+    write_command_result(format, options, save, resp, &mut io::stdout())
 }
 
-fn print_http_response(resp: &mut dyn Read) -> Result<()> {
-    let mut body = Vec::new();
-    debug!("response length: {}\n", resp.read_to_end(&mut body)?);
+/// Write a `CommandResult` in some format, e.g. to an `--output-file`.
+///
+/// The table `options` apply to table results, and to server responses rendered as a table or CSV.
+/// The raw response body is also written to `save`, for `--save-response`. An error status from
+/// the server fails with its body instead of being written.
+pub fn write_command_result(format: OutputFormat, options: &TableOptions, save: Option<&Path>, resp: CommandResult, out: &mut dyn Write) -> Result<()> {
+    debug!("response headers:\n{:#?}", resp.headers());
+
+    match resp {
+        CommandResult::Table(r) => {
+            if let Some(path) = save.filter(|_| !r.streamed) {
+                if r.response.is_empty() {
+                    save_response(path, &serde_json::to_vec_pretty(&r)?)?;
+                } else {
+                    save_response(path, &r.response)?;
+                }
+            }
+            write_table_result(format, options, r, out)
+        }
+        CommandResult::Http(mut r) => {
+            let mut body = Vec::new();
+            debug!("response length: {}\n", r.read_to_end(&mut body)?);
+            if let Err(err) = r.error_for_status_ref() {
+                return Err(Error::Status(err, String::from_utf8_lossy(&body).trim().to_owned()));
+            }
+            if let Some(path) = save {
+                save_response(path, &body)?;
+            }
+            write_http_response(format, options, r.headers().clone(), body, out)
+        }
+        CommandResult::Empty => {
+            if let Some(path) = save {
+                warn!("no response to save to {}", path.display());
+            }
+            Ok(())
+        }
+    }
+}
 
-    let out = if let Ok(json) = serde_json::from_slice::<Value>(&body) {
-        serde_json::to_vec_pretty(&json)?
-    } else {
-        body
-    };
+/// Write a raw response body to a file, as served.
+fn save_response(path: &Path, body: &[u8]) -> Result<()> {
+    fs::write(path, body)?;
+    info!("saved response to {}", path.display());
+    Ok(())
+}
 
-    io::copy(&mut out.as_slice(), &mut io::stdout())?;
+/// Write the rows of a table result, or the server response for raw output.
+fn write_table_result(format: OutputFormat, options: &TableOptions, mut r: TableResult, out: &mut dyn Write) -> Result<()> {
+    if format != OutputFormat::Raw {
+        options.apply(&mut r)?;
+    }
+    match format {
+        OutputFormat::Table => write!(out, "{}", r.table())?,
+        OutputFormat::Json => out.write_all(&serde_json::to_vec_pretty(&r)?)?,
+        OutputFormat::Compact => out.write_all(&serde_json::to_vec(&r)?)?,
+        OutputFormat::Yaml => serde_yaml::to_writer(out, &r)?,
+        OutputFormat::Csv => out.write_all(r.csv().as_bytes())?,
+        OutputFormat::Raw => write_pretty_body(&r.response, out)?,
+    }
+    Ok(())
+}
+
+/// Write a server response body, rendered as a table when it holds JSON objects or lists.
+fn write_http_response(format: OutputFormat, options: &TableOptions, headers: HeaderMap, body: Vec<u8>, out: &mut dyn Write) -> Result<()> {
+    match format {
+        OutputFormat::Table => match TableResult::from_json(headers, body.clone()) {
+            Some(table) => write_table_result(format, options, table, out),
+            None => write_pretty_body(&body, out),
+        },
+        OutputFormat::Csv => match TableResult::from_json(headers, body) {
+            Some(table) => write_table_result(format, options, table, out),
+            None => Err(Error::Args("CSV output needs a JSON list or object result".into())),
+        },
+        OutputFormat::Compact => match serde_json::from_slice::<Value>(&body) {
+            Ok(json) => Ok(out.write_all(&serde_json::to_vec(&json)?)?),
+            Err(_) => Ok(out.write_all(&body)?),
+        },
+        OutputFormat::Yaml => match serde_json::from_slice::<Value>(&body) {
+            Ok(json) => Ok(serde_yaml::to_writer(out, &json)?),
+            Err(_) => Ok(out.write_all(&body)?),
+        },
+        OutputFormat::Raw | OutputFormat::Json => write_pretty_body(&body, out),
+    }
+}
 
+/// Write a body as pretty-printed JSON, or unchanged when it is not JSON.
+fn write_pretty_body(body: &[u8], out: &mut dyn Write) -> Result<()> {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(json) => out.write_all(&serde_json::to_vec_pretty(&json)?)?,
+        Err(_) => out.write_all(body)?,
+    }
     Ok(())
 }
 
+/// Run a list command once per namespace, merging the results into one table with a `namespace` column.
+pub fn fan_out(namespaces: &[&str], mut run: impl FnMut() -> Result<CommandResult>) -> Result<CommandResult> {
+    let mut merged = TableResult::new(HeaderMap::new(), Vec::new(), &["namespace"]);
+    for namespace in namespaces {
+        debug!("listing namespace {}", namespace);
+        let table = match config::with_namespace(namespace, &mut run)? {
+            CommandResult::Table(table) => table,
+            CommandResult::Http(resp) => {
+                let mut resp = resp.error_for_status()?;
+                let mut body = Vec::new();
+                resp.read_to_end(&mut body)?;
+                TableResult::from_json(resp.headers().clone(), body).ok_or_else(|| Error::Parse(format!("namespace {} returned no list to merge", namespace)))?
+            }
+            CommandResult::Empty => continue,
+        };
+        for column in table.columns {
+            if !merged.columns.contains(&column) {
+                merged.columns.push(column);
+            }
+        }
+        for mut row in table.rows {
+            row.insert("namespace".into(), (*namespace).into());
+            merged.rows.push(row);
+        }
+    }
+    merged.response = serde_json::to_vec(&merged)?;
+    Ok(merged.into())
+}
+
 impl<'a> Exec<'a> for Command {
     fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
-        if let Command::Init = self {
-            Config::init_from_args(args)?;
-            Ok(CommandResult::Empty)
-        } else {
-            let (cmd, args) = args.subcommand();
-            let args = args.expect("sub-command args");
-            #[cfg_attr(rustfmt, rustfmt_skip)]
-            match self {
-                Command::Campaign => cmd.parse::<Campaign>()?.exec(args),
-                Command::Device   => cmd.parse::<Device>()?.exec(args),
-                Command::Group    => cmd.parse::<Group>()?.exec(args),
-                Command::Package  => cmd.parse::<Package>()?.exec(args),
-                Command::Update   => cmd.parse::<Update>()?.exec(args),
-                Command::Init     => unreachable!()
+        match self {
+            Command::Init(cmd) => {
+                Config::init_from_args(cmd, args)?;
+                Ok(CommandResult::Empty)
+            }
+            Command::Completions(cmd) => {
+                Cli::clap().gen_completions_to("ota", cmd.shell, &mut io::stdout());
+                Ok(CommandResult::Empty)
+            }
+            Command::Shell => shell::run(args),
+            _ => {
+                let (cmd, args) = args.subcommand();
+                let args = args.ok_or_else(|| Error::Args(format!("missing sub-command for {}", cmd)))?;
+                if let Some(namespaces) = args.values_of("namespaces") {
+                    if cmd != "list" {
+                        return Err(Error::Args(format!("--namespaces only works with list commands, not {}", cmd)));
+                    }
+                    return fan_out(&namespaces.collect::<Vec<_>>(), || self.exec_subcommand(args));
+                }
+                self.exec_subcommand(args)
             }
         }
     }
 }
 
-impl FromStr for Command {
-    type Err = Error;
-
-    fn from_str(s: &str) -> Result<Self> {
+impl Command {
+    fn exec_subcommand(&self, args: &ArgMatches<'_>) -> Result<CommandResult> {
         #[cfg_attr(rustfmt, rustfmt_skip)]
-        match s.to_lowercase().as_ref() {
-            "init"     => Ok(Command::Init),
-            "campaign" => Ok(Command::Campaign),
-            "device"   => Ok(Command::Device),
-            "group"    => Ok(Command::Group),
-            "package"  => Ok(Command::Package),
-            "update"   => Ok(Command::Update),
-            _ => Err(Error::Command(format!("unknown command: {}", s))),
+        match self {
+            Command::Auth(cmd)       => cmd.exec(args),
+            Command::Campaign(cmd)   => cmd.exec(args),
+            Command::Config(cmd)     => cmd.exec(args),
+            Command::Delegation(cmd) => cmd.exec(args),
+            Command::Device(cmd)     => cmd.exec(args),
+            Command::Group(cmd)      => cmd.exec(args),
+            Command::Package(cmd)    => cmd.exec(args),
+            Command::Repo(cmd)       => cmd.exec(args),
+            Command::Report(cmd)     => cmd.exec(args),
+            Command::Sign(cmd)       => cmd.exec(args),
+            Command::Treehub(cmd)    => cmd.exec(args),
+            Command::Update(cmd)     => cmd.exec(args),
+            Command::Init(_) | Command::Completions(_) | Command::Shell => unreachable!(),
         }
     }
 }
 
-/// Available campaign sub-commands.
-#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
-pub enum Campaign {
-    List,
-    Create,
-    Launch,
-    Cancel,
-    ListUpdates,
-    CreateUpdate,
-}
-
 impl<'a> Exec<'a> for Campaign {
     fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
-        let mut config = Config::load_default()?;
-        let campaign = || args.value_of("campaign").expect("--campaign").parse();
-        let update = || args.value_of("update").expect("--update").parse();
-        let name = || args.value_of("name").expect("--name");
-        let description = || args.value_of("description").expect("--description");
+        let mut config = Config::load_from_args(args)?;
 
         #[cfg_attr(rustfmt, rustfmt_skip)]
         match self {
-            Campaign::List   => Campaigner::list_from_args(&mut config, args),
-            Campaign::Create => Campaigner::create_from_args(&mut config, args),
-            Campaign::Launch => Campaigner::launch_campaign(&mut config, campaign()?),
-            Campaign::Cancel => Campaigner::cancel_campaign(&mut config, campaign()?),
-            Campaign::ListUpdates  => Campaigner::list_updates(&mut config,),
-            Campaign::CreateUpdate  => Campaigner::create_update(&mut config, update()?, name(), description())
+            Campaign::List(cmd)    => Campaigner::list_from_args(&mut config, cmd),
+            Campaign::Create(cmd)  => Campaigner::create_campaign(&mut config, cmd.update, &cmd.name, &cmd.groups, cmd.approval_required),
+            Campaign::Update(cmd)  => Campaigner::update_from_args(&mut config, cmd),
+            Campaign::Launch(cmd)  => Campaigner::launch_campaign(&mut config, cmd.campaign),
+            Campaign::Cancel(cmd)  => Campaigner::cancel_from_args(&mut config, cmd),
+            Campaign::Retry(cmd)   => Campaigner::retry_failed(&mut config, cmd.campaign, cmd.failure_code.as_deref()),
+            Campaign::Stats(cmd)   => Campaigner::list_campaign_stats(&mut config, cmd.campaign),
+            Campaign::Devices(cmd) => Campaigner::devices_from_args(&mut config, cmd),
+            Campaign::Approvals(cmd) => Campaigner::list_approvals(&mut config, cmd.campaign),
+            Campaign::Resolve(cmd) => Campaigner::resolve_from_args(&mut config, cmd),
+            Campaign::Wait(cmd)    => Campaigner::wait(&mut config, cmd.campaign, WaitOptions::from_args(cmd)?),
+            Campaign::ListUpdates  => Campaigner::list_updates(&mut config),
+            Campaign::CreateUpdate(cmd) => Campaigner::create_update(&mut config, cmd.update, &cmd.name, &cmd.description),
         }
-            .map(|r| r.into())
     }
 }
 
-impl FromStr for Campaign {
-    type Err = Error;
-
-    fn from_str(s: &str) -> Result<Self> {
-        #[cfg_attr(rustfmt, rustfmt_skip)]
-        match s.to_lowercase().as_ref() {
-            "list"   => Ok(Campaign::List),
-            "create" => Ok(Campaign::Create),
-            "launch" => Ok(Campaign::Launch),
-            "cancel" => Ok(Campaign::Cancel),
-            "createupdate" => Ok(Campaign::CreateUpdate),
-            "listupdates" => Ok(Campaign::ListUpdates),
-            _ => Err(Error::Command(format!("unknown campaign subcommand: {}", s))),
+impl<'a> Exec<'a> for AuthCommand {
+    fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
+        let mut config = Config::load_from_args(args)?;
+        match self {
+            AuthCommand::Refresh => {
+                let token = config.refresh_token()?;
+                let expires_at = token.expiry().map(|time| humantime::format_rfc3339_seconds(time).to_string());
+                let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["namespace", "scope", "expires at"]);
+                result.add_row(vec![token.namespace().ok().into(), token.scope.into(), expires_at.into()]);
+                result.response = serde_json::to_vec(&result)?;
+                Ok(result.into())
+            }
         }
     }
 }
 
-/// Available device sub-commands.
-#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
-pub enum Device {
-    List,
-    Create,
-    Delete,
+impl<'a> Exec<'a> for ConfigCommand {
+    fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
+        let mut config = Config::load_from_args(args)?;
+        match self {
+            ConfigCommand::Show => {
+                let source = config.source.as_ref().map(|path| path.display().to_string());
+                let mut values = vec![
+                    ("profile", config.profile.clone().unwrap_or_else(|| "default".into())),
+                    ("source", source.unwrap_or_default()),
+                ];
+                for key in SETTABLE_KEYS {
+                    values.push((key, config.get(key)?));
+                }
+                values.push(("token", config.token_origin().into()));
+
+                let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["key", "value"]);
+                for (key, value) in &values {
+                    result.add_row(vec![(*key).into(), value.clone().into()]);
+                }
+                result.response = serde_json::to_vec(&values.into_iter().map(|(key, value)| (key.to_string(), value.into())).collect::<serde_json::Map<_, _>>())?;
+                Ok(result.into())
+            }
+            ConfigCommand::Get(cmd) => {
+                let value = config.get(&cmd.key)?;
+                let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["key", "value"]);
+                result.add_row(vec![cmd.key.clone().into(), value.clone().into()]);
+                result.response = format!("{}\n", value).into_bytes();
+                Ok(result.into())
+            }
+            ConfigCommand::Set(cmd) => {
+                let mut config = Config::load_stored_from_args(args)?;
+                config.set(&cmd.key, &cmd.value)?;
+                config.save_default()?;
+                Ok(CommandResult::Empty)
+            }
+            ConfigCommand::ClearToken => {
+                if config.clear_token()? {
+                    info!("deleted the cached access token");
+                } else {
+                    info!("no cached access token to delete");
+                }
+                Ok(CommandResult::Empty)
+            }
+        }
+    }
 }
 
 impl<'a> Exec<'a> for Device {
     fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
-        let mut config = Config::load_default()?;
-        let device = || args.value_of("device").expect("--device").parse();
-        let name = || args.value_of("name").expect("--name");
-        let id = || args.value_of("id").expect("--id");
+        let mut config = Config::load_from_args(args)?;
 
         #[cfg_attr(rustfmt, rustfmt_skip)]
         match self {
-            Device::List   => Registry::list_device_args(&mut config, args),
-            Device::Create => Registry::create_device(&mut config, name(), id(), DeviceType::from_args(args)?),
-            Device::Delete => Registry::delete_device(&mut config, device()?),
+            Device::List(cmd)   => Registry::list_device_args(&mut config, cmd),
+            Device::Delete(cmd) => Registry::delete_device(&mut config, cmd.device),
+            Device::Rename(cmd) => Registry::rename_device(&mut config, &cmd.device, &cmd.name),
+            Device::Events(cmd) => Registry::events_table(&mut config, &cmd.device),
+            Device::Ecus(cmd)   => Director::ecus_table(&mut config, &cmd.device),
+            Device::Ecu(DeviceEcuCommand::Remove(cmd)) => Director::remove_ecu_from_args(&mut config, cmd),
+            Device::Watch(cmd)  => watch::device_from_args(&mut config, cmd),
+            Device::Import(cmd) => Registry::import_devices(&mut config, DeviceRow::from_file(&cmd.file)?,
+                BatchOptions::new(cmd.concurrency, !cmd.continue_on_error, ProgressMode::from_args(args))?),
         }
-            .map(|r| r.into())
     }
 }
 
-impl FromStr for Device {
-    type Err = Error;
+impl<'a> Exec<'a> for Group {
+    fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
+        let mut config = Config::load_from_args(args)?;
 
-    fn from_str(s: &str) -> Result<Self> {
         #[cfg_attr(rustfmt, rustfmt_skip)]
-        match s.to_lowercase().as_ref() {
-            "list"   => Ok(Device::List),
-            "create" => Ok(Device::Create),
-            "delete" => Ok(Device::Delete),
-            _ => Err(Error::Command(format!("unknown device subcommand: {}", s))),
+        match self {
+            Group::List(cmd)   => Registry::list_group_args(&mut config, cmd),
+            Group::Create(cmd) => Registry::create_group_from_args(&mut config, cmd),
+            Group::Add(cmd)    => Registry::add_device_to_group(&mut config, &cmd.group, &cmd.device, cmd.create_if_missing, cmd.retry_on_conflict),
+            Group::Remove(cmd) => Registry::remove_device_from_group(&mut config, &cmd.group, &cmd.device, cmd.retry_on_conflict),
+            Group::Rename(cmd) => Registry::rename_group(&mut config, cmd.group, &cmd.name),
+            Group::Delete(cmd) => Registry::delete_group_from_args(&mut config, cmd),
         }
     }
 }
 
-/// Available group sub-commands.
-#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
-pub enum Group {
-    List,
-    Create,
-    Add,
-    Rename,
-    Remove,
-}
-
-impl<'a> Exec<'a> for Group {
+impl<'a> Exec<'a> for Package {
     fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
-        let mut config = Config::load_default()?;
-        let group = || args.value_of("group").expect("--group").parse();
-        let device = || args.value_of("device").expect("--device").parse();
-        let name = || args.value_of("name").expect("--name");
+        let mut config = Config::load_from_args(args)?;
 
         #[cfg_attr(rustfmt, rustfmt_skip)]
         match self {
-            Group::List   => Registry::list_group_args(&mut config, args),
-            Group::Create => Registry::create_group(&mut config, name(), GroupType::Static),
-            Group::Add    => Registry::add_to_group(&mut config, group()?, device()?),
-            Group::Remove => Registry::remove_from_group(&mut config, group()?, device()?),
-            Group::Rename => Registry::rename_group(&mut config, group()?, name()),
+            Package::List       => Output::from_args(args)?.streamed(|raw| Reposerver::list_packages_to(&mut config, raw)),
+            Package::Add(cmd)   => Reposerver::add_package_from_args(&mut config, cmd, ProgressMode::from_args(args)),
+            Package::Fetch(cmd) => Reposerver::get_package(&mut config, &cmd.name, &cmd.version),
+            Package::Download(cmd) => Reposerver::download_package(&mut config, &cmd.name, &cmd.version, cmd.out.as_deref(), cmd.verify, ProgressMode::from_args(args)),
+            Package::Upload(cmd) => Reposerver::add_packages(&mut config, TufPackages::from(TargetPackages::from_file(&cmd.packages)?)?,
+                BatchOptions::new(cmd.concurrency, cmd.fail_fast, ProgressMode::from_args(args))?),
+            Package::Delete(cmd) => Reposerver::delete_from_args(&mut config, cmd),
+            Package::Diff(cmd)  => Reposerver::diff_packages(&mut config, TufPackages::from(TargetPackages::from_file(&cmd.file)?)?,
+                &TargetFilter::new(cmd.name.as_deref(), cmd.hardware.as_deref())),
+            Package::ExportMetadata(cmd) => Reposerver::export_metadata(&mut config, &cmd.out_dir, cmd.all, cmd.pretty),
+            Package::Promote(cmd) => {
+                let mut to = Config::load_profile_from_args(args, &cmd.to_profile)?;
+                Reposerver::promote_package(&mut config, &mut to, &cmd.name, &cmd.version, ProgressMode::from_args(args))
+            }
+            Package::FetchAll(cmd) => {
+                let filter = TargetFilter::new(cmd.name.as_deref(), cmd.hardware.as_deref());
+                let options = BatchOptions::new(cmd.concurrency, cmd.fail_fast, ProgressMode::from_args(args))?;
+                Reposerver::fetch_all(&mut config, &cmd.out_dir, &filter, cmd.verify, options)
+            }
         }
             .map(|r| r.into())
     }
 }
 
-impl FromStr for Group {
-    type Err = Error;
+impl<'a> Exec<'a> for Delegation {
+    fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
+        let mut config = Config::load_from_args(args)?;
 
-    fn from_str(s: &str) -> Result<Self> {
         #[cfg_attr(rustfmt, rustfmt_skip)]
-        match s.to_lowercase().as_ref() {
-            "list"   => Ok(Group::List),
-            "create" => Ok(Group::Create),
-            "add"    => Ok(Group::Add),
-            "rename" => Ok(Group::Rename),
-            "remove" => Ok(Group::Remove),
-            _ => Err(Error::Command(format!("unknown group subcommand: {}", s))),
+        match self {
+            Delegation::List         => Delegations::list_delegations(&mut config),
+            Delegation::Create(cmd)    => Delegations::create_from_args(&mut config, cmd),
+            Delegation::Delete(cmd)    => Delegations::delete_from_args(&mut config, cmd),
+            Delegation::AddTarget(cmd) => Delegations::add_target_from_args(&mut config, cmd),
         }
     }
 }
 
-/// Available package sub-commands.
-#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
-pub enum Package {
-    List,
-    Add,
-    Fetch,
-    Upload,
+impl<'a> Exec<'a> for RepoCommand {
+    fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
+        let mut config = Config::load_from_args(args)?;
+
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match self {
+            RepoCommand::Root(RootCommand::Show(cmd))       => Repo::root_table(&mut config, cmd),
+            RepoCommand::Root(RootCommand::RotateKeys(cmd)) => Repo::rotate_from_args(&mut config, cmd),
+            RepoCommand::Keys(KeysCommand::List)            => Repo::keys_table(&mut config),
+            RepoCommand::Diff(cmd)                          => Repo::diff_from_args(&mut config, cmd, args),
+        }
+    }
 }
 
-impl<'a> Exec<'a> for Package {
+impl<'a> Exec<'a> for Report {
     fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
-        let mut config = Config::load_default()?;
-        let name = || args.value_of("name").expect("--name");
-        let version = || args.value_of("version").expect("--version");
-        let packages = || args.value_of("packages").expect("--packages");
+        let mut config = Config::load_from_args(args)?;
 
-        #[cfg_attr(rustfmt, rustfmt_skip)]
         match self {
-            Package::List   => Reposerver::list_packages(&mut config),
-            Package::Add    => Reposerver::add_package(&mut config, TufPackage::from_args(args)?),
-            Package::Fetch  => Reposerver::get_package(&mut config, name(), version()),
-            Package::Upload => Reposerver::add_packages(&mut config, TufPackages::from(TargetPackages::from_file(packages())?)?),
+            Report::Fleet(cmd) => report::fleet_from_args(&mut config, cmd, &TableOptions::from_args(args)),
         }
-            .map(|r| r.into())
     }
 }
 
-impl FromStr for Package {
-    type Err = Error;
+impl<'a> Exec<'a> for Sign {
+    fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
+        // Signing is local, so it needs neither the config nor a token.
+        if let Sign::Targets(cmd) = self {
+            return sign::sign_from_args(cmd);
+        }
+        let mut config = Config::load_from_args(args)?;
 
-    fn from_str(s: &str) -> Result<Self> {
         #[cfg_attr(rustfmt, rustfmt_skip)]
-        match s.to_lowercase().as_ref() {
-            "list"   => Ok(Package::List),
-            "add"    => Ok(Package::Add),
-            "fetch"  => Ok(Package::Fetch),
-            "upload" => Ok(Package::Upload),
-            _ => Err(Error::Command(format!("unknown package subcommand: {}", s))),
+        match self {
+            Sign::Pull(cmd)  => Repo::pull_targets_from_args(&mut config, cmd),
+            Sign::Push(cmd)  => Repo::push_targets_from_args(&mut config, cmd),
+            Sign::Targets(_) => unreachable!(),
         }
     }
 }
 
-/// Available update sub-commands.
-#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
-pub enum Update {
-    Create,
-    Launch,
+impl<'a> Exec<'a> for TreehubCommand {
+    fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
+        let mut config = Config::load_from_args(args)?;
+
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match self {
+            TreehubCommand::Refs(RefsCommand::List)           => Treehub::refs_table(&mut config),
+            TreehubCommand::Ref(RefCommand::Show(cmd))        => Treehub::get_ref(&mut config, &cmd.name),
+            TreehubCommand::Commit(CommitCommand::Exists(cmd)) => Treehub::head_commit(&mut config, &cmd.commit),
+        }
+    }
 }
 
 impl<'a> Exec<'a> for Update {
     fn exec(&self, args: &ArgMatches<'a>) -> Result<CommandResult> {
-        let mut config = Config::load_default()?;
-        let update = || args.value_of("update").expect("--update").parse();
-        let device = || args.value_of("device").expect("--device").parse();
-        let targets = || args.value_of("targets").expect("--targets");
+        let mut config = Config::load_from_args(args)?;
 
         match self {
-            Update::Create => Director::create_mtu(&mut config, &TufUpdates::from(TargetRequests::from_file(targets())?)?),
-            Update::Launch => Director::launch_mtu(&mut config, update()?, device()?),
+            Update::Create(cmd) => {
+                let requests = match &cmd.targets {
+                    Some(targets) => TargetRequests::from_file(targets)?,
+                    None => TargetRequests::from_args(cmd)?,
+                };
+                Director::create_mtu(&mut config, &TufUpdates::from(requests)?)
+            }
+            Update::Launch(cmd) => match &cmd.group {
+                Some(group) => {
+                    let options = BatchOptions::new(cmd.concurrency, cmd.fail_fast, ProgressMode::from_args(args))?;
+                    Director::launch_mtu_to_group(&mut config, cmd.update, group, options)
+                }
+                None => Director::launch_mtu(&mut config, cmd.update, cmd.device.ok_or_else(|| missing("device"))?),
+            },
+            Update::Show(cmd) => Director::get_mtu(&mut config, cmd.update),
+            Update::Status(cmd) => Director::status_mtu(&mut config, cmd.update, &cmd.device),
+            Update::Pending(cmd) => Director::get_assignments(&mut config, cmd.device),
         }
-        .map(|r| r.into())
     }
 }
 
-impl FromStr for Update {
-    type Err = Error;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ErrorKind;
 
-    fn from_str(s: &str) -> Result<Self> {
-        #[cfg_attr(rustfmt, rustfmt_skip)]
-        match s.to_lowercase().as_ref() {
-            "create" => Ok(Update::Create),
-            "launch" => Ok(Update::Launch),
-            _ => Err(Error::Command(format!("unknown update subcommand: {}", s))),
+    fn is_args_error<T>(result: Result<T>, flag: &str) -> bool {
+        match result {
+            Err(Error::Args(msg)) => msg.contains(flag),
+            _ => false,
         }
     }
+
+    #[test]
+    fn fan_out_merges_namespaces() {
+        let server = crate::mock::MockServer::start(|req| {
+            let namespace = req.headers["x-ats-namespace"].clone();
+            crate::mock::MockResponse::json(200, json!({"total": 1, "values": [{"id": format!("{}-group", namespace)}]}))
+        });
+        let dir = env::temp_dir().join(format!("ota-cli-fan-out-{}", std::process::id()));
+        server.config().with_dir(&dir).save_default().expect("save");
+        let args = Cli::clap().get_matches_from(vec!["ota", "--config-dir", dir.to_str().unwrap(), "config", "show"]);
+
+        let result = fan_out(&["acme", "globex"], || {
+            let mut config = Config::load_from_args(&args)?;
+            let req = crate::http::Request::get(config.registry.join("api/v1/device_groups")?)?;
+            Ok(crate::http::Http::execute(req, config.token()?)?.into())
+        });
+        match result.expect("fan out") {
+            CommandResult::Table(table) => {
+                assert_eq!(table.columns, vec!["namespace", "id"]);
+                let rows = table.rows.iter().map(|row| (row["namespace"].as_str().unwrap(), row["id"].as_str().unwrap())).collect::<Vec<_>>();
+                assert_eq!(rows, vec![("acme", "acme-group"), ("globex", "globex-group")]);
+            }
+            _ => panic!("expected a table"),
+        }
+        assert!(config::with_namespace("acme", || true));
+        assert_eq!(Config::load_from_args(&args).unwrap().namespace, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nested_commands_see_global_flags() {
+        let args = Cli::clap().get_matches_from(vec!["ota", "treehub", "ref", "show", "--ref", "os", "--config-dir", "/tmp/ota-nested"]);
+        let args = args.subcommand().1.expect("treehub args");
+        assert_eq!(Config::dir_from_args(args), PathBuf::from("/tmp/ota-nested"));
+    }
+
+    #[test]
+    fn output_format_from_env() {
+        let with_env = |from_env: Option<&str>, args: &[&str]| {
+            let args = Cli::clap().get_matches_from(["ota"].iter().chain(args).chain(&["config", "show"]));
+            OutputFormat::from_args_with(&args, from_env.map(String::from))
+        };
+        let format = |args: &[&str]| with_env(Some("json"), args);
+
+        let from_env = format(&[]);
+        let from_flag = format(&["--output", "table"]);
+        let from_tables = format(&["--use-tables"]);
+        let from_file = format(&["--output-file", "report.CSV"]);
+        let flag_over_file = format(&["--output-file", "report.csv", "--output", "json"]);
+        let yaml = with_env(Some("yaml"), &[]);
+        let invalid = with_env(Some("xml"), &[]);
+        let from_columns = with_env(None, &["--columns", "name"]);
+        let from_save = with_env(None, &["--save-response", "targets.json"]);
+
+        assert_eq!(from_env.unwrap(), OutputFormat::Json);
+        assert_eq!(from_flag.unwrap(), OutputFormat::Table);
+        assert_eq!(from_tables.unwrap(), OutputFormat::Table);
+        assert_eq!(yaml.unwrap(), OutputFormat::Yaml);
+        assert_eq!(from_file.unwrap(), OutputFormat::Csv);
+        assert_eq!(flag_over_file.unwrap(), OutputFormat::Json);
+        assert!(is_args_error(invalid, OUTPUT_ENV));
+        assert_eq!(with_env(None, &[]).unwrap(), OutputFormat::Raw);
+        assert_eq!(from_columns.unwrap(), OutputFormat::Table);
+        assert_eq!(from_save.unwrap(), OutputFormat::Table);
+    }
+
+    #[test]
+    fn http_responses_in_each_format() {
+        let write = |format, body: &str| {
+            let mut out = Vec::new();
+            write_http_response(format, &TableOptions::default(), HeaderMap::new(), body.as_bytes().to_vec(), &mut out).expect("write");
+            String::from_utf8(out).unwrap()
+        };
+        let page = r#"{"total":2,"values":[{"id":"a","name":"foo"},{"id":"b","tags":["x","y"]}]}"#;
+
+        let table = write(OutputFormat::Table, page);
+        assert!(table.lines().any(|line| line.contains("id") && line.contains("name") && line.contains("tags")));
+        assert!(table.contains("foo") && table.contains("x, y"));
+        assert!(!table.contains("total"));
+        let fields = write(OutputFormat::Table, r#"{"id":"a","name":"foo"}"#);
+        assert!(fields.lines().any(|line| line.contains("field") && line.contains("value")));
+        assert!(fields.lines().any(|line| line.contains("name") && line.contains("foo")));
+
+        assert_eq!(write(OutputFormat::Compact, "{\n  \"id\": \"a\"\n}"), r#"{"id":"a"}"#);
+        assert_eq!(write(OutputFormat::Json, r#"{"id":"a"}"#), "{\n  \"id\": \"a\"\n}");
+        assert_eq!(write(OutputFormat::Table, "not json"), "not json");
+        assert_eq!(write(OutputFormat::Yaml, page), "total: 2\nvalues:\n- id: a\n  name: foo\n- id: b\n  tags:\n  - x\n  - y\n");
+        assert_eq!(write(OutputFormat::Yaml, "not json"), "not json");
+
+        let mut table = TableResult::new(HeaderMap::new(), Vec::new(), &["id", "count"]);
+        table.add_row(vec!["a".into(), 1.into()]);
+        let mut out = Vec::new();
+        write_table_result(OutputFormat::Yaml, &TableOptions::default(), table, &mut out).expect("yaml table");
+        assert_eq!(String::from_utf8(out).unwrap(), "- count: 1\n  id: a\n");
+
+        assert_eq!(write(OutputFormat::Csv, page), "id,name,tags\na,foo,\nb,,\"x, y\"\n");
+        assert!(write_http_response(OutputFormat::Csv, &TableOptions::default(), HeaderMap::new(), b"not json".to_vec(), &mut Vec::new()).is_err());
+        let mut table = TableResult::new(HeaderMap::new(), Vec::new(), &["name", "note"]);
+        table.add_row(vec!["a \"b\"".into(), "line\nbreak".into()]);
+        assert_eq!(table.csv(), "name,note\n\"a \"\"b\"\"\",\"line\nbreak\"\n");
+
+        let options = TableOptions { columns: Some(vec!["id".into()]), sort_by: Some("id".into()) };
+        let mut out = Vec::new();
+        let body = r#"[{"id":"b","name":"foo"},{"id":"a"}]"#.as_bytes().to_vec();
+        write_http_response(OutputFormat::Csv, &options, HeaderMap::new(), body, &mut out).expect("sorted csv");
+        assert_eq!(String::from_utf8(out).unwrap(), "id\na\nb\n");
+    }
+
+    #[test]
+    fn save_raw_responses() {
+        let path = env::temp_dir().join(format!("ota-save-response-{}.json", std::process::id()));
+        let mut table = TableResult::new(HeaderMap::new(), br#"{"signed":{}}"#.to_vec(), &["id"]);
+        table.add_row(vec!["a".into()]);
+        let mut out = Vec::new();
+        write_command_result(OutputFormat::Table, &TableOptions::default(), Some(&path), table.into(), &mut out).expect("save");
+        assert_eq!(fs::read(&path).unwrap(), br#"{"signed":{}}"#);
+        assert!(String::from_utf8(out).unwrap().contains("id"));
+
+        let mut table = TableResult::new(HeaderMap::new(), Vec::new(), &["id"]);
+        table.add_row(vec!["b".into()]);
+        write_command_result(OutputFormat::Table, &TableOptions::default(), Some(&path), table.into(), &mut Vec::new()).expect("save rows");
+        assert_eq!(serde_json::from_slice::<Value>(&fs::read(&path).unwrap()).unwrap(), json!([{"id": "b"}]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stream_served_body_to_outputs() {
+        let dir = env::temp_dir().join(format!("ota-cli-streamed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (saved, printed) = (dir.join("targets.json"), dir.join("out.json"));
+        let served = br#"{"signed":{"targets":{}}}"#;
+        let run = |raw: Option<&mut dyn Write>| -> Result<TableResult> {
+            raw.expect("raw sink").write_all(served)?;
+            let mut table = TableResult::new(HeaderMap::new(), Vec::new(), &["target"]);
+            table.add_row(vec!["foo-1".into()]);
+            Ok(table)
+        };
+
+        let output = Output { format: OutputFormat::Raw, table: TableOptions::default(), save: Some(saved.clone()), file: Some(printed.clone()) };
+        output.write(output.streamed(run).expect("raw")).expect("write raw");
+        assert_eq!(fs::read(&saved).unwrap(), served);
+        assert_eq!(fs::read(&printed).unwrap(), served);
+
+        let output = Output { format: OutputFormat::Csv, table: TableOptions::default(), save: Some(saved.clone()), file: Some(printed.clone()) };
+        output.write(output.streamed(run).expect("csv")).expect("write csv");
+        assert_eq!(fs::read(&saved).unwrap(), served);
+        assert_eq!(fs::read_to_string(&printed).unwrap(), "target\nfoo-1\n");
+
+        let output = Output { format: OutputFormat::Json, table: TableOptions::default(), save: None, file: None };
+        let kept = output.streamed(|raw| {
+            assert!(raw.is_none());
+            Ok(TableResult::new(HeaderMap::new(), Vec::new(), &[]))
+        });
+        assert!(matches!(kept, Ok(CommandResult::Table(table)) if !table.streamed));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn error_status_fails_the_command() {
+        let server = crate::mock::MockServer::start(|_| crate::mock::MockResponse::json(404, json!({"code": "missing_entity"})));
+        let dir = env::temp_dir().join(format!("ota-cli-error-status-{}", std::process::id()));
+        server.config().with_dir(&dir).save_default().expect("save");
+        let device = "00000000-0000-0000-0000-000000000001";
+        let args = Cli::clap().get_matches_from(vec!["ota", "device", "delete", "--device", device, "--config-dir", dir.to_str().unwrap()]);
+        let cli = Cli::from_clap(&args);
+
+        let mut out = Vec::new();
+        let result = cli.command.exec(args.subcommand().1.unwrap());
+        let err = result.and_then(|result| write_command_result(OutputFormat::Raw, &TableOptions::default(), None, result, &mut out)).expect_err("404");
+        assert_eq!(err.exit_code(), crate::error::EXIT_NOT_FOUND);
+        assert!(err.to_string().contains("missing_entity"));
+        assert!(out.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_package_args() {
+        let missing_hardware = |argv: &[&str]| match Cli::clap().get_matches_from_safe(["ota"].iter().chain(argv)) {
+            Err(err) => err.kind == ErrorKind::MissingRequiredArgument && err.message.contains("--hardware"),
+            Ok(_) => false,
+        };
+        assert!(missing_hardware(&["package", "add", "--name", "foo", "--version", "1", "--path", "foo.bin", "--binary"]));
+        assert!(missing_hardware(&["update", "create", "--name", "foo", "--version", "1", "--format", "binary"]));
+    }
 }
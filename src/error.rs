@@ -1,8 +1,11 @@
-use reqwest;
-use serde_json;
+use clap::ArgMatches;
+use reqwest::{self, StatusCode};
+use serde_json::{self, Value};
 use std::{
     self,
+    env,
     fmt::{self, Debug, Display, Formatter},
+    str::FromStr,
 };
 use toml;
 use url;
@@ -17,19 +20,156 @@ pub enum Error {
     Args(String),
     Auth(String),
     Command(String),
+    Conflict(String),
+    /// A command that ran but did not succeed, e.g. when some devices of a batch failed.
+    Failed(String),
     NotFound(String, Option<String>),
     Parse(String),
+    /// A wait that gave up before the awaited state was reached.
+    Timeout(String),
     Token(String),
+    Verify(String),
 
+    Crypto(openssl::error::ErrorStack),
     Http(reqwest::Error),
+    /// An error status from a server, with the response body.
+    Status(reqwest::Error, String),
     Io(std::io::Error),
     Json(serde_json::Error),
     Toml(toml::de::Error),
     Url(url::ParseError),
     Uuid(uuid::Error),
+    Yaml(serde_yaml::Error),
     Zip(zip::result::ZipError),
 }
 
+/// The exit code for invalid command arguments or input.
+pub const EXIT_USAGE: i32 = 2;
+/// The exit code for missing or rejected credentials, including 401 and 403 responses.
+pub const EXIT_AUTH: i32 = 3;
+/// The exit code for missing resources, including 404 responses.
+pub const EXIT_NOT_FOUND: i32 = 4;
+/// The exit code for 5xx server responses.
+pub const EXIT_SERVER: i32 = 5;
+/// The exit code for conflicting changes, including 409 and 412 responses.
+pub const EXIT_CONFLICT: i32 = 6;
+/// The exit code for requests that got no response, such as connection failures and timeouts.
+pub const EXIT_CONNECTION: i32 = 7;
+/// The exit code for a wait that timed out, e.g. `campaign wait --timeout`.
+pub const EXIT_TIMEOUT: i32 = 8;
+/// The exit code for any other failure.
+pub const EXIT_FAILURE: i32 = 1;
+
+/// The environment variable setting the default error format.
+pub const ERRORS_ENV: &str = "OTA_ERRORS";
+
+/// How a failed command reports its error on stderr.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+impl<'a> ErrorFormat {
+    /// Resolve the error format from `--errors`, then `OTA_ERRORS`, defaulting to text.
+    pub fn from_args(args: &ArgMatches<'a>) -> Result<Self> {
+        match args.value_of("errors") {
+            Some(errors) => errors.parse(),
+            None => match env::var(ERRORS_ENV) {
+                Ok(errors) => errors.parse().map_err(|_| Error::Args(format!("{} must be text or json, got {}", ERRORS_ENV, errors))),
+                Err(_) => Ok(ErrorFormat::Text),
+            },
+        }
+    }
+
+    /// Render an error for stderr, as an `Error: ` line or a single line JSON object.
+    pub fn render(&self, err: &Error) -> String {
+        match self {
+            ErrorFormat::Text => format!("Error: {}", err),
+            ErrorFormat::Json => err.to_json().to_string(),
+        }
+    }
+}
+
+impl FromStr for ErrorFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_ref() {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(Error::Parse(format!("unknown `ErrorFormat`: {}", s))),
+        }
+    }
+}
+
+impl Error {
+    /// A short name for the class of error, e.g. `not_found`.
+    pub fn kind(&self) -> &'static str {
+        #[cfg_attr(rustfmt, rustfmt_skip)]
+        match self {
+            Error::Args(_)     => "args",
+            Error::Auth(_)     => "auth",
+            Error::Command(_)  => "command",
+            Error::Conflict(_) => "conflict",
+            Error::Failed(_)   => "failed",
+            Error::NotFound(..) => "not_found",
+            Error::Parse(_)    => "parse",
+            Error::Timeout(_)  => "timeout",
+            Error::Token(_)    => "token",
+            Error::Verify(_)   => "verify",
+
+            Error::Crypto(_)   => "crypto",
+            Error::Http(_)     => "http",
+            Error::Status(..)  => "http",
+            Error::Io(_)       => "io",
+            Error::Json(_)     => "json",
+            Error::Toml(_)     => "toml",
+            Error::Url(_)      => "url",
+            Error::Uuid(_)     => "uuid",
+            Error::Yaml(_)     => "yaml",
+            Error::Zip(_)      => "zip",
+        }
+    }
+
+    /// Describe the error as a JSON object with its kind, message and exit code, and the HTTP
+    /// status and request URL of HTTP errors, which are null otherwise.
+    pub fn to_json(&self) -> Value {
+        let (status, url) = match self {
+            Error::Http(err) | Error::Status(err, _) => (err.status().map(|status| status.as_u16()), err.url().map(|url| url.to_string())),
+            _ => (None, None),
+        };
+        json!({
+            "kind": self.kind(),
+            "message": self.to_string(),
+            "http_status": status,
+            "request_url": url,
+            "exit_code": self.exit_code(),
+        })
+    }
+
+    /// The process exit code for this error, so scripts can tell failures apart without parsing stderr.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Args(_) | Error::Command(_) => EXIT_USAGE,
+            Error::Auth(_) | Error::Token(_) => EXIT_AUTH,
+            Error::NotFound(..) => EXIT_NOT_FOUND,
+            Error::Conflict(_) => EXIT_CONFLICT,
+            Error::Timeout(_) => EXIT_TIMEOUT,
+            Error::Http(err) | Error::Status(err, _) => match err.status() {
+                Some(status) if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN => EXIT_AUTH,
+                Some(StatusCode::NOT_FOUND) => EXIT_NOT_FOUND,
+                Some(StatusCode::CONFLICT) | Some(StatusCode::PRECONDITION_FAILED) => EXIT_CONFLICT,
+                Some(status) if status.is_server_error() => EXIT_SERVER,
+                Some(_) => EXIT_FAILURE,
+                None if err.is_connect() || err.is_timeout() => EXIT_CONNECTION,
+                None => EXIT_FAILURE,
+            },
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         #[cfg_attr(rustfmt, rustfmt_skip)]
@@ -37,19 +177,27 @@ impl Display for Error {
             Error::Args(err)    => format!("Command args: {}", err),
             Error::Auth(err)    => format!("Authorization: {}", err),
             Error::Command(err) => format!("Command input: {}", err),
+            Error::Conflict(err) => format!("Conflict: {}", err),
+            Error::Failed(err)  => format!("Failed: {}", err),
             Error::NotFound(name, help) => match help {
                 Some(help) => format!("{} not found. {}", name, help),
                 None       => format!("{} not found.", name)
             },
             Error::Parse(err)   => format!("Parse error: {}", err),
+            Error::Timeout(err) => format!("Timed out: {}", err),
             Error::Token(err)   => format!("Parsing access token: {}", err),
+            Error::Verify(err)  => format!("Verification failed: {}", err),
 
+            Error::Crypto(err)  => format!("Signing: {}", err),
             Error::Http(err)    => format!("HTTP: {}", err),
+            Error::Status(err, body) if body.is_empty() => format!("HTTP: {}", err),
+            Error::Status(err, body) => format!("HTTP: {}: {}", err, body),
             Error::Io(err)      => format!("I/O: {}", err),
             Error::Json(err)    => format!("Parsing JSON: {}", err),
             Error::Toml(err)    => format!("Parsing TOML: {}", err),
             Error::Url(err)     => format!("Parsing URL: {}", err),
             Error::Uuid(err)    => format!("Parsing UUID: {}", err),
+            Error::Yaml(err)    => format!("Writing YAML: {}", err),
             Error::Zip(err)     => format!("Zip I/O: {}", err),
         };
         write!(f, "{}", output)
@@ -63,8 +211,25 @@ impl Debug for Error {
 }
 
 impl std::error::Error for Error {
-    fn description(&self) -> &str {
-        "ota-cli error"
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Crypto(err) => Some(err),
+            Error::Http(err) | Error::Status(err, _) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::Toml(err) => Some(err),
+            Error::Url(err) => Some(err),
+            Error::Uuid(err) => Some(err),
+            Error::Yaml(err) => Some(err),
+            Error::Zip(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(err: openssl::error::ErrorStack) -> Self {
+        Error::Crypto(err)
     }
 }
 
@@ -104,8 +269,86 @@ impl From<uuid::Error> for Error {
     }
 }
 
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Self {
+        Error::Yaml(err)
+    }
+}
+
 impl From<zip::result::ZipError> for Error {
     fn from(err: zip::result::ZipError) -> Self {
         Error::Zip(err)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+    use std::{error::Error as _, net::TcpListener};
+
+    #[test]
+    fn http_error_source_chain() {
+        let port = TcpListener::bind("127.0.0.1:0").expect("bind").local_addr().expect("addr").port();
+        let err = Error::from(reqwest::blocking::get(format!("http://127.0.0.1:{}/", port)).expect_err("refused"));
+        assert!(err.to_string().starts_with("HTTP: "));
+
+        let source = err.source().expect("http source");
+        assert!(source.downcast_ref::<reqwest::Error>().is_some());
+        let mut next = source.source();
+        let mut found_io = false;
+        while let Some(err) = next {
+            found_io |= err.downcast_ref::<std::io::Error>().is_some();
+            next = err.source();
+        }
+        assert!(found_io);
+        assert!(Error::Args("bad".into()).source().is_none());
+        assert_eq!(err.exit_code(), EXIT_CONNECTION);
+    }
+
+    #[test]
+    fn exit_codes_per_error_class() {
+        let server = MockServer::start(|req| MockResponse::status(req.route()[1..].parse().unwrap()));
+        let status = |status: u16| {
+            let resp = reqwest::blocking::get(format!("{}{}", server.url, status)).expect("response");
+            Error::from(resp.error_for_status().expect_err("error status")).exit_code()
+        };
+        assert_eq!(status(401), EXIT_AUTH);
+        assert_eq!(status(403), EXIT_AUTH);
+        assert_eq!(status(404), EXIT_NOT_FOUND);
+        assert_eq!(status(409), EXIT_CONFLICT);
+        assert_eq!(status(503), EXIT_SERVER);
+        assert_eq!(status(400), EXIT_FAILURE);
+
+        assert_eq!(Error::Args("bad".into()).exit_code(), EXIT_USAGE);
+        assert_eq!(Error::Token("expired".into()).exit_code(), EXIT_AUTH);
+        assert_eq!(Error::NotFound("Device".into(), None).exit_code(), EXIT_NOT_FOUND);
+        assert_eq!(Error::Verify("bad signature".into()).exit_code(), EXIT_FAILURE);
+        assert_eq!(Error::Failed("1 of 2 devices failed".into()).exit_code(), EXIT_FAILURE);
+        assert_eq!(Error::Timeout("campaign still launched".into()).exit_code(), EXIT_TIMEOUT);
+    }
+
+    #[test]
+    fn render_errors_as_json() {
+        let server = MockServer::start(|_| MockResponse::status(404));
+        let resp = reqwest::blocking::get(format!("{}api/v1/devices/1", server.url)).expect("response");
+        let err = Error::from(resp.error_for_status().expect_err("not found"));
+        let rendered: Value = serde_json::from_str(&ErrorFormat::Json.render(&err)).expect("json");
+        assert_eq!(rendered["kind"], "http");
+        assert_eq!(rendered["http_status"], 404);
+        assert_eq!(rendered["request_url"], format!("{}api/v1/devices/1", server.url));
+        assert_eq!(rendered["exit_code"], EXIT_NOT_FOUND);
+        assert!(rendered["message"].as_str().unwrap().starts_with("HTTP: "));
+
+        let err = Error::NotFound("Group `eu`".into(), None);
+        assert_eq!(err.to_json(), json!({
+            "kind": "not_found",
+            "message": "Group `eu` not found.",
+            "http_status": null,
+            "request_url": null,
+            "exit_code": EXIT_NOT_FOUND,
+        }));
+        assert_eq!(ErrorFormat::Text.render(&err), "Error: Group `eu` not found.");
+        assert!("yaml".parse::<ErrorFormat>().is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use reqwest::{
+    blocking::{Request, Response},
+    header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE},
+};
+use serde_json::Value;
+use std::{
+    fs,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use url::{form_urlencoded, Url};
+
+use crate::error::Result;
+
+/// Headers whose values are never recorded.
+const SECRET_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie", "set-cookie"];
+/// Fields of JSON and form bodies, and query parameters, whose values are never recorded.
+const SECRET_FIELDS: &[&str] = &["access_token", "refresh_token", "id_token", "client_secret", "password", "private"];
+const REDACTED: &str = "<redacted>";
+
+/// The requests and responses of a CLI session, saved for `--har` as an HTTP Archive.
+///
+/// Credentials are redacted as each entry is recorded: auth and cookie headers, and secret
+/// fields of JSON and form bodies. Only text and JSON bodies are kept.
+#[derive(Default)]
+pub struct HarLog {
+    entries: Mutex<Vec<Value>>,
+}
+
+impl HarLog {
+    /// Describe a request as a HAR `request` object, before it is sent.
+    pub fn request(req: &Request) -> Value {
+        let mut request = json!({
+            "method": req.method().as_str(),
+            "url": redact_url(req.url()).as_str(),
+            "httpVersion": format!("{:?}", req.version()),
+            "headers": headers(req.headers()),
+            "queryString": redact_url(req.url()).query_pairs().map(|(name, value)| json!({"name": name, "value": value})).collect::<Vec<_>>(),
+            "cookies": [],
+            "headersSize": -1,
+            "bodySize": 0,
+        });
+        match req.body().map(|body| body.as_bytes()) {
+            Some(Some(bytes)) => {
+                let kind = content_type(req.headers());
+                request["bodySize"] = bytes.len().into();
+                request["postData"] = json!({"mimeType": kind, "text": redact_body(&kind, bytes)});
+            }
+            Some(None) => request["bodySize"] = (-1).into(),
+            None => (),
+        }
+        request
+    }
+
+    /// Record a request with its response, or with status 0 when no response arrived.
+    ///
+    /// The response `body` is the buffered text of the response, if it was read.
+    pub fn record(&self, request: Value, started: SystemTime, elapsed: Duration, response: Option<(&Response, Option<&[u8]>)>) {
+        let response = match response {
+            Some((resp, body)) => {
+                let kind = content_type(resp.headers());
+                let size = match body {
+                    Some(body) => body.len() as i64,
+                    None => resp.headers().get(CONTENT_LENGTH).and_then(|len| len.to_str().ok()?.parse().ok()).unwrap_or(-1),
+                };
+                let mut content = json!({"size": size, "mimeType": kind});
+                match body {
+                    Some(body) => content["text"] = redact_body(&kind, body).into(),
+                    None => content["comment"] = "body not recorded".into(),
+                }
+                json!({
+                    "status": resp.status().as_u16(),
+                    "statusText": resp.status().canonical_reason().unwrap_or(""),
+                    "httpVersion": format!("{:?}", resp.version()),
+                    "headers": headers(resp.headers()),
+                    "cookies": [],
+                    "content": content,
+                    "redirectURL": "",
+                    "headersSize": -1,
+                    "bodySize": size,
+                })
+            }
+            None => json!({
+                "status": 0,
+                "statusText": "",
+                "httpVersion": "",
+                "headers": [],
+                "cookies": [],
+                "content": {"size": 0, "mimeType": ""},
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": -1,
+            }),
+        };
+        let millis = elapsed.as_secs_f64() * 1000.0;
+        self.entries.lock().expect("har entries").push(json!({
+            "startedDateTime": humantime::format_rfc3339_millis(started).to_string(),
+            "time": millis,
+            "request": request,
+            "response": response,
+            "cache": {},
+            "timings": {"send": 0, "wait": millis, "receive": 0},
+        }));
+    }
+
+    /// The archive of all entries recorded so far.
+    pub fn archive(&self) -> Value {
+        json!({"log": {
+            "version": "1.2",
+            "creator": {"name": "ota-cli", "version": env!("CARGO_PKG_VERSION")},
+            "entries": *self.entries.lock().expect("har entries"),
+        }})
+    }
+
+    /// Write the archive to a file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(&self.archive())?)?;
+        info!("saved {} requests to {}", self.entries.lock().expect("har entries").len(), path.display());
+        Ok(())
+    }
+}
+
+fn headers(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if value.is_sensitive() || SECRET_HEADERS.contains(&name.as_str()) {
+                REDACTED.into()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            json!({"name": name.as_str(), "value": value})
+        })
+        .collect()
+}
+
+pub(crate) fn content_type(headers: &HeaderMap) -> String { headers.get(CONTENT_TYPE).and_then(|kind| kind.to_str().ok()).unwrap_or("").into() }
+
+fn secret(name: &str) -> bool { SECRET_FIELDS.contains(&name.to_lowercase().as_str()) }
+
+pub(crate) fn redact_url(url: &Url) -> Url {
+    let mut url = url.clone();
+    if url.query_pairs().any(|(name, _)| secret(&name)) {
+        let pairs = url
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = if secret(&name) { REDACTED.into() } else { value.into_owned() };
+                (name.into_owned(), value)
+            })
+            .collect::<Vec<_>>();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url
+}
+
+/// Show a body as text, with the values of secret fields replaced in JSON and form bodies.
+pub(crate) fn redact_body(kind: &str, body: &[u8]) -> String {
+    if let Ok(mut json) = serde_json::from_slice::<Value>(body) {
+        redact_json(&mut json);
+        return json.to_string();
+    }
+    if kind.starts_with("application/x-www-form-urlencoded") {
+        return form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(form_urlencoded::parse(body).map(|(name, value)| (name.clone(), if secret(&name) { REDACTED.into() } else { value })))
+            .finish();
+    }
+    String::from_utf8_lossy(body).into_owned()
+}
+
+fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, value) in fields.iter_mut() {
+                if secret(name) {
+                    *value = REDACTED.into();
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_json),
+        _ => (),
+    }
+}
@@ -1,53 +1,1224 @@
+use clap::ArgMatches;
 use reqwest::blocking::{Client, RequestBuilder, Response};
-use reqwest::Url;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER},
+    redirect::Policy,
+    Method,
+    NoProxy,
+    Proxy,
+    StatusCode,
+    Url,
+};
+use openssl::{
+    pkcs12::Pkcs12,
+    pkey::PKey,
+    stack::Stack,
+    x509::X509,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::{
+    cell::RefCell,
+    fmt::{self, Debug, Display, Formatter},
+    fs,
+    io::{self, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        OnceLock,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+#[cfg(feature = "async")]
+use std::{future::Future, pin::Pin};
 
 use crate::api::auth_plus::AccessToken;
 use crate::error::{Error, Result};
+use crate::har::{self, HarLog};
+use crate::interrupt;
+use crate::spinner::Spinner;
 
 /// Convenience methods for making simple HTTP requests.
 pub trait HttpMethods {
     fn get(url: impl AsRef<str>, token: Option<AccessToken>) -> Result<Response> {
-        Http::send(Client::new().get(Url::parse(url.as_ref())?), token)
+        Http::send(Http::client().get(Url::parse(url.as_ref())?), token)
     }
     fn post(url: impl AsRef<str>, token: Option<AccessToken>) -> Result<Response> {
-        Http::send(Client::new().post(Url::parse(url.as_ref())?), token)
+        Http::send(Http::client().post(Url::parse(url.as_ref())?), token)
     }
     fn put(url: impl AsRef<str>, token: Option<AccessToken>) -> Result<Response> {
-        Http::send(Client::new().put(Url::parse(url.as_ref())?), token)
+        Http::send(Http::client().put(Url::parse(url.as_ref())?), token)
     }
     fn delete(url: impl AsRef<str>, token: Option<AccessToken>) -> Result<Response> {
-        Http::send(Client::new().delete(Url::parse(url.as_ref())?), token)
+        Http::send(Http::client().delete(Url::parse(url.as_ref())?), token)
     }
 }
 
 /// Make HTTP requests to server endpoints.
 pub struct Http;
 
+/// The number of redirects followed before a request fails.
+pub const DEFAULT_MAX_REDIRECTS: usize = 3;
+/// The time a request waits before a spinner is shown.
+pub const DEFAULT_SPINNER_AFTER: Duration = Duration::from_secs(1);
+/// The number of times an idempotent request is retried.
+pub const DEFAULT_RETRIES: u32 = 2;
+/// The delay before the first retry of a request, doubling for each retry after.
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+/// The response statuses retried by default.
+pub const DEFAULT_RETRY_ON: &[u16] = &[502, 503, 504];
+/// The number of times a rate limited request is retried.
+pub const DEFAULT_RATE_LIMIT_RETRIES: u32 = 5;
+/// The longest `Retry-After` waited for before a rate limited request fails instead.
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+/// The number of bytes of each request and response body traced by `--debug-http`.
+pub const DEFAULT_DEBUG_HTTP_BODY: usize = 1024;
+/// The header marking the placeholder response to a request not sent with `--dry-run`.
+pub const DRY_RUN_HEADER: &str = "x-ota-dry-run";
+
+static SETTINGS: OnceLock<HttpSettings> = OnceLock::new();
+static DEFAULT_SETTINGS: OnceLock<HttpSettings> = OnceLock::new();
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static BUDGET: OnceLock<RetryBudget> = OnceLock::new();
+static CREDENTIALS_IDENTITY: OnceLock<ClientIdentity> = OnceLock::new();
+static CONFIG_SETTINGS: OnceLock<ConfigHttpSettings> = OnceLock::new();
+static HAR: OnceLock<HarLog> = OnceLock::new();
+
+/// Settings for the HTTP client shared by all requests.
+#[derive(Clone, Debug)]
+pub struct HttpSettings {
+    pub max_redirects: usize,
+    /// Show a spinner on stderr for requests taking longer than this.
+    pub spinner: Option<Duration>,
+    pub retry: RetryPolicy,
+    /// Retries shared by all requests of the command.
+    pub max_retries_total: Option<u32>,
+    /// Print requests that change server state instead of sending them.
+    pub dry_run: bool,
+    /// A client certificate from `--client-cert`, used for mutual TLS on every request.
+    pub identity: Option<ClientIdentity>,
+    /// A proxy from `--proxy`, used instead of the config or environment proxy.
+    pub proxy: Option<Url>,
+    /// The time allowed for each request, with zero for no limit, instead of the config or default 30 seconds.
+    pub timeout: Option<Duration>,
+    /// The time allowed to connect, with zero for no limit, instead of the config or no separate limit.
+    pub connect_timeout: Option<Duration>,
+    /// Trace every request and response to stderr for `--debug-http`, with up to this many bytes of each body.
+    pub debug_http: Option<usize>,
+    /// Record every request and response, with secrets redacted, to this HTTP Archive file for `--har`.
+    pub har: Option<PathBuf>,
+}
+
+/// HTTP settings saved in the config, used where no flag sets them.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigHttpSettings {
+    pub proxy: Option<Url>,
+    pub timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        HttpSettings {
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            spinner: None,
+            retry: RetryPolicy::default(),
+            max_retries_total: None,
+            dry_run: false,
+            identity: None,
+            proxy: None,
+            timeout: None,
+            connect_timeout: None,
+            debug_http: None,
+            har: None,
+        }
+    }
+}
+
+impl<'a> HttpSettings {
+    /// Parse `--max-redirects`, `--spinner-after`, retry flags, timeouts, `--dry-run`, `--debug-http`, `--har`, `--quiet`, `--proxy` and client certificate CLI arguments.
+    ///
+    /// The spinner is only enabled when both stdout and stderr are terminals.
+    pub fn from_args(args: &ArgMatches<'a>) -> Result<Self> {
+        let max_redirects = match args.value_of("max_redirects") {
+            Some(n) => n.parse().map_err(|_| Error::Args(format!("--max-redirects must be a number, got {}", n)))?,
+            None => DEFAULT_MAX_REDIRECTS,
+        };
+        let max_retries_total = match args.value_of("max_retries_total") {
+            Some(n) => Some(n.parse().map_err(|_| Error::Args(format!("--max-retries-total must be a number, got {}", n)))?),
+            None => None,
+        };
+        let spinner_after = match args.value_of("spinner_after") {
+            Some(ms) => Duration::from_millis(ms.parse().map_err(|_| Error::Args(format!("--spinner-after must be a number, got {}", ms)))?),
+            None => DEFAULT_SPINNER_AFTER,
+        };
+        let identity = match args.value_of("client_cert") {
+            Some(cert) => Some(ClientIdentity::from_files(Path::new(cert), args.value_of("client_key").map(Path::new), args.value_of("client_cert_password"))?),
+            None => None,
+        };
+        let debug_http = match args.value_of("debug_http_body") {
+            Some(n) => Some(n.parse().map_err(|_| Error::Args(format!("--debug-http-body must be a number, got {}", n)))?),
+            None if args.is_present("debug_http") => Some(DEFAULT_DEBUG_HTTP_BODY),
+            None => None,
+        };
+        let interactive = !args.is_present("quiet") && io::stdout().is_terminal() && io::stderr().is_terminal();
+        Ok(HttpSettings {
+            max_redirects,
+            spinner: if interactive { Some(spinner_after) } else { None },
+            retry: RetryPolicy::from_args(args)?,
+            max_retries_total,
+            dry_run: args.is_present("dry_run"),
+            identity,
+            proxy: args.value_of("proxy").map(Url::parse).transpose()?,
+            timeout: args.value_of("request_timeout").map(|value| parse_timeout("request-timeout", value)).transpose()?,
+            connect_timeout: args.value_of("connect_timeout").map(|value| parse_timeout("connect-timeout", value)).transpose()?,
+            debug_http,
+            har: args.value_of("har").map(PathBuf::from),
+        })
+    }
+}
+
+/// Parse a timeout as a duration such as `5m`, or a number of seconds, where zero means no limit.
+pub fn parse_timeout(name: &str, value: &str) -> Result<Duration> {
+    match value.parse::<u64>() {
+        Ok(secs) => Ok(Duration::from_secs(secs)),
+        Err(_) => humantime::parse_duration(value).map_err(|err| Error::Args(format!("--{} `{}`: {}", name, value, err))),
+    }
+}
+
+/// A client certificate and private key for mutual TLS, kept as PKCS#12.
+#[derive(Clone)]
+pub struct ClientIdentity {
+    pkcs12: Vec<u8>,
+    password: String,
+}
+
+impl ClientIdentity {
+    /// Read a DER-encoded PKCS#12 archive, checking that the password opens it.
+    pub fn from_pkcs12(der: Vec<u8>, password: &str) -> Result<Self> {
+        Pkcs12::from_der(&der)
+            .and_then(|pkcs12| pkcs12.parse2(password))
+            .map_err(|err| Error::Auth(format!("reading client certificate: {}", err)))?;
+        Ok(ClientIdentity { pkcs12: der, password: password.into() })
+    }
+
+    /// Read a PEM certificate, followed by any intermediate certificates, and a PEM private key.
+    pub fn from_pem(cert: &[u8], key: &[u8]) -> Result<Self> {
+        let auth = |err: openssl::error::ErrorStack| Error::Auth(format!("reading client certificate: {}", err));
+        let mut certs = X509::stack_from_pem(cert).map_err(auth)?.into_iter();
+        let leaf = certs.next().ok_or_else(|| Error::Auth("no certificate in the client certificate PEM".into()))?;
+        let mut chain = Stack::new()?;
+        for cert in certs {
+            chain.push(cert)?;
+        }
+        let key = PKey::private_key_from_pem(key).map_err(auth)?;
+        let pkcs12 = Pkcs12::builder().name("ota-cli").pkey(&key).cert(&leaf).ca(chain).build2("").map_err(auth)?;
+        Ok(ClientIdentity { pkcs12: pkcs12.to_der()?, password: String::new() })
+    }
+
+    /// Read a `.p12` or `.pfx` archive, or a PEM certificate with the key in `key` or the same file.
+    pub fn from_files(cert: &Path, key: Option<&Path>, password: Option<&str>) -> Result<Self> {
+        let pkcs12 = cert.extension().and_then(|ext| ext.to_str()).map(str::to_lowercase);
+        match pkcs12.as_deref() {
+            Some("p12") | Some("pfx") => Self::from_pkcs12(fs::read(cert)?, password.unwrap_or("")),
+            _ => {
+                let pem = fs::read(cert)?;
+                match key {
+                    Some(key) => Self::from_pem(&pem, &fs::read(key)?),
+                    None => Self::from_pem(&pem, &pem),
+                }
+            }
+        }
+    }
+
+    fn to_reqwest(&self) -> Result<reqwest::Identity> { Ok(reqwest::Identity::from_pkcs12_der(&self.pkcs12, &self.password)?) }
+}
+
+impl Debug for ClientIdentity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result { write!(f, "ClientIdentity(<redacted>)") }
+}
+
+/// When and how often an idempotent request is retried.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Retries for each request after a connection error or a `statuses` response.
+    pub retries: u32,
+    /// The delay before the first retry, doubling for each retry after.
+    pub backoff: Duration,
+    pub statuses: Vec<StatusCode>,
+    /// Retries for each request after a `429 Too Many Requests` response, of any method.
+    pub rate_limit_retries: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: DEFAULT_RETRIES,
+            backoff: DEFAULT_RETRY_BACKOFF,
+            statuses: DEFAULT_RETRY_ON.iter().map(|&status| StatusCode::from_u16(status).expect("retry status")).collect(),
+            rate_limit_retries: DEFAULT_RATE_LIMIT_RETRIES,
+        }
+    }
+}
+
+impl<'a> RetryPolicy {
+    /// Parse `--retries`, `--retry-backoff`, `--retry-on` and `--no-rate-limit-retry` CLI arguments.
+    pub fn from_args(args: &ArgMatches<'a>) -> Result<Self> {
+        let mut policy = Self::default();
+        if let Some(n) = args.value_of("retries") {
+            policy.retries = n.parse().map_err(|_| Error::Args(format!("--retries must be a number, got {}", n)))?;
+        }
+        if let Some(ms) = args.value_of("retry_backoff") {
+            policy.backoff = Duration::from_millis(ms.parse().map_err(|_| Error::Args(format!("--retry-backoff must be a number, got {}", ms)))?);
+        }
+        if args.is_present("no_rate_limit_retry") {
+            policy.rate_limit_retries = 0;
+        }
+        if let Some(statuses) = args.value_of("retry_on") {
+            policy.statuses = statuses
+                .split(',')
+                .map(|status| {
+                    status
+                        .trim()
+                        .parse::<u16>()
+                        .ok()
+                        .and_then(|status| StatusCode::from_u16(status).ok())
+                        .ok_or_else(|| Error::Args(format!("--retry-on must be a list of HTTP statuses, got {}", status)))
+                })
+                .collect::<Result<_>>()?;
+        }
+        Ok(policy)
+    }
+
+    /// Check whether a request failed in a way that may succeed on retry.
+    /// Return the delay before retrying a rate limited response, or `None` when it should not be retried.
+    ///
+    /// The server's `Retry-After` is waited for, up to `MAX_RETRY_AFTER`, and otherwise the backoff.
+    fn rate_limit_delay(&self, resp: &Response, retried: u32) -> Option<Duration> {
+        if retried >= self.rate_limit_retries {
+            return None;
+        }
+        match retry_after(resp.headers(), SystemTime::now()) {
+            Some(delay) if delay > MAX_RETRY_AFTER => {
+                warn!("rate limited for {}, failing without retrying", humantime::format_duration(delay));
+                None
+            }
+            Some(delay) => Some(delay),
+            None => Some(self.backoff_delay(retried)),
+        }
+    }
+
+    /// The backoff before a retry after `retried` earlier retries, doubling each time up to `MAX_RETRY_AFTER`.
+    fn backoff_delay(&self, retried: u32) -> Duration { self.backoff.saturating_mul(2u32.saturating_pow(retried)).min(MAX_RETRY_AFTER) }
+
+    fn retryable(&self, resp: &reqwest::Result<Response>) -> bool {
+        match resp {
+            Ok(resp) => self.statuses.contains(&resp.status()),
+            Err(err) => err.is_connect() || err.is_timeout() || err.is_request(),
+        }
+    }
+}
+
+/// Read a `Retry-After` header, either a number of seconds or an HTTP date.
+fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => Some(httpdate::parse_http_date(value).ok()?.duration_since(now).unwrap_or_default()),
+    }
+}
+
+/// A limit on the retries made across all requests, so a flaky server fails a batch fast.
+pub struct RetryBudget {
+    max: Option<u32>,
+    used: AtomicU32,
+    exhausted: AtomicBool,
+}
+
+impl RetryBudget {
+    pub fn new(max: Option<u32>) -> Self {
+        RetryBudget {
+            max,
+            used: AtomicU32::new(0),
+            exhausted: AtomicBool::new(false),
+        }
+    }
+
+    /// Take a retry from the budget, returning false once it has been used up.
+    fn take(&self) -> bool {
+        let max = self.max;
+        let taken = self.used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| match max {
+            Some(max) if used >= max => None,
+            _ => Some(used + 1),
+        });
+        if taken.is_err() && !self.exhausted.swap(true, Ordering::SeqCst) {
+            warn!("retry budget of {} used up, failing without retrying", max.unwrap_or_default());
+        }
+        taken.is_ok()
+    }
+
+    /// The retries used so far and the limit, when there is one.
+    pub fn usage(&self) -> Option<(u32, u32)> { self.max.map(|max| (self.used.load(Ordering::SeqCst), max)) }
+}
+
+/// A callback receiving the attempt number of each request sent.
+pub type AttemptObserver = Box<dyn Fn(u32)>;
+
+thread_local! {
+    static ATTEMPT_OBSERVER: RefCell<Option<AttemptObserver>> = RefCell::new(None);
+}
+
 impl HttpMethods for Http {}
 
+/// Limits for fetching a paginated list.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pagination {
+    /// The `limit` sent with each page request, or the server default.
+    pub limit: Option<u64>,
+    /// Follow `offset` until every value up to `total` has been fetched.
+    pub all_pages: bool,
+}
+
+impl Pagination {
+    /// Check `--limit` and `--all-pages` CLI arguments.
+    pub fn new(limit: Option<u64>, all_pages: bool) -> Result<Self> {
+        if limit == Some(0) {
+            return Err(Error::Args("--limit must be a positive number, got 0".into()));
+        }
+        Ok(Pagination { limit, all_pages })
+    }
+}
+
+/// An API request that can be sent by either the blocking or the async client.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: Method,
+    pub url: Url,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Value>,
+}
+
+impl Request {
+    pub fn new(method: Method, url: impl AsRef<str>) -> Result<Self> {
+        Ok(Request {
+            method,
+            url: Url::parse(url.as_ref())?,
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: None,
+        })
+    }
+
+    pub fn get(url: impl AsRef<str>) -> Result<Self> { Self::new(Method::GET, url) }
+
+    pub fn post(url: impl AsRef<str>) -> Result<Self> { Self::new(Method::POST, url) }
+
+    pub fn put(url: impl AsRef<str>) -> Result<Self> { Self::new(Method::PUT, url) }
+
+    pub fn delete(url: impl AsRef<str>) -> Result<Self> { Self::new(Method::DELETE, url) }
+
+    /// Append a query parameter.
+    pub fn query(mut self, key: &str, value: impl Display) -> Self {
+        self.query.push((key.into(), value.to_string()));
+        self
+    }
+
+    /// Append a request header.
+    pub fn header(mut self, key: &str, value: impl Display) -> Self {
+        self.headers.push((key.into(), value.to_string()));
+        self
+    }
+
+    /// Set a JSON request body.
+    pub fn json(mut self, body: Value) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    /// Convert into a request for the blocking client.
+    pub fn blocking(&self) -> RequestBuilder {
+        let mut builder = Http::client().request(self.method.clone(), self.url.clone());
+        if !self.query.is_empty() {
+            builder = builder.query(&self.query);
+        }
+        for (key, value) in &self.headers {
+            builder = builder.header(key.as_str(), value.as_str());
+        }
+        if let Some(ref body) = self.body {
+            builder = builder.json(body);
+        }
+        builder
+    }
+
+    /// Convert into a request for the async client.
+    #[cfg(feature = "async")]
+    pub fn nonblocking(&self) -> reqwest::RequestBuilder {
+        let mut builder = AsyncHttp::client().request(self.method.clone(), self.url.clone());
+        if !self.query.is_empty() {
+            builder = builder.query(&self.query);
+        }
+        for (key, value) in &self.headers {
+            builder = builder.header(key.as_str(), value.as_str());
+        }
+        if let Some(ref body) = self.body {
+            builder = builder.json(body);
+        }
+        builder
+    }
+}
+
+/// The bearer token and namespace headers for an optional token.
+fn auth_headers(token: Option<&AccessToken>) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    if let Some(token) = token {
+        debug!("request with token scopes: {:?}", token);
+        let mut bearer = HeaderValue::from_str(&format!("Bearer {}", token.access_token)).map_err(|err| Error::Token(err.to_string()))?;
+        bearer.set_sensitive(true);
+        headers.insert(AUTHORIZATION, bearer);
+        let namespace = token.namespace()?;
+        headers.insert("x-ats-namespace", HeaderValue::from_str(&namespace).map_err(|err| Error::Token(err.to_string()))?);
+    }
+    Ok(headers)
+}
+
+/// Follow at most `max_redirects`, relying on reqwest to drop sensitive headers across origins.
+fn redirect_policy(settings: &HttpSettings) -> Policy {
+    let max = settings.max_redirects;
+    Policy::custom(move |attempt| {
+        if attempt.previous().len() > max {
+            attempt.error(format!("more than {} redirects", max))
+        } else {
+            debug!("following redirect to {}", attempt.url());
+            attempt.follow()
+        }
+    })
+}
+
 impl Http {
+    /// Build a client following at most `max_redirects` redirects.
+    ///
+    /// The `Authorization` header is dropped on any redirect to another host,
+    /// port or scheme so the bearer token never leaves the original server.
+    ///
+    /// A client certificate from the settings, or else from the credentials, is
+    /// presented to every server asking for one.
+    ///
+    /// Requests go through the `--proxy` or config proxy, else any proxy from
+    /// `HTTPS_PROXY`, `HTTP_PROXY` or `ALL_PROXY`, except for hosts in `NO_PROXY`.
+    pub fn build_client(settings: &HttpSettings) -> Result<Client> {
+        let mut builder = Client::builder().redirect(redirect_policy(settings));
+        if let Some(identity) = Self::identity(settings) {
+            builder = builder.identity(identity.to_reqwest()?);
+        }
+        if let Some(proxy) = Self::proxy(settings)? {
+            builder = builder.proxy(proxy);
+        }
+        let (timeout, connect_timeout) = Self::timeouts(settings);
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(Some(timeout).filter(|timeout| !timeout.is_zero()));
+        }
+        if let Some(timeout) = connect_timeout.filter(|timeout| !timeout.is_zero()) {
+            builder = builder.connect_timeout(timeout);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Use the proxy and timeouts of the config where no flag sets them.
+    ///
+    /// They must be set before the first request, as the shared clients are built once.
+    pub fn use_config_settings(config: ConfigHttpSettings) {
+        if CONFIG_SETTINGS.get().is_some() {
+            return;
+        }
+        if CLIENT.get().is_some() {
+            warn!("config HTTP settings are ignored by the HTTP client already in use");
+        }
+        let _ = CONFIG_SETTINGS.set(config);
+    }
+
+    /// Return the request and connect timeouts from the settings, or else from the config.
+    fn timeouts(settings: &HttpSettings) -> (Option<Duration>, Option<Duration>) {
+        let config = CONFIG_SETTINGS.get();
+        (
+            settings.timeout.or_else(|| config.and_then(|config| config.timeout)),
+            settings.connect_timeout.or_else(|| config.and_then(|config| config.connect_timeout)),
+        )
+    }
+
+    /// Return the proxy from the settings, or else from the config, skipping hosts in `NO_PROXY`.
+    fn proxy(settings: &HttpSettings) -> Result<Option<Proxy>> {
+        match settings.proxy.as_ref().or_else(|| CONFIG_SETTINGS.get().and_then(|config| config.proxy.as_ref())) {
+            Some(url) => {
+                debug!("using proxy {}", url.host_str().unwrap_or(""));
+                Ok(Some(Proxy::all(url.clone())?.no_proxy(NoProxy::from_env())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Use a client certificate from the credentials when no `--client-cert` is set.
+    ///
+    /// It must be set before the first request, as the shared clients are built once.
+    pub fn use_credentials_identity(identity: &ClientIdentity) {
+        if CREDENTIALS_IDENTITY.get().is_some() {
+            return;
+        }
+        if CLIENT.get().is_some() {
+            warn!("client certificate from the credentials is ignored by the HTTP client already in use");
+        }
+        let _ = CREDENTIALS_IDENTITY.set(identity.clone());
+    }
+
+    /// Return the client certificate from the settings, or else from the credentials.
+    fn identity(settings: &HttpSettings) -> Option<&ClientIdentity> { settings.identity.as_ref().or_else(|| CREDENTIALS_IDENTITY.get()) }
+
+    /// Set up the shared clients, which otherwise use the default settings.
+    pub fn configure(settings: HttpSettings) -> Result<()> {
+        SETTINGS.set(settings).map_err(|_| Error::Failed("HTTP client already configured".into()))
+    }
+
+    /// Return the settings set by `configure`, or the defaults.
+    fn settings() -> &'static HttpSettings { SETTINGS.get().unwrap_or_else(|| DEFAULT_SETTINGS.get_or_init(HttpSettings::default)) }
+
+    /// Whether `--dry-run` is set, so changes are printed instead of sent.
+    pub fn dry_run() -> bool { Self::settings().dry_run }
+
+    /// Return the client shared by all requests.
+    pub(crate) fn client() -> &'static Client {
+        CLIENT.get_or_init(|| Self::build_client(Self::settings()).expect("default http client"))
+    }
+
+    /// Return the retry budget shared by all requests.
+    fn budget() -> &'static RetryBudget { BUDGET.get_or_init(|| RetryBudget::new(Self::settings().max_retries_total)) }
+
+    /// The retries used so far and the `--max-retries-total` limit, if set.
+    pub fn retry_usage() -> Option<(u32, u32)> { Self::budget().usage() }
+
+    /// Observe the attempts of each request sent from the current thread.
+    pub fn observe_attempts(observer: Option<AttemptObserver>) { ATTEMPT_OBSERVER.with(|cell| *cell.borrow_mut() = observer) }
+
+    /// Notify any observer on the current thread of a request attempt.
+    fn notify_attempt(attempt: u32) {
+        ATTEMPT_OBSERVER.with(|cell| {
+            if let Some(ref observer) = *cell.borrow() {
+                observer(attempt)
+            }
+        })
+    }
+
+    /// Start a spinner for a request, unless disabled or a batch is showing its own progress.
+    fn spinner(req: &reqwest::blocking::Request) -> Option<Spinner> {
+        let after = Self::settings().spinner?;
+        if ATTEMPT_OBSERVER.with(|cell| cell.borrow().is_some()) {
+            return None;
+        }
+        Some(Spinner::start(format!("Waiting for {} {}", req.method(), req.url().path()), after))
+    }
+
+    /// Send an API request with an optional bearer token.
+    pub fn execute(req: Request, token: Option<AccessToken>) -> Result<Response> { Self::send(req.blocking(), token) }
+
+    /// Send an API request, returning the headers and body of a successful response.
+    pub fn execute_body(req: Request, token: Option<AccessToken>) -> Result<(HeaderMap, Vec<u8>)> {
+        let mut resp = Self::execute(req, token)?.error_for_status()?;
+        let headers = resp.headers().to_owned();
+        let mut body = Vec::new();
+        resp.read_to_end(&mut body)?;
+        Ok((headers, body))
+    }
+
+    /// Send an API request, parsing the JSON body of a successful response.
+    pub fn execute_json<T: DeserializeOwned>(req: Request, token: Option<AccessToken>) -> Result<T> {
+        Ok(Self::execute(req, token)?.error_for_status()?.json()?)
+    }
+
+    /// Fetch a paginated list, returning the first page with the `values` of every page fetched.
+    ///
+    /// Without `all_pages`, only one page is fetched and a warning is logged if
+    /// the server holds more values.
+    pub fn execute_pages(req: Request, pages: Pagination, token: Option<AccessToken>) -> Result<(HeaderMap, Value)> {
+        let mut first: Option<(HeaderMap, Value)> = None;
+        let mut values = Vec::new();
+        loop {
+            let mut page_req = req.clone();
+            if let Some(limit) = pages.limit {
+                page_req = page_req.query("limit", limit);
+            }
+            if !values.is_empty() {
+                page_req = page_req.query("offset", values.len());
+            }
+            let resp = Self::execute(page_req, token.clone())?.error_for_status()?;
+            let headers = resp.headers().to_owned();
+            let mut page: Value = resp.json()?;
+            let fetched = match page.get_mut("values").map(Value::take) {
+                Some(Value::Array(fetched)) => fetched,
+                _ => return Err(Error::Parse("paginated list has no `values`".into())),
+            };
+            let last = fetched.is_empty();
+            values.extend(fetched);
+            let total = page.get("total").and_then(Value::as_u64).unwrap_or(0);
+            if first.is_none() {
+                first = Some((headers, page));
+            }
+            if !pages.all_pages {
+                if total > values.len() as u64 {
+                    warn!("showing {} of {} results, use --all-pages to fetch the rest", values.len(), total);
+                }
+                break;
+            }
+            if last || values.len() as u64 >= total {
+                break;
+            }
+        }
+
+        let (headers, mut page) = first.expect("first page");
+        if pages.all_pages {
+            page["offset"] = 0.into();
+            page["limit"] = values.len().into();
+        }
+        page["values"] = Value::Array(values);
+        Ok((headers, page))
+    }
+
     /// Send an HTTP request with an optional bearer token.
-    pub fn send(mut builder: RequestBuilder, token: Option<AccessToken>) -> Result<Response> {
-        if let Some(token) = token {
-            debug!("request with token scopes: {:?}", token);
-            builder = builder.bearer_auth(token.access_token.clone());
-
-            match token.namespace() {
-                Ok(name) => builder = builder.header("x-ats-namespace", name),
-                Err(err) => {
-                    error!("reading token namespace: {}", err)
+    ///
+    /// With `--dry-run`, requests that change server state are printed and
+    /// answered with an empty `204 No Content` marked with `DRY_RUN_HEADER` instead. Other requests are
+    /// sent once, without retries. No request is sent once the running command is cancelled.
+    pub fn send(builder: RequestBuilder, token: Option<AccessToken>) -> Result<Response> {
+        interrupt::check_cancelled()?;
+        if Self::settings().dry_run {
+            let req = builder.headers(auth_headers(token.as_ref())?).build()?;
+            if req.method().is_safe() {
+                return Self::execute_request(Self::client(), req).map_err(Error::Http);
+            }
+            println!("{}", render_request(&req));
+            let resp = http::Response::builder().status(StatusCode::NO_CONTENT).header(DRY_RUN_HEADER, "true").body(Vec::new());
+            return Ok(resp.expect("dry run response").into());
+        }
+        Self::send_live(builder, token)
+    }
+
+    /// Whether a response is the placeholder for a request not sent with `--dry-run`, so it has no body to parse.
+    pub fn is_dry_run(resp: &Response) -> bool { resp.headers().contains_key(DRY_RUN_HEADER) }
+
+    /// Send an HTTP request even with `--dry-run`, for requests that leave server state unchanged.
+    pub fn send_live(builder: RequestBuilder, token: Option<AccessToken>) -> Result<Response> {
+        interrupt::check_cancelled()?;
+        Self::send_with(Self::client(), builder, token, &Self::settings().retry, Self::budget())
+    }
+
+    /// Send a request, retrying idempotent requests as set by the policy while the budget allows.
+    ///
+    /// Rate limited requests of any method are retried, as the server did not handle them.
+    /// Requests with a streamed body, such as file uploads, cannot be cloned and are sent once.
+    fn send_with(client: &Client, builder: RequestBuilder, token: Option<AccessToken>, policy: &RetryPolicy, budget: &RetryBudget) -> Result<Response> {
+        let mut builder = builder.headers(auth_headers(token.as_ref())?);
+        let (mut attempt, mut retried, mut rate_limited) = (1, 0, 0);
+        loop {
+            let next = if retried < policy.retries || rate_limited < policy.rate_limit_retries { builder.try_clone() } else { None };
+            let req = builder.build()?;
+            let idempotent = req.method().is_idempotent();
+            if req.headers().len() > 0 {
+                debug!("request headers:\n{:#?}", req.headers());
+            }
+            if let Some(body) = req.body() {
+                debug!("request body:\n{:#?}\n", body);
+            }
+
+            Self::notify_attempt(attempt);
+            let resp = {
+                let _spinner = Self::spinner(&req);
+                Self::execute_request(client, req)
+            };
+            let delay = match resp {
+                Ok(ref resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    let delay = policy.rate_limit_delay(resp, rate_limited);
+                    rate_limited += 1;
+                    delay
+                }
+                _ if idempotent && retried < policy.retries && policy.retryable(&resp) => {
+                    retried += 1;
+                    Some(policy.backoff_delay(retried - 1))
+                }
+                _ => None,
+            };
+            match (delay, next) {
+                (Some(delay), Some(next)) if budget.take() => {
+                    debug!("retrying request after {:?}", delay);
+                    thread::sleep(delay);
+                    builder = next;
+                    attempt += 1;
                 }
+                _ => return resp.map_err(Error::Http),
+            }
+        }
+    }
+}
+
+impl Http {
+    /// Execute a request once, tracing it to stderr with `--debug-http` and recording it for `--har`.
+    fn execute_request(client: &Client, req: reqwest::blocking::Request) -> reqwest::Result<Response> {
+        let settings = Self::settings();
+        let har = settings.har.as_ref().map(|_| HAR.get_or_init(HarLog::default));
+        match settings.debug_http {
+            Some(limit) => execute_observed(client, req, Some((limit, &mut io::stderr())), har),
+            None if har.is_some() => execute_observed(client, req, None, har),
+            None => client.execute(req),
+        }
+    }
+
+    /// Save the requests recorded so far to the `--har` file, if set.
+    pub fn save_har() -> Result<()> {
+        match Self::settings().har.as_ref() {
+            Some(path) => HAR.get_or_init(HarLog::default).save(path),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Execute a request, tracing it to `trace`, if set, and adding it to the `har` log.
+///
+/// A trace shows the method, URL and body, then the response status, latency and body, with
+/// bodies cut to the trace limit and left out for zero. Only text and JSON response bodies are
+/// read, as reading one means buffering the whole response, while others are passed on unread.
+fn execute_observed(
+    client: &Client,
+    req: reqwest::blocking::Request,
+    mut trace: Option<(usize, &mut dyn Write)>,
+    har: Option<&HarLog>,
+) -> reqwest::Result<Response> {
+    let limit = trace.as_ref().map_or(0, |(limit, _)| *limit);
+    if let Some((_, ref mut out)) = trace {
+        let _ = writeln!(out, "> {} {}", req.method(), har::redact_url(req.url()));
+        match req.body().map(|body| body.as_bytes()) {
+            Some(Some(bytes)) if limit > 0 => {
+                let _ = writeln!(out, "> {}", trace_body(&har::content_type(req.headers()), bytes, limit));
+            }
+            Some(None) if limit > 0 => {
+                let _ = writeln!(out, "> <streamed body>");
+            }
+            _ => (),
+        }
+    }
+
+    let request = har.map(|_| HarLog::request(&req));
+    let (started_at, started) = (SystemTime::now(), Instant::now());
+    let resp = match client.execute(req) {
+        Ok(resp) => resp,
+        Err(err) => {
+            if let Some((_, ref mut out)) = trace {
+                let _ = writeln!(out, "< failed after {}ms: {}", started.elapsed().as_millis(), err);
             }
+            if let (Some(har), Some(request)) = (har, request) {
+                har.record(request, started_at, started.elapsed(), None);
+            }
+            return Err(err);
+        }
+    };
+    let elapsed = started.elapsed();
+    if let Some((_, ref mut out)) = trace {
+        let _ = writeln!(out, "< {} in {}ms", resp.status(), elapsed.as_millis());
+    }
+    let text = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|kind| kind.to_str().ok())
+        .is_some_and(|kind| kind.starts_with("text/") || kind.contains("json"));
+    if !text || (limit == 0 && har.is_none()) {
+        if let (Some(har), Some(request)) = (har, request) {
+            har.record(request, started_at, elapsed, Some((&resp, None)));
+        }
+        return Ok(resp);
+    }
+
+    let (status, version, headers) = (resp.status(), resp.version(), resp.headers().clone());
+    let body = resp.bytes()?.to_vec();
+    if let Some((_, ref mut out)) = trace {
+        if limit > 0 && !body.is_empty() {
+            let _ = writeln!(out, "< {}", trace_body(&har::content_type(&headers), &body, limit));
         }
+    }
+    let mut buffered = http::Response::builder().status(status).version(version);
+    if let Some(buffered_headers) = buffered.headers_mut() {
+        *buffered_headers = headers;
+    }
+    let resp: Response = buffered.body(body.clone()).expect("buffered response").into();
+    if let (Some(har), Some(request)) = (har, request) {
+        har.record(request, started_at, elapsed, Some((&resp, Some(&body))));
+    }
+    Ok(resp)
+}
+
+/// Show a body as text with secrets redacted as in `--har`, cut to `limit` bytes with a note of its full length.
+fn trace_body(kind: &str, bytes: &[u8], limit: usize) -> String {
+    let text = har::redact_body(kind, bytes);
+    if text.len() <= limit {
+        return text;
+    }
+    let end = (0..=limit).rev().find(|end| text.is_char_boundary(*end)).unwrap_or(0);
+    format!("{}... ({} bytes)", &text[..end], bytes.len())
+}
+
+/// Render a request as its method and URL, headers and body, with sensitive header values redacted.
+fn render_request(req: &reqwest::blocking::Request) -> String {
+    let mut out = format!("{} {}\n", req.method(), req.url());
+    for (name, value) in req.headers() {
+        let value = if value.is_sensitive() { "<redacted>".into() } else { String::from_utf8_lossy(value.as_bytes()).into_owned() };
+        out += &format!("{}: {}\n", name, value);
+    }
+    match req.body().map(|body| body.as_bytes()) {
+        Some(Some(bytes)) => match serde_json::from_slice::<Value>(bytes) {
+            Ok(json) => out += &format!("\n{}\n", serde_json::to_string_pretty(&json).expect("json body")),
+            Err(_) => out += &format!("\n{}\n", String::from_utf8_lossy(bytes)),
+        },
+        Some(None) => out += "\n<streamed body>\n",
+        None => (),
+    }
+    out
+}
+
+/// A pending response from the async client.
+#[cfg(feature = "async")]
+pub type ApiFuture = Pin<Box<dyn Future<Output = Result<reqwest::Response>> + Send>>;
+
+/// Make HTTP requests to server endpoints without blocking.
+#[cfg(feature = "async")]
+pub struct AsyncHttp;
+
+#[cfg(feature = "async")]
+static ASYNC_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+
+#[cfg(feature = "async")]
+impl AsyncHttp {
+    /// Return the async client shared by all requests.
+    pub(crate) fn client() -> &'static reqwest::Client {
+        ASYNC_CLIENT.get_or_init(|| {
+            let mut builder = reqwest::Client::builder().redirect(redirect_policy(Http::settings()));
+            if let Some(identity) = Http::identity(Http::settings()) {
+                builder = builder.identity(identity.to_reqwest().expect("client certificate"));
+            }
+            if let Some(proxy) = Http::proxy(Http::settings()).expect("proxy") {
+                builder = builder.proxy(proxy);
+            }
+            let (timeout, connect_timeout) = Http::timeouts(Http::settings());
+            if let Some(timeout) = timeout.filter(|timeout| !timeout.is_zero()) {
+                builder = builder.timeout(timeout);
+            }
+            if let Some(timeout) = connect_timeout.filter(|timeout| !timeout.is_zero()) {
+                builder = builder.connect_timeout(timeout);
+            }
+            builder.build().expect("default async http client")
+        })
+    }
+
+    /// Send an API request with an optional bearer token.
+    pub fn execute(req: Result<Request>, token: Option<AccessToken>) -> ApiFuture {
+        Box::pin(async move {
+            let req = req?.nonblocking().headers(auth_headers(token.as_ref())?).build()?;
+            debug!("sending async request: {} {}", req.method(), req.url());
+            Ok(Self::client().execute(req).await?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use crate::mock::{MockResponse, MockServer};
+    use structopt::StructOpt;
+
+    fn redirect_to(target: &MockServer) -> MockServer {
+        let location = format!("{}final", target.url);
+        MockServer::start(move |req| match req.route() {
+            "/final" => MockResponse::status(200),
+            "/loop" => MockResponse::status(302).header("location", "/loop"),
+            "/local" => MockResponse::status(302).header("location", "/final"),
+            _ => MockResponse::status(302).header("location", &location),
+        })
+    }
+
+    #[test]
+    fn cross_host_redirect_drops_auth() {
+        let target = MockServer::start(|_| MockResponse::status(200));
+        let origin = redirect_to(&target);
+        let token = origin.config().token;
+
+        let resp = Http::get(format!("{}start", origin.url), token.clone()).expect("redirected");
+        assert!(resp.status().is_success());
+        assert!(origin.requests()[0].headers.contains_key("authorization"));
+        assert!(!target.requests()[0].headers.contains_key("authorization"));
+
+        Http::get(format!("{}local", origin.url), token).expect("same host");
+        assert!(origin.requests().iter().filter(|r| r.route() == "/final").all(|r| r.headers.contains_key("authorization")));
+    }
+
+    #[test]
+    fn requests_go_through_proxy() {
+        let proxy = MockServer::start(|_| MockResponse::status(200));
+        let client = Http::build_client(&HttpSettings {
+            proxy: Some(proxy.url.clone()),
+            ..HttpSettings::default()
+        }).expect("client");
+        let resp = client.get("http://registry.example.invalid/api/v1/devices").send().expect("proxied");
+        assert_eq!(resp.status(), StatusCode::OK);
+        let requests = proxy.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "http://registry.example.invalid/api/v1/devices");
+    }
+
+    #[test]
+    fn slow_responses_time_out() {
+        let slow = MockServer::start(|_| {
+            thread::sleep(Duration::from_millis(500));
+            MockResponse::status(200)
+        });
+        let client = |timeout| Http::build_client(&HttpSettings { timeout: Some(timeout), ..HttpSettings::default() }).expect("client");
+        assert!(client(Duration::from_millis(100)).get(slow.url.clone()).send().is_err());
+        assert!(client(Duration::ZERO).get(slow.url.clone()).send().is_ok());
 
-        let req = builder.build()?;
-        if req.headers().len() > 0 {
-            debug!("request headers:\n{:#?}", req.headers());
+        assert_eq!(parse_timeout("request-timeout", "90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_timeout("request-timeout", "5m").unwrap(), Duration::from_secs(300));
+        assert!(parse_timeout("request-timeout", "soon").is_err());
+    }
+
+    #[test]
+    fn redirects_are_limited() {
+        let target = MockServer::start(|_| MockResponse::status(200));
+        let origin = redirect_to(&target);
+        let client = Http::build_client(&HttpSettings {
+            max_redirects: 2,
+            ..HttpSettings::default()
+        }).expect("client");
+        assert!(client.get(format!("{}loop", origin.url)).send().is_err());
+        assert_eq!(origin.requests().len(), 3);
+    }
+
+    fn retries(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
         }
-        if let Some(body) = req.body() {
-            debug!("request body:\n{:#?}\n", body);
+    }
+
+    #[test]
+    fn retry_budget_caps_total_retries() {
+        let server = MockServer::start(|_| MockResponse::status(503));
+        let client = Http::build_client(&HttpSettings::default()).expect("client");
+        let get = || client.get(format!("{}flaky", server.url));
+
+        let budget = RetryBudget::new(Some(2));
+        for _ in 0..3 {
+            let resp = Http::send_with(&client, get(), None, &retries(3), &budget).expect("response");
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
         }
+        assert_eq!(server.requests().len(), 5);
+        assert_eq!(budget.usage(), Some((2, 2)));
+
+        let unlimited = RetryBudget::new(None);
+        Http::send_with(&client, get(), None, &retries(1), &unlimited).expect("response");
+        assert_eq!(server.requests().len(), 7);
+        assert_eq!(unlimited.usage(), None);
+    }
+
+    #[test]
+    fn retry_rate_limited_requests() {
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let server = MockServer::start(move |req| match req.route() {
+            "/limited" if calls.fetch_add(1, Ordering::SeqCst) < 2 => MockResponse::status(429).header("Retry-After", "0"),
+            "/limited" => MockResponse::status(201),
+            "/slow-down" => MockResponse::status(429).header("Retry-After", "3600"),
+            _ => MockResponse::status(429),
+        });
+        let client = Http::build_client(&HttpSettings::default()).expect("client");
+        let send = |builder, policy: &RetryPolicy| {
+            Http::send_with(&client, builder, None, policy, &RetryBudget::new(None)).expect("response").status()
+        };
+        let count = |route: &str| server.requests().iter().filter(|req| req.route() == route).count();
+
+        assert_eq!(send(client.post(format!("{}limited", server.url)).body("{}"), &retries(0)), StatusCode::CREATED);
+        assert_eq!(count("/limited"), 3);
+        assert_eq!(send(client.get(format!("{}slow-down", server.url)), &retries(2)), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(count("/slow-down"), 1);
+        assert_eq!(send(client.get(format!("{}backoff", server.url)), &retries(2)), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(count("/backoff"), 1 + DEFAULT_RATE_LIMIT_RETRIES as usize);
+
+        let args = Cli::clap().get_matches_from(vec!["ota", "--no-rate-limit-retry", "config", "show"]);
+        let policy = RetryPolicy::from_args(&args).expect("policy");
+        send(client.get(format!("{}backoff", server.url)), &RetryPolicy { statuses: vec![StatusCode::TOO_MANY_REQUESTS], ..policy });
+        assert_eq!(count("/backoff"), 2 + DEFAULT_RATE_LIMIT_RETRIES as usize);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        let before = httpdate::parse_http_date("Wed, 21 Oct 2015 07:27:30 GMT").unwrap();
+        assert_eq!(retry_after(&headers, before), Some(Duration::from_secs(30)));
+        assert_eq!(retry_after(&headers, SystemTime::now()), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_idempotent_requests_on_listed_statuses() {
+        let server = MockServer::start(|req| match req.route() {
+            "/down" => MockResponse::status(502),
+            _ => MockResponse::status(500),
+        });
+        let client = Http::build_client(&HttpSettings::default()).expect("client");
+        let send = |builder, policy: &RetryPolicy| {
+            Http::send_with(&client, builder, None, policy, &RetryBudget::new(None)).expect("response").status()
+        };
+        let count = |route: &str, method: &str| server.requests().iter().filter(|req| req.route() == route && req.method == method).count();
+
+        assert_eq!(send(client.get(format!("{}down", server.url)), &retries(2)), StatusCode::BAD_GATEWAY);
+        assert_eq!(count("/down", "GET"), 3);
+        send(client.put(format!("{}down", server.url)).body("replayable"), &retries(2));
+        assert_eq!(count("/down", "PUT"), 3);
+        send(client.post(format!("{}down", server.url)), &retries(2));
+        assert_eq!(count("/down", "POST"), 1);
+        send(client.get(format!("{}error", server.url)), &retries(2));
+        assert_eq!(count("/error", "GET"), 1);
+
+        let retry_on = |statuses| RetryPolicy::from_args(&Cli::clap().get_matches_from(vec!["ota", "--retry-on", statuses, "config", "show"]));
+        let policy = retry_on("500, 503").expect("policy");
+        assert_eq!(policy.statuses, vec![StatusCode::INTERNAL_SERVER_ERROR, StatusCode::SERVICE_UNAVAILABLE]);
+        assert_eq!(policy.retries, DEFAULT_RETRIES);
+        send(client.get(format!("{}error", server.url)), &RetryPolicy {
+            backoff: Duration::from_millis(1),
+            ..policy
+        });
+        assert_eq!(count("/error", "GET"), 1 + 1 + DEFAULT_RETRIES as usize);
+        assert!(retry_on("bad").is_err());
+
+        let policy = RetryPolicy { backoff: Duration::from_secs(1), ..RetryPolicy::default() };
+        assert_eq!(policy.backoff_delay(0), Duration::from_secs(1));
+        assert_eq!(policy.backoff_delay(3), Duration::from_secs(8));
+        assert_eq!(policy.backoff_delay(40), MAX_RETRY_AFTER);
+    }
+
+    #[test]
+    fn trace_requests_and_responses() {
+        let server = MockServer::start(|req| match req.route() {
+            "/devices" => MockResponse::json(200, json!({"values": ["a".repeat(40)]})),
+            "/token" => MockResponse::json(200, json!({"access_token": "secret-token", "expires_in": 3600})),
+            _ => MockResponse {
+                status: 200,
+                headers: vec![("content-type".into(), "application/octet-stream".into())],
+                body: b"binary".to_vec(),
+            },
+        });
+        let client = Http::build_client(&HttpSettings::default()).expect("client");
+        let mut out = Vec::new();
+        let req = client.post(format!("{}devices", server.url)).body(r#"{"name":"alpha"}"#).build().unwrap();
+        let resp = execute_observed(&client, req, Some((16, &mut out)), None).expect("traced");
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<Value>().unwrap()["values"][0].as_str().unwrap().len(), 40);
+
+        let trace = String::from_utf8(out).unwrap();
+        let lines = trace.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], format!("> POST {}devices", server.url));
+        assert_eq!(lines[1], r#"> {"name":"alpha"}"#);
+        assert!(lines[2].starts_with("< 200 OK in ") && lines[2].ends_with("ms"));
+        assert_eq!(lines[3], r#"< {"values":["aaaa... (55 bytes)"#);
+
+        let mut out = Vec::new();
+        let resp = execute_observed(&client, client.get(format!("{}file", server.url)).build().unwrap(), Some((16, &mut out)), None).expect("traced");
+        assert_eq!(resp.bytes().unwrap().as_ref(), b"binary");
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+
+        let mut out = Vec::new();
+        let req = client.post(format!("{}token?password=hunter2", server.url)).form(&[("client_secret", "secret-form")]).build().unwrap();
+        execute_observed(&client, req, Some((1024, &mut out)), None).expect("traced");
+        let trace = String::from_utf8(out).unwrap();
+        assert!(trace.contains("client_secret=%3Credacted%3E") && trace.contains("password=%3Credacted%3E"));
+        assert!(!trace.contains("secret-") && !trace.contains("hunter2"));
+    }
+
+    #[test]
+    fn record_redacted_har() {
+        let server = MockServer::start(|req| match req.route() {
+            "/token" => MockResponse::json(200, json!({"access_token": "secret-token", "expires_in": 3600})),
+            _ => MockResponse::status(204),
+        });
+        let client = Http::build_client(&HttpSettings::default()).expect("client");
+        let har = HarLog::default();
+        let token = client
+            .post(format!("{}token?password=hunter2&scope=all", server.url))
+            .basic_auth("client", Some("secret-password"))
+            .form(&[("grant_type", "client_credentials"), ("client_secret", "secret-form")]);
+        let resp = execute_observed(&client, token.build().unwrap(), None, Some(&har)).expect("token");
+        assert_eq!(resp.json::<Value>().unwrap()["access_token"], "secret-token");
+        let update = client.put(format!("{}devices/1", server.url)).json(&json!({"name": "alpha", "keys": [{"private": "secret-key"}]}));
+        execute_observed(&client, update.build().unwrap(), None, Some(&har)).expect("update");
+        assert!(execute_observed(&client, client.get("http://127.0.0.1:1/unreachable").build().unwrap(), None, Some(&har)).is_err());
+
+        let archive = har.archive();
+        assert!(!archive.to_string().contains("secret-"));
+        let entries = archive["log"]["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 3);
+        let token = &entries[0];
+        assert_eq!(token["request"]["method"], "POST");
+        assert!(token["request"]["url"].as_str().unwrap().ends_with("/token?password=%3Credacted%3E&scope=all"));
+        assert!(token["request"]["headers"].as_array().unwrap().contains(&json!({"name": "authorization", "value": "<redacted>"})));
+        assert_eq!(token["request"]["postData"]["text"], "grant_type=client_credentials&client_secret=%3Credacted%3E");
+        assert_eq!(token["response"]["status"], 200);
+        assert_eq!(serde_json::from_str::<Value>(token["response"]["content"]["text"].as_str().unwrap()).unwrap()["access_token"], "<redacted>");
+        assert_eq!(entries[1]["request"]["postData"]["text"], r#"{"keys":[{"private":"<redacted>"}],"name":"alpha"}"#);
+        assert_eq!(entries[1]["response"]["status"], 204);
+        assert_eq!(entries[2]["response"]["status"], 0);
+    }
+
+    #[test]
+    fn dry_run_renders_request() {
+        let token = AccessToken {
+            access_token: "secret".into(),
+            scope: Some("namespace.acme".into()),
+            credentials_namespace: None,
+            namespace_override: None,
+            expires_in: None,
+            expires_at: None,
+        };
+        let req = Request::post("http://campaigner/api/v2/campaigns/1/cancel")
+            .unwrap()
+            .query("force", true)
+            .json(json!({"reason": "bad firmware"}))
+            .blocking()
+            .headers(auth_headers(Some(&token)).unwrap())
+            .build()
+            .unwrap();
+
+        let rendered = render_request(&req);
+        assert!(rendered.starts_with("POST http://campaigner/api/v2/campaigns/1/cancel?force=true\n"));
+        assert!(rendered.contains("authorization: <redacted>\n"));
+        assert!(rendered.contains("x-ats-namespace: acme\n"));
+        assert!(rendered.contains("content-type: application/json\n"));
+        assert!(rendered.ends_with("\n{\n  \"reason\": \"bad firmware\"\n}\n"));
+        assert!(!rendered.contains("secret"));
+    }
+
+    #[test]
+    fn follow_all_pages() {
+        let server = MockServer::start(|req| {
+            let offset = req.path.split("offset=").nth(1).and_then(|n| n.split('&').next()).unwrap_or("0").parse::<u64>().unwrap();
+            let values = (offset..(offset + 2).min(5)).collect::<Vec<_>>();
+            MockResponse::json(200, json!({"total": 5, "offset": offset, "limit": 2, "values": values}))
+        });
+        let req = || Request::get(format!("{}list", server.url)).unwrap();
+        let all = Pagination {
+            limit: Some(2),
+            all_pages: true,
+        };
+
+        let (_, page) = Http::execute_pages(req(), all, None).expect("pages");
+        assert_eq!(page["values"], json!([0, 1, 2, 3, 4]));
+        assert_eq!(page["total"], 5);
+        assert_eq!(page["limit"], 5);
+        let paths = server.requests().into_iter().map(|req| req.path).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["/list?limit=2", "/list?limit=2&offset=2", "/list?limit=2&offset=4"]);
 
-        Client::new().execute(req).map_err(Error::Http)
+        let (_, page) = Http::execute_pages(req(), Pagination::default(), None).expect("first page");
+        assert_eq!(page["values"], json!([0, 1]));
+        assert_eq!(server.requests().len(), 4);
     }
 }
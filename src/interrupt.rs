@@ -0,0 +1,173 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use crate::error::{Error, Result};
+
+/// The exit code after an interrupt, following the shell convention for SIGINT.
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static SCOPES: Mutex<Vec<ScopeKind>> = Mutex::new(Vec::new());
+static PARTIAL: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Install a Ctrl-C handler.
+///
+/// Outside of any scope the process exits straight away. In a scope the first
+/// Ctrl-C sets the interrupt flag and prints the message of the innermost
+/// scope, e.g. so a batch stops new items from starting while in-flight ones
+/// finish, and a second Ctrl-C exits immediately. Temporary downloads that
+/// can't be resumed are removed before exiting.
+pub fn install() -> Result<()> {
+    ctrlc::set_handler(|| {
+        let scope = SCOPES.lock().map(|scopes| scopes.last().copied()).unwrap_or(None);
+        match scope {
+            Some(scope) if !INTERRUPTED.swap(true, Ordering::SeqCst) => {
+                CANCELLED.store(scope.cancels(), Ordering::SeqCst);
+                eprintln!("\n{}", scope.message());
+            }
+            _ => {
+                remove_partial_files();
+                process::exit(EXIT_CODE);
+            }
+        }
+    })
+    .map_err(|err| Error::Failed(format!("installing Ctrl-C handler: {}", err)))
+}
+
+/// Check whether a scope was interrupted.
+pub fn interrupted() -> bool { INTERRUPTED.load(Ordering::SeqCst) }
+
+/// Check whether the running command should stop, after a Ctrl-C in the shell.
+pub fn cancelled() -> bool { CANCELLED.load(Ordering::SeqCst) }
+
+/// Fail with an I/O error once the running command is cancelled, to stop a transfer.
+pub(crate) fn check_cancelled() -> io::Result<()> {
+    if cancelled() {
+        return Err(io::Error::new(io::ErrorKind::Other, "interrupted"));
+    }
+    Ok(())
+}
+
+/// Clear the flags once an interrupted command is done, before running another command.
+pub(crate) fn reset() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    CANCELLED.store(false, Ordering::SeqCst);
+}
+
+/// The flag set by the first Ctrl-C in a scope.
+pub(crate) fn flag() -> &'static AtomicBool { &INTERRUPTED }
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScopeKind {
+    Batch,
+    Shell,
+    Watch,
+}
+
+impl ScopeKind {
+    fn message(self) -> &'static str {
+        match self {
+            ScopeKind::Batch => "Interrupted, finishing in-flight requests. Press Ctrl-C again to exit now.",
+            ScopeKind::Shell => "Interrupted, cancelling the command. Press Ctrl-C again to exit the shell.",
+            ScopeKind::Watch => "Stopped watching.",
+        }
+    }
+
+    /// Whether the running command is cancelled, rather than left to finish what it started.
+    fn cancels(self) -> bool { self == ScopeKind::Shell }
+}
+
+/// Handle Ctrl-C in a scope until the guard is dropped, instead of exiting.
+///
+/// Scopes nest, and the innermost one decides what the first Ctrl-C does.
+pub(crate) struct Scope(ScopeKind);
+
+impl Scope {
+    fn enter(kind: ScopeKind) -> Self {
+        SCOPES.lock().expect("interrupt scopes").push(kind);
+        Scope(kind)
+    }
+
+    /// A batch, which starts no new items after a Ctrl-C.
+    pub(crate) fn batch() -> Self { Self::enter(ScopeKind::Batch) }
+
+    /// An `ota shell` session, where a Ctrl-C cancels the running command.
+    pub(crate) fn shell() -> Self { Self::enter(ScopeKind::Shell) }
+
+    /// A watch loop, which returns its last result after a Ctrl-C.
+    pub(crate) fn watch() -> Self { Self::enter(ScopeKind::Watch) }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let mut scopes = SCOPES.lock().expect("interrupt scopes");
+        if let Some(at) = scopes.iter().rposition(|kind| *kind == self.0) {
+            scopes.remove(at);
+        }
+    }
+}
+
+/// Remove a partial file when the process exits on Ctrl-C, until the guard is dropped.
+pub(crate) struct PartialFile(PathBuf);
+
+impl PartialFile {
+    pub(crate) fn track(path: &Path) -> Self {
+        PARTIAL.lock().expect("partial files").push(path.to_path_buf());
+        PartialFile(path.to_path_buf())
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        let mut partial = PARTIAL.lock().expect("partial files");
+        if let Some(at) = partial.iter().rposition(|path| *path == self.0) {
+            partial.remove(at);
+        }
+    }
+}
+
+/// Check whether a file would be removed on Ctrl-C.
+#[cfg(test)]
+pub(crate) fn is_partial(path: &Path) -> bool { PARTIAL.lock().expect("partial files").iter().any(|partial| partial == path) }
+
+fn remove_partial_files() {
+    if let Ok(partial) = PARTIAL.lock() {
+        for path in partial.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_scopes() {
+        let shell = Scope::shell();
+        let batch = Scope::batch();
+        assert!(SCOPES.lock().unwrap().contains(&ScopeKind::Shell));
+        drop(batch);
+        assert!(SCOPES.lock().unwrap().contains(&ScopeKind::Shell));
+        drop(shell);
+        assert!(!SCOPES.lock().unwrap().contains(&ScopeKind::Shell));
+        assert!(ScopeKind::Shell.cancels() && !ScopeKind::Batch.cancels() && !ScopeKind::Watch.cancels());
+
+        let path = std::env::temp_dir().join(format!("ota-cli-partial-{}.part", process::id()));
+        fs::write(&path, b"partial").unwrap();
+        {
+            let _partial = PartialFile::track(&path);
+            assert!(PARTIAL.lock().unwrap().contains(&path));
+        }
+        assert!(!PARTIAL.lock().unwrap().contains(&path));
+        fs::remove_file(&path).unwrap();
+    }
+}
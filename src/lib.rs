@@ -5,7 +5,21 @@ extern crate log;
 extern crate serde_json;
 
 pub mod api;
+pub mod batch;
+pub mod cli;
 pub mod command;
 pub mod config;
 pub mod error;
+pub mod har;
 pub mod http;
+pub mod interrupt;
+pub mod progress;
+pub mod report;
+pub mod shell;
+pub mod sign;
+pub mod spinner;
+pub mod table;
+pub mod watch;
+
+#[cfg(test)]
+mod mock;
@@ -0,0 +1,172 @@
+//! A minimal HTTP server for testing API calls without a live backend.
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+use url::Url;
+
+use crate::api::auth_plus::AccessToken;
+use crate::config::Config;
+use crate::http::DRY_RUN_HEADER;
+
+/// A request received by the `MockServer`.
+#[derive(Clone, Debug)]
+pub struct MockRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl MockRequest {
+    /// Return the request path without any query string.
+    pub fn route(&self) -> &str { self.path.split('?').next().unwrap_or("") }
+
+    /// Return the body parsed as JSON.
+    pub fn json(&self) -> serde_json::Value { serde_json::from_slice(&self.body).expect("json request body") }
+}
+
+/// A canned response returned by the `MockServer`.
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MockResponse {
+    pub fn status(status: u16) -> Self {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        MockResponse {
+            status,
+            headers: vec![("content-type".into(), "application/json".into())],
+            body: serde_json::to_vec(&body).expect("json response body"),
+        }
+    }
+
+    /// The placeholder answer to a request not sent with `--dry-run`.
+    pub fn dry_run() -> Self { Self::status(204).header(DRY_RUN_HEADER, "true") }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+/// Serve HTTP requests on localhost using a handler function.
+pub struct MockServer {
+    pub url: Url,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockServer {
+    /// Start a server on a random local port.
+    pub fn start(handler: impl Fn(&MockRequest) -> MockResponse + Send + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let url = format!("http://{}/", listener.local_addr().expect("local addr")).parse().expect("url");
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                if let Some(req) = read_request(&mut stream) {
+                    recorded.lock().unwrap().push(req.clone());
+                    write_response(&mut stream, handler(&req));
+                }
+            }
+        });
+
+        MockServer { url, requests }
+    }
+
+    /// Return all requests received so far.
+    pub fn requests(&self) -> Vec<MockRequest> { self.requests.lock().unwrap().clone() }
+
+    /// Return a config pointing every service at this server.
+    pub fn config(&self) -> Config {
+        Config {
+            credentials_zip: "credentials.zip".into(),
+            credentials: None,
+            token: Some(AccessToken {
+                access_token: "token".into(),
+                scope: Some("namespace.default".into()),
+                credentials_namespace: None,
+                namespace_override: None,
+                expires_in: None,
+                expires_at: None,
+            }),
+            static_token: false,
+            dir: None,
+            cache_dir: None,
+            source: None,
+            profile: None,
+            campaigner: self.url.clone(),
+            director: self.url.clone(),
+            registry: self.url.clone(),
+            reposerver: self.url.clone(),
+            proxy: None,
+            timeout: None,
+            connect_timeout: None,
+            namespace: None,
+        }
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<MockRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(idx) = line.find(':') {
+            headers.insert(line[..idx].trim().to_lowercase(), line[idx + 1..].trim().to_string());
+        }
+    }
+
+    let length = headers.get("content-length").and_then(|len| len.parse().ok()).unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(MockRequest {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+fn write_response(stream: &mut TcpStream, resp: MockResponse) {
+    let mut head = format!("HTTP/1.1 {} Mock\r\ncontent-length: {}\r\nconnection: close\r\n", resp.status, resp.body.len());
+    for (name, value) in resp.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(&resp.body);
+    let _ = stream.flush();
+}
@@ -0,0 +1,324 @@
+use clap::ArgMatches;
+use std::{
+    cell::RefCell,
+    io::{self, IsTerminal, Read, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::interrupt;
+
+const BAR_WIDTH: usize = 20;
+const REDRAW_AFTER: Duration = Duration::from_millis(100);
+
+/// How batch progress is reported on stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ProgressMode {
+    /// An overall bar plus one line per active transfer, redrawn in place.
+    Bars,
+    /// A single summary line once the batch has finished.
+    Summary,
+    /// No progress output.
+    #[default]
+    Hidden,
+}
+
+impl<'a> ProgressMode {
+    /// Show bars only when stderr is a terminal and `--quiet` is not set.
+    pub fn from_args(args: &ArgMatches<'a>) -> Self {
+        if !args.is_present("quiet") && io::stderr().is_terminal() {
+            ProgressMode::Bars
+        } else {
+            ProgressMode::Summary
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<(Arc<Progress>, String)>> = const { RefCell::new(None) };
+}
+
+/// Track the item being worked on by this thread, for `report` calls.
+pub(crate) fn track(item: Option<(Arc<Progress>, String)>) { CURRENT.with(|cell| *cell.borrow_mut() = item) }
+
+/// Report the bytes transferred so far for the tracked item of this thread, if any.
+pub(crate) fn report(bytes: u64, length: Option<u64>) {
+    CURRENT.with(|cell| {
+        if let Some((ref progress, ref label)) = *cell.borrow() {
+            progress.transferred(label, bytes, length);
+        }
+    })
+}
+
+/// Run `f` as the only item of a progress display, drawn as bars on a terminal and hidden otherwise.
+pub(crate) fn single<R, E>(action: &str, label: &str, mode: ProgressMode, f: impl FnOnce() -> Result<R, E>) -> Result<R, E> {
+    let mode = match mode {
+        ProgressMode::Summary => ProgressMode::Hidden,
+        mode => mode,
+    };
+    let progress = Arc::new(Progress::new(action, 1, mode));
+    progress.start_item(label);
+    track(Some((progress.clone(), label.into())));
+    let result = f();
+    track(None);
+    progress.finish_item(label, result.is_ok());
+    progress.finish();
+    result
+}
+
+/// A writer reporting the bytes written to the tracked item of this thread, failing once the command is cancelled.
+pub(crate) struct ProgressWriter<W: Write> {
+    inner: W,
+    written: u64,
+    length: Option<u64>,
+}
+
+impl<W: Write> ProgressWriter<W> {
+    pub(crate) fn new(inner: W, offset: u64, length: Option<u64>) -> Self {
+        ProgressWriter {
+            inner,
+            written: offset,
+            length,
+        }
+    }
+}
+
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        interrupt::check_cancelled()?;
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        report(self.written, self.length);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+/// A reader reporting the bytes read to the tracked item of this thread, for streamed uploads, failing once the command is cancelled.
+pub(crate) struct ProgressReader<R: Read> {
+    inner: R,
+    read: u64,
+    length: Option<u64>,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub(crate) fn new(inner: R, length: Option<u64>) -> Self { ProgressReader { inner, read: 0, length } }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        interrupt::check_cancelled()?;
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        report(self.read, self.length);
+        Ok(read)
+    }
+}
+
+/// Progress of a batch, written to stderr so stdout output is left intact.
+pub struct Progress {
+    action: String,
+    total: usize,
+    mode: ProgressMode,
+    output: Mutex<Box<dyn Write + Send>>,
+    state: Mutex<ProgressState>,
+}
+
+#[derive(Default)]
+struct ProgressState {
+    done: usize,
+    failed: usize,
+    active: Vec<Transfer>,
+    drawn: usize,
+    last_draw: Option<Instant>,
+}
+
+struct Transfer {
+    label: String,
+    attempt: u32,
+    bytes: u64,
+    length: Option<u64>,
+}
+
+impl Progress {
+    pub fn new(action: &str, total: usize, mode: ProgressMode) -> Self { Self::with_output(action, total, mode, Box::new(io::stderr())) }
+
+    fn with_output(action: &str, total: usize, mode: ProgressMode, output: Box<dyn Write + Send>) -> Self {
+        Progress {
+            action: action.into(),
+            total,
+            mode,
+            output: Mutex::new(output),
+            state: Mutex::new(ProgressState::default()),
+        }
+    }
+
+    pub fn start_item(&self, label: &str) {
+        self.state.lock().unwrap().active.push(Transfer {
+            label: label.into(),
+            attempt: 1,
+            bytes: 0,
+            length: None,
+        });
+        self.draw(true);
+    }
+
+    /// Record an attempt for the request of an item, where any attempt after the first is a retry.
+    pub fn attempt(&self, label: &str, attempt: u32) {
+        if let Some(transfer) = self.state.lock().unwrap().active.iter_mut().find(|transfer| transfer.label == label) {
+            transfer.attempt = attempt;
+        }
+        if attempt > 1 {
+            self.draw(true);
+        }
+    }
+
+    pub fn transferred(&self, label: &str, bytes: u64, length: Option<u64>) {
+        if let Some(transfer) = self.state.lock().unwrap().active.iter_mut().find(|transfer| transfer.label == label) {
+            transfer.bytes = bytes;
+            transfer.length = length;
+        }
+        self.draw(false);
+    }
+
+    pub fn finish_item(&self, label: &str, ok: bool) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.done += 1;
+            if !ok {
+                state.failed += 1;
+            }
+            state.active.retain(|transfer| transfer.label != label);
+        }
+        self.draw(true);
+    }
+
+    /// The overall line followed by a line for each active transfer.
+    fn lines(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let mut lines = vec![format!("{} {} {}/{}", self.action, bar(state.done as u64, self.total as u64), state.done, self.total)];
+        for transfer in &state.active {
+            let mut line = format!("  {}", transfer.label);
+            match transfer.length {
+                Some(length) => line += &format!(" {} {}/{}", bar(transfer.bytes, length), bytes(transfer.bytes), bytes(length)),
+                None if transfer.bytes > 0 => line += &format!(" {}", bytes(transfer.bytes)),
+                None => (),
+            }
+            if transfer.attempt > 1 {
+                line += &format!(" (retrying, attempt {})", transfer.attempt);
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn summary(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut summary = format!("{}: {} of {} done", self.action, state.done, self.total);
+        if state.failed > 0 {
+            summary += &format!(", {} failed", state.failed);
+        }
+        summary
+    }
+
+    /// Redraw the bars in place, skipping byte updates that arrive faster than the redraw interval.
+    fn draw(&self, force: bool) {
+        if self.mode != ProgressMode::Bars {
+            return;
+        }
+        let lines = self.lines();
+        let mut state = self.state.lock().unwrap();
+        if !force && state.last_draw.map(|last| last.elapsed() < REDRAW_AFTER).unwrap_or(false) {
+            return;
+        }
+        let mut output = self.output.lock().unwrap();
+        if state.drawn > 1 {
+            let _ = write!(output, "\x1b[{}A", state.drawn - 1);
+        }
+        let _ = write!(output, "\r\x1b[J{}", lines.join("\n"));
+        let _ = output.flush();
+        state.drawn = lines.len();
+        state.last_draw = Some(Instant::now());
+    }
+
+    /// Leave the final bars on screen, or write the summary line.
+    pub fn finish(&self) {
+        let mut output = self.output.lock().unwrap();
+        match self.mode {
+            ProgressMode::Bars if self.total > 0 => {
+                let _ = writeln!(output);
+            }
+            ProgressMode::Summary if self.total > 0 => {
+                let _ = writeln!(output, "{}", self.summary());
+            }
+            _ => (),
+        }
+    }
+}
+
+fn bar(done: u64, total: u64) -> String {
+    let filled = (done.min(total) * BAR_WIDTH as u64).checked_div(total).map(|filled| filled as usize).unwrap_or(BAR_WIDTH);
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled))
+}
+
+fn bytes(n: u64) -> String {
+    match n {
+        n if n >= 1 << 30 => format!("{:.1} GiB", n as f64 / (1u64 << 30) as f64),
+        n if n >= 1 << 20 => format!("{:.1} MiB", n as f64 / (1u64 << 20) as f64),
+        n if n >= 1 << 10 => format!("{:.1} KiB", n as f64 / (1u64 << 10) as f64),
+        n => format!("{} B", n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use structopt::StructOpt;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.lock().unwrap().write(buf) }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn progress_lines_show_transfers() {
+        let progress = Progress::new("uploading", 3, ProgressMode::Hidden);
+        assert_eq!(progress.lines(), vec!["uploading [--------------------] 0/3"]);
+        progress.start_item("foo-1");
+        progress.finish_item("foo-1", true);
+        progress.start_item("bar-1");
+        progress.attempt("bar-1", 1);
+        progress.transferred("bar-1", 512, Some(2048));
+        assert_eq!(progress.lines(), vec![
+            "uploading [######--------------] 1/3",
+            "  bar-1 [#####---------------] 512 B/2.0 KiB",
+        ]);
+        progress.attempt("bar-1", 2);
+        assert_eq!(progress.lines()[1], "  bar-1 [#####---------------] 512 B/2.0 KiB (retrying, attempt 2)");
+        progress.finish_item("bar-1", false);
+        assert_eq!(progress.lines(), vec!["uploading [#############-------] 2/3"]);
+    }
+
+    #[test]
+    fn quiet_suppresses_progress() {
+        let mode = ProgressMode::from_args(&Cli::clap().get_matches_from(vec!["ota", "-q", "config", "show"]));
+        assert_eq!(mode, ProgressMode::Summary);
+
+        let buffer = Buffer::default();
+        let progress = Progress::with_output("downloading", 2, mode, Box::new(buffer.clone()));
+        for (label, ok) in &[("foo-1", true), ("bar-1", false)] {
+            progress.start_item(label);
+            progress.transferred(label, 10, Some(20));
+            progress.finish_item(label, *ok);
+        }
+        assert!(buffer.0.lock().unwrap().is_empty());
+        progress.finish();
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output, "downloading: 2 of 2 done, 1 failed\n");
+    }
+}
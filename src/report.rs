@@ -0,0 +1,120 @@
+use reqwest::{header::HeaderMap, StatusCode};
+use serde_json::Value;
+use std::{collections::HashMap, fs::File};
+use uuid::Uuid;
+
+use crate::api::director::{Director, Ecu};
+use crate::api::registry::{DeviceList, GroupList, Members, Registry};
+use crate::cli::ReportFleetArgs;
+use crate::command::{write_command_result, CommandResult, OutputFormat, TableResult};
+use crate::config::Config;
+use crate::error::Result;
+use crate::http::{Http, Pagination};
+use crate::table::TableOptions;
+
+const FLEET_COLUMNS: &[&str] = &["device", "name", "device id", "type", "status", "last seen", "groups", "primary ecu", "hardware id", "installed"];
+
+/// Report on every device, written to `--out` as JSON for a `.json` file and CSV otherwise.
+pub fn fleet_from_args(config: &mut Config, cmd: &ReportFleetArgs, options: &TableOptions) -> Result<CommandResult> {
+    let report = fleet_report(config)?;
+    match cmd.out {
+        Some(ref out) => {
+            let format = if out.to_string_lossy().to_lowercase().ends_with(".json") { OutputFormat::Json } else { OutputFormat::Csv };
+            let devices = report.rows.len();
+            write_command_result(format, options, None, report.into(), &mut File::create(out)?)?;
+            info!("saved a report of {} devices to {}", devices, out.display());
+            Ok(CommandResult::Empty)
+        }
+        None => Ok(report.into()),
+    }
+}
+
+/// Combine the registry devices, their group names and the image installed on their primary ECU
+/// into one row per device.
+///
+/// A device the director has never heard from has no ECUs, and is reported with empty ECU columns.
+pub fn fleet_report(config: &mut Config) -> Result<TableResult> {
+    let pages = Pagination { limit: None, all_pages: true };
+    let (_, page) = Http::execute_pages(Registry::list_all_devices_request(config)?, pages, config.token()?)?;
+    let devices: DeviceList = serde_json::from_value(page)?;
+    let (_, page) = Http::execute_pages(Registry::list_all_groups_request(config)?, pages, config.token()?)?;
+    let groups: GroupList = serde_json::from_value(page)?;
+
+    let mut memberships = HashMap::<Uuid, Vec<Value>>::new();
+    for group in groups.values {
+        let (_, page) = Http::execute_pages(Registry::list_devices_request(config, group.id)?, pages, config.token()?)?;
+        for device in serde_json::from_value::<Members>(page)?.values {
+            memberships.entry(device).or_default().push(group.name.clone().into());
+        }
+    }
+
+    let mut result = TableResult::new(HeaderMap::new(), Vec::new(), FLEET_COLUMNS);
+    for device in devices.values {
+        let ecus = installed_ecus(config, device.uuid)?;
+        let primary = ecus.iter().find(|ecu| ecu.primary).or_else(|| ecus.first());
+        result.add_row(vec![
+            device.uuid.to_string().into(),
+            device.name.into(),
+            device.device_id.into(),
+            device.device_type.into(),
+            device.status.into(),
+            device.last_seen.into(),
+            memberships.remove(&device.uuid).unwrap_or_default().into(),
+            primary.map(|ecu| ecu.id.clone()).into(),
+            primary.map(|ecu| ecu.hardware_id.clone()).into(),
+            primary.and_then(|ecu| ecu.image.as_ref()).map(|image| image.filepath.clone()).into(),
+        ]);
+    }
+    result.response = serde_json::to_vec(&result)?;
+    Ok(result)
+}
+
+/// Fetch the ECUs of a device, or none when the director does not know the device.
+pub(crate) fn installed_ecus(config: &mut Config, device: Uuid) -> Result<Vec<Ecu>> {
+    let resp = Http::execute(Director::get_device_ecus_request(config, device)?, config.token()?)?;
+    if resp.status() == StatusCode::NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    Ok(resp.error_for_status()?.json()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+
+    #[test]
+    fn fleet_report_joins_services() {
+        let (one, two) = ("00000000-0000-0000-0000-000000000001", "00000000-0000-0000-0000-000000000002");
+        let server = MockServer::start(move |req| match (req.method.as_ref(), req.route()) {
+            ("GET", "/api/v1/devices") => MockResponse::json(200, json!({"values": [
+                {"uuid": one, "deviceName": "alpha", "deviceId": "a", "deviceType": "Vehicle", "deviceStatus": "UpToDate", "lastSeen": "2026-01-01T00:00:00Z"},
+                {"uuid": two, "deviceName": "beta, the second"}
+            ], "total": 2})),
+            ("GET", "/api/v1/device_groups") => MockResponse::json(200, json!({"values": [
+                {"id": "00000000-0000-0000-0000-00000000000a", "groupName": "eu"},
+                {"id": "00000000-0000-0000-0000-00000000000b", "groupName": "beta"}
+            ], "total": 2})),
+            ("GET", "/api/v1/device_groups/00000000-0000-0000-0000-00000000000a/devices") => MockResponse::json(200, json!({"values": [one, two], "total": 2})),
+            ("GET", "/api/v1/device_groups/00000000-0000-0000-0000-00000000000b/devices") => MockResponse::json(200, json!({"values": [one], "total": 1})),
+            ("GET", route) if route.ends_with(one) => MockResponse::json(200, json!([
+                {"id": "secondary", "hardwareId": "mcu", "primary": false, "image": {"filepath": "mcu-1"}},
+                {"id": "main", "hardwareId": "rpi", "primary": true, "image": {"filepath": "os-2"}}
+            ])),
+            _ => MockResponse::status(404),
+        });
+        let mut config = server.config();
+        let report = fleet_report(&mut config).expect("report");
+
+        assert_eq!(report.rows[0]["groups"], json!(["eu", "beta"]));
+        assert_eq!(report.rows[0]["primary ecu"], "main");
+        assert_eq!(report.rows[0]["installed"], "os-2");
+        assert_eq!(report.rows[1]["groups"], json!(["eu"]));
+        assert_eq!(report.rows[1]["installed"], Value::Null);
+        let csv = report.csv();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "device,name,device id,type,status,last seen,groups,primary ecu,hardware id,installed");
+        assert_eq!(lines[1], format!("{},alpha,a,Vehicle,UpToDate,2026-01-01T00:00:00Z,\"eu, beta\",main,rpi,os-2", one));
+        assert_eq!(lines[2], format!("{},\"beta, the second\",,,,,eu,,,", two));
+    }
+}
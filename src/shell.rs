@@ -0,0 +1,207 @@
+use clap::{App, ArgMatches, ErrorKind};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter, history::DefaultHistory, validate::Validator, Context, Editor, Helper,
+};
+use std::{fs, iter};
+use structopt::StructOpt;
+
+use crate::cli::{Cli, Command};
+use crate::command::{CommandResult, Exec, Output};
+use crate::config::{self, Config};
+use crate::error::{Error, ErrorFormat, Result};
+use crate::interrupt;
+
+const PROMPT: &str = "ota> ";
+const HISTORY_FILE: &str = "shell_history";
+
+/// Run commands read from a prompt until `exit` or the end of input, with the config and token loaded once.
+///
+/// Each line is parsed like the arguments of `ota`, and a failing or interrupted command prints its
+/// error without ending the session. The HTTP client, and the HTTP flags of the `ota shell` command line, are
+/// shared by every command.
+pub fn run(args: &ArgMatches<'_>) -> Result<CommandResult> {
+    if config::in_session() {
+        return Err(Error::Args("already running in an ota shell".into()));
+    }
+    start_session(args)?;
+    let _scope = interrupt::Scope::shell();
+    let history = Config::load_from_args(args)?.cache_path().map(|dir| dir.join(HISTORY_FILE));
+
+    let mut editor = Editor::<ShellHelper, DefaultHistory>::new().map_err(readline_error)?;
+    editor.set_helper(Some(ShellHelper { app: Cli::clap() }));
+    if let Some(ref path) = history {
+        if let Err(err) = editor.load_history(path) {
+            debug!("no shell history loaded from {}: {}", path.display(), err);
+        }
+    }
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(readline_error(err)),
+        };
+        let words = match split_line(&line) {
+            Ok(words) if words.is_empty() => continue,
+            Ok(words) => words,
+            Err(err) => {
+                eprintln!("{}", ErrorFormat::Text.render(&err));
+                continue;
+            }
+        };
+        editor.add_history_entry(line.as_str()).map_err(readline_error)?;
+        if words[0] == "exit" || words[0] == "quit" {
+            break;
+        }
+        run_line(args, words);
+        interrupt::reset();
+    }
+    config::use_session(None);
+
+    if let Some(path) = history {
+        if let Err(err) = path.parent().map_or(Ok(()), fs::create_dir_all).map_err(ReadlineError::from).and_then(|_| editor.save_history(&path)) {
+            warn!("couldn't save the shell history to {}: {}", path.display(), err);
+        }
+    }
+    Ok(CommandResult::Empty)
+}
+
+/// Load the config and a token from the `ota shell` arguments for the commands to reuse.
+fn start_session(args: &ArgMatches<'_>) -> Result<()> {
+    config::use_session(None);
+    let mut config = Config::load_from_args(args)?;
+    config.token()?;
+    config::use_session(Some(config));
+    Ok(())
+}
+
+/// Parse and run one line of words, printing its result or error.
+fn run_line(shell_args: &ArgMatches<'_>, words: Vec<String>) {
+    let matches = match Cli::clap().get_matches_from_safe(iter::once("ota".to_string()).chain(words)) {
+        Ok(matches) => matches,
+        Err(err) if err.kind == ErrorKind::HelpDisplayed || err.kind == ErrorKind::VersionDisplayed => return println!("{}", err.message),
+        Err(err) => return eprintln!("{}", err.message),
+    };
+    let cli = Cli::from_clap(&matches);
+    let args = matches.subcommand().1.expect("cli args");
+    let result = ErrorFormat::from_args(args).map(|errors| (errors, exec(&cli.command, args)));
+    match result {
+        Ok((errors, Err(err))) => eprintln!("{}", errors.render(&err)),
+        Ok((_, Ok(()))) => (),
+        Err(err) => eprintln!("{}", ErrorFormat::Text.render(&err)),
+    }
+
+    // Pick up any changed config values or token.
+    if let Command::Init(_) | Command::Config(_) | Command::Auth(_) = cli.command {
+        if let Err(err) = start_session(shell_args) {
+            warn!("reloading the config for new commands: {}", err);
+        }
+    }
+}
+
+fn exec(command: &Command, args: &ArgMatches<'_>) -> Result<()> {
+    let output = Output::from_args(args)?;
+    output.write(command.exec(args)?)
+}
+
+fn readline_error(err: ReadlineError) -> Error { Error::Failed(format!("reading the shell prompt: {}", err)) }
+
+/// Split a line into words at whitespace, keeping quoted text and backslash escaped characters together.
+fn split_line(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' | '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some('\\') if c == '"' => word.extend(chars.next()),
+                        Some(next) => word.push(next),
+                        None => return Err(Error::Args(format!("unclosed {} quote", c))),
+                    }
+                }
+            }
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Completes sub-commands and flags from the CLI definition.
+struct ShellHelper {
+    app: App<'static, 'static>,
+}
+
+impl Helper for ShellHelper {}
+impl Highlighter for ShellHelper {}
+impl Validator for ShellHelper {}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> { Ok(complete(&self.app, &line[..pos])) }
+}
+
+/// Complete the last word of `line` with the sub-commands of the command typed before it, or with
+/// its long flags and the global flags when the word starts with `-`.
+///
+/// Returns where the completed word starts, as rustyline expects.
+fn complete(app: &App<'_, '_>, line: &str) -> (usize, Vec<String>) {
+    let start = line.rfind(char::is_whitespace).map_or(0, |space| space + 1);
+    let (typed, word) = line.split_at(start);
+    // The CLI definition is only readable through the parser fields clap uses for its own completions.
+    let mut command = app;
+    for name in typed.split_whitespace() {
+        let named = |sub: &&App<'_, '_>| sub.p.meta.name == name || sub.p.meta.aliases.iter().flatten().any(|(alias, _)| *alias == name);
+        if let Some(sub) = command.p.subcommands.iter().find(named) {
+            command = sub;
+        }
+    }
+
+    let candidates = if word.starts_with('-') {
+        let mut flags = Vec::new();
+        for parser in if std::ptr::eq(command, app) { vec![&app.p] } else { vec![&command.p, &app.p] } {
+            let longs = parser.flags.iter().map(|flag| flag.s.long).chain(parser.opts.iter().map(|opt| opt.s.long));
+            flags.extend(longs.flatten().map(|long| format!("--{}", long)).filter(|flag| flag.starts_with(word)));
+        }
+        flags
+    } else {
+        command.p.subcommands.iter().map(|sub| sub.p.meta.name.clone()).filter(|name| name.starts_with(word)).collect()
+    };
+    (start, candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_quoted_words() {
+        assert_eq!(split_line("  group create  --name 'eu west' ").unwrap(), vec!["group", "create", "--name", "eu west"]);
+        assert_eq!(split_line(r#"package add -n "say \"hi\"" -v 1\ 0 ''"#).unwrap(), vec!["package", "add", "-n", "say \"hi\"", "-v", "1 0", ""]);
+        assert_eq!(split_line("a'b'c").unwrap(), vec!["abc"]);
+        assert!(split_line("").unwrap().is_empty());
+        assert!(split_line("device list --name 'alpha").is_err());
+    }
+
+    #[test]
+    fn complete_from_cli() {
+        let app = Cli::clap();
+        assert_eq!(complete(&app, "dev"), (0, vec!["device".to_string()]));
+        assert_eq!(complete(&app, "devices l"), (8, vec!["list".to_string()]));
+        assert_eq!(complete(&app, "campaign create --nam").1, vec!["--name", "--namespace", "--namespaces"]);
+        let flags = complete(&app, "device list --").1;
+        assert!(flags.contains(&"--all-pages".to_string()) && flags.contains(&"--output".to_string()));
+        assert!(complete(&app, "--out").1.contains(&"--output-file".to_string()));
+    }
+}
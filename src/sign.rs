@@ -0,0 +1,189 @@
+use openssl::{
+    hash::MessageDigest,
+    pkey::{Id, PKey, Private},
+    rsa::Padding,
+    sign::{RsaPssSaltlen, Signer},
+};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{fs, path::Path};
+
+use crate::cli::SignTargetsArgs;
+use crate::command::CommandResult;
+use crate::config;
+use crate::error::{Error, Result};
+
+/// A private key read from a local PEM file, used to sign metadata without uploading the key.
+pub struct SigningKey {
+    pkey: PKey<Private>,
+}
+
+impl SigningKey {
+    /// Read an ed25519 or RSA private key from a PEM file.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let pkey = PKey::private_key_from_pem(&fs::read(path)?)?;
+        match pkey.id() {
+            Id::ED25519 | Id::RSA => Ok(SigningKey { pkey }),
+            _ => Err(Error::Args(format!("{}: only ed25519 and RSA keys are supported", path.display()))),
+        }
+    }
+
+    /// The public key in TUF form, i.e. `{"keytype": .., "keyval": {"public": ..}}`.
+    pub fn public_key(&self) -> Result<Value> {
+        if self.pkey.id() == Id::ED25519 {
+            // The raw key is the last 32 bytes of the DER-encoded SubjectPublicKeyInfo.
+            let der = self.pkey.public_key_to_der()?;
+            Ok(json!({"keytype": "ED25519", "keyval": {"public": hex(&der[der.len() - 32..])}}))
+        } else {
+            let pem = String::from_utf8_lossy(&self.pkey.public_key_to_pem()?).into_owned();
+            Ok(json!({"keytype": "RSA", "keyval": {"public": pem}}))
+        }
+    }
+
+    /// The TUF key id: the SHA256 of the canonical public key.
+    pub fn keyid(&self) -> Result<String> { Ok(hex(&Sha256::digest(canonical_json(&self.public_key()?)))) }
+
+    /// Sign `data`, returning a signature object for the `keyid`.
+    pub fn sign(&self, keyid: &str, data: &[u8]) -> Result<Value> {
+        let (method, sig) = if self.pkey.id() == Id::ED25519 {
+            ("ed25519", Signer::new_without_digest(&self.pkey)?.sign_oneshot_to_vec(data)?)
+        } else {
+            let mut signer = Signer::new(MessageDigest::sha256(), &self.pkey)?;
+            signer.set_rsa_padding(Padding::PKCS1_PSS)?;
+            signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+            signer.update(data)?;
+            ("rsassa-pss-sha256", signer.sign_to_vec()?)
+        };
+        Ok(json!({"keyid": keyid, "method": method, "sig": base64::encode(sig)}))
+    }
+}
+
+/// Add a signature over the canonical `signed` part of some metadata, replacing an older one by the same key.
+pub fn add_signature(metadata: &mut Value, key: &SigningKey, keyid: Option<&str>) -> Result<String> {
+    let keyid = match keyid {
+        Some(keyid) => keyid.to_string(),
+        None => key.keyid()?,
+    };
+    let signed = metadata.get("signed").ok_or_else(|| Error::Parse("metadata has no `signed` field".into()))?;
+    let signature = key.sign(&keyid, &canonical_json(signed))?;
+
+    let signatures = metadata.as_object_mut().expect("metadata object").entry("signatures").or_insert_with(|| json!([]));
+    let signatures = signatures.as_array_mut().ok_or_else(|| Error::Parse("metadata `signatures` is not a list".into()))?;
+    signatures.retain(|signature| signature.get("keyid").and_then(Value::as_str) != Some(keyid.as_str()));
+    signatures.push(signature);
+    Ok(keyid)
+}
+
+/// Sign a metadata file in place, or into `--out`.
+pub fn sign_from_args(cmd: &SignTargetsArgs) -> Result<CommandResult> {
+    let key = SigningKey::from_file(&cmd.key)?;
+    let mut metadata: Value = serde_json::from_slice(&fs::read(&cmd.file)?)?;
+    let keyid = add_signature(&mut metadata, &key, cmd.keyid.as_deref())?;
+
+    let out = cmd.out.as_ref().unwrap_or(&cmd.file);
+    let mut body = serde_json::to_vec_pretty(&metadata)?;
+    body.push(b'\n');
+    config::write_atomic(out, &body)?;
+    info!("signed {} with key {}", out.display(), keyid);
+    Ok(CommandResult::Empty)
+}
+
+/// Serialize as canonical JSON, with sorted object keys and no whitespace.
+pub fn canonical_json(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push(b'{');
+            for (n, (key, value)) in entries.into_iter().enumerate() {
+                if n > 0 {
+                    out.push(b',');
+                }
+                out.extend(serde_json::to_vec(key).expect("json string"));
+                out.push(b':');
+                write_canonical(value, out);
+            }
+            out.push(b'}');
+        }
+        Value::Array(values) => {
+            out.push(b'[');
+            for (n, value) in values.iter().enumerate() {
+                if n > 0 {
+                    out.push(b',');
+                }
+                write_canonical(value, out);
+            }
+            out.push(b']');
+        }
+        _ => out.extend(serde_json::to_vec(value).expect("json value")),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String { bytes.iter().map(|b| format!("{:02x}", b)).collect() }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{rsa::Rsa, sign::Verifier};
+    use structopt::StructOpt;
+
+    fn metadata() -> Value { json!({"signed": {"version": 2, "_type": "Targets", "targets": {"foo-1": {"length": 3}}}, "signatures": []}) }
+
+    #[test]
+    fn canonical_json_sorts_keys() {
+        let value = json!({"b": [1, {"d": "é", "c": null}], "a": true});
+        assert_eq!(String::from_utf8(canonical_json(&value)).unwrap(), r#"{"a":true,"b":[1,{"c":null,"d":"é"}]}"#);
+    }
+
+    #[test]
+    fn sign_file_in_place() {
+        let dir = std::env::temp_dir().join(format!("ota-cli-sign-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (file, key) = (dir.join("targets.json"), dir.join("key.pem"));
+        fs::write(&file, serde_json::to_vec(&metadata()).unwrap()).unwrap();
+        fs::write(&key, PKey::generate_ed25519().unwrap().private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        sign_from_args(&SignTargetsArgs::from_iter(&["targets", "--file", file.to_str().unwrap(), "--key", key.to_str().unwrap()])).expect("sign");
+
+        let signed: Value = serde_json::from_slice(&fs::read(&file).unwrap()).unwrap();
+        assert_eq!(signed["signatures"].as_array().unwrap().len(), 1);
+        assert_eq!(signed["signed"], metadata()["signed"]);
+        let mut names = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["key.pem", "targets.json"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sign_with_ed25519_and_rsa_keys() {
+        let ed25519 = SigningKey { pkey: PKey::generate_ed25519().unwrap() };
+        let rsa = SigningKey { pkey: PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap() };
+        let mut targets = metadata();
+        let ed25519_id = add_signature(&mut targets, &ed25519, None).expect("ed25519");
+        let rsa_id = add_signature(&mut targets, &rsa, Some("rsa-key")).expect("rsa");
+        add_signature(&mut targets, &ed25519, None).expect("re-sign");
+
+        assert_eq!(ed25519_id.len(), 64);
+        assert_eq!(rsa_id, "rsa-key");
+        assert_eq!(ed25519.public_key().unwrap()["keyval"]["public"].as_str().unwrap().len(), 64);
+        let signatures = targets["signatures"].as_array().unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(signatures[0]["method"], "rsassa-pss-sha256");
+        assert_eq!(signatures[1]["method"], "ed25519");
+
+        let data = canonical_json(&metadata()["signed"]);
+        let sig = |n: usize| base64::decode(signatures[n]["sig"].as_str().unwrap()).unwrap();
+        assert!(Verifier::new_without_digest(&ed25519.pkey).unwrap().verify_oneshot(&sig(1), &data).unwrap());
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &rsa.pkey).unwrap();
+        verifier.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+        verifier.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH).unwrap();
+        verifier.update(&data).unwrap();
+        assert!(verifier.verify(&sig(0)).unwrap());
+    }
+}
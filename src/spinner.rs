@@ -0,0 +1,65 @@
+use std::{
+    io::{self, Write},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+const FRAMES: &[char] = &['|', '/', '-', '\\'];
+const TICK: Duration = Duration::from_millis(100);
+
+/// A stderr spinner shown once a request has been waiting longer than a threshold.
+///
+/// The spinner line is cleared when dropped, before any response output is written.
+pub struct Spinner {
+    stop: Option<Sender<()>>,
+    ticker: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Start a ticker thread that draws a spinner after `after` has elapsed.
+    pub fn start(message: String, after: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let ticker = thread::spawn(move || {
+            if stopped.recv_timeout(after) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+            let mut stderr = io::stderr();
+            for frame in FRAMES.iter().cycle() {
+                let _ = write!(stderr, "\r{} {}\x1b[K", frame, message);
+                let _ = stderr.flush();
+                if stopped.recv_timeout(TICK) != Err(RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+            let _ = write!(stderr, "\r\x1b[K");
+            let _ = stderr.flush();
+        });
+        Spinner {
+            stop: Some(stop),
+            ticker: Some(ticker),
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(ticker) = self.ticker.take() {
+            let _ = ticker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn stops_before_threshold() {
+        let started = Instant::now();
+        drop(Spinner::start("waiting".into(), Duration::from_secs(60)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
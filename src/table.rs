@@ -0,0 +1,279 @@
+use clap::ArgMatches;
+use comfy_table::Table;
+use reqwest::header::HeaderMap;
+use serde::{Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+
+use crate::error::{Error, Result};
+
+/// Tabular command output, kept as structured rows for serialization.
+pub struct TableResult {
+    pub headers: HeaderMap,
+    pub columns: Vec<String>,
+    pub rows: Vec<Map<String, Value>>,
+    pub response: Vec<u8>,
+    /// Whether the served body was already written out as it was read, see `Output::streamed`.
+    pub streamed: bool,
+}
+
+impl TableResult {
+    pub fn new(headers: HeaderMap, response: Vec<u8>, columns: &[&str]) -> TableResult {
+        TableResult {
+            headers,
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows: Vec::new(),
+            response,
+            streamed: false,
+        }
+    }
+
+    /// Add a row of values, in the same order as the columns.
+    pub fn add_row(&mut self, values: Vec<Value>) {
+        self.rows.push(self.columns.iter().cloned().zip(values).collect());
+    }
+
+    /// Build a table from a JSON response body, if it holds objects or a list.
+    ///
+    /// A paginated `values` list or a top-level array becomes one row per item,
+    /// with columns in order of first appearance. A single object becomes one
+    /// row per field.
+    pub fn from_json(headers: HeaderMap, response: Vec<u8>) -> Option<TableResult> {
+        let items = match serde_json::from_slice::<Value>(&response).ok()? {
+            Value::Object(mut page) if page.get("values").map(Value::is_array).unwrap_or(false) => match page.remove("values") {
+                Some(Value::Array(items)) => items,
+                _ => return None,
+            },
+            Value::Object(fields) => {
+                let mut result = TableResult::new(headers, response, &["field", "value"]);
+                for (field, value) in fields {
+                    result.add_row(vec![field.into(), value]);
+                }
+                return Some(result);
+            }
+            Value::Array(items) => items,
+            _ => return None,
+        };
+
+        let mut columns = Vec::<String>::new();
+        for item in &items {
+            match item {
+                Value::Object(fields) => {
+                    for key in fields.keys() {
+                        if !columns.contains(key) {
+                            columns.push(key.clone());
+                        }
+                    }
+                }
+                _ if !columns.iter().any(|col| col == "value") => columns.push("value".into()),
+                _ => (),
+            }
+        }
+        let mut result = TableResult::new(headers, response, &columns.iter().map(String::as_str).collect::<Vec<_>>());
+        for item in items {
+            result.rows.push(match item {
+                Value::Object(fields) => fields,
+                value => std::iter::once(("value".to_string(), value)).collect(),
+            });
+        }
+        Some(result)
+    }
+
+    /// Render the rows as a human-readable table.
+    pub fn table(&self) -> Table {
+        let mut table = Table::new();
+        table.set_header(self.columns.clone());
+        for row in &self.rows {
+            table.add_row(self.columns.iter().map(|col| display_value(row.get(col))).collect::<Vec<_>>());
+        }
+        table
+    }
+
+    /// Keep only the given columns, in the given order, dropping other fields from the rows.
+    pub fn select(&mut self, columns: &[String]) -> Result<()> {
+        let columns = columns.iter().map(|col| self.column(col)).collect::<Result<Vec<_>>>()?;
+        for row in &mut self.rows {
+            row.retain(|key, _| columns.contains(key));
+        }
+        self.columns = columns;
+        Ok(())
+    }
+
+    /// Sort the rows by a column, comparing numbers by value and strings in natural order.
+    ///
+    /// The sort is stable and rows without a value come last.
+    pub fn sort_by(&mut self, column: &str) -> Result<()> {
+        let column = self.column(column)?;
+        self.rows.sort_by(|a, b| compare_values(a.get(&column), b.get(&column)));
+        Ok(())
+    }
+
+    /// Find a column by its name, ignoring case and `_`, `-` or space separators, or by a unique prefix.
+    fn column(&self, name: &str) -> Result<String> {
+        let normalize = |name: &str| name.to_lowercase().replace(['_', '-'], " ");
+        let wanted = normalize(name);
+        if let Some(col) = self.columns.iter().find(|col| normalize(col) == wanted) {
+            return Ok(col.clone());
+        }
+        match self.columns.iter().filter(|col| normalize(col).starts_with(&wanted)).collect::<Vec<_>>()[..] {
+            [col] => Ok(col.clone()),
+            _ => Err(Error::Args(format!("unknown column `{}`, expected one of: {}", name, self.columns.join(", ")))),
+        }
+    }
+
+    /// Render as CSV with a header row, leaving missing values empty.
+    pub fn csv(&self) -> String {
+        let mut csv = csv_line(self.columns.iter().cloned());
+        for row in &self.rows {
+            csv.push_str(&csv_line(self.columns.iter().map(|col| match row.get(col) {
+                None | Some(Value::Null) => String::new(),
+                value => display_value(value),
+            })));
+        }
+        csv
+    }
+}
+
+/// Join fields into a CSV line, quoting those with separators, quotes or newlines.
+fn csv_line(fields: impl Iterator<Item = String>) -> String {
+    let fields = fields
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect::<Vec<_>>();
+    format!("{}\n", fields.join(","))
+}
+
+impl Serialize for TableResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.rows.serialize(serializer)
+    }
+}
+
+/// Format a JSON value for display in a table cell.
+pub(crate) fn display_value(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => "None".into(),
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(values)) => values.iter().map(|v| display_value(Some(v))).collect::<Vec<_>>().join(", "),
+        Some(other) => other.to_string(),
+    }
+}
+
+/// Order two cells for sorting, with missing values last.
+fn compare_values(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+    match (a, b) {
+        (None | Some(Value::Null), None | Some(Value::Null)) => Ordering::Equal,
+        (None | Some(Value::Null), _) => Ordering::Greater,
+        (_, None | Some(Value::Null)) => Ordering::Less,
+        (Some(Value::Number(a)), Some(Value::Number(b))) => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+        (a, b) => natural_cmp(&display_value(a), &display_value(b)),
+    }
+}
+
+/// Compare strings with runs of digits compared as numbers, so `1.9` sorts before `1.10`.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, y) = (digits(&mut a), digits(&mut b));
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                match x.len().cmp(&y.len()).then_with(|| x.cmp(y)) {
+                    Ordering::Equal => (),
+                    ordering => return ordering,
+                }
+            }
+            (Some(x), Some(y)) if x != y => return x.cmp(&y),
+            _ => {
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+fn digits(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+}
+
+/// Column selection and sorting from `--columns` and `--sort-by`, applied before a table is written.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct TableOptions {
+    pub columns: Option<Vec<String>>,
+    pub sort_by: Option<String>,
+}
+
+impl<'a> TableOptions {
+    /// Parse a comma separated `--columns` list and a `--sort-by` column.
+    pub fn from_args(args: &ArgMatches<'a>) -> Self {
+        TableOptions {
+            columns: args.value_of("columns").map(|columns| {
+                columns.split(',').map(str::trim).filter(|col| !col.is_empty()).map(String::from).collect()
+            }),
+            sort_by: args.value_of("sort_by").map(String::from),
+        }
+    }
+
+    /// Sort then select the columns of a table, so rows may be sorted by a column that is not shown.
+    pub fn apply(&self, table: &mut TableResult) -> Result<()> {
+        if let Some(column) = &self.sort_by {
+            table.sort_by(column)?;
+        }
+        if let Some(columns) = &self.columns {
+            table.select(columns)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packages() -> TableResult {
+        let mut table = TableResult::new(HeaderMap::new(), Vec::new(), &["target", "name", "version", "hardware ids", "target_format"]);
+        table.add_row(vec!["app-1.10".into(), "app".into(), "1.10".into(), json!(["rpi"]), "BINARY".into()]);
+        table.add_row(vec!["app-1.9".into(), "app".into(), "1.9".into(), json!(["rpi"]), "BINARY".into()]);
+        table.add_row(vec!["os".into(), "os".into(), Value::Null, json!([]), "OSTREE".into()]);
+        table.add_row(vec!["app-1.2".into(), "app".into(), "1.2".into(), json!(["qemu"]), "BINARY".into()]);
+        table
+    }
+
+    #[test]
+    fn select_and_sort_columns() {
+        let mut table = packages();
+        let options = TableOptions { columns: Some(vec!["name".into(), "Version".into(), "hardware".into()]), sort_by: Some("version".into()) };
+        options.apply(&mut table).expect("apply");
+        assert_eq!(table.columns, vec!["name", "version", "hardware ids"]);
+        let versions = table.rows.iter().map(|row| row["version"].clone()).collect::<Vec<_>>();
+        assert_eq!(versions, vec![json!("1.2"), json!("1.9"), json!("1.10"), Value::Null]);
+        assert!(table.rows.iter().all(|row| row.len() == 3));
+
+        let mut table = packages();
+        table.sort_by("target-format").expect("sort by separator");
+        assert_eq!(table.rows[3]["name"], "os");
+        assert!(matches!(table.select(&["missing".into()]), Err(Error::Args(_))));
+        assert!(matches!(table.sort_by("t"), Err(Error::Args(_))));
+    }
+
+    #[test]
+    fn natural_order() {
+        assert_eq!(natural_cmp("1.9", "1.10"), Ordering::Less);
+        assert_eq!(natural_cmp("v02", "v2"), Ordering::Equal);
+        assert_eq!(natural_cmp("a", "b1"), Ordering::Less);
+        assert_eq!(compare_values(Some(&json!(9)), Some(&json!(10.5))), Ordering::Less);
+        assert_eq!(compare_values(None, Some(&json!("a"))), Ordering::Greater);
+    }
+}
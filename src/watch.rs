@@ -0,0 +1,153 @@
+use reqwest::header::HeaderMap;
+use serde_json::Value;
+use std::{
+    io::{self, IsTerminal},
+    thread,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::api::{director::Director, registry::Registry};
+use crate::cli::DeviceWatchArgs;
+use crate::command::{CommandResult, TableResult};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::interrupt::{self, Scope};
+use crate::report;
+
+const CAMPAIGN_PREFIX: &str = "urn:here-ota:campaign:";
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the sleep between polls checks for an interrupt.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Parse CLI arguments to watch the status of a device until interrupted.
+pub fn device_from_args(config: &mut Config, cmd: &DeviceWatchArgs) -> Result<CommandResult> {
+    let interval = match cmd.interval.as_deref() {
+        Some(value) => humantime::parse_duration(value).map_err(|err| Error::Args(format!("--interval `{}`: {}", value, err)))?,
+        None => DEFAULT_INTERVAL,
+    };
+    let device = Registry::resolve_device(config, &cmd.device)?;
+    watch_device(config, device, interval)
+}
+
+/// Poll a device every `interval`, redrawing its status line on stderr, until Ctrl-C.
+///
+/// Without a terminal a line is written each time the status changes instead. The last status
+/// is returned as the command result.
+pub fn watch_device(config: &mut Config, device: Uuid, interval: Duration) -> Result<CommandResult> {
+    let _watching = Scope::watch();
+    let terminal = io::stderr().is_terminal();
+    let mut last: Option<DeviceStatus> = None;
+    loop {
+        let status = DeviceStatus::fetch(config, device)?;
+        if terminal {
+            eprint!("\r{}\x1b[K", status.line());
+        } else if last.as_ref() != Some(&status) {
+            eprintln!("{}", status.line());
+        }
+        last = Some(status);
+
+        let polled = Instant::now();
+        while !interrupt::interrupted() && polled.elapsed() < interval {
+            thread::sleep(TICK.min(interval));
+        }
+        if interrupt::interrupted() {
+            break;
+        }
+    }
+    if terminal {
+        eprintln!();
+    }
+    Ok(last.expect("polled status").result(device)?.into())
+}
+
+/// What `device watch` shows of a device, from the registry and the director.
+#[derive(Clone, Debug, PartialEq)]
+struct DeviceStatus {
+    name: String,
+    status: Option<String>,
+    last_seen: Option<String>,
+    /// The campaign id of the first queued assignment, or its correlation id when not from a campaign.
+    campaign: Option<String>,
+    /// The hardware id and installed image of each ECU, primary first.
+    installed: Vec<(String, String)>,
+}
+
+impl DeviceStatus {
+    fn fetch(config: &mut Config, device: Uuid) -> Result<Self> {
+        let registered = Registry::fetch_device(config, device)?;
+        let campaign = Director::fetch_assignments(config, device)?.into_iter().find_map(|assignment| assignment.correlation_id);
+        let mut ecus = report::installed_ecus(config, device)?;
+        ecus.sort_by(|a, b| b.primary.cmp(&a.primary).then_with(|| a.id.cmp(&b.id)));
+        Ok(DeviceStatus {
+            name: registered.name,
+            status: registered.status,
+            last_seen: registered.last_seen,
+            campaign: campaign.map(|id| id.strip_prefix(CAMPAIGN_PREFIX).map(String::from).unwrap_or(id)),
+            installed: ecus.into_iter().filter_map(|ecu| Some((ecu.hardware_id, ecu.image?.filepath))).collect(),
+        })
+    }
+
+    /// Render the status as a single line, e.g. `rpi-1: Outdated, last seen ..., campaign ..., installed rpi=os-2`.
+    fn line(&self) -> String {
+        let installed = self.installed.iter().map(|(hardware, image)| format!("{}={}", hardware, image)).collect::<Vec<_>>();
+        format!(
+            "{}: {}, last seen {}, {}, installed {}",
+            self.name,
+            self.status.as_deref().unwrap_or("unknown"),
+            self.last_seen.as_deref().unwrap_or("never"),
+            self.campaign.as_ref().map_or_else(|| "no campaign".to_string(), |campaign| format!("campaign {}", campaign)),
+            if installed.is_empty() { "nothing".to_string() } else { installed.join(" ") },
+        )
+    }
+
+    fn result(self, device: Uuid) -> Result<TableResult> {
+        let mut result = TableResult::new(HeaderMap::new(), Vec::new(), &["device", "name", "status", "last seen", "campaign", "installed"]);
+        let installed = self.installed.iter().map(|(hardware, image)| Value::from(format!("{}={}", hardware, image))).collect::<Vec<_>>();
+        result.add_row(vec![
+            device.to_string().into(),
+            self.name.into(),
+            self.status.into(),
+            self.last_seen.into(),
+            self.campaign.into(),
+            installed.into(),
+        ]);
+        result.response = serde_json::to_vec(&result)?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer};
+
+    #[test]
+    fn device_status_from_services() {
+        let device = "00000000-0000-0000-0000-000000000001";
+        let server = MockServer::start(move |req| match req.route() {
+            "/api/v1/devices/00000000-0000-0000-0000-000000000001" => MockResponse::json(200, json!({
+                "uuid": device, "deviceName": "alpha", "deviceStatus": "Outdated", "lastSeen": "2026-01-01T00:00:00Z"
+            })),
+            "/api/v1/assignments/00000000-0000-0000-0000-000000000001" => MockResponse::json(200, json!([
+                {"correlationId": "urn:here-ota:campaign:00000000-0000-0000-0000-00000000000c", "targets": {"main": {}}}
+            ])),
+            "/api/v1/admin/devices/00000000-0000-0000-0000-000000000001" => MockResponse::json(200, json!([
+                {"id": "secondary", "hardwareId": "mcu", "primary": false, "image": {"filepath": "mcu-1"}},
+                {"id": "main", "hardwareId": "rpi", "primary": true, "image": {"filepath": "os-2"}},
+                {"id": "new", "hardwareId": "cam", "primary": false}
+            ])),
+            _ => MockResponse::status(404),
+        });
+        let status = DeviceStatus::fetch(&mut server.config(), device.parse().unwrap()).expect("status");
+
+        assert_eq!(
+            status.line(),
+            "alpha: Outdated, last seen 2026-01-01T00:00:00Z, campaign 00000000-0000-0000-0000-00000000000c, installed rpi=os-2 mcu=mcu-1"
+        );
+        let result = status.result(device.parse().unwrap()).expect("result");
+        assert_eq!(result.rows[0]["installed"], json!(["rpi=os-2", "mcu=mcu-1"]));
+        let idle = DeviceStatus { name: "beta".into(), status: None, last_seen: None, campaign: None, installed: Vec::new() };
+        assert_eq!(idle.line(), "beta: unknown, last seen never, no campaign, installed nothing");
+    }
+}